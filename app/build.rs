@@ -0,0 +1,10 @@
+fn main() {
+    // esp-wifi calls into the ROM, which its linker script
+    // provides
+    if std::env::var_os("CARGO_FEATURE_MQTT").is_some()
+        && std::env::var("CARGO_CFG_TARGET_OS").as_deref()
+            == Ok("none")
+    {
+        println!("cargo:rustc-link-arg=-Trom_functions.x");
+    }
+}
@@ -0,0 +1,310 @@
+//! The alarm of the timer and microwave apps, for when a
+//! countdown runs out.
+//!
+//! An [`Alarm`] rings until it is dismissed. The longer it
+//! rings, the higher its [level](Alarm::level): the beeps
+//! get louder and come more often, and the LED blinks
+//! faster. It can be snoozed for a while, and goes quiet by
+//! itself after [`AUTO_SILENCE_MS`].
+//!
+//! The alarm only keeps time; the apps feed it the current
+//! time, play the [beeps](AlarmEvent::Beep) it asks for
+//! and show the [`AlarmScreen`].
+
+mod screen;
+
+pub use screen::{AlarmAction, AlarmScreen};
+
+/// Number of escalation levels.
+pub const LEVELS: u8 = 4;
+/// Ringing time per level.
+pub const ESCALATE_MS: u64 = 15_000;
+/// After ringing this long the alarm goes quiet, but stays
+/// on screen until it is dismissed.
+pub const AUTO_SILENCE_MS: u64 = 5 * 60_000;
+/// Snooze time of the "+1 min" button.
+pub const SNOOZE_SHORT_MS: u64 = 60_000;
+/// Snooze time of the "+5 min" button.
+pub const SNOOZE_LONG_MS: u64 = 5 * 60_000;
+/// How fast the screen flashes, the same on every level so
+/// it isn't redrawn too often.
+pub const FLASH_MS: u64 = 500;
+
+/// Time between two beeps, by level.
+const BEEP_INTERVAL_MS: [u64; LEVELS as usize] =
+    [2000, 1500, 1000, 500];
+/// LED on and off time, by level.
+const BLINK_MS: [u64; LEVELS as usize] =
+    [500, 300, 200, 100];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmState {
+    Idle,
+    Ringing,
+    /// Quiet until the snooze is over.
+    Snoozed,
+    /// Rang for [`AUTO_SILENCE_MS`] without an answer.
+    Silenced,
+}
+
+/// What [`Alarm::update`] asks the app to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmEvent {
+    /// Play a beep of this level.
+    Beep { level: u8 },
+    /// The snooze is over and the alarm rings again.
+    Rings,
+    /// Nobody answered; stop beeping and blinking.
+    Silenced,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alarm {
+    state: AlarmState,
+    /// When it started ringing, the last time.
+    since_ms: u64,
+    /// End of the snooze, or time of the next beep while
+    /// ringing.
+    next_ms: u64,
+}
+
+impl Alarm {
+    pub const fn new() -> Self {
+        Self {
+            state: AlarmState::Idle,
+            since_ms: 0,
+            next_ms: 0,
+        }
+    }
+
+    pub fn state(&self) -> AlarmState {
+        self.state
+    }
+
+    pub fn is_ringing(&self) -> bool {
+        self.state == AlarmState::Ringing
+    }
+
+    /// Ringing or silenced: the alarm screen is up.
+    pub fn is_shown(&self) -> bool {
+        matches!(
+            self.state,
+            AlarmState::Ringing | AlarmState::Silenced
+        )
+    }
+
+    /// Anything but idle: there is something left to
+    /// dismiss.
+    pub fn is_active(&self) -> bool {
+        self.state != AlarmState::Idle
+    }
+
+    /// Starts ringing from the lowest level, unless it
+    /// rings already.
+    pub fn trigger(&mut self, now: u64) {
+        if !self.is_ringing() {
+            self.ring(now);
+        }
+    }
+
+    /// Goes quiet for `ms`, then rings again. Only while
+    /// the alarm is shown; returns whether it was.
+    pub fn snooze(&mut self, now: u64, ms: u64) -> bool {
+        if !self.is_shown() {
+            return false;
+        }
+        self.state = AlarmState::Snoozed;
+        self.next_ms = now + ms;
+        true
+    }
+
+    pub fn dismiss(&mut self) {
+        self.state = AlarmState::Idle;
+    }
+
+    /// How far the alarm has escalated, from `0` to
+    /// `LEVELS - 1`.
+    pub fn level(&self, now: u64) -> u8 {
+        if !self.is_ringing() {
+            return 0;
+        }
+        let level =
+            now.saturating_sub(self.since_ms) / ESCALATE_MS;
+        level.min(LEVELS as u64 - 1) as u8
+    }
+
+    /// Whether the alarm screen is shown inverted right
+    /// now. Only while ringing.
+    pub fn flash_on(&self, now: u64) -> bool {
+        self.is_ringing()
+            && now.saturating_sub(self.since_ms) / FLASH_MS
+                % 2
+                == 0
+    }
+
+    /// Whether the LED is lit right now. It blinks faster
+    /// on every level, and only while ringing.
+    pub fn led_on(&self, now: u64) -> bool {
+        if !self.is_ringing() {
+            return false;
+        }
+        let blink = BLINK_MS[self.level(now) as usize];
+        now.saturating_sub(self.since_ms) / blink % 2 == 0
+    }
+
+    /// Call regularly (e.g. every frame). Returns at most
+    /// one event per call.
+    pub fn update(
+        &mut self,
+        now: u64,
+    ) -> Option<AlarmEvent> {
+        match self.state {
+            AlarmState::Snoozed if now >= self.next_ms => {
+                self.ring(now);
+                Some(AlarmEvent::Rings)
+            }
+            AlarmState::Ringing
+                if now.saturating_sub(self.since_ms)
+                    >= AUTO_SILENCE_MS =>
+            {
+                self.state = AlarmState::Silenced;
+                Some(AlarmEvent::Silenced)
+            }
+            AlarmState::Ringing if now >= self.next_ms => {
+                let level = self.level(now);
+                self.next_ms =
+                    now + BEEP_INTERVAL_MS[level as usize];
+                Some(AlarmEvent::Beep { level })
+            }
+            _ => None,
+        }
+    }
+
+    fn ring(&mut self, now: u64) {
+        self.state = AlarmState::Ringing;
+        self.since_ms = now;
+        self.next_ms = now;
+    }
+}
+
+impl Default for Alarm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The times of the beeps from `from` to `to`, checked
+    /// every 10 ms like a frame loop would.
+    fn beeps(
+        alarm: &mut Alarm,
+        from: u64,
+        to: u64,
+    ) -> std::vec::Vec<(u64, u8)> {
+        (from..to)
+            .step_by(10)
+            .filter_map(|t| match alarm.update(t) {
+                Some(AlarmEvent::Beep { level }) => {
+                    Some((t, level))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn beeps_get_louder_and_faster() {
+        let mut a = Alarm::new();
+        assert_eq!(a.update(0), None);
+        a.trigger(1000);
+        let b = beeps(&mut a, 1000, 1000 + 4 * ESCALATE_MS);
+        assert_eq!(b[0], (1000, 0));
+        assert_eq!(b[1], (3000, 0));
+        let levels: std::vec::Vec<u8> =
+            b.iter().map(|&(_, l)| l).collect();
+        assert!(levels.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(levels.last(), Some(&(LEVELS - 1)));
+        let last = &b[b.len() - 2..];
+        assert_eq!(last[1].0 - last[0].0, 500);
+        // ringing again doesn't start over
+        a.trigger(100_000);
+        assert_eq!(a.level(100_000), LEVELS - 1);
+    }
+
+    #[test]
+    fn snooze_rings_again_from_the_start() {
+        let mut a = Alarm::new();
+        assert!(!a.snooze(0, SNOOZE_SHORT_MS));
+        a.trigger(0);
+        a.update(0);
+        assert!(a.snooze(40_000, SNOOZE_SHORT_MS));
+        assert!(!a.is_shown());
+        assert!(!a.led_on(40_000));
+        assert_eq!(beeps(&mut a, 40_000, 100_000), []);
+        assert_eq!(
+            a.update(100_000),
+            Some(AlarmEvent::Rings)
+        );
+        assert!(a.is_ringing());
+        assert_eq!(a.level(100_000), 0);
+        assert_eq!(
+            a.update(100_010),
+            Some(AlarmEvent::Beep { level: 0 })
+        );
+    }
+
+    #[test]
+    fn a_new_alarm_ends_the_snooze() {
+        let mut a = Alarm::new();
+        a.trigger(0);
+        a.snooze(1000, SNOOZE_LONG_MS);
+        a.trigger(2000);
+        assert!(a.is_ringing());
+        assert_eq!(
+            a.update(2000),
+            Some(AlarmEvent::Beep { level: 0 })
+        );
+    }
+
+    #[test]
+    fn goes_quiet_by_itself_but_stays_shown() {
+        let mut a = Alarm::new();
+        a.trigger(0);
+        let b = beeps(&mut a, 0, AUTO_SILENCE_MS);
+        assert!(!b.is_empty());
+        assert_eq!(
+            a.update(AUTO_SILENCE_MS),
+            Some(AlarmEvent::Silenced)
+        );
+        assert_eq!(
+            beeps(
+                &mut a,
+                AUTO_SILENCE_MS,
+                2 * AUTO_SILENCE_MS
+            ),
+            []
+        );
+        assert!(a.is_shown());
+        assert!(!a.flash_on(AUTO_SILENCE_MS));
+        // it can still be snoozed, and dismissed
+        assert!(a.snooze(AUTO_SILENCE_MS, SNOOZE_SHORT_MS));
+        a.dismiss();
+        assert!(!a.is_active());
+    }
+
+    #[test]
+    fn led_and_screen_blink_while_ringing() {
+        let mut a = Alarm::new();
+        a.trigger(0);
+        assert!(a.led_on(0) && a.flash_on(0));
+        assert!(!a.led_on(BLINK_MS[0]));
+        assert!(!a.flash_on(FLASH_MS));
+        let late = 3 * ESCALATE_MS;
+        assert!(
+            a.led_on(late) != a.led_on(late + BLINK_MS[3])
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use embedded_graphics::{
+    mono_font::ascii,
+    pixelcolor::Rgb565,
+    prelude::*,
+};
+use kolibri_embedded_gui::{
+    button::Button,
+    label::Label,
+    smartstate::SmartstateProvider,
+    spacer::Spacer,
+    ui::Ui,
+};
+
+use super::{Alarm, SNOOZE_LONG_MS, SNOOZE_SHORT_MS};
+use crate::focus::Focus;
+
+/// What the user picked on the [`AlarmScreen`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmAction {
+    /// Snooze for this many milliseconds.
+    Snooze(u64),
+    Dismiss,
+}
+
+/// Kolibri page of a shown [`Alarm`]. While the alarm
+/// rings the page flashes between normal and inverse
+/// colours.
+#[derive(Debug, Clone)]
+pub struct AlarmScreen {
+    title: &'static str,
+    inverted: bool,
+}
+
+impl AlarmScreen {
+    pub const fn new(title: &'static str) -> Self {
+        Self {
+            title,
+            inverted: false,
+        }
+    }
+
+    /// Draws the title and `lines` with the snooze and
+    /// dismiss buttons. Returns the button the user pressed
+    /// this frame, if any.
+    pub fn show<
+        'a,
+        D: DrawTarget<Color = Rgb565>,
+        const N: usize,
+    >(
+        &mut self,
+        ui: &mut Ui<D, Rgb565>,
+        sm: &mut SmartstateProvider<N>,
+        focus: &mut Focus,
+        alarm: &Alarm,
+        now: u64,
+        lines: impl IntoIterator<Item = &'a str>,
+    ) -> Option<AlarmAction> {
+        let inverted = alarm.flash_on(now);
+        if inverted {
+            let style = ui.style_mut();
+            core::mem::swap(
+                &mut style.background_color,
+                &mut style.text_color,
+            );
+        }
+        if inverted != self.inverted {
+            self.inverted = inverted;
+            ui.clear_background().ok();
+            sm.force_redraw_all();
+        }
+
+        ui.add(Spacer::new(Size::new(0, 30)));
+        ui.add_centered(
+            Label::new(self.title)
+                .smartstate(sm.next())
+                .with_font(ascii::FONT_10X20),
+        );
+        for line in lines {
+            ui.add_centered(
+                Label::new(line).smartstate(sm.next()),
+            );
+        }
+        if !alarm.is_ringing() {
+            ui.add_centered(
+                Label::new("(silenced)")
+                    .smartstate(sm.next())
+                    .with_font(ascii::FONT_6X10),
+            );
+        }
+
+        let mut action = None;
+        ui.add(Spacer::new(Size::new(0, 20)));
+        ui.add_horizontal(Spacer::new(Size::new(60, 0)));
+        if focus
+            .track(ui.add_horizontal(
+                Button::new("+1 min").smartstate(sm.next()),
+            ))
+            .clicked()
+        {
+            action =
+                Some(AlarmAction::Snooze(SNOOZE_SHORT_MS));
+        }
+        ui.add_horizontal(Spacer::new(Size::new(10, 0)));
+        if focus
+            .track(ui.add(
+                Button::new("+5 min").smartstate(sm.next()),
+            ))
+            .clicked()
+        {
+            action =
+                Some(AlarmAction::Snooze(SNOOZE_LONG_MS));
+        }
+        ui.add(Spacer::new(Size::new(0, 10)));
+        if focus
+            .track(
+                ui.add_centered(
+                    Button::new("Dismiss")
+                        .smartstate(sm.next()),
+                ),
+            )
+            .clicked()
+        {
+            action = Some(AlarmAction::Dismiss);
+        }
+        action
+    }
+}
@@ -6,21 +6,18 @@ use core::cell::RefCell;
 use display_interface_spi::SPIInterface;
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_sync::{
-    blocking_mutex::{raw::NoopRawMutex, NoopMutex},
-    signal::Signal,
-};
+use embassy_sync::blocking_mutex::NoopMutex;
 use embassy_time::{Duration, Timer};
 use embedded_graphics::{
     mono_font::ascii,
     pixelcolor::Rgb565,
-    prelude::{DrawTarget, Point, RgbColor},
+    prelude::{DrawTarget, RgbColor},
 };
 use esp_backtrace as _;
 use esp_hal::{
     clock::ClockControl,
     gpio,
-    gpio::{GpioPin, Input, Io, Level, Output, Pull, NO_PIN},
+    gpio::{Io, Level, Output, NO_PIN},
     peripherals::{Peripherals, SPI2, SPI3},
     prelude::*,
     rtc_cntl::Rtc,
@@ -29,12 +26,15 @@ use esp_hal::{
     timer::{timg::TimerGroup, OneShotTimer},
 };
 use esp_println::println;
-use kolibri_cyd_tester_app_embassy::Debouncer;
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchInteraction},
+    touch_task,
+};
 use kolibri_embedded_gui::{
     button::Button,
     label::Label,
     style::medsize_rgb565_style,
-    ui::{Interaction, Ui},
+    ui::Ui,
 };
 use mipidsi::{
     models::{ILI9486Rgb565, ILI9486Rgb666},
@@ -42,39 +42,6 @@ use mipidsi::{
     Builder,
 };
 use static_cell::{make_static, StaticCell};
-use xpt2046::Xpt2046;
-
-#[embassy_executor::task]
-async fn touch_task(
-    touch_irq: GpioPin<36>,
-    spi: &'static mut NoopMutex<RefCell<Spi<'static, SPI3, FullDuplexMode>>>,
-    touch_cs: GpioPin<33>,
-    touch_signal: &'static Signal<NoopRawMutex, Option<Point>>,
-) -> ! {
-    let mut touch_driver = Xpt2046::new(
-        SpiDevice::new(spi, Output::new(touch_cs, Level::Low)),
-        Input::new(touch_irq, Pull::Up),
-        xpt2046::Orientation::LandscapeFlipped,
-    );
-    touch_driver.set_num_samples(16);
-    touch_driver.init(&mut embassy_time::Delay).unwrap();
-
-    let mut debounce = Debouncer::new();
-    println!("touch task");
-
-    loop {
-        touch_driver.run().expect("Running Touch driver failed");
-        if touch_driver.is_touched() {
-            let point = touch_driver.get_touch_point();
-            touch_signal.signal(Some(Point::new(point.x + 25, 240 - point.y)));
-        } else {
-            touch_signal.signal(None);
-        }
-        Timer::after(Duration::from_millis(1)).await; // 100 a second
-
-        // Your touch handling logic here
-    }
-}
 
 #[main]
 async fn main(spawner: Spawner) {
@@ -154,12 +121,11 @@ async fn main(spawner: Spawner) {
     let touch_spi_bus = NoopMutex::new(RefCell::new(touch_spi));
     let touch_spi_bus = TOUCH_SPI_BUS.init(touch_spi_bus);
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<Signal<NoopRawMutex, Option<Point>>> = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
+    static TOUCH_EVENTS: StaticCell<TouchChannel> = StaticCell::new();
+    let touch_events = &*TOUCH_EVENTS.init(TouchChannel::new());
 
     spawner
-        .spawn(touch_task(touch_irq, touch_spi_bus, touch_cs, touch_signal))
+        .spawn(touch_task(touch_irq, touch_spi_bus, touch_cs, touch_events))
         .unwrap();
 
     // init RGB LED pins
@@ -182,7 +148,7 @@ async fn main(spawner: Spawner) {
     // TODO: Spawn some tasks
     let _ = spawner;
 
-    let mut last_touch = None;
+    let mut touch = TouchInteraction::new();
 
     static BUF_CELL: StaticCell<[Rgb565; 100 * 100]> = StaticCell::new();
     let buf = BUF_CELL.init([Rgb565::BLACK; 100 * 100]);
@@ -191,18 +157,7 @@ async fn main(spawner: Spawner) {
     loop {
         let mut ui = Ui::new_fullscreen(&mut display, medsize_rgb565_style());
         ui.set_buffer(buf);
-        if let Some(touch) = touch_signal.try_take() {
-            let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => Interaction::Drag(point),
-                (Some(point), None) => Interaction::Click(point),
-                (None, Some(point)) => Interaction::Release(point),
-                (None, None) => Interaction::None,
-            };
-            ui.interact(interact);
-            println!("{:?}, {:?}, {:?}", last_touch, touch, interact);
-
-            last_touch = touch;
-        }
+        ui.interact(touch.next(touch_events.try_receive()));
         ui.sub_ui(|ui| {
             ui.style_mut().default_font = ascii::FONT_9X18_BOLD;
             ui.add(Label::new("Kolibri Tester"));
@@ -1,52 +1,133 @@
 #![no_std]
 #![no_main]
 
+//! Touch calibration. Shows five crosshairs, fits an affine
+//! transform to the presses and stores it in flash, where
+//! the shared touch task picks it up on the next boot.
+
 use core::cell::RefCell;
 
 use display_interface_spi::SPIInterface;
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_sync::{
-    blocking_mutex::{raw::NoopRawMutex, NoopMutex},
-    signal::Signal,
-};
+use embassy_sync::blocking_mutex::NoopMutex;
 use embassy_time::{Duration, Timer};
-use embedded_graphics::{mono_font::ascii, prelude::Point};
-use embedded_graphics::pixelcolor::Rgb565;
-use embedded_graphics::prelude::{DrawTarget, RgbColor};
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle},
+    text::{Alignment, Text},
+};
 use esp_backtrace as _;
 use esp_hal::{
     clock::ClockControl,
-    gpio,
-    gpio::{GpioPin, Input, Io, Level, Output, Pull, NO_PIN},
+    gpio::{Input, Io, Level, Output, Pull, NO_PIN},
     peripherals::{Peripherals, SPI2, SPI3},
     prelude::*,
     rtc_cntl::Rtc,
     spi::{master::Spi, FullDuplexMode, SpiMode},
     system::SystemControl,
-    timer::{timg::TimerGroup, OneShotTimer},
+    timer::timg::TimerGroup,
 };
 use esp_println::println;
-use kolibri_cyd_tester_app_embassy::Debouncer;
-use kolibri_embedded_gui::{button::Button, label::Label, style::medsize_rgb565_style, ui::Ui};
-use kolibri_embedded_gui::ui::Interaction;
+use kolibri_cyd_tester_app_embassy::{
+    calibration::{self, Calibrator, Fit},
+    storage,
+    touch::driver::{TouchController, MIN_PRESSURE},
+};
+use kolibri_embedded_gui::{
+    button::Button,
+    label::Label,
+    smartstate::SmartstateProvider,
+    style::medsize_rgb565_style,
+    ui::{Interaction, Ui},
+};
 use mipidsi::{
-    models::{ILI9486Rgb565, ILI9486Rgb666},
-    options::{ColorInversion, ColorOrder, Orientation, Rotation},
+    models::ILI9341Rgb565,
+    options::{ColorOrder, Orientation, Rotation},
     Builder,
 };
-use static_cell::{make_static, StaticCell};
-use xpt2046::Xpt2046;
+use static_cell::StaticCell;
+
+/// Reads the raw touch point, `None` unless the panel is
+/// pressed firmly enough.
+fn read_raw<SPI: embedded_hal::spi::SpiDevice>(
+    controller: &mut TouchController<SPI>,
+    pen_down: bool,
+) -> Option<Point> {
+    pen_down
+        .then(|| {
+            controller
+                .sample()
+                .expect("Reading touch controller failed")
+        })
+        .filter(|s| s.pressure() >= MIN_PRESSURE)
+        .map(|s| s.point())
+}
+
+/// Draws the crosshair for `target` and the progress text.
+fn draw_target<D: DrawTarget<Color = Rgb565>>(
+    display: &mut D,
+    target: Point,
+    (done, total): (usize, usize),
+) -> Result<(), D::Error> {
+    display.clear(Rgb565::BLACK)?;
+    let style =
+        PrimitiveStyle::with_stroke(Rgb565::WHITE, 1);
+    Line::new(
+        target - Point::new(10, 0),
+        target + Point::new(10, 0),
+    )
+    .into_styled(style)
+    .draw(display)?;
+    Line::new(
+        target - Point::new(0, 10),
+        target + Point::new(0, 10),
+    )
+    .into_styled(style)
+    .draw(display)?;
+    Circle::with_center(target, 9)
+        .into_styled(PrimitiveStyle::with_stroke(
+            Rgb565::RED,
+            1,
+        ))
+        .draw(display)?;
+
+    let mut textbuf = [0u8; 32];
+    let text = format_no_std::show(
+        &mut textbuf,
+        format_args!(
+            "Tap the cross {}/{}",
+            done + 1,
+            total
+        ),
+    )
+    .unwrap_or("Tap the cross");
+    Text::with_alignment(
+        text,
+        Point::new(160, 100),
+        MonoTextStyle::new(
+            &ascii::FONT_9X18_BOLD,
+            Rgb565::WHITE,
+        ),
+        Alignment::Center,
+    )
+    .draw(display)?;
+    Ok(())
+}
 
 #[main]
-async fn main(spawner: Spawner) {
+async fn main(_spawner: Spawner) {
     let peripherals = Peripherals::take();
     let system = SystemControl::new(peripherals.SYSTEM);
-    let mut clocks = ClockControl::boot_defaults(system.clock_control).freeze();
+    let mut clocks =
+        ClockControl::boot_defaults(system.clock_control)
+            .freeze();
 
     // Enable the RWDT watchdog timer:
     let mut rtc = Rtc::new(peripherals.LPWR);
-    rtc.rwdt.set_timeout(200000.secs());
+    rtc.rwdt.set_timeout(2.secs());
     rtc.rwdt.enable();
     println!("RWDT watchdog enabled!");
 
@@ -56,49 +137,50 @@ async fn main(spawner: Spawner) {
     println!("Embassy initialized!");
 
     let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
-    // let mut led = Output::new(io.pins.gpio5, Level::High);
-    // Initialize SPI
     let sclk = io.pins.gpio14;
     let miso = io.pins.gpio12;
     let mosi = io.pins.gpio13;
     let cs = io.pins.gpio15;
     let dc = io.pins.gpio2;
-    let mut backlight = Output::new(io.pins.gpio21, Level::Low);
-
-    // Note: RST is not initialized as it's set to -1 in the instructions
+    let mut backlight =
+        Output::new(io.pins.gpio21, Level::Low);
 
-    // up to 80MHz is possible (even tho the display driver isn't supposed to be
-    // that fast) Dataseheet sais 10MHz, so we're gonna go with that
-    let mut spi = Spi::new(peripherals.SPI2, 10.MHz(), SpiMode::Mode0, &mut clocks).with_pins(
+    let spi = Spi::new(
+        peripherals.SPI2,
+        10.MHz(),
+        SpiMode::Mode0,
+        &mut clocks,
+    )
+    .with_pins(
         Some(sclk),
         Some(mosi),
         Some(miso),
         NO_PIN,
     );
 
-    static DISP_SPI_BUS: StaticCell<NoopMutex<RefCell<Spi<SPI2, FullDuplexMode>>>> =
-        StaticCell::new();
+    static DISP_SPI_BUS: StaticCell<
+        NoopMutex<RefCell<Spi<SPI2, FullDuplexMode>>>,
+    > = StaticCell::new();
     let spi_bus = NoopMutex::new(RefCell::new(spi));
     let spi_bus = DISP_SPI_BUS.init(spi_bus);
 
     let di = SPIInterface::new(
-        SpiDevice::new(spi_bus, Output::new(cs, Level::Low)),
+        SpiDevice::new(
+            spi_bus,
+            Output::new(cs, Level::Low),
+        ),
         Output::new(dc, Level::Low),
     );
-    let mut display = Builder::new(ILI9486Rgb565, di)
+    let mut display = Builder::new(ILI9341Rgb565, di)
         .orientation(Orientation {
             rotation: Rotation::Deg90,
             mirrored: true,
         })
         .color_order(ColorOrder::Bgr)
-        .invert_colors(ColorInversion::Inverted)
         .init(&mut embassy_time::Delay)
         .unwrap();
 
-    {
-        let mut ui = Ui::new_fullscreen(&mut display, medsize_rgb565_style());
-        ui.clear_background().ok();
-    }
+    display.clear(Rgb565::BLACK).ok();
     backlight.set_high();
 
     // init touchscreen pins
@@ -108,72 +190,227 @@ async fn main(spawner: Spawner) {
     let touch_clk = io.pins.gpio25;
     let touch_cs = io.pins.gpio33;
 
-    let mut touch_spi = Spi::new(peripherals.SPI3, 10.MHz(), SpiMode::Mode0, &mut clocks)
-        .with_pins(Some(touch_clk), Some(touch_mosi), Some(touch_miso), NO_PIN);
-    static TOUCH_SPI_BUS: StaticCell<NoopMutex<RefCell<Spi<SPI3, FullDuplexMode>>>> =
-        StaticCell::new();
-    let touch_spi_bus = NoopMutex::new(RefCell::new(touch_spi));
+    // 2MHz is the MAX! DO NOT DECREASE! This is really
+    // important.
+    let touch_spi = Spi::new(
+        peripherals.SPI3,
+        2.MHz(),
+        SpiMode::Mode0,
+        &mut clocks,
+    )
+    .with_pins(
+        Some(touch_clk),
+        Some(touch_mosi),
+        Some(touch_miso),
+        NO_PIN,
+    );
+    static TOUCH_SPI_BUS: StaticCell<
+        NoopMutex<RefCell<Spi<SPI3, FullDuplexMode>>>,
+    > = StaticCell::new();
+    let touch_spi_bus =
+        NoopMutex::new(RefCell::new(touch_spi));
     let touch_spi_bus = TOUCH_SPI_BUS.init(touch_spi_bus);
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<Signal<NoopRawMutex, Option<Point>>> = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
-
-    let mut touch_driver = Xpt2046::new(
-        SpiDevice::new(touch_spi_bus, Output::new(touch_cs, Level::Low)),
-        Input::new(touch_irq, Pull::Up),
-        xpt2046::Orientation::LandscapeFlipped,
-    );
-    touch_driver.set_num_samples(128);
-    touch_driver.init(&mut embassy_time::Delay).unwrap();
-    touch_driver.calibrate(&mut display, &mut embassy_time::Delay).unwrap();
+    // The touch task applies the stored calibration, so
+    // this app drives the controller itself to get raw
+    // points.
+    let mut controller =
+        TouchController::new(SpiDevice::new(
+            touch_spi_bus,
+            Output::new(touch_cs, Level::Low),
+        ));
+    let pen_irq = Input::new(touch_irq, Pull::Up);
 
-    // println!("{:?}", touch_driver.calibration_data);
+    static BUF_CELL: StaticCell<[Rgb565; 100 * 100]> =
+        StaticCell::new();
+    let buf = BUF_CELL.init([Rgb565::BLACK; 100 * 100]);
+    let mut textbuf = [0u8; 64];
 
     loop {
-        touch_driver.run();
-        println!("{:?}", touch_driver.get_touch_point());
-    }
-
-    // init RGB LED pins
-
-    let mut red_led = Output::new(io.pins.gpio4, Level::High);
-    let mut green_led = Output::new(io.pins.gpio16, Level::High);
-    let mut blue_led = Output::new(io.pins.gpio17, Level::High);
-
-    // TODO: Spawn some tasks
-    let _ = spawner;
+        // collect one press per target
+        let mut calibrator =
+            Calibrator::new(&calibration::TARGETS);
+        while let Some(target) = calibrator.current_target()
+        {
+            draw_target(
+                &mut display,
+                target,
+                calibrator.progress(),
+            )
+            .ok();
+            loop {
+                let sample = read_raw(
+                    &mut controller,
+                    pen_irq.is_low(),
+                );
+                rtc.rwdt.feed();
+                if calibrator.feed(sample) {
+                    break;
+                }
+                Timer::after(Duration::from_millis(1))
+                    .await;
+            }
+        }
 
-    let mut last_touch = None;
+        let Some(fit) = calibrator.fit() else {
+            println!(
+                "calibration failed: points are collinear"
+            );
+            display.clear(Rgb565::BLACK).ok();
+            Text::with_alignment(
+                "Calibration failed,\nplease try again",
+                Point::new(160, 110),
+                MonoTextStyle::new(
+                    &ascii::FONT_9X18_BOLD,
+                    Rgb565::WHITE,
+                ),
+                Alignment::Center,
+            )
+            .draw(&mut display)
+            .ok();
+            for _ in 0..20 {
+                rtc.rwdt.feed();
+                Timer::after(Duration::from_millis(100))
+                    .await;
+            }
+            continue;
+        };
+        println!(
+            "calibration: {:?}, rms error {} px, max error {} px",
+            fit.transform, fit.rms_error, fit.max_error
+        );
 
-    static BUF_CELL: StaticCell<[Rgb565; 100*100]> = StaticCell::new();
-    let buf = BUF_CELL.init([Rgb565::BLACK; 100 * 100]);
+        // show the result, mapping touches with the new fit
+        // so it can be tried out before saving
+        let Fit {
+            transform,
+            rms_error,
+            max_error,
+        } = fit;
+        {
+            let mut ui = Ui::new_fullscreen(
+                &mut display,
+                medsize_rgb565_style(),
+            );
+            ui.clear_background().ok();
+        }
+        let mut sm = SmartstateProvider::<20>::new();
+        let mut last_touch = None;
+        let mut last_point = Point::zero();
+        let mut saved = None;
+        loop {
+            sm.restart_counter();
+            let touch =
+                read_raw(&mut controller, pen_irq.is_low())
+                    .map(|p| transform.apply(p));
 
-    // Periodically feed the RWDT watchdog timer when our tasks are not running:
-    loop {
-        let mut ui = Ui::new_fullscreen(&mut display, medsize_rgb565_style());
-        ui.set_buffer(buf);
-        if let Some(touch) = touch_signal.try_take() {
+            let mut ui = Ui::new_fullscreen(
+                &mut display,
+                medsize_rgb565_style(),
+            );
             let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => Interaction::Drag(point),
-                (Some(point), None) => Interaction::Click(point),
-                (None, Some(point)) => Interaction::Release(point),
+                (Some(point), Some(_)) => {
+                    Interaction::Drag(point)
+                }
+                (Some(point), None) => {
+                    Interaction::Click(point)
+                }
+                (None, Some(point)) => {
+                    Interaction::Release(point)
+                }
                 (None, None) => Interaction::None,
             };
             ui.interact(interact);
-            println!("{:?}, {:?}, {:?}", last_touch, touch, interact);
-
             last_touch = touch;
-        }
-        ui.sub_ui(|ui| {
-            ui.style_mut().default_font = ascii::FONT_9X18_BOLD;
-            ui.add(Label::new("Kolibri Tester"));
-            Ok(())
-        })
+            ui.set_buffer(buf);
+
+            ui.sub_ui(|ui| {
+                ui.style_mut().default_font =
+                    ascii::FONT_9X18_BOLD;
+                ui.add(
+                    Label::new("Touch Calibration")
+                        .smartstate(sm.next()),
+                );
+                Ok(())
+            })
             .ok();
-        ui.add_horizontal(Button::new("Works!"));
-        ui.add(Button::new("And pretty nicely!"));
-        rtc.rwdt.feed();
-        Timer::after(Duration::from_millis(17)).await; // 60 a second
+            ui.add(
+                Label::new(
+                    format_no_std::show(
+                        &mut textbuf,
+                        format_args!(
+                            "error: {:.1} px rms, {:.1} px max",
+                            rms_error, max_error
+                        ),
+                    )
+                    .unwrap(),
+                )
+                .smartstate(sm.next()),
+            );
+
+            if let Some(point) = touch {
+                if point != last_point {
+                    sm.peek().force_redraw();
+                    last_point = point;
+                }
+            }
+            ui.add(
+                Label::new(
+                    format_no_std::show(
+                        &mut textbuf,
+                        format_args!(
+                            "touch: {}, {}",
+                            last_point.x, last_point.y
+                        ),
+                    )
+                    .unwrap(),
+                )
+                .smartstate(sm.next()),
+            );
+
+            let save = ui
+                .add_horizontal(
+                    Button::new("Save")
+                        .smartstate(sm.next()),
+                )
+                .clicked();
+            let retry = ui
+                .add(
+                    Button::new("Retry")
+                        .smartstate(sm.next()),
+                )
+                .clicked();
+
+            if save {
+                saved = Some(
+                    match transform
+                        .save(&mut storage::open())
+                    {
+                        Ok(()) => true,
+                        Err(e) => {
+                            println!(
+                                "saving calibration failed: {:?}",
+                                e
+                            );
+                            false
+                        }
+                    },
+                );
+                sm.peek().force_redraw();
+            }
+            ui.add(
+                Label::new(match saved {
+                    Some(true) => "Saved, restart to apply",
+                    Some(false) => "Saving failed",
+                    None => "",
+                })
+                .smartstate(sm.next()),
+            );
+            rtc.rwdt.feed();
+            if retry {
+                break;
+            }
+            Timer::after(Duration::from_millis(17)).await;
+        }
     }
 }
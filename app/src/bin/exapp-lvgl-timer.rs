@@ -8,21 +8,18 @@ use core::{cell::RefCell, cmp::min, fmt};
 use display_interface_spi::SPIInterface;
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_sync::{
-    blocking_mutex::{raw::NoopRawMutex, NoopMutex},
-    signal::Signal,
-};
+use embassy_sync::blocking_mutex::NoopMutex;
 use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::{
     mono_font::ascii,
     pixelcolor::Rgb565,
-    prelude::{DrawTarget, Point, RgbColor, Size, WebColors},
+    prelude::{DrawTarget, RgbColor, Size, WebColors},
 };
 use embedded_graphics_profiler_display::ProfilerDisplay;
 use esp_backtrace as _;
 use esp_hal::{
     clock::ClockControl,
-    gpio::{GpioPin, Input, Io, Level, Output, Pull, NO_PIN},
+    gpio::{Io, Level, Output, NO_PIN},
     peripherals::{Peripherals, SPI2, SPI3},
     prelude::*,
     rtc_cntl::Rtc,
@@ -31,7 +28,11 @@ use esp_hal::{
     timer::timg::TimerGroup,
 };
 use esp_println::println;
-use kolibri_cyd_tester_app_embassy::Debouncer;
+use kolibri_cyd_tester_app_embassy::countdown::{CountdownTimer, EmbassyClock, TimerEvent};
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchInteraction},
+    touch_task,
+};
 use kolibri_embedded_gui::{
     button::Button,
     checkbox::Checkbox,
@@ -42,7 +43,7 @@ use kolibri_embedded_gui::{
     smartstate::SmartstateProvider,
     spacer::Spacer,
     style::medsize_rgb565_style,
-    ui::{Interaction, Ui},
+    ui::Ui,
 };
 use lvgl::{self, Display, DrawBuffer};
 use mipidsi::{
@@ -51,7 +52,6 @@ use mipidsi::{
     Builder,
 };
 use static_cell::StaticCell;
-use xpt2046::Xpt2046;
 
 // fn init_heap() {
 //     const HEAP_SIZE: usize = 32 * 1024;
@@ -66,132 +66,6 @@ use xpt2046::Xpt2046;
 //     }
 // }
 
-#[embassy_executor::task]
-async fn touch_task(
-    touch_irq: GpioPin<36>,
-    spi: &'static mut NoopMutex<RefCell<Spi<'static, SPI3, FullDuplexMode>>>,
-    touch_cs: GpioPin<33>,
-    touch_signal: &'static Signal<NoopRawMutex, Option<Point>>,
-) -> ! {
-    let mut touch_driver = Xpt2046::new(
-        SpiDevice::new(spi, Output::new(touch_cs, Level::Low)),
-        Input::new(touch_irq, Pull::Up),
-        xpt2046::Orientation::LandscapeFlipped,
-    );
-    touch_driver.set_num_samples(16);
-    touch_driver.init(&mut embassy_time::Delay).unwrap();
-
-    println!("touch task");
-
-    loop {
-        touch_driver.run().expect("Running Touch driver failed");
-        if touch_driver.is_touched() {
-            let point = touch_driver.get_touch_point();
-            touch_signal.signal(Some(Point::new(point.x + 25, 240 - point.y)));
-        } else {
-            touch_signal.signal(None);
-        }
-        Timer::after(Duration::from_millis(1)).await; // 100 a second
-
-        // Your touch handling logic here
-    }
-}
-
-struct AppData {
-    timer_start: Instant,
-    timer_set_duration: Duration,
-    timer_remaining_duration: Duration,
-    timer_running: bool,
-    timer_paused: bool,
-}
-
-impl AppData {
-    fn new() -> Self {
-        Self {
-            timer_start: Instant::now(),
-            timer_set_duration: Duration::from_secs(10),
-            timer_remaining_duration: Duration::from_secs(10),
-            timer_running: false,
-            timer_paused: false,
-        }
-    }
-
-    fn set_timer_duration(&mut self, duration: Duration) {
-        self.timer_set_duration = duration;
-    }
-
-    fn add_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_add(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(5999))
-                .min(Duration::from_secs(5999)),
-        );
-    }
-
-    fn sub_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_sub(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(10))
-                .max(Duration::from_secs(10)),
-        );
-    }
-
-    fn start_timer(&mut self) {
-        if !self.timer_paused {
-            self.timer_remaining_duration = self.timer_set_duration;
-        }
-
-        self.timer_start = Instant::now();
-        self.timer_running = true;
-        self.timer_paused = false;
-    }
-
-    fn pause_timer(&mut self) {
-        self.timer_paused = true;
-        self.timer_remaining_duration = self
-            .timer_remaining_duration
-            .checked_sub(self.timer_start.elapsed())
-            .unwrap_or(Duration::from_secs(0));
-    }
-
-    fn reset_timer(&mut self) {
-        self.timer_start = Instant::now();
-        self.timer_paused = false;
-        self.timer_running = false;
-        self.timer_remaining_duration = self.timer_set_duration;
-    }
-
-    fn remaining(&self) -> Duration {
-        if self.timer_stopped() {
-            self.timer_set_duration
-        } else if self.timer_paused() {
-            self.timer_remaining_duration
-        } else {
-            self.timer_remaining_duration
-                .checked_sub(self.timer_start.elapsed())
-                .unwrap_or(Duration::from_secs(0))
-        }
-    }
-
-    fn timer_stopped(&self) -> bool {
-        !self.timer_running
-    }
-
-    fn timer_paused(&self) -> bool {
-        self.timer_running && self.timer_paused
-    }
-
-    fn timer_running(&self) -> bool {
-        self.timer_running && !self.timer_paused
-    }
-
-    fn timer_finished(&self) -> bool {
-        self.timer_running && self.remaining() == Duration::from_secs(0)
-    }
-}
-
 #[main]
 async fn main(spawner: Spawner) {
     let peripherals = Peripherals::take();
@@ -276,25 +150,23 @@ async fn main(spawner: Spawner) {
     let touch_spi_bus = NoopMutex::new(RefCell::new(touch_spi));
     let touch_spi_bus = TOUCH_SPI_BUS.init(touch_spi_bus);
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<Signal<NoopRawMutex, Option<Point>>> = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
+    static TOUCH_EVENTS: StaticCell<TouchChannel> = StaticCell::new();
+    let touch_events = &*TOUCH_EVENTS.init(TouchChannel::new());
 
     spawner
-        .spawn(touch_task(touch_irq, touch_spi_bus, touch_cs, touch_signal))
+        .spawn(touch_task(touch_irq, touch_spi_bus, touch_cs, touch_events))
         .unwrap();
 
     // TODO: Spawn some tasks
     let _ = spawner;
 
     // variables
-    let mut appdata = AppData::new();
+    let mut timer = CountdownTimer::new(EmbassyClock);
     let (mut prev_mins, mut prev_secs, mut prev_millis) = (0, 0, 0);
-    let mut finished = false;
 
     // touchpoints
 
-    let mut last_touch = None;
+    let mut touch = TouchInteraction::new();
 
     static BUF_CELL: StaticCell<[Rgb565; 100 * 100]> = StaticCell::new();
     let buf = BUF_CELL.init([Rgb565::BLACK; 100 * 100]);
@@ -311,18 +183,7 @@ async fn main(spawner: Spawner) {
         let start_time = embassy_time::Instant::now();
         sm.restart_counter();
         let mut ui = Ui::new_fullscreen(&mut display, medsize_rgb565_style());
-        if let Some(touch) = touch_signal.try_take() {
-            let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => Interaction::Drag(point),
-                (Some(point), None) => Interaction::Click(point),
-                (None, Some(point)) => Interaction::Release(point),
-                (None, None) => Interaction::None,
-            };
-            ui.interact(interact);
-            // println!("{:?}, {:?}, {:?}", last_touch, touch, interact);
-
-            last_touch = touch;
-        }
+        ui.interact(touch.next(touch_events.try_receive()));
 
         // BUFFER ENABLE/DISABLE
         ui.set_buffer(buf);
@@ -335,7 +196,8 @@ async fn main(spawner: Spawner) {
         })
         .ok();
 
-        let remaining = appdata.remaining();
+        let timer_event = timer.update();
+        let remaining = Duration::from_millis(timer.remaining_ms());
 
         ui.add(Spacer::new(Size::new(0, 60)));
         ui.add_horizontal(Spacer::new(Size::new(80, 0)));
@@ -393,25 +255,21 @@ async fn main(spawner: Spawner) {
 
         ui.add_horizontal(Spacer::new(Size::new(65, 0)));
         ui.sub_ui(|ui| {
-            if appdata.timer_running() {
+            if !timer.is_stopped() {
                 ui.style_mut().icon_color = Rgb565::CSS_LIGHT_GRAY;
             }
             if ui
                 .add_horizontal(IconButton::new(size32px::actions::AddCircle).smartstate(sm.next()))
                 .clicked()
             {
-                if !appdata.timer_running() {
-                    appdata.add_secs(10);
-                }
+                timer.add_secs(10);
             }
             ui.add_horizontal(Label::new("+/- 10s").smartstate(sm.next()));
             if ui
                 .add(IconButton::new(size32px::actions::MinusCircle).smartstate(sm.next()))
                 .clicked()
             {
-                if !appdata.timer_running() {
-                    appdata.sub_secs(10);
-                }
+                timer.sub_secs(10);
             }
             Ok(())
         })
@@ -422,30 +280,30 @@ async fn main(spawner: Spawner) {
             .add_horizontal(IconButton::new(size48px::actions::Undo).smartstate(sm.next()))
             .clicked()
         {
-            appdata.reset_timer();
+            timer.reset();
             sm.force_redraw_all();
         }
 
-        if !finished && appdata.timer_finished() {
+        if timer_event == Some(TimerEvent::Finished) {
             sm.peek().force_redraw();
         }
 
-        if appdata.timer_finished() {
+        if timer.is_finished() {
             if ui
                 .add_horizontal(
                     IconButton::new(size48px::actions::RemoveSquare).smartstate(sm.next()),
                 )
                 .clicked()
             {
-                appdata.reset_timer();
+                timer.reset();
                 sm.force_redraw_all();
             }
-        } else if appdata.timer_running() {
+        } else if timer.is_running() {
             if ui
                 .add_horizontal(IconButton::new(size48px::music::Pause).smartstate(sm.next()))
                 .clicked()
             {
-                appdata.pause_timer();
+                timer.pause();
                 sm.force_redraw_all();
             }
         } else {
@@ -453,13 +311,11 @@ async fn main(spawner: Spawner) {
                 .add_horizontal(IconButton::new(size48px::music::Play).smartstate(sm.next()))
                 .clicked()
             {
-                appdata.start_timer();
+                timer.start();
                 sm.force_redraw_all();
             }
         }
 
-        finished = appdata.timer_finished();
-
         let end_time = embassy_time::Instant::now();
         let draw_time = display.get_time();
         let prep_time = start_draw_time - start_time;
@@ -1,33 +1,32 @@
 #![no_std]
 #![no_main]
 
-use core::{cell::RefCell, cmp::min, str::FromStr};
+use core::{cell::RefCell, cmp::min};
 
 use display_interface_spi::SPIInterface;
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_sync::{
-    blocking_mutex::{raw::NoopRawMutex, NoopMutex},
-    signal::Signal,
-};
-use embassy_time::{Duration, Timer};
+use embassy_sync::blocking_mutex::NoopMutex;
+use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::{
     mono_font::ascii,
     pixelcolor::Rgb565,
-    prelude::{Point, RgbColor, Size, WebColors},
+    prelude::{RgbColor, Size, WebColors},
+};
+use embedded_graphics_profiler_display::{
+    LatencyTag,
+    ProfilerDisplay,
 };
-use embedded_graphics_profiler_display::ProfilerDisplay;
 use esp_backtrace as _;
 use esp_hal::{
     clock::ClockControl,
-    gpio::{
-        GpioPin,
-        Input,
-        Io,
-        Level,
-        Output,
-        Pull,
-        NO_PIN,
+    gpio::{Io, Level, Output, NO_PIN},
+    ledc::{
+        channel::{self, ChannelIFace},
+        timer::{self, TimerIFace},
+        LSGlobalClkSource,
+        Ledc,
+        LowSpeed,
     },
     peripherals::{Peripherals, SPI2, SPI3},
     prelude::*,
@@ -35,9 +34,50 @@ use esp_hal::{
     spi::{master::Spi, FullDuplexMode, SpiMode},
     system::SystemControl,
     timer::timg::TimerGroup,
+    uart::Uart,
 };
 use esp_println::println;
+use kolibri_cyd_tester_app_embassy::{
+    buttons::{button_task, ActionChannel},
+    console::{console_task, LineChannel},
+    crash::{self, ReportAction},
+    focus::Focus,
+    gesture::GestureRecognizer,
+    lamp::{
+        Colour,
+        LampBackend,
+        LampEvent,
+        LampKind,
+        LampState,
+        MAX_KELVIN,
+        MIN_KELVIN,
+    },
+    latency::LatencyStats,
+    lighting::{
+        stored_setup,
+        Home,
+        HueWheel,
+        SceneAction,
+        SceneEditor,
+        WheelState,
+    },
+    storage::{self, TextRecord},
+    touch::{event::TouchChannel, touch_task},
+    wallclock::{
+        stored_clock,
+        ClockFace,
+        Idle,
+        SetTimeAction,
+        SetTimePage,
+    },
+};
+#[cfg(not(feature = "lamp-serial"))]
+use kolibri_cyd_tester_app_embassy::{
+    lamp::PwmBackend,
+    output::Polarity,
+};
 use kolibri_embedded_gui::{
+    button::Button,
     iconbutton::IconButton,
     icons::size32px,
     label::{HashLabel, Hasher, Label},
@@ -54,52 +94,30 @@ use mipidsi::{
     Builder,
 };
 use static_cell::StaticCell;
-use xpt2046::Xpt2046;
-
-#[embassy_executor::task]
-async fn touch_task(
-    touch_irq: GpioPin<36>,
-    spi: &'static mut NoopMutex<
-        RefCell<Spi<'static, SPI3, FullDuplexMode>>,
-    >,
-    touch_cs: GpioPin<33>,
-    touch_signal: &'static Signal<
-        NoopRawMutex,
-        Option<Point>,
-    >,
-) -> ! {
-    let mut touch_driver = Xpt2046::new(
-        SpiDevice::new(
-            spi,
-            Output::new(touch_cs, Level::Low),
-        ),
-        Input::new(touch_irq, Pull::Up),
-        xpt2046::Orientation::LandscapeFlipped,
-    );
-    touch_driver.set_num_samples(16);
-    touch_driver.init(&mut embassy_time::Delay).unwrap();
-
-    println!("touch task");
-
-    loop {
-        touch_driver
-            .run()
-            .expect("Running Touch driver failed");
-        if touch_driver.is_touched() {
-            let point = touch_driver.get_touch_point();
-            touch_signal.signal(Some(Point::new(
-                point.x + 25,
-                240 - point.y,
-            )));
-        } else {
-            touch_signal.signal(None);
-        }
-        Timer::after(Duration::from_millis(1)).await; // 100
-                                                      // a second
-
-        // Your touch handling logic here
-    }
-}
+#[cfg(feature = "lamp-serial")]
+use {
+    core::fmt,
+    kolibri_cyd_tester_app_embassy::lamp::{
+        serial::Reply,
+        SerialBackend,
+    },
+};
+#[cfg(feature = "mqtt")]
+use {
+    embassy_net::{Stack, StackResources},
+    embassy_sync::blocking_mutex::Mutex,
+    esp_hal::rng::Rng,
+    esp_wifi::{wifi::WifiStaDevice, EspWifiInitFor},
+    kolibri_cyd_tester_app_embassy::mqtt::{
+        mqtt_task,
+        net_task,
+        wifi_task,
+        Client,
+        MqttBackend,
+        SharedClient,
+        WifiStack,
+    },
+};
 
 fn lerp_fixed(start: u8, end: u8, t: u8, max_t: u8) -> u8 {
     let (start, end, t, max_t) =
@@ -111,43 +129,30 @@ fn lerp_fixed(start: u8, end: u8, t: u8, max_t: u8) -> u8 {
     result as u8
 }
 
-#[derive(Debug, Clone)]
-struct Lamp {
-    pub name: heapless::String<64>,
-    pub on: bool,
-    pub brightness: i16,
-}
-
-impl Lamp {
-    pub fn new(name: &str) -> Self {
-        Self {
-            name: heapless::String::from_str(name).unwrap(),
-            on: false,
-            brightness: 255,
-        }
-    }
-}
-
 enum Page {
     Home,
     LampCtrl(usize),
-}
-struct AppData {
-    lamps: heapless::Vec<Lamp, 8>,
+    SceneEdit(SceneEditor),
 }
 
-impl AppData {
-    fn new() -> Self {
-        Self {
-            lamps: heapless::Vec::new(),
-        }
-    }
+/// Writes the requests of the [`SerialBackend`] to the
+/// serial console, which the lamp controller listens to.
+#[cfg(feature = "lamp-serial")]
+struct ConsoleOut;
 
-    fn add_lamp(&mut self, name: &str) {
-        self.lamps.push(Lamp::new(name)).unwrap();
+#[cfg(feature = "lamp-serial")]
+impl fmt::Write for ConsoleOut {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        esp_println::print!("{}", s);
+        Ok(())
     }
 }
 
+/// Stamps the first frame that shows the result of a touch,
+/// in the touch task's clock.
+static LATENCY: LatencyTag =
+    LatencyTag::new(|| Instant::now().as_millis());
+
 #[main]
 async fn main(spawner: Spawner) {
     let peripherals = Peripherals::take();
@@ -220,7 +225,8 @@ async fn main(spawner: Spawner) {
         .init(&mut embassy_time::Delay)
         .unwrap();
 
-    let mut display = ProfilerDisplay::new(display);
+    let mut display = ProfilerDisplay::new(display)
+        .with_latency_tag(&LATENCY);
     let style = medsize_rgb565_style();
 
     {
@@ -258,40 +264,189 @@ async fn main(spawner: Spawner) {
         NoopMutex::new(RefCell::new(touch_spi));
     let touch_spi_bus = TOUCH_SPI_BUS.init(touch_spi_bus);
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<
-        Signal<NoopRawMutex, Option<Point>>,
-    > = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
+    static TOUCH_EVENTS: StaticCell<TouchChannel> =
+        StaticCell::new();
+    let touch_events =
+        &*TOUCH_EVENTS.init(TouchChannel::new());
 
     spawner
         .spawn(touch_task(
             touch_irq,
             touch_spi_bus,
             touch_cs,
-            touch_signal,
+            touch_events,
+        ))
+        .unwrap();
+
+    static BUTTON_ACTIONS: StaticCell<ActionChannel> =
+        StaticCell::new();
+    let button_actions =
+        &*BUTTON_ACTIONS.init(ActionChannel::new());
+    spawner
+        .spawn(button_task(
+            io.pins.gpio0,
+            io.pins.gpio22,
+            io.pins.gpio27,
+            button_actions,
         ))
         .unwrap();
 
+    static CONSOLE_LINES: StaticCell<LineChannel> =
+        StaticCell::new();
+    let console_lines =
+        &*CONSOLE_LINES.init(LineChannel::new());
+    let uart = Uart::new(
+        peripherals.UART0,
+        &clocks,
+        io.pins.gpio1,
+        io.pins.gpio3,
+    )
+    .unwrap();
+    spawner
+        .spawn(console_task(uart, console_lines))
+        .unwrap();
+
     // TODO: Spawn some tasks
     let _ = spawner;
 
     // variables
-    let mut appdata = AppData::new();
-
-    appdata.add_lamp("Front Door");
-    appdata.add_lamp("Living Room");
-    // appdata.lamps[1].on = true;
-    appdata.add_lamp("Bedroom");
-    // appdata.lamps[2].on = true;
-    appdata.add_lamp("Bathroom");
-    appdata.add_lamp("Porch");
+    let mut home = Home::new();
+
+    home.add_lamp("Front Door", LampKind::Rgb);
+    home.add_lamp("Living Room", LampKind::TunableWhite);
+    home.add_lamp("Bedroom", LampKind::Dimmable);
+    home.add_lamp("Bathroom", LampKind::Dimmable);
+    home.add_lamp("Porch", LampKind::Switch);
+    // groups and scenes, lamps by number
+    home.setup = stored_setup();
+
+    // the front door is the RGB LED, unless the lamps are
+    // on an external controller
+    let mut ledc = Ledc::new(peripherals.LEDC, &clocks);
+    ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+    let mut led_timer =
+        ledc.get_timer::<LowSpeed>(timer::Number::Timer0);
+    led_timer
+        .configure(timer::config::Config {
+            duty: timer::config::Duty::Duty8Bit,
+            clock_source: timer::LSClockSource::APBClk,
+            frequency: 1.kHz(),
+        })
+        .unwrap();
+    let led_config = channel::config::Config {
+        timer: &led_timer,
+        // the LED is active low: off
+        duty_pct: 100,
+        pin_config: channel::config::PinConfig::PushPull,
+    };
+    let mut red = ledc.get_channel(
+        channel::Number::Channel0,
+        io.pins.gpio4,
+    );
+    red.configure(led_config).unwrap();
+    let mut green = ledc.get_channel(
+        channel::Number::Channel1,
+        io.pins.gpio16,
+    );
+    green.configure(led_config).unwrap();
+    let mut blue = ledc.get_channel(
+        channel::Number::Channel2,
+        io.pins.gpio17,
+    );
+    blue.configure(led_config).unwrap();
+
+    #[cfg(not(feature = "lamp-serial"))]
+    let mut lamps = {
+        let channels: [&mut dyn embedded_hal::pwm::SetDutyCycle<
+            Error = channel::Error,
+        >; 3] = [&mut red, &mut green, &mut blue];
+        PwmBackend::new(
+            channels,
+            Polarity::ActiveLow,
+            &home.kinds(),
+        )
+        .unwrap()
+    };
+    #[cfg(feature = "lamp-serial")]
+    let mut lamps = {
+        let mut lamps =
+            SerialBackend::new(ConsoleOut, &home.kinds());
+        lamps.sync().ok();
+        lamps
+    };
+    // Home Assistant sees the lamps through the broker
+    #[cfg(feature = "mqtt")]
+    let mut lamps = {
+        static MQTT_CLIENT: StaticCell<SharedClient> =
+            StaticCell::new();
+        let mut client = Client::new(
+            option_env!("MQTT_NODE").unwrap_or("cyd-light"),
+        );
+        for lamp in &home.lamps {
+            client.add_lamp(&lamp.name, lamp.kind);
+        }
+        let client = &*MQTT_CLIENT
+            .init(Mutex::new(RefCell::new(client)));
+
+        let timg1 =
+            TimerGroup::new(peripherals.TIMG1, &clocks);
+        let mut rng = Rng::new(peripherals.RNG);
+        let seed = (rng.random() as u64) << 32
+            | rng.random() as u64;
+        let init = esp_wifi::initialize(
+            EspWifiInitFor::Wifi,
+            timg1.timer0,
+            rng,
+            peripherals.RADIO_CLK,
+            &clocks,
+        )
+        .unwrap();
+        let (device, controller) =
+            esp_wifi::wifi::new_with_mode(
+                &init,
+                peripherals.WIFI,
+                WifiStaDevice,
+            )
+            .unwrap();
+        static RESOURCES: StaticCell<StackResources<3>> =
+            StaticCell::new();
+        static STACK: StaticCell<WifiStack> =
+            StaticCell::new();
+        let stack = &*STACK.init(Stack::new(
+            device,
+            embassy_net::Config::dhcpv4(Default::default()),
+            RESOURCES.init(StackResources::new()),
+            seed,
+        ));
+        spawner.spawn(wifi_task(controller)).unwrap();
+        spawner.spawn(net_task(stack)).unwrap();
+        spawner.spawn(mqtt_task(stack, client)).unwrap();
+        MqttBackend::new(lamps, client)
+    };
+    // the state the UI asked for last, whose confirmation
+    // needs no redraw on its lamp's page
+    let mut requested: Option<LampEvent> = None;
 
     let mut cur_page = Page::Home;
+    // the colour wheel on the page of an RGB lamp
+    let mut wheel = WheelState::new();
+
+    // report of the crash before the last reboot, if any
+    let mut crash_report = crash::stored_report();
+
+    // time of day, shown on the idle screen
+    let mut wallclock = stored_clock(rtc.get_time_ms());
+    let mut idle =
+        Idle::new(embassy_time::Instant::now().as_millis());
+    let mut face = ClockFace::new();
+    let mut set_time: Option<SetTimePage> = None;
 
     // touchpoints
 
-    let mut last_touch = None;
+    let mut gestures = GestureRecognizer::default();
+    let mut focus = Focus::new();
+    let mut latency =
+        LatencyStats::new("kolibri", "light-control");
 
     static BUF_CELL: StaticCell<[Rgb565; 200 * 100]> =
         StaticCell::new();
@@ -308,32 +463,140 @@ async fn main(spawner: Spawner) {
         // sm.force_redraw_all();
 
         let start_time = embassy_time::Instant::now();
+        let now = embassy_time::Instant::now().as_millis();
+        let rtc_ms = rtc.get_time_ms();
         sm.restart_counter();
-        let mut ui =
-            Ui::new_fullscreen(&mut display, style);
-        if let Some(touch) = touch_signal.try_take() {
-            let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => {
-                    Interaction::Drag(point)
-                }
-                (Some(point), None) => {
-                    Interaction::Click(point)
+        while let Ok(line) = console_lines.try_receive() {
+            #[cfg(feature = "lamp-serial")]
+            match lamps.receive(&line) {
+                Some(Ok(Reply::Error(lamp))) => {
+                    println!(
+                        "lamp {} refused a request",
+                        lamp
+                    );
+                    continue;
                 }
-                (None, Some(point)) => {
-                    Interaction::Release(point)
+                Some(Ok(Reply::State(_))) => continue,
+                Some(Err(_)) => {
+                    println!("bad lamp reply: {}", line);
+                    continue;
                 }
-                (None, None) => Interaction::None,
+                None => {}
+            }
+            if !wallclock.run_command(&line, rtc_ms)
+                && !home.run_command(&line)
+            {
+                println!("unknown command: {}", line);
+            }
+        }
+        while let Some(event) = lamps.poll() {
+            home.apply(event);
+            let shown = match cur_page {
+                Page::LampCtrl(i) => i == event.lamp,
+                _ => false,
             };
-            ui.interact(interact);
-            // println!("{:?}, {:?}, {:?}", last_touch,
-            // touch, interact);
-
-            last_touch = touch;
+            if !(shown && requested == Some(event)) {
+                LATENCY.state_changed();
+                sm.force_redraw_all();
+            }
+        }
+        let mut go_back = false;
+        while let Ok(action) = button_actions.try_receive()
+        {
+            // the first press only wakes the app up
+            if !idle.is_idle() {
+                go_back |= !focus.apply(action);
+            }
+            idle.wake(now);
+        }
+        if focus.take_moved() {
+            sm.force_redraw_all();
+        }
+        let mut ui =
+            Ui::new_fullscreen(&mut display, style);
+        let events = match touch_events.try_receive() {
+            Some(event) => {
+                LATENCY.touched(event.t_ms);
+                gestures.event(event)
+            }
+            None => gestures.poll(now),
+        };
+        ui.interact(if idle.is_idle() {
+            Interaction::None
+        } else {
+            focus
+                .interaction()
+                .unwrap_or(events.interaction)
+        });
+        if idle.touch(now, &events) {
+            set_time = Some(SetTimePage::new(
+                wallclock.now(rtc_ms),
+            ));
         }
 
         // BUFFER ENABLE/DISABLE
         ui.set_buffer(buf);
 
+        if let Some(report) = &crash_report {
+            match crash::report_page(
+                &mut ui, &mut sm, report,
+            ) {
+                Some(ReportAction::Dismiss) => {
+                    crash::clear_stored_report();
+                    crash_report = None;
+                    ui.clear_background().ok();
+                    sm.force_redraw_all();
+                }
+                Some(ReportAction::SendOverSerial) => {
+                    crash::dump_to_serial(report);
+                }
+                None => {}
+            }
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
+
+        idle.update(now, false);
+        if idle.take_changed() {
+            face.invalidate();
+            focus.reset();
+            ui.clear_background().ok();
+            sm.force_redraw_all();
+        }
+        if idle.is_idle() {
+            drop(ui);
+            face.draw(&mut display, wallclock.now(rtc_ms))
+                .ok();
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
+
+        if let Some(page) = &mut set_time {
+            let action =
+                page.show(&mut ui, &mut sm, &mut focus);
+            if let Some(SetTimeAction::Save(time)) = action
+            {
+                wallclock.set_and_save(rtc_ms, time);
+            }
+            if action.is_some() || go_back {
+                set_time = None;
+                LATENCY.state_changed();
+                focus.reset();
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            drop(ui);
+            focus
+                .draw(&mut display, Rgb565::CSS_ORANGE)
+                .ok();
+            focus.end_frame();
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
+
         let start_draw_time = embassy_time::Instant::now();
         if let Page::Home = cur_page {
             ui.add_centered(
@@ -347,51 +610,199 @@ async fn main(spawner: Spawner) {
             );
         }
 
-        match cur_page {
-            Page::Home => {
+        match &mut cur_page {
+            Page::Home => 'home: {
+                // a tap activates a scene, holding it opens
+                // it for editing
+                let mut edit = None;
+                for (s, scene) in
+                    home.setup.scenes.iter().enumerate()
+                {
+                    let response = focus.track(
+                        ui.add_horizontal(
+                            Button::new(scene.name())
+                                .smartstate(sm.next()),
+                        ),
+                    );
+                    if events.long_press().is_some()
+                        && response.down()
+                    {
+                        edit = Some(s);
+                    } else if response.clicked() {
+                        // shown once the backend confirms
+                        // it
+                        if let Err(e) =
+                            home.activate(scene, &mut lamps)
+                        {
+                            println!(
+                                "scene {}: {:?}",
+                                scene.name(),
+                                e
+                            );
+                        }
+                    }
+                }
+                if !home.setup.scenes.is_full()
+                    && focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new("+ Scene")
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                {
+                    edit = home.add_scene();
+                    home.setup
+                        .save(&mut storage::open())
+                        .ok();
+                }
+                if let Some(s) = edit {
+                    cur_page = Page::SceneEdit(
+                        SceneEditor::new(&home, s),
+                    );
+                    LATENCY.state_changed();
+                    focus.reset();
+                    ui.clear_background().ok();
+                    sm.force_redraw_all();
+                    break 'home;
+                }
+                ui.new_row();
+
                 for (i, lamp) in
-                    appdata.lamps.iter().enumerate()
+                    home.lamps.iter().enumerate()
                 {
-                    let mut break_loop = false;
+                    let mut open = false;
                     ui.sub_ui(|ui| {
-                        ui.style_mut().icon_color = if lamp.on {
+                        ui.style_mut().icon_color = if lamp.state.on {
                             Rgb565::CSS_GOLD
                         } else {
                             Rgb565::WHITE
                         };
-                        if ui
-                            .add_horizontal(
-                                IconButton::new(size32px::home::LightBulb)
-                                    .label(lamp.name.as_str())
-                                    .smartstate(sm.next()),
-                            )
-                            .clicked()
+                        let response = focus.track(ui.add_horizontal(
+                            IconButton::new(size32px::home::LightBulb)
+                                .label(lamp.name.as_str())
+                                .smartstate(sm.next()),
+                        ));
+                        // long-press toggles the lamp without
+                        // opening it
+                        if events.long_press().is_some()
+                            && response.down()
                         {
-                            cur_page = Page::LampCtrl(i);
-                            ui.clear_background().ok();
-                            sm.force_redraw_all();
-                            break_loop = true;
+                            // shown once the backend
+                            // confirms it
+                            if let Err(e) =
+                                lamps.set_on(i, !lamp.state.on)
+                            {
+                                println!("lamp {}: {:?}", i, e);
+                            }
+                        } else if response.clicked() {
+                            open = true;
                         }
                         Ok(())
                     })
                     .ok();
-                    if break_loop {
-                        break;
+                    if open {
+                        cur_page = Page::LampCtrl(i);
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        break 'home;
                     }
                     if i % 3 == 2 {
                         ui.new_row();
                     }
                 }
-            }
-            Page::LampCtrl(lamp) => {
-                let lamp = &mut appdata.lamps[lamp];
-                if ui
-                    .add_horizontal(
-                        IconButton::new(size32px::navigation::NavArrowLeft).smartstate(sm.next()),
+                ui.new_row();
+
+                // a tap switches a group: off if any of its
+                // lamps is on, otherwise on
+                if focus
+                    .track(
+                        ui.add_horizontal(
+                            Button::new("All off")
+                                .smartstate(sm.next()),
+                        ),
                     )
                     .clicked()
                 {
+                    if let Err(e) = home.all_off(&mut lamps)
+                    {
+                        println!("all off: {:?}", e);
+                    }
+                }
+                for group in &home.setup.groups {
+                    if focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new(group.name())
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                    {
+                        if let Err(e) =
+                            home.toggle(group, &mut lamps)
+                        {
+                            println!(
+                                "group {}: {:?}",
+                                group.name(),
+                                e
+                            );
+                        }
+                    }
+                }
+            }
+            Page::SceneEdit(editor) => {
+                let action = editor.show(
+                    &mut ui, &mut sm, &mut focus, &home,
+                );
+                let s = editor.scene();
+                match action {
+                    Some(SceneAction::Capture {
+                        members,
+                    }) => {
+                        let scene = home.capture(
+                            home.setup.scenes[s].name(),
+                            members,
+                        );
+                        home.setup.scenes[s] = scene;
+                        home.setup
+                            .save(&mut storage::open())
+                            .ok();
+                    }
+                    Some(SceneAction::Delete) => {
+                        home.setup.scenes.remove(s);
+                        home.setup
+                            .save(&mut storage::open())
+                            .ok();
+                    }
+                    Some(SceneAction::Back) | None => {}
+                }
+                if action.is_some()
+                    || go_back
+                    || events.back_swipe()
+                {
+                    cur_page = Page::Home;
+                    LATENCY.state_changed();
+                    focus.reset();
+                    ui.clear_background().ok();
+                    sm.force_redraw_all();
+                }
+            }
+            Page::LampCtrl(i) => {
+                let i = *i;
+                let lamp = &home.lamps[i];
+                let back = focus
+                    .track(ui.add_horizontal(
+                        IconButton::new(size32px::navigation::NavArrowLeft).smartstate(sm.next()),
+                    ))
+                    .clicked();
+                if back || go_back || events.back_swipe() {
                     cur_page = Page::Home;
+                    LATENCY.state_changed();
+                    focus.reset();
                     ui.clear_background().ok();
                     sm.force_redraw_all();
                     continue;
@@ -404,25 +815,151 @@ async fn main(spawner: Spawner) {
                         .smartstate(sm.next())
                         .with_font(ascii::FONT_9X18_BOLD),
                 );
-                ui.add(Spacer::new(Size::new(0, 20)));
-                ui.add_centered(
-                    Slider::new(
-                        &mut lamp.brightness,
-                        0..=255,
+                // the colour wheel needs the room of the
+                // spacers
+                let gap = if lamp.kind == LampKind::Rgb {
+                    0
+                } else {
+                    10
+                };
+                ui.add(Spacer::new(Size::new(0, 2 * gap)));
+                // the widgets only ask the backend, the
+                // lamp changes once it confirms
+                let mut brightness =
+                    lamp.state.brightness as i16;
+                let mut on = lamp.state.on;
+                if lamp.kind.is_dimmable()
+                    && ui
+                        .add_centered(
+                            Slider::new(
+                                &mut brightness,
+                                0..=255,
+                            )
+                            .width(300)
+                            .label("Brightness")
+                            .smartstate(sm.next()),
+                        )
+                        .changed()
+                {
+                    LATENCY.state_changed();
+                    let brightness = brightness as u8;
+                    requested =
+                        lamps.state(i).map(|state| {
+                            LampEvent {
+                                lamp: i,
+                                state: LampState {
+                                    brightness,
+                                    ..state
+                                },
+                            }
+                        });
+                    if let Err(e) =
+                        lamps.set_brightness(i, brightness)
+                    {
+                        println!("lamp {}: {:?}", i, e);
+                    }
+                }
+                ui.add(Spacer::new(Size::new(0, gap)));
+                if focus
+                    .track(
+                        ui.add_centered(
+                            ToggleSwitch::new(&mut on)
+                                .smartstate(sm.next()),
+                        ),
                     )
-                    .width(300)
-                    .label("Brightness")
-                    .smartstate(sm.next()),
-                );
-                ui.add(Spacer::new(Size::new(0, 10)));
-                ui.add_centered(
-                    ToggleSwitch::new(&mut lamp.on)
-                        .smartstate(sm.next()),
-                );
+                    .changed()
+                {
+                    LATENCY.state_changed();
+                    requested =
+                        lamps.state(i).map(|state| {
+                            LampEvent {
+                                lamp: i,
+                                state: LampState {
+                                    on,
+                                    ..state
+                                },
+                            }
+                        });
+                    if let Err(e) = lamps.set_on(i, on) {
+                        println!("lamp {}: {:?}", i, e);
+                    }
+                }
                 ui.add_centered(
                     Label::new("Turn on/off")
                         .smartstate(sm.next()),
                 );
+                let colour = match lamp.state.colour {
+                    Some(Colour::Temperature(kelvin)) => {
+                        let mut kelvin = kelvin as i16;
+                        let range = MIN_KELVIN as i16
+                            ..=MAX_KELVIN as i16;
+                        ui.add(Spacer::new(Size::new(
+                            0, gap,
+                        )));
+                        let changed = ui
+                            .add_centered(
+                                Slider::new(
+                                    &mut kelvin,
+                                    range,
+                                )
+                                .width(300)
+                                .label("Colour temperature")
+                                .smartstate(sm.next()),
+                            )
+                            .changed();
+                        changed.then_some(
+                            Colour::temperature(
+                                kelvin as u16,
+                            ),
+                        )
+                    }
+                    Some(Colour::Hs {
+                        mut hue,
+                        mut saturation,
+                    }) => {
+                        let changed = ui
+                            .add_centered(
+                                HueWheel::new(
+                                    &mut hue,
+                                    &mut saturation,
+                                    &mut wheel,
+                                )
+                                .smartstate(sm.next()),
+                            )
+                            .changed();
+                        changed.then_some(Colour::hs(
+                            hue, saturation,
+                        ))
+                    }
+                    None => None,
+                };
+                if let Some(colour) = colour {
+                    LATENCY.state_changed();
+                    requested =
+                        lamps.state(i).map(|state| {
+                            LampEvent {
+                                lamp: i,
+                                state: LampState {
+                                    colour: Some(colour),
+                                    ..state
+                                },
+                            }
+                        });
+                    if let Err(e) =
+                        lamps.set_colour(i, colour)
+                    {
+                        println!("lamp {}: {:?}", i, e);
+                    }
+                }
+            }
+        }
+
+        drop(ui);
+        focus.draw(&mut display, Rgb565::CSS_ORANGE).ok();
+        focus.end_frame();
+        if let Some(ms) = LATENCY.take_latency() {
+            if latency.add(ms) {
+                println!("{}", latency);
             }
         }
 
@@ -6,37 +6,75 @@ use core::{cell::RefCell, cmp::min};
 use display_interface_spi::SPIInterface;
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_sync::{
-    blocking_mutex::{raw::NoopRawMutex, NoopMutex},
-    signal::Signal,
-};
+use embassy_sync::blocking_mutex::NoopMutex;
 use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::{
     mono_font::ascii,
     pixelcolor::Rgb565,
-    prelude::{Point, RgbColor, Size, WebColors},
+    prelude::{RgbColor, Size, WebColors},
+};
+use embedded_graphics_profiler_display::{
+    LatencyTag,
+    ProfilerDisplay,
 };
-use embedded_graphics_profiler_display::ProfilerDisplay;
 use esp_hal::{
-    clock::ClockControl,
-    gpio::{
-        GpioPin,
-        Input,
-        Io,
-        Level,
-        Output,
-        Pull,
-        NO_PIN,
-    },
+    clock::{ClockControl, Clocks},
+    gpio::{Io, Level, Output, NO_PIN},
     peripherals::{Peripherals, SPI2, SPI3},
     prelude::*,
     rtc_cntl::Rtc,
     spi::{master::Spi, FullDuplexMode, SpiMode},
     system::SystemControl,
     timer::timg::TimerGroup,
+    uart::Uart,
 };
 use esp_println::println;
+use kolibri_cyd_tester_app_embassy::{
+    alarm::{Alarm, AlarmAction, AlarmEvent, AlarmScreen},
+    buttons::{button_task, ActionChannel},
+    buzzer::{buzzer_task, Chime, ChimeSignal},
+    console::{console_task, LineChannel},
+    countdown::{CountdownTimer, EmbassyClock, TimerEvent},
+    crash::{self, ReportAction},
+    focus::Focus,
+    gesture::GestureRecognizer,
+    latency::LatencyStats,
+    microwave::{
+        self,
+        stored_auto_table,
+        stored_cook_programs,
+        AutoAction,
+        AutoCook,
+        AutoTable,
+        Command,
+        CookProgram,
+        CookPrograms,
+        CookState,
+        DoorChannel,
+        Input,
+        Polarity,
+        PowerConfig,
+        PowerController,
+        ProgramAction,
+        ProgramRun,
+        Safety,
+        Stage,
+        LIBRARY_LEN,
+    },
+    touch::{
+        event::{TouchChannel, TouchKind},
+        touch_task,
+    },
+    wallclock::{
+        stored_clock,
+        ClockFace,
+        Idle,
+        SetTimeAction,
+        SetTimePage,
+    },
+};
 use kolibri_embedded_gui::{
+    button::Button,
     iconbutton::IconButton,
     icons::{size32px, size48px},
     label::{HashLabel, Hasher, Label},
@@ -51,182 +89,138 @@ use mipidsi::{
     Builder,
 };
 use static_cell::StaticCell;
-use xpt2046::Xpt2046;
-
-#[embassy_executor::task]
-async fn touch_task(
-    touch_irq: GpioPin<36>,
-    spi: &'static mut NoopMutex<
-        RefCell<Spi<'static, SPI3, FullDuplexMode>>,
-    >,
-    touch_cs: GpioPin<33>,
-    touch_signal: &'static Signal<
-        NoopRawMutex,
-        Option<Point>,
-    >,
-) -> ! {
-    let mut touch_driver = Xpt2046::new(
-        SpiDevice::new(
-            spi,
-            Output::new(touch_cs, Level::Low),
-        ),
-        Input::new(touch_irq, Pull::Up),
-        xpt2046::Orientation::LandscapeFlipped,
-    );
-    touch_driver.set_num_samples(16);
-    touch_driver.init(&mut embassy_time::Delay).unwrap();
-
-    println!("touch task");
-
-    loop {
-        touch_driver
-            .run()
-            .expect("Running Touch driver failed");
-        if touch_driver.is_touched() {
-            let point = touch_driver.get_touch_point();
-            touch_signal.signal(Some(Point::new(
-                point.x + 25,
-                240 - point.y,
-            )));
-        } else {
-            touch_signal.signal(None);
-        }
-        Timer::after(Duration::from_millis(1)).await; // 100
-                                                      // a second
 
-        // Your touch handling logic here
-    }
-}
+/// Polarity of the magnetron and turntable outputs, the
+/// blue and green channels of the RGB LED. Relays on other
+/// pins may need `ActiveHigh`.
+const LED_POLARITY: Polarity = Polarity::ActiveLow;
+/// Polarity of the lamp output on GPIO5.
+const LAMP_POLARITY: Polarity = Polarity::ActiveHigh;
 
 struct AppData {
-    timer_start: Instant,
-    timer_set_duration: Duration,
-    timer_remaining_duration: Duration,
-    timer_running: bool,
-    timer_paused: bool,
-    wattage_level: u8,
+    timer: CountdownTimer<EmbassyClock>,
+    power: PowerController,
+    safety: Safety,
+    /// The user's cook programs, besides the built-in
+    /// ones.
+    programs: CookPrograms,
+    /// The program loaded, if not cooking by hand.
+    run: Option<ProgramRun>,
+    /// Foods of the auto mode.
+    auto_table: AutoTable,
+    /// Food and weight last picked in the auto mode.
+    auto: AutoCook,
 }
 
 impl AppData {
     fn new() -> Self {
+        let auto_table = stored_auto_table();
         Self {
-            timer_start: Instant::now(),
-            timer_set_duration: Duration::from_secs(10),
-            timer_remaining_duration: Duration::from_secs(
-                10,
+            timer: CountdownTimer::new(EmbassyClock),
+            power: PowerController::new(
+                PowerConfig::DEFAULT,
             ),
-            timer_running: false,
-            timer_paused: false,
-            wattage_level: 5,
+            safety: Safety::new(),
+            programs: stored_cook_programs(),
+            run: None,
+            auto: AutoCook::new(&auto_table),
+            auto_table,
         }
     }
 
-    fn set_timer_duration(&mut self, duration: Duration) {
-        self.timer_set_duration = duration;
+    /// Stops whatever cooks and sets the timer and power
+    /// up for what the auto mode worked out.
+    fn set_auto(&mut self, setting: Stage) {
+        self.handle(Input::Cancel);
+        self.run = None;
+        self.timer
+            .set_duration_ms(setting.secs as u64 * 1000);
+        self.power.set_level(setting.level);
     }
 
-    fn add_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_add(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(5999))
-                .min(Duration::from_secs(5999)),
-        );
+    /// Stops whatever cooks and sets the timer and power
+    /// up for `program`.
+    fn load(&mut self, program: CookProgram) {
+        self.handle(Input::Cancel);
+        let run = ProgramRun::new(program);
+        self.timer.set_duration_ms(run.duration_ms());
+        self.power.set_level(run.stage().level);
+        self.run = Some(run);
     }
 
-    fn sub_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_sub(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(10))
-                .max(Duration::from_secs(10)),
-        );
+    /// Sets the power of the program's current stage, if a
+    /// program is loaded. Returns whether the stage
+    /// changed.
+    fn update_stage(&mut self) -> bool {
+        let Some(run) = &mut self.run else {
+            return false;
+        };
+        let elapsed = self
+            .timer
+            .duration_ms()
+            .saturating_sub(self.timer.remaining_ms());
+        let changed = run.update(elapsed).is_some();
+        self.power.set_level(run.stage().level);
+        changed
     }
 
-    fn start_timer(&mut self) {
-        if !self.timer_paused {
-            self.timer_remaining_duration =
-                self.timer_set_duration;
+    /// Passes `input` through the safety interlock and does
+    /// what it asks for to the timer. Returns the timer's
+    /// event, if any.
+    fn handle(
+        &mut self,
+        input: Input,
+    ) -> Option<TimerEvent> {
+        match self.safety.handle(input) {
+            Ok(Some(Command::Start | Command::Resume)) => {
+                self.timer.start()
+            }
+            Ok(Some(Command::Pause)) => self.timer.pause(),
+            Ok(Some(Command::Reset)) => self.timer.reset(),
+            Ok(None) => None,
+            Err(refused) => {
+                println!(
+                    "{:?} refused: {:?}",
+                    input, refused
+                );
+                None
+            }
         }
-
-        self.timer_start = Instant::now();
-        self.timer_running = true;
-        self.timer_paused = false;
-    }
-
-    fn pause_timer(&mut self) {
-        self.timer_paused = true;
-        self.timer_remaining_duration = self
-            .timer_remaining_duration
-            .checked_sub(self.timer_start.elapsed())
-            .unwrap_or(Duration::from_secs(0));
-    }
-
-    fn reset_timer(&mut self) {
-        self.timer_start = Instant::now();
-        self.timer_paused = false;
-        self.timer_running = false;
-        self.timer_remaining_duration =
-            self.timer_set_duration;
     }
 
-    fn remaining(&self) -> Duration {
-        if self.timer_stopped() {
-            self.timer_set_duration
-        } else if self.timer_paused() {
-            self.timer_remaining_duration
+    /// The title line: what keeps the oven from starting,
+    /// if anything.
+    fn status(&self) -> &'static str {
+        if self.safety.is_locked() {
+            "Locked: hold 3 s"
+        } else if self.safety.is_door_open() {
+            "Door open"
+        } else if self.safety.state()
+            == CookState::Interrupted
+        {
+            "Press start to resume"
         } else {
-            self.timer_remaining_duration
-                .checked_sub(self.timer_start.elapsed())
-                .unwrap_or(Duration::from_secs(0))
-        }
-    }
-
-    fn timer_stopped(&self) -> bool {
-        !self.timer_running
-    }
-
-    fn timer_paused(&self) -> bool {
-        self.timer_running && self.timer_paused
-    }
-
-    fn timer_running(&self) -> bool {
-        self.timer_running && !self.timer_paused
-    }
-
-    fn timer_finished(&self) -> bool {
-        self.timer_running
-            && self.remaining() == Duration::from_secs(0)
-    }
-
-    fn set_wattage_level(&mut self, level: u8) {
-        assert!(
-            level < 6,
-            "wattage level cannot be over 6"
-        );
-        self.wattage_level = level;
-    }
-
-    fn get_wattage_level_str(&self) -> &'static str {
-        match self.wattage_level {
-            0 => "180W",
-            1 => "220W",
-            2 => "360W",
-            3 => "480W",
-            4 => "620W",
-            5 => "800W",
-            _ => unreachable!(),
+            "Kolibri Microwave UI"
         }
     }
 }
 
+/// Stamps the first frame that shows the result of a touch,
+/// in the touch task's clock.
+static LATENCY: LatencyTag =
+    LatencyTag::new(|| Instant::now().as_millis());
+
 #[main]
 async fn main(spawner: Spawner) {
     let peripherals = Peripherals::take();
     let system = SystemControl::new(peripherals.SYSTEM);
-    let mut clocks =
+    // 'static for the buzzer task
+    static CLOCKS: StaticCell<Clocks<'static>> =
+        StaticCell::new();
+    let clocks = &*CLOCKS.init(
         ClockControl::boot_defaults(system.clock_control)
-            .freeze();
+            .freeze(),
+    );
 
     // Enable the RWDT watchdog timer:
     let mut rtc = Rtc::new(peripherals.LPWR);
@@ -235,8 +229,8 @@ async fn main(spawner: Spawner) {
     println!("RWDT watchdog enabled!");
 
     // Initialize the SYSTIMER peripheral, and then Embassy:
-    let timg0 = TimerGroup::new(peripherals.TIMG0, &clocks);
-    esp_hal_embassy::init(&clocks, timg0.timer0);
+    let timg0 = TimerGroup::new(peripherals.TIMG0, clocks);
+    esp_hal_embassy::init(clocks, timg0.timer0);
     println!("Embassy initialized!");
 
     let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
@@ -260,7 +254,7 @@ async fn main(spawner: Spawner) {
         peripherals.SPI2,
         10.MHz(),
         SpiMode::Mode0,
-        &mut clocks,
+        clocks,
     )
     .with_pins(
         Some(sclk),
@@ -293,7 +287,8 @@ async fn main(spawner: Spawner) {
         .init(&mut embassy_time::Delay)
         .unwrap();
 
-    let mut display = ProfilerDisplay::new(display);
+    let mut display = ProfilerDisplay::new(display)
+        .with_latency_tag(&LATENCY);
 
     {
         let mut ui = Ui::new_fullscreen(
@@ -317,7 +312,7 @@ async fn main(spawner: Spawner) {
         peripherals.SPI3,
         2.MHz(),
         SpiMode::Mode0,
-        &mut clocks,
+        clocks,
     )
     .with_pins(
         Some(touch_clk),
@@ -332,21 +327,75 @@ async fn main(spawner: Spawner) {
         NoopMutex::new(RefCell::new(touch_spi));
     let touch_spi_bus = TOUCH_SPI_BUS.init(touch_spi_bus);
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<
-        Signal<NoopRawMutex, Option<Point>>,
-    > = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
+    static TOUCH_EVENTS: StaticCell<TouchChannel> =
+        StaticCell::new();
+    let touch_events =
+        &*TOUCH_EVENTS.init(TouchChannel::new());
 
     spawner
         .spawn(touch_task(
             touch_irq,
             touch_spi_bus,
             touch_cs,
-            touch_signal,
+            touch_events,
+        ))
+        .unwrap();
+
+    static BUTTON_ACTIONS: StaticCell<ActionChannel> =
+        StaticCell::new();
+    let button_actions =
+        &*BUTTON_ACTIONS.init(ActionChannel::new());
+    spawner
+        .spawn(button_task(
+            io.pins.gpio0,
+            io.pins.gpio22,
+            io.pins.gpio27,
+            button_actions,
+        ))
+        .unwrap();
+
+    static CHIMES: StaticCell<ChimeSignal> =
+        StaticCell::new();
+    let chimes = &*CHIMES.init(ChimeSignal::new());
+    spawner
+        .spawn(buzzer_task(
+            peripherals.LEDC,
+            clocks,
+            io.pins.gpio26,
+            chimes,
         ))
         .unwrap();
 
+    static CONSOLE_LINES: StaticCell<LineChannel> =
+        StaticCell::new();
+    let console_lines =
+        &*CONSOLE_LINES.init(LineChannel::new());
+    let uart = Uart::new(
+        peripherals.UART0,
+        clocks,
+        io.pins.gpio1,
+        io.pins.gpio3,
+    )
+    .unwrap();
+    spawner
+        .spawn(console_task(uart, console_lines))
+        .unwrap();
+
+    static DOOR_EVENTS: StaticCell<DoorChannel> =
+        StaticCell::new();
+    let door_events =
+        &*DOOR_EVENTS.init(DoorChannel::new());
+    #[cfg(feature = "door-switch")]
+    spawner
+        .spawn(microwave::door_task(
+            io.pins.gpio35,
+            door_events,
+        ))
+        .unwrap();
+    // without a switch the door counts as closed
+    #[cfg(not(feature = "door-switch"))]
+    door_events.try_send(microwave::DoorEvent::Closed).ok();
+
     // TODO: Spawn some tasks
     let _ = spawner;
 
@@ -354,17 +403,57 @@ async fn main(spawner: Spawner) {
     let mut appdata = AppData::new();
     let (mut prev_mins, mut prev_secs, mut prev_millis) =
         (0, 0, 0);
-    let mut finished = false;
+    let mut alarm = Alarm::new();
+    let mut alarm_screen = AlarmScreen::new("Done!");
+    // the red channel of the RGB LED, which is active low
+    let mut alert_led =
+        Output::new(io.pins.gpio4, Level::High);
+
+    // outputs of the power controller; GPIO5 is the SD
+    // card's chip select, which this app doesn't use
+    let mut magnetron_out = Output::new(
+        io.pins.gpio17,
+        LED_POLARITY.is_high(false).into(),
+    );
+    let mut turntable_out = Output::new(
+        io.pins.gpio16,
+        LED_POLARITY.is_high(false).into(),
+    );
+    let mut lamp_out = Output::new(
+        io.pins.gpio5,
+        LAMP_POLARITY.is_high(false).into(),
+    );
+
+    // report of the crash before the last reboot, if any
+    let mut crash_report = crash::stored_report();
+
+    // time of day, shown on the idle screen
+    let mut wallclock = stored_clock(rtc.get_time_ms());
+    let mut idle =
+        Idle::new(embassy_time::Instant::now().as_millis());
+    let mut face = ClockFace::new();
+    let mut set_time: Option<SetTimePage> = None;
+    // the library while the program page is open
+    let mut picking: Option<
+        heapless::Vec<CookProgram, LIBRARY_LEN>,
+    > = None;
+    let mut auto_shown = false;
 
     // touchpoints
 
-    let mut last_touch = None;
+    let mut gestures = GestureRecognizer::default();
+    // a single page, so there is nothing to go back from
+    let mut focus = Focus::new();
+    let mut latency =
+        LatencyStats::new("kolibri", "microwave-ui");
 
     static BUF_CELL: StaticCell<[Rgb565; 100 * 100]> =
         StaticCell::new();
     let buf = BUF_CELL.init([Rgb565::BLACK; 100 * 100]);
 
     let mut textbuf = [0u8; 64];
+    let mut touch_held = false;
+    let mut status = appdata.status();
 
     // Periodically feed the RWDT watchdog timer when our
     // tasks are not running:
@@ -375,50 +464,354 @@ async fn main(spawner: Spawner) {
         // sm.force_redraw_all();
 
         let start_time = embassy_time::Instant::now();
+        let now = embassy_time::Instant::now().as_millis();
+        let rtc_ms = rtc.get_time_ms();
         sm.restart_counter();
+        while let Ok(line) = console_lines.try_receive() {
+            if !wallclock.run_command(&line, rtc_ms)
+                && !appdata.programs.run_command(&line)
+                && !appdata.auto_table.run_command(&line)
+            {
+                println!("unknown command: {}", line);
+            }
+        }
+        while let Ok(action) = button_actions.try_receive()
+        {
+            // the first press only wakes the app up
+            if !idle.is_idle()
+                && !appdata.safety.is_locked()
+            {
+                focus.apply(action);
+            }
+            idle.wake(now);
+        }
+        if focus.take_moved() {
+            sm.force_redraw_all();
+        }
         let mut ui = Ui::new_fullscreen(
             &mut display,
             medsize_rgb565_style(),
         );
-        if let Some(touch) = touch_signal.try_take() {
-            let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => {
-                    Interaction::Drag(point)
+        let events = match touch_events.try_receive() {
+            Some(event) => {
+                LATENCY.touched(event.t_ms);
+                touch_held =
+                    event.kind != TouchKind::Release;
+                gestures.event(event)
+            }
+            None => gestures.poll(now),
+        };
+        if appdata.safety.hold(now, touch_held) {
+            LATENCY.state_changed();
+        }
+        ui.interact(
+            if idle.is_idle() || appdata.safety.is_locked()
+            {
+                Interaction::None
+            } else {
+                focus
+                    .interaction()
+                    .unwrap_or(events.interaction)
+            },
+        );
+        if idle.touch(now, &events)
+            && !appdata.safety.is_locked()
+        {
+            set_time = Some(SetTimePage::new(
+                wallclock.now(rtc_ms),
+            ));
+        }
+
+        // BUFFER ENABLE/DISABLE
+        ui.set_buffer(buf);
+
+        if let Some(report) = &crash_report {
+            match crash::report_page(
+                &mut ui, &mut sm, report,
+            ) {
+                Some(ReportAction::Dismiss) => {
+                    crash::clear_stored_report();
+                    crash_report = None;
+                    ui.clear_background().ok();
+                    sm.force_redraw_all();
+                }
+                Some(ReportAction::SendOverSerial) => {
+                    crash::dump_to_serial(report);
                 }
-                (Some(point), None) => {
-                    Interaction::Click(point)
+                None => {}
+            }
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
+
+        let start_draw_time = embassy_time::Instant::now();
+
+        // the door goes first: opening it has to stop the
+        // magnetron even if the time just ran out
+        let mut door_event = None;
+        while let Ok(event) = door_events.try_receive() {
+            door_event = appdata
+                .handle(Input::Door(event))
+                .or(door_event);
+            idle.wake(now);
+        }
+        let timer_event =
+            appdata.timer.update().or(door_event);
+        if timer_event == Some(TimerEvent::Finished) {
+            appdata.handle(Input::Finished);
+            alarm.trigger(now);
+            focus.reset();
+            ui.clear_background().ok();
+            sm.force_redraw_all();
+        }
+        if appdata.update_stage() {
+            if appdata.timer.is_running() {
+                chimes.signal(Chime::Phase);
+            }
+            sm.force_redraw_all();
+        }
+        if !appdata.timer.is_finished() {
+            alarm.dismiss();
+        }
+        match alarm.update(now) {
+            Some(AlarmEvent::Beep { level }) => {
+                chimes.signal(Chime::Alarm { level });
+            }
+            Some(AlarmEvent::Rings) => {
+                focus.reset();
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            Some(AlarmEvent::Silenced) => {
+                sm.force_redraw_all();
+            }
+            None => {}
+        }
+        if alarm.led_on(now) {
+            alert_led.set_low();
+        } else {
+            alert_led.set_high();
+        }
+        let mut outputs = appdata
+            .power
+            .update(now, appdata.timer.state());
+        // never heat with the door open, whatever the timer
+        // says, and light the inside while it is
+        let door_open = appdata.safety.is_door_open();
+        outputs.magnetron &= !door_open;
+        outputs.lamp |= door_open;
+        magnetron_out.set_level(
+            LED_POLARITY.is_high(outputs.magnetron).into(),
+        );
+        turntable_out.set_level(
+            LED_POLARITY.is_high(outputs.turntable).into(),
+        );
+        lamp_out.set_level(
+            LAMP_POLARITY.is_high(outputs.lamp).into(),
+        );
+
+        idle.update(
+            now,
+            appdata.timer.is_running() || alarm.is_active(),
+        );
+        if idle.take_changed() {
+            face.invalidate();
+            focus.reset();
+            ui.clear_background().ok();
+            sm.force_redraw_all();
+        }
+        if idle.is_idle() {
+            drop(ui);
+            face.draw(&mut display, wallclock.now(rtc_ms))
+                .ok();
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
+
+        // the title shows why the oven won't start
+        if appdata.status() != status {
+            status = appdata.status();
+            ui.clear_background().ok();
+            sm.force_redraw_all();
+        }
+
+        if alarm.is_shown() {
+            let action = alarm_screen.show(
+                &mut ui,
+                &mut sm,
+                &mut focus,
+                &alarm,
+                now,
+                ["Your food is ready"],
+            );
+            match action {
+                Some(AlarmAction::Snooze(ms)) => {
+                    alarm.snooze(now, ms);
                 }
-                (None, Some(point)) => {
-                    Interaction::Release(point)
+                Some(AlarmAction::Dismiss) => {
+                    alarm.dismiss();
+                    appdata.handle(Input::Cancel);
                 }
-                (None, None) => Interaction::None,
-            };
-            ui.interact(interact);
-            // println!("{:?}, {:?}, {:?}", last_touch,
-            // touch, interact);
+                None => {}
+            }
+            if action.is_some() {
+                LATENCY.state_changed();
+                focus.reset();
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            drop(ui);
+            focus
+                .draw(&mut display, Rgb565::CSS_ORANGE)
+                .ok();
+            focus.end_frame();
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
 
-            last_touch = touch;
+        if let Some(page) = &mut set_time {
+            let action =
+                page.show(&mut ui, &mut sm, &mut focus);
+            if let Some(SetTimeAction::Save(time)) = action
+            {
+                wallclock.set_and_save(rtc_ms, time);
+            }
+            if action.is_some() {
+                set_time = None;
+                LATENCY.state_changed();
+                focus.reset();
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            drop(ui);
+            focus
+                .draw(&mut display, Rgb565::CSS_ORANGE)
+                .ok();
+            focus.end_frame();
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
         }
 
-        // BUFFER ENABLE/DISABLE
-        ui.set_buffer(buf);
+        if let Some(library) = &picking {
+            let action = microwave::program_page(
+                &mut ui, &mut sm, &mut focus, library,
+            );
+            match action {
+                Some(ProgramAction::Pick(i)) => {
+                    appdata.load(library[i].clone());
+                }
+                Some(ProgramAction::Manual) => {
+                    appdata.run = None;
+                }
+                Some(ProgramAction::Auto) => {
+                    auto_shown = true;
+                }
+                Some(ProgramAction::Cancel) | None => {}
+            }
+            if action.is_some() {
+                picking = None;
+                LATENCY.state_changed();
+                focus.reset();
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            drop(ui);
+            focus
+                .draw(&mut display, Rgb565::CSS_ORANGE)
+                .ok();
+            focus.end_frame();
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
+
+        if auto_shown {
+            let action = microwave::auto_page(
+                &mut ui,
+                &mut sm,
+                &mut focus,
+                &appdata.auto_table,
+                &mut appdata.auto,
+            );
+            if let Some(AutoAction::Set(setting)) = action {
+                appdata.set_auto(setting);
+            }
+            if action.is_some() {
+                auto_shown = false;
+                LATENCY.state_changed();
+                focus.reset();
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            drop(ui);
+            focus
+                .draw(&mut display, Rgb565::CSS_ORANGE)
+                .ok();
+            focus.end_frame();
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
 
-        let start_draw_time = embassy_time::Instant::now();
         ui.sub_ui(|ui| {
             ui.style_mut().default_font =
                 ascii::FONT_9X18_BOLD;
+            if focus
+                .track(
+                    ui.add_horizontal(
+                        Button::new("Lock")
+                            .smartstate(sm.next()),
+                    ),
+                )
+                .clicked()
+            {
+                appdata.handle(Input::Lock);
+            }
             ui.add(
-                Label::new("Kolibri Microwave UI")
-                    .smartstate(sm.next()),
+                Label::new(status).smartstate(sm.next()),
             );
             Ok(())
         })
         .ok();
 
-        let remaining = appdata.remaining();
+        let remaining = Duration::from_millis(
+            appdata.timer.remaining_ms(),
+        );
+        let mut open_programs = false;
 
         ui.right_panel_ui(200, false, |ui| {
-            ui.add(Spacer::new(Size::new(0, 30)));
+            ui.add(Spacer::new(Size::new(0, 5)));
+            // the loaded program and its stage; tap to pick
+            // another one
+            let program = match &appdata.run {
+                Some(run) => format_no_std::show(
+                    &mut textbuf,
+                    format_args!(
+                        "{} {}/{}",
+                        run.program().name(),
+                        run.stage_index() + 1,
+                        run.program().stages().len()
+                    ),
+                )
+                .unwrap(),
+                None => "Programs",
+            };
+            if focus
+                .track(
+                    ui.add(
+                        Button::new(program)
+                            .smartstate(sm.next()),
+                    ),
+                )
+                .clicked()
+                && !appdata.timer.is_running()
+            {
+                open_programs = true;
+            }
             ui.add_horizontal(Spacer::new(Size::new(
                 15, 0,
             )));
@@ -481,42 +874,38 @@ async fn main(spawner: Spawner) {
             // ui.add_horizontal(Spacer::new(Size::new(65,
             // 0)));
             ui.sub_ui(|ui| {
-                if appdata.timer_running() {
+                if !appdata.timer.is_stopped() {
                     ui.style_mut().icon_color =
                         Rgb565::CSS_LIGHT_GRAY;
                 }
-                if ui
-                    .add_horizontal(
+                if focus
+                    .track(ui.add_horizontal(
                         IconButton::new(
                             size32px::actions::AddCircle,
                         )
                         .smartstate(sm.next()),
-                    )
+                    ))
                     .clicked()
                 {
-                    if !(appdata.timer_running()
-                        || appdata.timer_paused())
-                    {
-                        appdata.add_secs(10);
+                    if appdata.timer.add_secs(10) {
+                        LATENCY.state_changed();
                     }
                 }
                 ui.add_horizontal(
                     Label::new("+/- 10s")
                         .smartstate(sm.next()),
                 );
-                if ui
-                    .add(
+                if focus
+                    .track(ui.add(
                         IconButton::new(
                             size32px::actions::MinusCircle,
                         )
                         .smartstate(sm.next()),
-                    )
+                    ))
                     .clicked()
                 {
-                    if !(appdata.timer_running()
-                        || appdata.timer_paused())
-                    {
-                        appdata.sub_secs(10);
+                    if appdata.timer.sub_secs(10) {
+                        LATENCY.state_changed();
                     }
                 }
                 Ok(())
@@ -526,60 +915,70 @@ async fn main(spawner: Spawner) {
             ui.add_horizontal(Spacer::new(Size::new(
                 15, 0,
             )));
-            if ui
-                .add_horizontal(
-                    IconButton::new(
-                        size48px::actions::Undo,
-                    )
-                    .smartstate(sm.next()),
+            if focus
+                .track(
+                    ui.add_horizontal(
+                        IconButton::new(
+                            size48px::actions::Undo,
+                        )
+                        .smartstate(sm.next()),
+                    ),
                 )
                 .clicked()
             {
-                appdata.reset_timer();
+                appdata.handle(Input::Cancel);
+                LATENCY.state_changed();
                 sm.force_redraw_all();
             }
 
-            if !finished && appdata.timer_finished() {
+            if timer_event == Some(TimerEvent::Finished) {
                 sm.peek().force_redraw();
             }
 
-            if appdata.timer_finished() {
-                if ui
-                    .add_horizontal(
+            if appdata.timer.is_finished() {
+                if focus
+                    .track(ui.add_horizontal(
                         IconButton::new(
                             size48px::actions::RemoveSquare,
                         )
                         .smartstate(sm.next()),
-                    )
+                    ))
                     .clicked()
                 {
-                    appdata.reset_timer();
+                    appdata.handle(Input::Cancel);
+                    LATENCY.state_changed();
                     sm.force_redraw_all();
                 }
-            } else if appdata.timer_running() {
-                if ui
-                    .add_horizontal(
-                        IconButton::new(
-                            size48px::music::Pause,
-                        )
-                        .smartstate(sm.next()),
+            } else if appdata.timer.is_running() {
+                if focus
+                    .track(
+                        ui.add_horizontal(
+                            IconButton::new(
+                                size48px::music::Pause,
+                            )
+                            .smartstate(sm.next()),
+                        ),
                     )
                     .clicked()
                 {
-                    appdata.pause_timer();
+                    appdata.handle(Input::Pause);
+                    LATENCY.state_changed();
                     sm.force_redraw_all();
                 }
             } else {
-                if ui
-                    .add_horizontal(
-                        IconButton::new(
-                            size48px::music::Play,
-                        )
-                        .smartstate(sm.next()),
+                if focus
+                    .track(
+                        ui.add_horizontal(
+                            IconButton::new(
+                                size48px::music::Play,
+                            )
+                            .smartstate(sm.next()),
+                        ),
                     )
                     .clicked()
                 {
-                    appdata.start_timer();
+                    appdata.handle(Input::Start);
+                    LATENCY.state_changed();
                     sm.force_redraw_all();
                 }
             }
@@ -589,23 +988,27 @@ async fn main(spawner: Spawner) {
         ui.add(Spacer::new(Size::new(0, 20)));
         ui.expand_row_height(65);
         ui.right_panel_ui(80, false, |ui| {
-            if appdata.timer_running() {
+            if appdata.timer.is_running() {
                 ui.style_mut().icon_color =
                     Rgb565::CSS_LIGHT_GRAY;
             }
-            if ui
-                .add(
-                    IconButton::new(
-                        size32px::actions::AddCircle,
-                    )
-                    .smartstate(sm.next()),
+            if focus
+                .track(
+                    ui.add(
+                        IconButton::new(
+                            size32px::actions::AddCircle,
+                        )
+                        .smartstate(sm.next()),
+                    ),
                 )
                 .clicked()
             {
-                if !appdata.timer_running() {
-                    appdata.set_wattage_level(
-                        (appdata.wattage_level + 1).min(5),
+                if !appdata.timer.is_running() {
+                    appdata.run = None;
+                    appdata.power.set_level(
+                        appdata.power.level().up(),
                     );
+                    LATENCY.state_changed();
                 }
             }
             ui.expand_row_height(40);
@@ -613,34 +1016,51 @@ async fn main(spawner: Spawner) {
             // 40)));
             ui.add(
                 HashLabel::new(
-                    appdata.get_wattage_level_str(),
+                    appdata.power.level().label(),
                     sm.next(),
                     &hasher,
                 )
                 .with_font(ascii::FONT_10X20),
             );
-            if ui
-                .add(
-                    IconButton::new(
-                        size32px::actions::MinusCircle,
-                    )
-                    .smartstate(sm.next()),
+            if focus
+                .track(
+                    ui.add(
+                        IconButton::new(
+                            size32px::actions::MinusCircle,
+                        )
+                        .smartstate(sm.next()),
+                    ),
                 )
                 .clicked()
             {
-                if !appdata.timer_running() {
-                    appdata.set_wattage_level(
-                        appdata
-                            .wattage_level
-                            .saturating_sub(1),
+                if !appdata.timer.is_running() {
+                    appdata.run = None;
+                    appdata.power.set_level(
+                        appdata.power.level().down(),
                     );
+                    LATENCY.state_changed();
                 }
             }
             Ok(())
         })
         .ok();
 
-        finished = appdata.timer_finished();
+        if open_programs {
+            picking = Some(appdata.programs.library());
+            LATENCY.state_changed();
+            focus.reset();
+            ui.clear_background().ok();
+            sm.force_redraw_all();
+        }
+
+        drop(ui);
+        focus.draw(&mut display, Rgb565::CSS_ORANGE).ok();
+        focus.end_frame();
+        if let Some(ms) = LATENCY.take_latency() {
+            if latency.add(ms) {
+                println!("{}", latency);
+            }
+        }
 
         let end_time = embassy_time::Instant::now();
         let draw_time = display.get_time();
@@ -1,34 +1,26 @@
 #![no_std]
 #![no_main]
 
-use core::{cell::RefCell, cmp::min};
+use core::{cell::RefCell, cmp::min, fmt};
 
 use display_interface_spi::SPIInterface;
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_sync::{
-    blocking_mutex::{raw::NoopRawMutex, NoopMutex},
-    signal::Signal,
-};
+use embassy_sync::blocking_mutex::NoopMutex;
 use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::{
     mono_font::ascii,
     pixelcolor::Rgb565,
-    prelude::{Point, RgbColor, Size, WebColors},
+    prelude::{DrawTarget, RgbColor, Size, WebColors},
+};
+use embedded_graphics_profiler_display::{
+    LatencyTag,
+    ProfilerDisplay,
 };
-use embedded_graphics_profiler_display::ProfilerDisplay;
 use esp_backtrace as _;
 use esp_hal::{
-    clock::ClockControl,
-    gpio::{
-        GpioPin,
-        Input,
-        Io,
-        Level,
-        Output,
-        Pull,
-        NO_PIN,
-    },
+    clock::{ClockControl, Clocks},
+    gpio::{Io, Level, Output, NO_PIN},
     peripherals::{Peripherals, SPI2, SPI3},
     prelude::*,
     rtc_cntl::Rtc,
@@ -37,14 +29,35 @@ use esp_hal::{
     timer::timg::TimerGroup,
 };
 use esp_println::println;
+use kolibri_cyd_tester_app_embassy::{
+    alarm::{Alarm, AlarmAction, AlarmEvent, AlarmScreen},
+    buttons::{button_task, ActionChannel},
+    buzzer::{buzzer_task, Chime, ChimeSignal},
+    countdown::{EmbassyClock, DEFAULT_SECS},
+    crash::{self, ReportAction},
+    focus::Focus,
+    gesture::{GestureRecognizer, SwipeDirection},
+    intervals::{
+        stored_programs,
+        IntervalEvent,
+        IntervalRunner,
+    },
+    keypad::DurationEntry,
+    latency::LatencyStats,
+    stopwatch::Stopwatch,
+    storage,
+    timers::{stored_presets, TimerList, LABEL_LEN},
+    touch::{event::TouchChannel, touch_task},
+};
 use kolibri_embedded_gui::{
+    button::Button,
     iconbutton::IconButton,
     icons::{size32px, size48px},
-    label::Label,
+    label::{HashLabel, Hasher, Label},
     smartstate::SmartstateProvider,
     spacer::Spacer,
     style::medsize_rgb565_style,
-    ui::{Interaction, Ui},
+    ui::Ui,
 };
 use mipidsi::{
     models::ILI9341Rgb565,
@@ -52,160 +65,128 @@ use mipidsi::{
     Builder,
 };
 use static_cell::StaticCell;
-use xpt2046::Xpt2046;
-
-#[embassy_executor::task]
-async fn touch_task(
-    touch_irq: GpioPin<36>,
-    spi: &'static mut NoopMutex<
-        RefCell<Spi<'static, SPI3, FullDuplexMode>>,
-    >,
-    touch_cs: GpioPin<33>,
-    touch_signal: &'static Signal<
-        NoopRawMutex,
-        Option<Point>,
-    >,
-) -> ! {
-    let mut touch_driver = Xpt2046::new(
-        SpiDevice::new(
-            spi,
-            Output::new(touch_cs, Level::Low),
-        ),
-        Input::new(touch_irq, Pull::Up),
-        xpt2046::Orientation::LandscapeFlipped,
-    );
-    touch_driver.set_num_samples(16);
-    touch_driver.init(&mut embassy_time::Delay).unwrap();
-
-    println!("touch task");
 
-    loop {
-        touch_driver
-            .run()
-            .expect("Running Touch driver failed");
-        if touch_driver.is_touched() {
-            let point = touch_driver.get_touch_point();
-            touch_signal.signal(Some(Point::new(
-                point.x + 25,
-                240 - point.y,
-            )));
-        } else {
-            touch_signal.signal(None);
-        }
-        Timer::after(Duration::from_millis(1)).await; // 100
-                                                      // a second
-
-        // Your touch handling logic here
-    }
-}
+/// Labels a timer can have. Tapping the label on a
+/// timer's page picks the next one.
+const LABELS: [&str; 6] =
+    ["Timer", "Pasta", "Oven", "Eggs", "Rice", "Tea"];
 
-struct AppData {
-    timer_start: Instant,
-    timer_set_duration: Duration,
-    timer_remaining_duration: Duration,
-    timer_running: bool,
-    timer_paused: bool,
+enum Page {
+    /// All timers, and the one that runs out next.
+    List,
+    /// One timer, to set it up.
+    Timer(usize),
+    /// Typing in the duration of a timer.
+    Keypad(usize),
+    Stopwatch,
+    /// The interval program and its current phase.
+    Intervals,
 }
 
-impl AppData {
-    fn new() -> Self {
-        Self {
-            timer_start: Instant::now(),
-            timer_set_duration: Duration::from_secs(10),
-            timer_remaining_duration: Duration::from_secs(
-                10,
-            ),
-            timer_running: false,
-            timer_paused: false,
-        }
-    }
+/// Laps shown at once on the stopwatch page.
+const LAP_ROWS: usize = 4;
 
-    fn set_timer_duration(&mut self, duration: Duration) {
-        self.timer_set_duration = duration;
-    }
+/// Longest text of a label, a line of the small font
+/// across the screen and a bit.
+const TEXT_LEN: usize = 64;
 
-    fn add_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_add(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(5999))
-                .min(Duration::from_secs(5999)),
-        );
-    }
+/// The keys of the keypad page. `C` clears the entry, `<`
+/// removes the last digit.
+const KEYS: [[&str; 3]; 4] = [
+    ["1", "2", "3"],
+    ["4", "5", "6"],
+    ["7", "8", "9"],
+    ["C", "0", "<"],
+];
 
-    fn sub_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_sub(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(10))
-                .max(Duration::from_secs(10)),
-        );
-    }
+/// Milliseconds left on a timer as `mm:ss`, or `h:mm:ss`
+/// from an hour on. Rounded up, so `00:00` only shows once
+/// it ran out.
+struct Hms(u64);
 
-    fn start_timer(&mut self) {
-        if !self.timer_paused {
-            self.timer_remaining_duration =
-                self.timer_set_duration;
+impl fmt::Display for Hms {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        let secs = self.0.div_ceil(1000);
+        let (h, m, s) =
+            (secs / 3600, secs / 60 % 60, secs % 60);
+        if h > 0 {
+            write!(f, "{}:{:02}:{:02}", h, m, s)
+        } else {
+            write!(f, "{:02}:{:02}", m, s)
         }
-
-        self.timer_start = Instant::now();
-        self.timer_running = true;
-        self.timer_paused = false;
-    }
-
-    fn pause_timer(&mut self) {
-        self.timer_paused = true;
-        self.timer_remaining_duration = self
-            .timer_remaining_duration
-            .checked_sub(self.timer_start.elapsed())
-            .unwrap_or(Duration::from_secs(0));
-    }
-
-    fn reset_timer(&mut self) {
-        self.timer_start = Instant::now();
-        self.timer_paused = false;
-        self.timer_running = false;
-        self.timer_remaining_duration =
-            self.timer_set_duration;
     }
+}
 
-    fn remaining(&self) -> Duration {
-        if self.timer_stopped() {
-            self.timer_set_duration
-        } else if self.timer_paused() {
-            self.timer_remaining_duration
+/// Draws `ms` as `mm:ss:mmm`, or `hh:mm:ss` from an hour
+/// on. Every field has its own label, so only the ones that
+/// changed since `prev` are redrawn.
+fn time_labels<
+    D: DrawTarget<Color = Rgb565>,
+    const N: usize,
+>(
+    ui: &mut Ui<D, Rgb565>,
+    sm: &mut SmartstateProvider<N>,
+    prev: &mut [u64; 3],
+    ms: u64,
+) {
+    let (fields, last_width) = if ms >= 3_600_000 {
+        (
+            [
+                ms / 3_600_000,
+                ms / 60_000 % 60,
+                ms / 1000 % 60,
+            ],
+            2,
+        )
+    } else {
+        ([ms / 60_000, ms / 1000 % 60, ms % 1000], 3)
+    };
+    let mut textbuf = [0u8; 8];
+    for (f, (&value, prev)) in
+        fields.iter().zip(prev.iter_mut()).enumerate()
+    {
+        if value != *prev {
+            sm.peek().force_redraw();
+            *prev = value;
+        }
+        let width = if f == 2 { last_width } else { 2 };
+        let text = format_no_std::show(
+            &mut textbuf,
+            format_args!("{:01$}", value, width),
+        )
+        .unwrap();
+        if f == 2 {
+            ui.add(Label::new(text).smartstate(sm.next()));
         } else {
-            self.timer_remaining_duration
-                .checked_sub(self.timer_start.elapsed())
-                .unwrap_or(Duration::from_secs(0))
+            ui.add_horizontal(
+                Label::new(text).smartstate(sm.next()),
+            );
+            ui.add_horizontal(
+                Label::new(":").smartstate(sm.next()),
+            );
         }
     }
-
-    fn timer_stopped(&self) -> bool {
-        !self.timer_running
-    }
-
-    fn timer_paused(&self) -> bool {
-        self.timer_running && self.timer_paused
-    }
-
-    fn timer_running(&self) -> bool {
-        self.timer_running && !self.timer_paused
-    }
-
-    fn timer_finished(&self) -> bool {
-        self.timer_running
-            && self.remaining() == Duration::from_secs(0)
-    }
 }
 
+/// Stamps the first frame that shows the result of a touch,
+/// in the touch task's clock.
+static LATENCY: LatencyTag =
+    LatencyTag::new(|| Instant::now().as_millis());
+
 #[main]
 async fn main(spawner: Spawner) {
     let peripherals = Peripherals::take();
     let system = SystemControl::new(peripherals.SYSTEM);
-    let mut clocks =
+    // 'static for the buzzer task
+    static CLOCKS: StaticCell<Clocks<'static>> =
+        StaticCell::new();
+    let clocks = &*CLOCKS.init(
         ClockControl::boot_defaults(system.clock_control)
-            .freeze();
+            .freeze(),
+    );
 
     // Enable the RWDT watchdog timer:
     let mut rtc = Rtc::new(peripherals.LPWR);
@@ -214,8 +195,8 @@ async fn main(spawner: Spawner) {
     println!("RWDT watchdog enabled!");
 
     // Initialize the SYSTIMER peripheral, and then Embassy:
-    let timg0 = TimerGroup::new(peripherals.TIMG0, &clocks);
-    esp_hal_embassy::init(&clocks, timg0.timer0);
+    let timg0 = TimerGroup::new(peripherals.TIMG0, clocks);
+    esp_hal_embassy::init(clocks, timg0.timer0);
     println!("Embassy initialized!");
 
     let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
@@ -239,7 +220,7 @@ async fn main(spawner: Spawner) {
         peripherals.SPI2,
         10.MHz(),
         SpiMode::Mode0,
-        &mut clocks,
+        clocks,
     )
     .with_pins(
         Some(sclk),
@@ -271,7 +252,8 @@ async fn main(spawner: Spawner) {
         .init(&mut embassy_time::Delay)
         .unwrap();
 
-    let mut display = ProfilerDisplay::new(display);
+    let mut display = ProfilerDisplay::new(display)
+        .with_latency_tag(&LATENCY);
 
     {
         let mut ui = Ui::new_fullscreen(
@@ -295,7 +277,7 @@ async fn main(spawner: Spawner) {
         peripherals.SPI3,
         2.MHz(),
         SpiMode::Mode0,
-        &mut clocks,
+        clocks,
     )
     .with_pins(
         Some(touch_clk),
@@ -310,18 +292,42 @@ async fn main(spawner: Spawner) {
         NoopMutex::new(RefCell::new(touch_spi));
     let touch_spi_bus = TOUCH_SPI_BUS.init(touch_spi_bus);
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<
-        Signal<NoopRawMutex, Option<Point>>,
-    > = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
+    static TOUCH_EVENTS: StaticCell<TouchChannel> =
+        StaticCell::new();
+    let touch_events =
+        &*TOUCH_EVENTS.init(TouchChannel::new());
 
     spawner
         .spawn(touch_task(
             touch_irq,
             touch_spi_bus,
             touch_cs,
-            touch_signal,
+            touch_events,
+        ))
+        .unwrap();
+
+    static BUTTON_ACTIONS: StaticCell<ActionChannel> =
+        StaticCell::new();
+    let button_actions =
+        &*BUTTON_ACTIONS.init(ActionChannel::new());
+    spawner
+        .spawn(button_task(
+            io.pins.gpio0,
+            io.pins.gpio22,
+            io.pins.gpio27,
+            button_actions,
+        ))
+        .unwrap();
+
+    static CHIMES: StaticCell<ChimeSignal> =
+        StaticCell::new();
+    let chimes = &*CHIMES.init(ChimeSignal::new());
+    spawner
+        .spawn(buzzer_task(
+            peripherals.LEDC,
+            clocks,
+            io.pins.gpio26,
+            chimes,
         ))
         .unwrap();
 
@@ -329,20 +335,49 @@ async fn main(spawner: Spawner) {
     let _ = spawner;
 
     // variables
-    let mut appdata = AppData::new();
-    let (mut prev_mins, mut prev_secs, mut prev_millis) =
-        (0, 0, 0);
-    let mut finished = false;
+    let mut timers = TimerList::new(EmbassyClock);
+    timers.add(LABELS[0], DEFAULT_SECS);
+    let mut cur_page = Page::List;
+    let mut prev_fields = [0; 3];
+    let mut keypad = DurationEntry::new();
+    let mut presets = stored_presets();
+    let mut stopwatch = Stopwatch::new(EmbassyClock);
+    // laps scrolled past on the stopwatch page, newest
+    // first
+    let mut lap_scroll = 0;
+    let programs = stored_programs();
+    let mut program = 0;
+    let mut intervals = IntervalRunner::new(
+        programs.list[program].clone(),
+        EmbassyClock,
+    );
+
+    let mut alarm = Alarm::new();
+    let mut alarm_screen = AlarmScreen::new("Time's up!");
+    // the red channel of the RGB LED, which is active low
+    let mut alert_led =
+        Output::new(io.pins.gpio4, Level::High);
+    // the green one, lit for a moment when an interval
+    // phase changes
+    let mut phase_led =
+        Output::new(io.pins.gpio16, Level::High);
+    let mut phase_led_until = 0;
+
+    // report of the crash before the last reboot, if any
+    let mut crash_report = crash::stored_report();
 
     // touchpoints
 
-    let mut last_touch = None;
+    let mut gestures = GestureRecognizer::default();
+    let mut focus = Focus::new();
+    let mut latency = LatencyStats::new("kolibri", "timer");
 
     static BUF_CELL: StaticCell<[Rgb565; 100 * 100]> =
         StaticCell::new();
     let buf = BUF_CELL.init([Rgb565::BLACK; 100 * 100]);
 
-    let mut textbuf = [0u8; 64];
+    let mut textbuf = [0u8; TEXT_LEN];
+    let hasher = Hasher::new();
 
     // Periodically feed the RWDT watchdog timer when our
     // tasks are not running:
@@ -353,212 +388,1111 @@ async fn main(spawner: Spawner) {
 
         let start_time = embassy_time::Instant::now();
         sm.restart_counter();
+        let mut go_back = false;
+        while let Ok(action) = button_actions.try_receive()
+        {
+            go_back |= !focus.apply(action);
+        }
+        if focus.take_moved() {
+            sm.force_redraw_all();
+        }
         let mut ui = Ui::new_fullscreen(
             &mut display,
             medsize_rgb565_style(),
         );
-        if let Some(touch) = touch_signal.try_take() {
-            let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => {
-                    Interaction::Drag(point)
-                }
-                (Some(point), None) => {
-                    Interaction::Click(point)
+        let now = embassy_time::Instant::now().as_millis();
+        let events = match touch_events.try_receive() {
+            Some(event) => {
+                LATENCY.touched(event.t_ms);
+                gestures.event(event)
+            }
+            None => gestures.poll(now),
+        };
+        ui.interact(
+            focus
+                .interaction()
+                .unwrap_or(events.interaction),
+        );
+
+        // BUFFER ENABLE/DISABLE
+        ui.set_buffer(buf);
+
+        if let Some(report) = &crash_report {
+            match crash::report_page(
+                &mut ui, &mut sm, report,
+            ) {
+                Some(ReportAction::Dismiss) => {
+                    crash::clear_stored_report();
+                    crash_report = None;
+                    ui.clear_background().ok();
+                    sm.force_redraw_all();
                 }
-                (None, Some(point)) => {
-                    Interaction::Release(point)
+                Some(ReportAction::SendOverSerial) => {
+                    crash::dump_to_serial(report);
                 }
-                (None, None) => Interaction::None,
-            };
-            ui.interact(interact);
-            // println!("{:?}, {:?}, {:?}", last_touch,
-            // touch, interact);
+                None => {}
+            }
+            rtc.rwdt.feed();
+            Timer::after(Duration::from_millis(17)).await;
+            continue;
+        }
 
-            last_touch = touch;
+        let start_draw_time = embassy_time::Instant::now();
+
+        if timers.update() {
+            // a timer ran out: ring, or add it to the
+            // alarm that is shown
+            alarm.trigger(now);
+            focus.reset();
+            ui.clear_background().ok();
+            sm.force_redraw_all();
+        }
+        if !timers.is_alerting() {
+            // the finished timers were reset or removed
+            alarm.dismiss();
+        }
+        match alarm.update(now) {
+            Some(AlarmEvent::Beep { level }) => {
+                chimes.signal(Chime::Alarm { level });
+            }
+            Some(AlarmEvent::Rings) => {
+                focus.reset();
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            Some(AlarmEvent::Silenced) => {
+                sm.force_redraw_all();
+            }
+            None => {}
+        }
+        if alarm.led_on(now) {
+            alert_led.set_low();
+        } else {
+            alert_led.set_high();
         }
 
-        // BUFFER ENABLE/DISABLE
-        ui.set_buffer(buf);
+        match intervals.update() {
+            Some(IntervalEvent::Phase { .. }) => {
+                chimes.signal(Chime::Phase);
+                phase_led_until = now + 1000;
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            Some(IntervalEvent::Finished) => {
+                chimes.signal(Chime::Finished);
+                phase_led_until = now + 3000;
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+            None => {}
+        }
+        if now < phase_led_until {
+            phase_led.set_low();
+        } else {
+            phase_led.set_high();
+        }
 
-        let start_draw_time = embassy_time::Instant::now();
-        ui.sub_ui(|ui| {
-            ui.style_mut().default_font =
-                ascii::FONT_9X18_BOLD;
-            ui.add(
-                Label::new("Kolibri Timer App")
-                    .smartstate(sm.next()),
+        if alarm.is_shown() {
+            let action = alarm_screen.show(
+                &mut ui,
+                &mut sm,
+                &mut focus,
+                &alarm,
+                now,
+                timers.alerting().map(|t| t.label()),
             );
-            Ok(())
-        })
-        .ok();
+            match action {
+                Some(AlarmAction::Snooze(ms)) => {
+                    alarm.snooze(now, ms);
+                }
+                Some(AlarmAction::Dismiss) => {
+                    timers.acknowledge();
+                    alarm.dismiss();
+                }
+                None if go_back => {
+                    timers.acknowledge();
+                    alarm.dismiss();
+                }
+                None => {}
+            }
+            if action.is_some() || go_back {
+                LATENCY.state_changed();
+                focus.reset();
+                ui.clear_background().ok();
+                sm.force_redraw_all();
+            }
+        } else {
+            match cur_page {
+                Page::List => {
+                    ui.add_horizontal(
+                        Label::new("Timers")
+                            .smartstate(sm.next())
+                            .with_font(
+                                ascii::FONT_9X18_BOLD,
+                            ),
+                    );
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(6, 0),
+                    ));
+                    let open_stopwatch = focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new("Stopwatch")
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked();
+                    if open_stopwatch
+                        || events.swipe()
+                            == Some(SwipeDirection::Left)
+                    {
+                        cur_page = Page::Stopwatch;
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(6, 0),
+                    ));
+                    if focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new("Intervals")
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                    {
+                        cur_page = Page::Intervals;
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                    let mut add = false;
+                    ui.sub_ui(|ui| {
+                        if timers.is_full() {
+                            ui.style_mut().icon_color =
+                                Rgb565::CSS_LIGHT_GRAY;
+                        }
+                        add = focus
+                            .track(
+                                ui.add(
+                                    IconButton::new(
+                                        size32px::actions::AddCircle,
+                                    )
+                                    .smartstate(sm.next()),
+                                ),
+                            )
+                            .clicked();
+                        Ok(())
+                    })
+                    .ok();
+                    if add {
+                        if let Some(i) = timers
+                            .add(LABELS[0], DEFAULT_SECS)
+                        {
+                            cur_page = Page::Timer(i);
+                            LATENCY.state_changed();
+                            focus.reset();
+                            ui.clear_background().ok();
+                            sm.force_redraw_all();
+                            continue;
+                        }
+                    }
 
-        let remaining = appdata.remaining();
+                    // the timer that runs out first
+                    let next = match timers.soonest() {
+                        Some(i) => {
+                            let t = timers.get(i).unwrap();
+                            format_no_std::show(
+                                &mut textbuf,
+                                format_args!(
+                                    "Next: {} {}",
+                                    t.label(),
+                                    Hms(t
+                                        .timer
+                                        .remaining_ms()),
+                                ),
+                            )
+                            .unwrap()
+                        }
+                        None => "No timer running",
+                    };
+                    ui.add(HashLabel::new(
+                        next,
+                        sm.next(),
+                        &hasher,
+                    ));
+                    ui.add(Spacer::new(Size::new(0, 5)));
 
-        ui.add(Spacer::new(Size::new(0, 60)));
-        ui.add_horizontal(Spacer::new(Size::new(80, 0)));
-        ui.sub_ui(|ui| {
-            ui.style_mut().default_font = ascii::FONT_10X20;
-            if remaining.as_secs() / 60 != prev_mins {
-                sm.peek().force_redraw();
-                prev_mins = remaining.as_secs() / 60;
-            }
-            ui.add_horizontal(
-                Label::new(
-                    &format_no_std::show(
+                    let mut open = None;
+                    for (i, t) in
+                        timers.iter_mut().enumerate()
+                    {
+                        let toggle = if t.timer.is_running()
+                        {
+                            ui.add_horizontal(
+                                IconButton::new(
+                                    size32px::music::Pause,
+                                )
+                                .smartstate(sm.next()),
+                            )
+                        } else {
+                            ui.add_horizontal(
+                                IconButton::new(
+                                    size32px::music::Play,
+                                )
+                                .smartstate(sm.next()),
+                            )
+                        };
+                        if focus.track(toggle).clicked() {
+                            if t.timer.is_running() {
+                                t.timer.pause();
+                            } else {
+                                t.timer.start();
+                            }
+                            LATENCY.state_changed();
+                            sm.force_redraw_all();
+                        }
+                        // padded, so all the times line up
+                        let label = format_no_std::show(
+                            &mut textbuf,
+                            format_args!(
+                                "{:<1$}",
+                                t.label(),
+                                LABEL_LEN
+                            ),
+                        )
+                        .unwrap();
+                        if focus
+                            .track(
+                                ui.add_horizontal(
+                                    Button::new(label)
+                                        .smartstate(
+                                            sm.next(),
+                                        ),
+                                ),
+                            )
+                            .clicked()
+                        {
+                            open = Some(i);
+                        }
+                        ui.add(HashLabel::new(
+                            format_no_std::show(
+                                &mut textbuf,
+                                format_args!(
+                                    "{}",
+                                    Hms(t
+                                        .timer
+                                        .remaining_ms())
+                                ),
+                            )
+                            .unwrap(),
+                            sm.next(),
+                            &hasher,
+                        ));
+                    }
+                    if let Some(i) = open {
+                        cur_page = Page::Timer(i);
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                }
+                Page::Timer(i) => {
+                    let back = focus
+                        .track(ui.add_horizontal(
+                            IconButton::new(
+                                size32px::navigation::NavArrowLeft,
+                            )
+                            .smartstate(sm.next()),
+                        ))
+                        .clicked();
+                    if back
+                        || go_back
+                        || events.swipe()
+                            == Some(SwipeDirection::Right)
+                    {
+                        cur_page = Page::List;
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                    let entry = timers.get_mut(i).unwrap();
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(10, 0),
+                    ));
+                    // tapping the label picks the next one
+                    let label = format_no_std::show(
                         &mut textbuf,
                         format_args!(
-                            "{:02}",
-                            remaining.as_secs() / 60
+                            "{:<1$}",
+                            entry.label(),
+                            LABEL_LEN
                         ),
                     )
-                    .unwrap(),
-                )
-                .smartstate(sm.next()),
-            );
-            ui.add_horizontal(
-                Label::new(":").smartstate(sm.next()),
-            );
+                    .unwrap();
+                    if focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new(label)
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                    {
+                        let next = LABELS
+                            .iter()
+                            .position(|l| {
+                                *l == entry.label()
+                            })
+                            .map_or(0, |l| {
+                                (l + 1) % LABELS.len()
+                            });
+                        entry.set_label(LABELS[next]);
+                        LATENCY.state_changed();
+                        sm.force_redraw_all();
+                    }
+                    if focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new("Set")
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                    {
+                        keypad.clear();
+                        cur_page = Page::Keypad(i);
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                    if focus
+                        .track(
+                            ui.add(
+                                Button::new("Delete")
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                    {
+                        timers.remove(i);
+                        cur_page = Page::List;
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
 
-            if remaining.as_secs() % 60 != prev_secs {
-                sm.peek().force_redraw();
-                prev_secs = remaining.as_secs() % 60;
-            }
-            ui.add_horizontal(
-                Label::new(
-                    &format_no_std::show(
+                    let timer = &mut entry.timer;
+                    let ms = timer.remaining_ms();
+
+                    ui.add(Spacer::new(Size::new(0, 40)));
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(80, 0),
+                    ));
+                    ui.sub_ui(|ui| {
+                        ui.style_mut().default_font =
+                            ascii::FONT_10X20;
+                        time_labels(
+                            ui,
+                            &mut sm,
+                            &mut prev_fields,
+                            ms,
+                        );
+                        Ok(())
+                    })
+                    .ok();
+
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(65, 0),
+                    ));
+                    ui.sub_ui(|ui| {
+                        if !timer.is_stopped() {
+                            ui.style_mut().icon_color =
+                                Rgb565::CSS_LIGHT_GRAY;
+                        }
+                        if focus
+                            .track(
+                                ui.add_horizontal(
+                                    IconButton::new(
+                                        size32px::actions::AddCircle,
+                                    )
+                                    .smartstate(sm.next()),
+                                ),
+                            )
+                            .clicked()
+                        {
+                            if timer.add_secs(10) {
+                                LATENCY.state_changed();
+                            }
+                        }
+                        ui.add_horizontal(
+                            Label::new("+/- 10s")
+                                .smartstate(sm.next()),
+                        );
+                        if focus
+                            .track(
+                                ui.add(
+                                    IconButton::new(
+                                        size32px::actions::MinusCircle,
+                                    )
+                                    .smartstate(sm.next()),
+                                ),
+                            )
+                            .clicked()
+                        {
+                            if timer.sub_secs(10) {
+                                LATENCY.state_changed();
+                            }
+                        }
+                        Ok(())
+                    })
+                    .ok();
+
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(80, 0),
+                    ));
+                    if focus
+                        .track(
+                            ui.add_horizontal(
+                                IconButton::new(
+                                    size48px::actions::Undo,
+                                )
+                                .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                    {
+                        timer.reset();
+                        LATENCY.state_changed();
+                        sm.force_redraw_all();
+                    }
+
+                    if timer.is_running() {
+                        if focus
+                            .track(
+                                ui.add_horizontal(
+                                    IconButton::new(
+                                        size48px::music::Pause,
+                                    )
+                                    .smartstate(sm.next()),
+                                ),
+                            )
+                            .clicked()
+                        {
+                            timer.pause();
+                            LATENCY.state_changed();
+                            sm.force_redraw_all();
+                        }
+                    } else {
+                        if focus
+                            .track(
+                                ui.add_horizontal(
+                                    IconButton::new(
+                                        size48px::music::Play,
+                                    )
+                                    .smartstate(sm.next()),
+                                ),
+                            )
+                            .clicked()
+                        {
+                            timer.start();
+                            LATENCY.state_changed();
+                            sm.force_redraw_all();
+                        }
+                    }
+                }
+                Page::Stopwatch => {
+                    let back = focus
+                        .track(ui.add_horizontal(
+                            IconButton::new(
+                                size32px::navigation::NavArrowLeft,
+                            )
+                            .smartstate(sm.next()),
+                        ))
+                        .clicked();
+                    if back
+                        || go_back
+                        || events.swipe()
+                            == Some(SwipeDirection::Right)
+                    {
+                        cur_page = Page::List;
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                    if events.swipe()
+                        == Some(SwipeDirection::Left)
+                    {
+                        cur_page = Page::Intervals;
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(10, 0),
+                    ));
+                    ui.add(
+                        Label::new("Stopwatch")
+                            .smartstate(sm.next())
+                            .with_font(
+                                ascii::FONT_9X18_BOLD,
+                            ),
+                    );
+
+                    let ms = stopwatch.elapsed_ms();
+                    ui.add(Spacer::new(Size::new(0, 10)));
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(80, 0),
+                    ));
+                    ui.sub_ui(|ui| {
+                        ui.style_mut().default_font =
+                            ascii::FONT_10X20;
+                        time_labels(
+                            ui,
+                            &mut sm,
+                            &mut prev_fields,
+                            ms,
+                        );
+                        Ok(())
+                    })
+                    .ok();
+
+                    // laps while running, a clean start
+                    // while stopped
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(70, 0),
+                    ));
+                    let running = stopwatch.is_running();
+                    let lap_or_reset = focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new(if running {
+                                    "  Lap"
+                                } else {
+                                    "Reset"
+                                })
+                                .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked();
+                    if lap_or_reset {
+                        if running {
+                            stopwatch.lap();
+                        } else {
+                            stopwatch.reset();
+                        }
+                        // back to the newest lap
+                        lap_scroll = 0;
+                        LATENCY.state_changed();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                    }
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(20, 0),
+                    ));
+                    let start_stop = focus
+                        .track(
+                            ui.add(
+                                IconButton::new(if running {
+                                    size48px::music::Pause
+                                } else {
+                                    size48px::music::Play
+                                })
+                                .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked();
+                    if start_stop {
+                        if running {
+                            stopwatch.stop();
+                        } else {
+                            stopwatch.start();
+                        }
+                        LATENCY.state_changed();
+                        sm.force_redraw_all();
+                    }
+
+                    // newest lap first, the best one green
+                    // and the worst one red
+                    let laps = stopwatch.laps();
+                    let max_scroll =
+                        laps.len().saturating_sub(LAP_ROWS);
+                    let (best, worst) = (
+                        stopwatch.best(),
+                        stopwatch.worst(),
+                    );
+                    for row in 0..LAP_ROWS {
+                        let Some(n) =
+                            laps.len().checked_sub(
+                                lap_scroll + row + 1,
+                            )
+                        else {
+                            break;
+                        };
+                        let lap = laps[n];
+                        let text = format_no_std::show(
+                            &mut textbuf,
+                            format_args!(
+                                "Lap {:>2}  {:02}:{:02}:{:03}",
+                                n + 1,
+                                lap / 60_000,
+                                lap / 1000 % 60,
+                                lap % 1000,
+                            ),
+                        )
+                        .unwrap();
+                        ui.add_horizontal(Spacer::new(
+                            Size::new(70, 0),
+                        ));
+                        ui.sub_ui(|ui| {
+                            if Some(n) == best {
+                                ui.style_mut().text_color =
+                                    Rgb565::CSS_LIME_GREEN;
+                            } else if Some(n) == worst {
+                                ui.style_mut().text_color =
+                                    Rgb565::CSS_RED;
+                            }
+                            ui.add(
+                                Label::new(text)
+                                    .smartstate(sm.next()),
+                            );
+                            Ok(())
+                        })
+                        .ok();
+                    }
+
+                    // older laps further down
+                    if max_scroll > 0 {
+                        ui.add_horizontal(Spacer::new(
+                            Size::new(70, 0),
+                        ));
+                        let newer = focus
+                            .track(
+                                ui.add_horizontal(
+                                    Button::new("Newer")
+                                        .smartstate(
+                                            sm.next(),
+                                        ),
+                                ),
+                            )
+                            .clicked()
+                            || events.swipe()
+                                == Some(
+                                    SwipeDirection::Down,
+                                );
+                        let older = focus
+                            .track(
+                                ui.add(
+                                    Button::new("Older")
+                                        .smartstate(
+                                            sm.next(),
+                                        ),
+                                ),
+                            )
+                            .clicked()
+                            || events.swipe()
+                                == Some(SwipeDirection::Up);
+                        let scrolled = if newer {
+                            lap_scroll.saturating_sub(1)
+                        } else if older {
+                            (lap_scroll + 1).min(max_scroll)
+                        } else {
+                            lap_scroll
+                        };
+                        if scrolled != lap_scroll {
+                            lap_scroll = scrolled;
+                            LATENCY.state_changed();
+                            sm.force_redraw_all();
+                        }
+                    }
+                }
+                Page::Intervals => {
+                    let back = focus
+                        .track(ui.add_horizontal(
+                            IconButton::new(
+                                size32px::navigation::NavArrowLeft,
+                            )
+                            .smartstate(sm.next()),
+                        ))
+                        .clicked();
+                    if back
+                        || go_back
+                        || events.swipe()
+                            == Some(SwipeDirection::Right)
+                    {
+                        cur_page = Page::List;
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(10, 0),
+                    ));
+                    // tapping the name picks the next
+                    // program, unless one is running
+                    let name = format_no_std::show(
                         &mut textbuf,
                         format_args!(
-                            "{:02}",
-                            remaining.as_secs() % 60
+                            "{:<1$}",
+                            intervals.program().name(),
+                            LABEL_LEN
                         ),
                     )
-                    .unwrap(),
-                )
-                .smartstate(sm.next()),
-            );
-            ui.add_horizontal(
-                Label::new(":").smartstate(sm.next()),
-            );
+                    .unwrap();
+                    if focus
+                        .track(
+                            ui.add(
+                                Button::new(name)
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                        && intervals.is_reset()
+                    {
+                        program = (program + 1)
+                            % programs.list.len();
+                        intervals.set_program(
+                            programs.list[program].clone(),
+                        );
+                        LATENCY.state_changed();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                    }
+                    ui.add(
+                        HashLabel::new(
+                            format_no_std::show(
+                                &mut textbuf,
+                                format_args!(
+                                    "{}",
+                                    intervals.program()
+                                ),
+                            )
+                            // too long for the screen
+                            .unwrap_or(
+                                intervals.program().name(),
+                            ),
+                            sm.next(),
+                            &hasher,
+                        )
+                        .with_font(ascii::FONT_6X10),
+                    );
 
-            if remaining.as_millis() % 1000 != prev_millis {
-                sm.peek().force_redraw();
-                prev_millis = remaining.as_millis() % 1000;
-            }
-            ui.add(
-                Label::new(
-                    &format_no_std::show(
+                    // the current phase and what is left
+                    // of it
+                    ui.add(Spacer::new(Size::new(0, 10)));
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(20, 0),
+                    ));
+                    let phase = format_no_std::show(
                         &mut textbuf,
                         format_args!(
-                            "{:03}",
-                            remaining.as_millis() % 1000
+                            "{:<1$}",
+                            intervals.phase().name(),
+                            LABEL_LEN
                         ),
                     )
-                    .unwrap(),
-                )
-                .smartstate(sm.next()),
-            );
-            Ok(())
-        })
-        .ok();
+                    .unwrap();
+                    ui.add_horizontal(
+                        HashLabel::new(
+                            phase,
+                            sm.next(),
+                            &hasher,
+                        )
+                        .with_font(ascii::FONT_10X20),
+                    );
+                    let ms = intervals.phase_remaining_ms();
+                    ui.sub_ui(|ui| {
+                        ui.style_mut().default_font =
+                            ascii::FONT_10X20;
+                        time_labels(
+                            ui,
+                            &mut sm,
+                            &mut prev_fields,
+                            ms,
+                        );
+                        Ok(())
+                    })
+                    .ok();
 
-        ui.add_horizontal(Spacer::new(Size::new(65, 0)));
-        ui.sub_ui(|ui| {
-            if appdata.timer_running() {
-                ui.style_mut().icon_color =
-                    Rgb565::CSS_LIGHT_GRAY;
-            }
-            if ui
-                .add_horizontal(
-                    IconButton::new(
-                        size32px::actions::AddCircle,
-                    )
-                    .smartstate(sm.next()),
-                )
-                .clicked()
-            {
-                if !(appdata.timer_running()
-                    || appdata.timer_paused())
-                {
-                    appdata.add_secs(10);
+                    // round and progress of the whole
+                    // program
+                    let round = intervals.round() + 1;
+                    let repeats =
+                        intervals.program().repeats();
+                    let percent = intervals.elapsed_ms()
+                        * 100
+                        / intervals.total_ms().max(1);
+                    let status = if intervals.is_finished()
+                    {
+                        format_no_std::show(
+                            &mut textbuf,
+                            format_args!(
+                                "Done, {} rounds",
+                                repeats
+                            ),
+                        )
+                    } else if let Some(next) =
+                        intervals.next_phase()
+                    {
+                        format_no_std::show(
+                            &mut textbuf,
+                            format_args!(
+                                "{}/{}  {:>3}%  next: {}",
+                                round,
+                                repeats,
+                                percent,
+                                next.name()
+                            ),
+                        )
+                    } else {
+                        format_no_std::show(
+                            &mut textbuf,
+                            format_args!(
+                                "{}/{}  {:>3}%  last phase",
+                                round, repeats, percent
+                            ),
+                        )
+                    }
+                    .unwrap();
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(20, 0),
+                    ));
+                    ui.add(HashLabel::new(
+                        status,
+                        sm.next(),
+                        &hasher,
+                    ));
+
+                    ui.add(Spacer::new(Size::new(0, 10)));
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(70, 0),
+                    ));
+                    if focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new("Reset")
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                    {
+                        intervals.reset();
+                        LATENCY.state_changed();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                    }
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(20, 0),
+                    ));
+                    let running = intervals.is_running();
+                    if focus
+                        .track(
+                            ui.add(
+                                IconButton::new(if running {
+                                    size48px::music::Pause
+                                } else {
+                                    size48px::music::Play
+                                })
+                                .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                    {
+                        if running {
+                            intervals.pause();
+                        } else {
+                            intervals.start();
+                        }
+                        LATENCY.state_changed();
+                        sm.force_redraw_all();
+                    }
                 }
-            }
-            ui.add_horizontal(
-                Label::new("+/- 10s").smartstate(sm.next()),
-            );
-            if ui
-                .add(
-                    IconButton::new(
-                        size32px::actions::MinusCircle,
-                    )
-                    .smartstate(sm.next()),
-                )
-                .clicked()
-            {
-                if !(appdata.timer_running()
-                    || appdata.timer_paused())
-                {
-                    appdata.sub_secs(10);
+                Page::Keypad(i) => {
+                    let back = focus
+                        .track(ui.add_horizontal(
+                            IconButton::new(
+                                size32px::navigation::NavArrowLeft,
+                            )
+                            .smartstate(sm.next()),
+                        ))
+                        .clicked();
+                    if back
+                        || go_back
+                        || events.swipe()
+                            == Some(SwipeDirection::Right)
+                    {
+                        cur_page = Page::Timer(i);
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
+                    ui.add_horizontal(Spacer::new(
+                        Size::new(60, 0),
+                    ));
+                    ui.add(
+                        HashLabel::new(
+                            format_no_std::show(
+                                &mut textbuf,
+                                format_args!("{}", keypad),
+                            )
+                            .unwrap(),
+                            sm.next(),
+                            &hasher,
+                        )
+                        .with_font(ascii::FONT_10X20),
+                    );
+
+                    // digits on the left, presets on the
+                    // right
+                    let mut key = None;
+                    let mut chosen = None;
+                    for (row, keys) in
+                        KEYS.iter().enumerate()
+                    {
+                        for k in keys {
+                            if focus
+                                .track(
+                                    ui.add_horizontal(
+                                        Button::new(k)
+                                            .smartstate(
+                                                sm.next(),
+                                            ),
+                                    ),
+                                )
+                                .clicked()
+                            {
+                                key = Some(*k);
+                            }
+                        }
+                        ui.add_horizontal(Spacer::new(
+                            Size::new(40, 0),
+                        ));
+                        let preset = focus.track(
+                            ui.add(
+                                Button::new(
+                                    format_no_std::show(
+                                        &mut textbuf,
+                                        format_args!(
+                                            "{}",
+                                            Hms(
+                                                presets.secs
+                                                    [row]
+                                                    as u64
+                                                    * 1000
+                                            )
+                                        ),
+                                    )
+                                    .unwrap(),
+                                )
+                                .smartstate(sm.next()),
+                            ),
+                        );
+                        // holding a preset stores the entry
+                        // in it
+                        if events.long_press().is_some()
+                            && preset.down()
+                        {
+                            if !keypad.is_empty() {
+                                presets.set(
+                                    row,
+                                    keypad.secs(),
+                                );
+                                presets
+                                    .save(
+                                        &mut storage::open(
+                                        ),
+                                    )
+                                    .ok();
+                                LATENCY.state_changed();
+                                ui.clear_background().ok();
+                                sm.force_redraw_all();
+                            }
+                        } else if preset.clicked() {
+                            chosen = Some(
+                                presets.secs[row] as u64,
+                            );
+                        }
+                    }
+                    if focus
+                        .track(
+                            ui.add_horizontal(
+                                Button::new("Set timer")
+                                    .smartstate(sm.next()),
+                            ),
+                        )
+                        .clicked()
+                        && !keypad.is_empty()
+                    {
+                        chosen = Some(keypad.secs());
+                    }
+                    ui.add(
+                        Label::new("hold a preset to save")
+                            .smartstate(sm.next())
+                            .with_font(ascii::FONT_6X10),
+                    );
+
+                    match key {
+                        Some("C") => keypad.clear(),
+                        Some("<") => keypad.backspace(),
+                        Some(k) => {
+                            keypad.push(
+                                k.as_bytes()[0] - b'0',
+                            );
+                        }
+                        None => {}
+                    }
+                    if key.is_some() {
+                        LATENCY.state_changed();
+                    }
+                    if let Some(secs) = chosen {
+                        let timer = &mut timers
+                            .get_mut(i)
+                            .unwrap()
+                            .timer;
+                        timer.reset();
+                        timer.set_duration_ms(secs * 1000);
+                        cur_page = Page::Timer(i);
+                        LATENCY.state_changed();
+                        focus.reset();
+                        ui.clear_background().ok();
+                        sm.force_redraw_all();
+                        continue;
+                    }
                 }
             }
-            Ok(())
-        })
-        .ok();
-
-        ui.add_horizontal(Spacer::new(Size::new(80, 0)));
-        if ui
-            .add_horizontal(
-                IconButton::new(size48px::actions::Undo)
-                    .smartstate(sm.next()),
-            )
-            .clicked()
-        {
-            appdata.reset_timer();
-            sm.force_redraw_all();
         }
 
-        if !finished && appdata.timer_finished() {
-            sm.peek().force_redraw();
-        }
-
-        if appdata.timer_finished() {
-            if ui
-                .add_horizontal(
-                    IconButton::new(
-                        size48px::actions::RemoveSquare,
-                    )
-                    .smartstate(sm.next()),
-                )
-                .clicked()
-            {
-                appdata.reset_timer();
-                sm.force_redraw_all();
-            }
-        } else if appdata.timer_running() {
-            if ui
-                .add_horizontal(
-                    IconButton::new(size48px::music::Pause)
-                        .smartstate(sm.next()),
-                )
-                .clicked()
-            {
-                appdata.pause_timer();
-                sm.force_redraw_all();
-            }
-        } else {
-            if ui
-                .add_horizontal(
-                    IconButton::new(size48px::music::Play)
-                        .smartstate(sm.next()),
-                )
-                .clicked()
-            {
-                appdata.start_timer();
-                sm.force_redraw_all();
+        drop(ui);
+        focus.draw(&mut display, Rgb565::CSS_ORANGE).ok();
+        focus.end_frame();
+        if let Some(ms) = LATENCY.take_latency() {
+            if latency.add(ms) {
+                println!("{}", latency);
             }
         }
 
-        finished = appdata.timer_finished();
-
         let end_time = embassy_time::Instant::now();
         let draw_time = display.get_time();
         let prep_time = start_draw_time - start_time;
@@ -0,0 +1,227 @@
+//! Physical buttons as a second way through the UI, next to
+//! the touch screen: the BOOT button (GPIO0) and two
+//! buttons on the spare header pins (GPIO22 and GPIO27, to
+//! ground).
+//!
+//! Each button tells short from long presses and maps them
+//! to an [`Action`]; the apps move their
+//! [focus](crate::focus::Focus) with them. See
+//! [`BUTTONS`] for the mapping.
+
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::Channel,
+};
+
+use crate::{Debouncer, Edge};
+
+#[cfg(target_os = "none")]
+mod task;
+
+#[cfg(target_os = "none")]
+pub use task::button_task;
+
+/// Time between two samples of the buttons. With a
+/// [`Debouncer`] of four samples a press has to last 20 ms
+/// to count.
+pub const SAMPLE_MS: u64 = 5;
+/// Holding a button this long is a long press.
+pub const LONG_PRESS_MS: u64 = 600;
+
+/// What a button press asks the app to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    FocusNext,
+    FocusPrev,
+    /// Press the focused widget.
+    Activate,
+    /// Leave the current page, like the on-screen back
+    /// buttons.
+    Back,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Short,
+    Long,
+}
+
+/// What the short and long presses of a button do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonMap {
+    pub short: Action,
+    pub long: Action,
+}
+
+impl ButtonMap {
+    pub fn action(&self, press: Press) -> Action {
+        match press {
+            Press::Short => self.short,
+            Press::Long => self.long,
+        }
+    }
+}
+
+/// BOOT alone is enough to get through every app; the two
+/// external buttons make it quicker.
+pub const BUTTONS: [(&str, ButtonMap); 3] = [
+    (
+        "BOOT (GPIO0)",
+        ButtonMap {
+            short: Action::FocusNext,
+            long: Action::Activate,
+        },
+    ),
+    (
+        "GPIO22",
+        ButtonMap {
+            short: Action::FocusPrev,
+            long: Action::Back,
+        },
+    ),
+    (
+        "GPIO27",
+        ButtonMap {
+            short: Action::Activate,
+            long: Action::Back,
+        },
+    ),
+];
+
+/// Carries the actions from the button task to the UI.
+pub type ActionChannel =
+    Channel<CriticalSectionRawMutex, Action, 8>;
+
+/// Tells short from long presses of one button, sampled
+/// every [`SAMPLE_MS`]. Long presses are reported as soon
+/// as they last [`LONG_PRESS_MS`], without waiting for the
+/// release.
+#[derive(Debug, Clone)]
+pub struct PressDetector {
+    debouncer: Debouncer<4>,
+    long_press_ms: u64,
+    pressed_at: Option<u64>,
+    long_reported: bool,
+}
+
+impl PressDetector {
+    pub const fn new(long_press_ms: u64) -> Self {
+        Self {
+            debouncer: Debouncer::new(),
+            long_press_ms,
+            pressed_at: None,
+            long_reported: false,
+        }
+    }
+
+    /// Adds a sample taken at `now_ms`, `true` while the
+    /// button is held.
+    pub fn update(
+        &mut self,
+        now_ms: u64,
+        pressed: bool,
+    ) -> Option<Press> {
+        match self.debouncer.sample(pressed) {
+            Some(Edge::Pressed) => {
+                self.pressed_at = Some(now_ms);
+                self.long_reported = false;
+                None
+            }
+            Some(Edge::Released) => {
+                let short =
+                    self.pressed_at.take().is_some()
+                        && !self.long_reported;
+                short.then_some(Press::Short)
+            }
+            None => {
+                let at = self.pressed_at?;
+                if self.long_reported
+                    || now_ms.saturating_sub(at)
+                        < self.long_press_ms
+                {
+                    return None;
+                }
+                self.long_reported = true;
+                Some(Press::Long)
+            }
+        }
+    }
+}
+
+impl Default for PressDetector {
+    fn default() -> Self {
+        Self::new(LONG_PRESS_MS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds one sample per character every [`SAMPLE_MS`],
+    /// `1` while the button is held, and collects the
+    /// presses with the sample they came at.
+    fn run(
+        d: &mut PressDetector,
+        samples: &str,
+    ) -> Vec<(usize, Press)> {
+        samples
+            .chars()
+            .enumerate()
+            .filter_map(|(i, c)| {
+                d.update(i as u64 * SAMPLE_MS, c == '1')
+                    .map(|p| (i, p))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn short_press_on_release() {
+        let mut d = PressDetector::default();
+        let presses = run(&mut d, "0000111111110000000");
+        // pressed after 4 samples, released after 4 more
+        assert_eq!(presses, [(15, Press::Short)]);
+    }
+
+    #[test]
+    fn bounces_are_no_presses() {
+        let mut d = PressDetector::default();
+        assert!(
+            run(&mut d, "0101101001011010000").is_empty()
+        );
+    }
+
+    #[test]
+    fn long_press_while_held_and_only_once() {
+        let mut d = PressDetector::new(100);
+        let held = "1".repeat(60);
+        let presses = run(&mut d, &format!("{held}0000"));
+        // pressed at sample 3 (15 ms), long 100 ms later
+        assert_eq!(presses, [(23, Press::Long)]);
+    }
+
+    #[test]
+    fn mapping() {
+        let boot = BUTTONS[0].1;
+        assert_eq!(
+            boot.action(Press::Short),
+            Action::FocusNext
+        );
+        assert_eq!(
+            boot.action(Press::Long),
+            Action::Activate
+        );
+        // every action can be reached
+        for action in [
+            Action::FocusNext,
+            Action::FocusPrev,
+            Action::Activate,
+            Action::Back,
+        ] {
+            assert!(BUTTONS
+                .iter()
+                .any(|(_, m)| m.short == action
+                    || m.long == action));
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::gpio::{AnyInput, GpioPin, Pull};
+use esp_println::println;
+
+use super::{
+    ActionChannel,
+    PressDetector,
+    BUTTONS,
+    SAMPLE_MS,
+};
+
+/// Samples the BOOT button and the two external buttons
+/// every [`SAMPLE_MS`] and sends the
+/// [`Action`](super::Action)s their presses map to. The
+/// buttons pull the pins to ground; the internal pull-ups
+/// keep them high otherwise.
+#[embassy_executor::task]
+pub async fn button_task(
+    boot: GpioPin<0>,
+    ext1: GpioPin<22>,
+    ext2: GpioPin<27>,
+    actions: &'static ActionChannel,
+) -> ! {
+    let inputs = [
+        AnyInput::new(boot, Pull::Up),
+        AnyInput::new(ext1, Pull::Up),
+        AnyInput::new(ext2, Pull::Up),
+    ];
+    let mut detectors =
+        [(); 3].map(|_| PressDetector::default());
+
+    loop {
+        let now = Instant::now().as_millis();
+        for ((input, detector), (name, map)) in
+            inputs.iter().zip(&mut detectors).zip(BUTTONS)
+        {
+            let Some(press) =
+                detector.update(now, input.is_low())
+            else {
+                continue;
+            };
+            let action = map.action(press);
+            println!(
+                "{}: {:?} -> {:?}",
+                name, press, action
+            );
+            if actions.try_send(action).is_err() {
+                println!("button action dropped");
+            }
+        }
+        Timer::after(Duration::from_millis(SAMPLE_MS))
+            .await;
+    }
+}
@@ -0,0 +1,126 @@
+//! A speaker on the CYD's speaker connector (P4, driven by
+//! GPIO26 through the board's amplifier), used as a buzzer
+//! for short [`Chime`]s.
+//!
+//! The apps [signal](ChimeSignal) a chime and the buzzer
+//! task plays it in the background.
+
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    signal::Signal,
+};
+
+#[cfg(target_os = "none")]
+mod task;
+
+#[cfg(target_os = "none")]
+pub use task::buzzer_task;
+
+/// A square wave of `hz` for `ms`, or silence if `hz` is
+/// zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tone {
+    pub hz: u32,
+    pub ms: u32,
+    /// Percent of every period the pin is high, up to 50.
+    /// The pin can't do volume, but narrower pulses sound
+    /// quieter.
+    pub duty: u8,
+}
+
+const fn tone(hz: u32, ms: u32, duty: u8) -> Tone {
+    Tone { hz, ms, duty }
+}
+
+const fn pause(ms: u32) -> Tone {
+    Tone { hz: 0, ms, duty: 0 }
+}
+
+/// What the buzzer can announce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chime {
+    /// The next phase of an interval program started.
+    Phase,
+    /// An interval program is done.
+    Finished,
+    /// A beep of a ringing [`Alarm`](crate::alarm::Alarm),
+    /// louder on every level.
+    Alarm { level: u8 },
+}
+
+impl Chime {
+    pub fn tones(self) -> &'static [Tone] {
+        match self {
+            Chime::Phase => &PHASE,
+            Chime::Finished => &FINISHED,
+            Chime::Alarm { level } => {
+                ALARM[(level as usize).min(ALARM.len() - 1)]
+            }
+        }
+    }
+}
+
+/// Two short beeps.
+const PHASE: [Tone; 3] =
+    [tone(1760, 120, 50), pause(60), tone(1760, 120, 50)];
+
+/// Three rising notes.
+const FINISHED: [Tone; 3] = [
+    tone(1319, 150, 50),
+    tone(1568, 150, 50),
+    tone(2093, 400, 50),
+];
+
+/// The alarm beeps by level: more of them, and louder.
+const ALARM: [&[Tone]; 4] = [
+    &[tone(2093, 100, 5)],
+    &[tone(2093, 100, 15), pause(80), tone(2093, 100, 15)],
+    &[
+        tone(2093, 100, 30),
+        pause(80),
+        tone(2093, 100, 30),
+        pause(80),
+        tone(2093, 100, 30),
+    ],
+    &[tone(2093, 150, 50), pause(50), tone(2637, 150, 50)],
+];
+
+/// Carries chimes from the UI to the buzzer task. A chime
+/// sent while another one waits replaces it.
+pub type ChimeSignal =
+    Signal<CriticalSectionRawMutex, Chime>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::countdown::MIN_SECS;
+
+    #[test]
+    fn chimes_end_before_the_next_phase() {
+        for chime in [
+            Chime::Phase,
+            Chime::Finished,
+            Chime::Alarm { level: 3 },
+        ] {
+            let ms: u32 =
+                chime.tones().iter().map(|t| t.ms).sum();
+            assert!((ms as u64) < MIN_SECS * 1000 / 2);
+        }
+    }
+
+    #[test]
+    fn alarm_beeps_get_louder() {
+        let loudest = |level| {
+            Chime::Alarm { level }
+                .tones()
+                .iter()
+                .map(|t| t.duty)
+                .max()
+        };
+        for level in 0..3 {
+            assert!(loudest(level) < loudest(level + 1));
+        }
+        // levels past the last one stay at the top
+        assert_eq!(loudest(9), loudest(3));
+    }
+}
@@ -0,0 +1,73 @@
+use embassy_time::{Duration, Timer};
+use esp_hal::{
+    clock::Clocks,
+    gpio::GpioPin,
+    ledc::{
+        channel::{self, ChannelIFace},
+        timer::{self, TimerIFace},
+        LSGlobalClkSource,
+        Ledc,
+        LowSpeed,
+    },
+    peripherals::LEDC,
+    prelude::*,
+};
+
+use super::ChimeSignal;
+
+/// Plays the [`Chime`](super::Chime)s signalled on
+/// `chimes`, one at a time, on GPIO26.
+///
+/// The tones come from the LEDC, which keeps them going
+/// while a redraw blocks the executor; the task only starts
+/// and stops them.
+#[embassy_executor::task]
+pub async fn buzzer_task(
+    ledc: LEDC,
+    clocks: &'static Clocks<'static>,
+    mut pin: GpioPin<26>,
+    chimes: &'static ChimeSignal,
+) -> ! {
+    let mut ledc = Ledc::new(ledc, clocks);
+    ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+
+    loop {
+        let chime = chimes.wait().await;
+        for tone in chime.tones() {
+            let length =
+                Duration::from_millis(tone.ms as u64);
+            if tone.hz == 0 {
+                Timer::after(length).await;
+                continue;
+            }
+            let mut pwm_timer = ledc.get_timer::<LowSpeed>(
+                timer::Number::Timer0,
+            );
+            let started = pwm_timer
+                .configure(timer::config::Config {
+                    duty: timer::config::Duty::Duty8Bit,
+                    clock_source:
+                        timer::LSClockSource::APBClk,
+                    frequency: tone.hz.Hz(),
+                })
+                .is_ok();
+            let mut out = ledc.get_channel(
+                channel::Number::Channel0,
+                &mut pin,
+            );
+            let started = started
+                && out
+                    .configure(channel::config::Config {
+                        timer: &pwm_timer,
+                        duty_pct: tone.duty.min(50),
+                        pin_config:
+                            channel::config::PinConfig::PushPull,
+                    })
+                    .is_ok();
+            Timer::after(length).await;
+            if started {
+                out.set_duty(0).ok();
+            }
+        }
+    }
+}
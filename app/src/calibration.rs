@@ -0,0 +1,548 @@
+//! Touch calibration: a least-squares affine fit from the
+//! raw XPT2046 readings (see
+//! [`RawSample::point`](crate::touch::driver::RawSample::point))
+//! to screen coordinates.
+//!
+//! The `calibrate` bin collects the samples with a
+//! [`Calibrator`], shows the [`Fit`]'s residual error and
+//! stores the transform. The shared touch task loads it at
+//! start-up (see [`stored`]).
+
+use embedded_graphics::prelude::Point;
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::storage::{RecordStore, Slot, StorageError};
+
+/// Where the calibration crosshairs are drawn on the
+/// 320x240 screen: the four corners (inset so they can be
+/// hit reliably) and the centre.
+pub const TARGETS: [Point; 5] = [
+    Point::new(30, 30),
+    Point::new(290, 30),
+    Point::new(290, 210),
+    Point::new(30, 210),
+    Point::new(160, 120),
+];
+
+/// A press needs at least this many samples to count, so
+/// short bounces don't end up as calibration points.
+pub const MIN_SAMPLES: u32 = 8;
+
+/// Maps a touch point to screen coordinates:
+///
+/// ```text
+/// x' = a * x + b * y + c
+/// y' = d * x + e * y + f
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AffineTransform {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+}
+
+impl AffineTransform {
+    /// What the apps used before there was calibration:
+    /// the `xpt2046` crate's points, then
+    /// `(x + 25, 240 - y)`. Used until the `calibrate` app
+    /// has been run.
+    pub const DEFAULT: Self = Self {
+        a: -0.0885542,
+        b: 0.0016532,
+        c: 374.8,
+        d: -0.0007309,
+        e: -0.06543699,
+        f: 255.29,
+    };
+
+    /// Stored in front of the coefficients, so records of
+    /// another format are ignored.
+    const FORMAT_VERSION: u8 = 2;
+
+    pub const ENCODED_LEN: usize = 1 + 6 * 4;
+
+    pub fn apply(&self, p: Point) -> Point {
+        let (x, y) = self.apply_f32(p);
+        Point::new(
+            libm::roundf(x) as i32,
+            libm::roundf(y) as i32,
+        )
+    }
+
+    fn apply_f32(&self, p: Point) -> (f32, f32) {
+        let (x, y) = (p.x as f32, p.y as f32);
+        (
+            self.a * x + self.b * y + self.c,
+            self.d * x + self.e * y + self.f,
+        )
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        buf[0] = Self::FORMAT_VERSION;
+        for (chunk, v) in
+            buf[1..].chunks_exact_mut(4).zip([
+                self.a, self.b, self.c, self.d, self.e,
+                self.f,
+            ])
+        {
+            chunk.copy_from_slice(&v.to_le_bytes());
+        }
+        buf
+    }
+
+    /// Inverse of [`AffineTransform::to_bytes`]. Rejects
+    /// other format versions, NaNs and infinities.
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() != Self::ENCODED_LEN
+            || buf[0] != Self::FORMAT_VERSION
+        {
+            return None;
+        }
+        let mut v = [0f32; 6];
+        for (v, chunk) in
+            v.iter_mut().zip(buf[1..].chunks_exact(4))
+        {
+            *v = f32::from_le_bytes(chunk.try_into().ok()?);
+            if !v.is_finite() {
+                return None;
+            }
+        }
+        let [a, b, c, d, e, f] = v;
+        Some(Self { a, b, c, d, e, f })
+    }
+
+    pub fn save<F: NorFlash>(
+        &self,
+        store: &mut RecordStore<F>,
+    ) -> Result<(), StorageError<F::Error>> {
+        store.store(Slot::Calibration, &self.to_bytes())
+    }
+
+    pub fn load<F: NorFlash>(
+        store: &mut RecordStore<F>,
+    ) -> Result<Option<Self>, StorageError<F::Error>> {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        Ok(store
+            .load(Slot::Calibration, &mut buf)?
+            .and_then(Self::from_bytes))
+    }
+}
+
+impl Default for AffineTransform {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Result of [`fit`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fit {
+    pub transform: AffineTransform,
+    /// Root mean square distance between the targets and
+    /// the transformed samples, in pixels.
+    pub rms_error: f32,
+    /// Largest distance between a target and its
+    /// transformed sample, in pixels.
+    pub max_error: f32,
+}
+
+/// Fits an affine transform to `(sample, target)` pairs.
+///
+/// Needs at least three pairs that aren't all on one line;
+/// returns `None` otherwise.
+pub fn fit(pairs: &[(Point, Point)]) -> Option<Fit> {
+    if pairs.len() < 3 {
+        return None;
+    }
+
+    // Work relative to the samples' centroid to keep the
+    // normal equations well conditioned in f32.
+    let n = pairs.len() as f32;
+    let (mx, my) =
+        pairs.iter().fold((0.0, 0.0), |(x, y), (s, _)| {
+            (x + s.x as f32 / n, y + s.y as f32 / n)
+        });
+
+    // Normal equations: M * [a b c]^T = r.
+    let mut m = [[0f32; 3]; 3];
+    let mut rx = [0f32; 3];
+    let mut ry = [0f32; 3];
+    for (s, t) in pairs {
+        let row = [s.x as f32 - mx, s.y as f32 - my, 1.0];
+        for i in 0..3 {
+            for j in 0..3 {
+                m[i][j] += row[i] * row[j];
+            }
+            rx[i] += row[i] * t.x as f32;
+            ry[i] += row[i] * t.y as f32;
+        }
+    }
+
+    let [a, b, c] = solve3(m, rx)?;
+    let [d, e, f] = solve3(m, ry)?;
+    // Undo the centering: a * (x - mx) + b * (y - my) + c.
+    let transform = AffineTransform {
+        a,
+        b,
+        c: c - a * mx - b * my,
+        d,
+        e,
+        f: f - d * mx - e * my,
+    };
+
+    let (mut sum_sq, mut max_error) = (0.0, 0f32);
+    for (s, t) in pairs {
+        let (x, y) = transform.apply_f32(*s);
+        let (dx, dy) = (x - t.x as f32, y - t.y as f32);
+        let sq = dx * dx + dy * dy;
+        sum_sq += sq;
+        max_error = max_error.max(libm::sqrtf(sq));
+    }
+
+    Some(Fit {
+        transform,
+        rms_error: libm::sqrtf(sum_sq / n),
+        max_error,
+    })
+}
+
+/// Solves `m * x = r` with Cramer's rule.
+fn solve3(
+    m: [[f32; 3]; 3],
+    r: [f32; 3],
+) -> Option<[f32; 3]> {
+    let det = det3(m);
+    // Relative threshold: the entries scale with the square
+    // of the sample spread.
+    let scale = m[0][0] * m[1][1] * m[2][2];
+    if det.abs() <= scale.abs() * 1e-6 || det == 0.0 {
+        return None;
+    }
+    let mut x = [0f32; 3];
+    for (col, x) in x.iter_mut().enumerate() {
+        let mut mc = m;
+        for row in 0..3 {
+            mc[row][col] = r[row];
+        }
+        *x = det3(mc) / det;
+    }
+    Some(x)
+}
+
+fn det3(m: [[f32; 3]; 3]) -> f32 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// Walks the user through the calibration targets,
+/// averaging the samples of one press per target.
+pub struct Calibrator {
+    targets: &'static [Point],
+    pairs: heapless::Vec<(Point, Point), 8>,
+    sum: (i64, i64),
+    count: u32,
+}
+
+impl Calibrator {
+    pub fn new(targets: &'static [Point]) -> Self {
+        Self {
+            targets: &targets[..targets.len().min(8)],
+            pairs: heapless::Vec::new(),
+            sum: (0, 0),
+            count: 0,
+        }
+    }
+
+    /// The target the user should press next, `None` once
+    /// all are done.
+    pub fn current_target(&self) -> Option<Point> {
+        self.targets.get(self.pairs.len()).copied()
+    }
+
+    /// Index of the current target, for "n of m" displays.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.pairs.len(), self.targets.len())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.current_target().is_none()
+    }
+
+    /// Feeds one touch sample (`None` when not touched).
+    ///
+    /// Returns `true` when the press for the current target
+    /// has been recorded and the next one is up.
+    pub fn feed(&mut self, sample: Option<Point>) -> bool {
+        let Some(target) = self.current_target() else {
+            return false;
+        };
+        match sample {
+            Some(p) => {
+                self.sum.0 += p.x as i64;
+                self.sum.1 += p.y as i64;
+                self.count += 1;
+                false
+            }
+            None if self.count >= MIN_SAMPLES => {
+                let n = self.count as i64;
+                let avg = Point::new(
+                    (self.sum.0 / n) as i32,
+                    (self.sum.1 / n) as i32,
+                );
+                self.pairs.push((avg, target)).ok();
+                self.sum = (0, 0);
+                self.count = 0;
+                true
+            }
+            None => {
+                self.sum = (0, 0);
+                self.count = 0;
+                false
+            }
+        }
+    }
+
+    /// Fits the collected samples. See [`fit`].
+    pub fn fit(&self) -> Option<Fit> {
+        fit(&self.pairs)
+    }
+}
+
+/// Loads the stored calibration, falling back to
+/// [`AffineTransform::DEFAULT`].
+#[cfg(target_os = "none")]
+pub fn stored() -> AffineTransform {
+    AffineTransform::load(&mut crate::storage::open())
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::MockFlash;
+
+    /// The mapping the apps used before calibration
+    /// existed, a handy non-trivial transform.
+    const FLIP: AffineTransform = AffineTransform {
+        a: 1.0,
+        b: 0.0,
+        c: 25.0,
+        d: 0.0,
+        e: -1.0,
+        f: 240.0,
+    };
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{a} != {b}");
+    }
+
+    fn pairs_for(
+        t: &AffineTransform,
+    ) -> Vec<(Point, Point)> {
+        [
+            Point::new(12, 40),
+            Point::new(300, 35),
+            Point::new(305, 220),
+            Point::new(8, 215),
+            Point::new(150, 128),
+        ]
+        .into_iter()
+        .map(|s| (s, t.apply(s)))
+        .collect()
+    }
+
+    #[test]
+    fn default_matches_old_mapping() {
+        // the raw readings run against the screen's axes
+        let t = AffineTransform::DEFAULT;
+        assert_eq!(
+            t.apply(Point::new(3800, 300)),
+            Point::new(39, 233)
+        );
+        assert_eq!(
+            t.apply(Point::new(300, 3500)),
+            Point::new(354, 26)
+        );
+    }
+
+    #[test]
+    fn fit_recovers_exact_transform() {
+        let fit = fit(&pairs_for(&FLIP)).unwrap();
+        let t = fit.transform;
+        assert_close(t.a, 1.0);
+        assert_close(t.b, 0.0);
+        assert_close(t.c, 25.0);
+        assert_close(t.d, 0.0);
+        assert_close(t.e, -1.0);
+        assert_close(t.f, 240.0);
+        assert!(fit.rms_error < 1e-3);
+        assert!(fit.max_error < 1e-3);
+    }
+
+    #[test]
+    fn fit_recovers_scale_rotation_and_offset() {
+        let pairs: Vec<_> = [
+            (0, 0),
+            (100, 0),
+            (0, 100),
+            (100, 100),
+            (40, 20),
+        ]
+        .into_iter()
+        .map(|(x, y)| {
+            let s = Point::new(x, y);
+            // x' = 2x + y/2 + 10, y' = -x/4 + 1.5y + 5
+            let t = Point::new(
+                2 * x + y / 2 + 10,
+                -x / 4 + 3 * y / 2 + 5,
+            );
+            (s, t)
+        })
+        .collect();
+        let t = fit(&pairs).unwrap().transform;
+        assert_close(t.a, 2.0);
+        assert_close(t.b, 0.5);
+        assert_close(t.c, 10.0);
+        assert_close(t.d, -0.25);
+        assert_close(t.e, 1.5);
+        assert_close(t.f, 5.0);
+    }
+
+    #[test]
+    fn fit_reports_residuals_of_noisy_samples() {
+        let mut pairs = pairs_for(&FLIP);
+        pairs[4].1 += Point::new(4, 0);
+        let fit = fit(&pairs).unwrap();
+        assert!(fit.rms_error > 0.5);
+        assert!(fit.max_error >= fit.rms_error);
+        assert!(fit.max_error < 4.0);
+    }
+
+    #[test]
+    fn fit_needs_three_non_collinear_points() {
+        let two = [
+            (Point::new(0, 0), Point::new(0, 0)),
+            (Point::new(1, 1), Point::new(1, 1)),
+        ];
+        assert_eq!(fit(&two), None);
+
+        let collinear: Vec<_> = (0..5)
+            .map(|i| {
+                let p = Point::new(i * 10, i * 20);
+                (p, p)
+            })
+            .collect();
+        assert_eq!(fit(&collinear), None);
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let t = AffineTransform {
+            a: 1.5,
+            b: -0.25,
+            c: 12.0,
+            d: 0.125,
+            e: -1.0,
+            f: 240.5,
+        };
+        assert_eq!(
+            AffineTransform::from_bytes(&t.to_bytes()),
+            Some(t)
+        );
+    }
+
+    #[test]
+    fn from_bytes_rejects_bad_input() {
+        assert_eq!(
+            AffineTransform::from_bytes(&[0; 24]),
+            None
+        );
+        let mut bytes = FLIP.to_bytes();
+        bytes[0] = 1;
+        assert_eq!(
+            AffineTransform::from_bytes(&bytes),
+            None
+        );
+        let mut bytes = FLIP.to_bytes();
+        bytes[1..5]
+            .copy_from_slice(&f32::NAN.to_le_bytes());
+        assert_eq!(
+            AffineTransform::from_bytes(&bytes),
+            None
+        );
+    }
+
+    #[test]
+    fn save_and_load_through_flash() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(
+            AffineTransform::load(&mut store),
+            Ok(None)
+        );
+        let t = AffineTransform { c: 20.0, ..FLIP };
+        t.save(&mut store).unwrap();
+        assert_eq!(
+            AffineTransform::load(&mut store),
+            Ok(Some(t))
+        );
+    }
+
+    #[test]
+    fn calibrator_averages_one_press_per_target() {
+        static TARGETS: [Point; 2] =
+            [Point::new(10, 10), Point::new(20, 20)];
+        let mut cal = Calibrator::new(&TARGETS);
+        assert_eq!(cal.current_target(), Some(TARGETS[0]));
+
+        for i in 0..MIN_SAMPLES as i32 {
+            assert!(!cal
+                .feed(Some(Point::new(100 + i % 2, 50))));
+        }
+        assert!(cal.feed(None));
+        assert_eq!(cal.progress(), (1, 2));
+        assert_eq!(
+            cal.pairs[0],
+            (Point::new(100, 50), TARGETS[0])
+        );
+        assert_eq!(cal.current_target(), Some(TARGETS[1]));
+    }
+
+    #[test]
+    fn calibrator_ignores_short_presses() {
+        let mut cal = Calibrator::new(&TARGETS);
+        for _ in 0..MIN_SAMPLES - 1 {
+            cal.feed(Some(Point::new(1, 1)));
+        }
+        assert!(!cal.feed(None));
+        assert_eq!(cal.progress(), (0, TARGETS.len()));
+    }
+
+    #[test]
+    fn calibrator_fits_after_all_targets() {
+        let mut cal = Calibrator::new(&TARGETS);
+        for target in TARGETS {
+            // what the driver would report for `target`
+            let sample =
+                Point::new(target.x - 25, 240 - target.y);
+            for _ in 0..MIN_SAMPLES {
+                cal.feed(Some(sample));
+            }
+            cal.feed(None);
+        }
+        assert!(cal.is_done());
+        assert!(!cal.feed(Some(Point::zero())));
+        let fit = cal.fit().unwrap();
+        assert_eq!(
+            fit.transform.apply(Point::new(75, 40)),
+            Point::new(100, 200)
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Commands typed on the serial console (UART0, the USB
+//! port), a line at a time.
+//!
+//! The console task collects the bytes into lines and sends
+//! them to the app, which decides what to do with them
+//! (e.g. [`TimeCommand`](crate::wallclock::TimeCommand)).
+
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::Channel,
+};
+use heapless::String;
+
+use crate::{
+    lighting::LightingCommand,
+    microwave::{ProgramCommand, TableCommand},
+};
+
+#[cfg(target_os = "none")]
+mod task;
+
+#[cfg(target_os = "none")]
+pub use task::console_task;
+
+/// Longest line, as long as the longest command any app
+/// takes. Longer ones are reported as [`TooLong`].
+pub const LINE_LEN: usize = max(
+    LightingCommand::LINE_LEN,
+    max(ProgramCommand::LINE_LEN, TableCommand::LINE_LEN),
+);
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+pub type Line = String<LINE_LEN>;
+
+/// Lines from the console task to the app.
+pub type LineChannel =
+    Channel<CriticalSectionRawMutex, Line, 2>;
+
+/// A line was longer than [`LINE_LEN`] and got dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooLong;
+
+/// Collects bytes into lines.
+#[derive(Debug, Clone, Default)]
+pub struct LineBuffer {
+    line: Line,
+    /// The line got too long, skip it up to its end.
+    overflow: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            overflow: false,
+        }
+    }
+
+    /// Adds a received byte. Returns the line once it is
+    /// complete, or [`TooLong`] if it didn't fit; empty
+    /// lines are skipped. Backspace deletes the last
+    /// character.
+    pub fn push(
+        &mut self,
+        byte: u8,
+    ) -> Option<Result<Line, TooLong>> {
+        match byte {
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                if core::mem::take(&mut self.overflow) {
+                    return Some(Err(TooLong));
+                }
+                (!line.is_empty()).then_some(Ok(line))
+            }
+            0x08 | 0x7F => {
+                self.line.pop();
+                None
+            }
+            byte if byte.is_ascii()
+                && !byte.is_ascii_control() =>
+            {
+                if self.line.push(byte as char).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(
+        input: &[u8],
+    ) -> std::vec::Vec<Result<Line, TooLong>> {
+        let mut buf = LineBuffer::new();
+        input.iter().filter_map(|&b| buf.push(b)).collect()
+    }
+
+    fn ok(line: &str) -> Result<Line, TooLong> {
+        Ok(Line::try_from(line).unwrap())
+    }
+
+    #[test]
+    fn splits_lines() {
+        assert_eq!(
+            lines(b"time\r\n\r\ntime 12:00\n"),
+            [ok("time"), ok("time 12:00")]
+        );
+        assert!(lines(b"no end").is_empty());
+    }
+
+    #[test]
+    fn backspace_deletes() {
+        assert_eq!(
+            lines(b"tume\x08\x08\x08ime\r"),
+            [ok("time")]
+        );
+        assert_eq!(lines(b"\x7ftime\r"), [ok("time")]);
+    }
+
+    #[test]
+    fn long_lines_are_reported() {
+        let mut input = [b'x'; LINE_LEN].to_vec();
+        input.push(b'\r');
+        assert!(matches!(lines(&input)[..], [Ok(_)]));
+        input.insert(0, b'x');
+        input.extend_from_slice(b"time\r");
+        assert_eq!(
+            lines(&input),
+            [Err(TooLong), ok("time")]
+        );
+    }
+
+    #[test]
+    fn scenes_of_all_lamps_fit() {
+        use crate::lighting::Scene;
+
+        let mut line = std::string::String::from(
+            "scene set Longest name:",
+        );
+        for lamp in 0..crate::lamp::MAX_LAMPS {
+            let sep = if lamp == 0 { "" } else { "," };
+            line += &std::format!(
+                "{} {} OFF 255 HS 359 255",
+                sep,
+                lamp
+            );
+        }
+        let scene = Scene::parse(&line[10..]).unwrap();
+        line.push('\r');
+        let typed = lines(line.as_bytes()).remove(0);
+        assert_eq!(
+            LightingCommand::parse(&typed.unwrap()),
+            Some(Ok(LightingCommand::SetScene(scene)))
+        );
+    }
+}
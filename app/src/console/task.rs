@@ -0,0 +1,36 @@
+use embassy_time::{Duration, Timer};
+use esp_hal::{peripherals::UART0, uart::Uart, Blocking};
+use esp_println::{print, println};
+
+use super::{LineBuffer, LineChannel, TooLong, LINE_LEN};
+
+/// Reads lines from `uart` and sends them to `lines`,
+/// echoing what is typed. Lines that arrive while the
+/// channel is full are dropped, lines that are too long
+/// are reported.
+#[embassy_executor::task]
+pub async fn console_task(
+    mut uart: Uart<'static, UART0, Blocking>,
+    lines: &'static LineChannel,
+) -> ! {
+    let mut buf = LineBuffer::new();
+    loop {
+        while let Ok(byte) = uart.read_byte() {
+            match byte {
+                b'\r' | b'\n' => print!("\r\n"),
+                byte => print!("{}", byte as char),
+            }
+            match buf.push(byte) {
+                Some(Ok(line)) => {
+                    lines.try_send(line).ok();
+                }
+                Some(Err(TooLong)) => println!(
+                    "line too long, at most {} characters",
+                    LINE_LEN
+                ),
+                None => {}
+            }
+        }
+        Timer::after(Duration::from_millis(10)).await;
+    }
+}
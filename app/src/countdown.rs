@@ -0,0 +1,407 @@
+//! The countdown behind the timer and microwave apps of
+//! every framework.
+//!
+//! [`CountdownTimer`] moves between the [`TimerState`]s
+//! only through its methods, and each successful move
+//! returns a [`TimerEvent`]:
+//!
+//! - [`start`](CountdownTimer::start): `Stopped` or
+//!   `Finished` to `Running`
+//!   ([`Started`](TimerEvent::Started)), `Paused` to
+//!   `Running` ([`Resumed`](TimerEvent::Resumed)),
+//! - [`pause`](CountdownTimer::pause): `Running` to
+//!   `Paused`,
+//! - [`update`](CountdownTimer::update): `Running` to
+//!   `Finished` once it reaches zero,
+//! - [`reset`](CountdownTimer::reset): any other state back
+//!   to `Stopped`.
+//!
+//! Anything else returns `None` and changes nothing.
+//!
+//! Time comes from a [`Clock`], so the same code runs on
+//! embassy, on std and in the host tests.
+
+/// Shortest duration that can be set.
+pub const MIN_SECS: u64 = 10;
+/// Longest duration that can be set by default, 99:59.
+/// See [`CountdownTimer::with_max`] for longer ones.
+pub const MAX_SECS: u64 = 5999;
+/// Duration a new timer starts with.
+pub const DEFAULT_SECS: u64 = 10;
+
+/// A monotonic millisecond clock. Implemented for
+/// closures and functions, e.g.
+/// `|| embassy_time::Instant::now().as_millis()`.
+pub trait Clock {
+    fn now_ms(&self) -> u64;
+}
+
+impl<F: Fn() -> u64> Clock for F {
+    fn now_ms(&self) -> u64 {
+        self()
+    }
+}
+
+/// The embassy clock the apps on the CYD run on.
+#[cfg(target_os = "none")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmbassyClock;
+
+#[cfg(target_os = "none")]
+impl Clock for EmbassyClock {
+    fn now_ms(&self) -> u64 {
+        embassy_time::Instant::now().as_millis()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerState {
+    /// Set up, not started. The duration can be changed.
+    Stopped,
+    Running,
+    Paused,
+    /// Ran down to zero; stays there until reset or
+    /// restarted.
+    Finished,
+}
+
+/// A state change, as returned by the method that caused
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerEvent {
+    Started,
+    Paused,
+    Resumed,
+    Reset,
+    Finished,
+}
+
+/// A countdown from a set duration to zero that can be
+/// paused. Call [`update`](Self::update) regularly (e.g.
+/// every frame) to notice when it finishes.
+#[derive(Debug, Clone)]
+pub struct CountdownTimer<C: Clock> {
+    clock: C,
+    state: TimerState,
+    set_ms: u64,
+    /// Longest duration that can be set.
+    max_ms: u64,
+    /// When a running timer reaches zero.
+    end_ms: u64,
+    /// What was left when the timer was paused.
+    paused_ms: u64,
+}
+
+impl<C: Clock> CountdownTimer<C> {
+    /// A stopped timer set to [`DEFAULT_SECS`].
+    pub fn new(clock: C) -> Self {
+        Self::with_max(clock, MAX_SECS)
+    }
+
+    /// Like [`new`](Self::new), but with durations of up to
+    /// `max_secs` instead of [`MAX_SECS`].
+    pub fn with_max(clock: C, max_secs: u64) -> Self {
+        Self {
+            clock,
+            state: TimerState::Stopped,
+            set_ms: DEFAULT_SECS * 1000,
+            max_ms: max_secs.max(MIN_SECS) * 1000,
+            end_ms: 0,
+            paused_ms: 0,
+        }
+    }
+
+    pub fn state(&self) -> TimerState {
+        self.state
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state == TimerState::Stopped
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == TimerState::Running
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == TimerState::Paused
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == TimerState::Finished
+    }
+
+    /// The duration the timer counts down from.
+    pub fn duration_ms(&self) -> u64 {
+        self.set_ms
+    }
+
+    /// Sets the duration, clamped to [`MIN_SECS`] ..=
+    /// [`MAX_SECS`] (or the maximum given to
+    /// [`with_max`](Self::with_max)). Only a stopped timer
+    /// can be changed; returns whether the duration
+    /// changed.
+    pub fn set_duration_ms(&mut self, ms: u64) -> bool {
+        if !self.is_stopped() {
+            return false;
+        }
+        let ms = ms.clamp(MIN_SECS * 1000, self.max_ms);
+        let changed = ms != self.set_ms;
+        self.set_ms = ms;
+        changed
+    }
+
+    /// See [`set_duration_ms`](Self::set_duration_ms).
+    pub fn add_secs(&mut self, secs: u64) -> bool {
+        self.set_duration_ms(
+            self.set_ms
+                .saturating_add(secs.saturating_mul(1000)),
+        )
+    }
+
+    /// See [`set_duration_ms`](Self::set_duration_ms).
+    pub fn sub_secs(&mut self, secs: u64) -> bool {
+        self.set_duration_ms(
+            self.set_ms
+                .saturating_sub(secs.saturating_mul(1000)),
+        )
+    }
+
+    /// What is left: the full duration while stopped,
+    /// nothing once finished.
+    pub fn remaining_ms(&self) -> u64 {
+        match self.state {
+            TimerState::Stopped => self.set_ms,
+            TimerState::Running => self
+                .end_ms
+                .saturating_sub(self.clock.now_ms()),
+            TimerState::Paused => self.paused_ms,
+            TimerState::Finished => 0,
+        }
+    }
+
+    /// Starts a stopped or finished timer from its full
+    /// duration, or resumes a paused one.
+    pub fn start(&mut self) -> Option<TimerEvent> {
+        let now = self.clock.now_ms();
+        let (left, event) = match self.state {
+            TimerState::Stopped | TimerState::Finished => {
+                (self.set_ms, TimerEvent::Started)
+            }
+            TimerState::Paused => {
+                (self.paused_ms, TimerEvent::Resumed)
+            }
+            TimerState::Running => return None,
+        };
+        self.end_ms = now.saturating_add(left);
+        self.state = TimerState::Running;
+        Some(event)
+    }
+
+    /// Pauses a running timer.
+    pub fn pause(&mut self) -> Option<TimerEvent> {
+        if !self.is_running() {
+            return None;
+        }
+        // it may have run out since the last update
+        if let Some(event) = self.update() {
+            return Some(event);
+        }
+        self.paused_ms = self.remaining_ms();
+        self.state = TimerState::Paused;
+        Some(TimerEvent::Paused)
+    }
+
+    /// Stops the timer and sets it back to its full
+    /// duration.
+    pub fn reset(&mut self) -> Option<TimerEvent> {
+        if self.is_stopped() {
+            return None;
+        }
+        self.state = TimerState::Stopped;
+        Some(TimerEvent::Reset)
+    }
+
+    /// Moves a running timer that has reached zero to
+    /// [`TimerState::Finished`], once.
+    pub fn update(&mut self) -> Option<TimerEvent> {
+        if self.is_running() && self.remaining_ms() == 0 {
+            self.state = TimerState::Finished;
+            return Some(TimerEvent::Finished);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+
+    fn timer(
+        now: &Cell<u64>,
+    ) -> CountdownTimer<impl Clock + '_> {
+        CountdownTimer::new(move || now.get())
+    }
+
+    #[test]
+    fn starts_stopped_with_the_default() {
+        let now = Cell::new(0);
+        let mut t = timer(&now);
+        assert_eq!(t.state(), TimerState::Stopped);
+        assert_eq!(t.remaining_ms(), DEFAULT_SECS * 1000);
+        now.set(60_000);
+        assert_eq!(t.update(), None);
+        assert_eq!(t.remaining_ms(), DEFAULT_SECS * 1000);
+    }
+
+    #[test]
+    fn counts_down_and_finishes_once() {
+        let now = Cell::new(1000);
+        let mut t = timer(&now);
+        t.add_secs(20);
+        assert_eq!(t.start(), Some(TimerEvent::Started));
+        now.set(11_000);
+        assert_eq!(t.remaining_ms(), 20_000);
+        assert_eq!(t.update(), None);
+        now.set(31_000);
+        assert_eq!(t.remaining_ms(), 0);
+        assert!(t.is_running());
+        assert_eq!(t.update(), Some(TimerEvent::Finished));
+        assert!(t.is_finished());
+        assert_eq!(t.update(), None);
+        now.set(99_000);
+        assert_eq!(t.remaining_ms(), 0);
+    }
+
+    #[test]
+    fn pause_keeps_the_remaining_time() {
+        let now = Cell::new(0);
+        let mut t = timer(&now);
+        t.start();
+        now.set(4_000);
+        assert_eq!(t.pause(), Some(TimerEvent::Paused));
+        assert!(t.is_paused());
+        now.set(100_000);
+        assert_eq!(t.remaining_ms(), 6_000);
+        assert_eq!(t.update(), None);
+        assert_eq!(t.start(), Some(TimerEvent::Resumed));
+        now.set(105_000);
+        assert_eq!(t.remaining_ms(), 1_000);
+        now.set(106_000);
+        assert_eq!(t.update(), Some(TimerEvent::Finished));
+    }
+
+    #[test]
+    fn pausing_a_run_out_timer_finishes_it() {
+        let now = Cell::new(0);
+        let mut t = timer(&now);
+        t.start();
+        now.set(10_000);
+        assert_eq!(t.pause(), Some(TimerEvent::Finished));
+        assert!(t.is_finished());
+    }
+
+    #[test]
+    fn reset_from_every_state() {
+        let now = Cell::new(0);
+        let mut t = timer(&now);
+        assert_eq!(t.reset(), None);
+
+        t.start();
+        assert_eq!(t.reset(), Some(TimerEvent::Reset));
+        assert!(t.is_stopped());
+
+        t.start();
+        t.pause();
+        assert_eq!(t.reset(), Some(TimerEvent::Reset));
+        assert_eq!(t.remaining_ms(), 10_000);
+
+        t.start();
+        now.set(20_000);
+        t.update();
+        assert_eq!(t.reset(), Some(TimerEvent::Reset));
+        assert_eq!(t.remaining_ms(), 10_000);
+    }
+
+    #[test]
+    fn invalid_transitions_do_nothing() {
+        let now = Cell::new(0);
+        let mut t = timer(&now);
+        assert_eq!(t.pause(), None);
+        assert!(t.is_stopped());
+        t.start();
+        assert_eq!(t.start(), None);
+        now.set(10_000);
+        t.update();
+        assert_eq!(t.pause(), None);
+        assert!(t.is_finished());
+    }
+
+    #[test]
+    fn finished_timer_restarts() {
+        let now = Cell::new(0);
+        let mut t = timer(&now);
+        t.start();
+        now.set(10_000);
+        t.update();
+        assert_eq!(t.start(), Some(TimerEvent::Started));
+        assert_eq!(t.remaining_ms(), 10_000);
+    }
+
+    #[test]
+    fn duration_is_clamped() {
+        let now = Cell::new(0);
+        let mut t = timer(&now);
+        assert!(!t.sub_secs(10));
+        assert_eq!(t.duration_ms(), MIN_SECS * 1000);
+        assert!(t.add_secs(u64::MAX));
+        assert_eq!(t.duration_ms(), MAX_SECS * 1000);
+        assert!(!t.add_secs(10));
+        assert!(t.sub_secs(u64::MAX));
+        assert_eq!(t.duration_ms(), MIN_SECS * 1000);
+        assert!(t.set_duration_ms(90_500));
+        assert_eq!(t.remaining_ms(), 90_500);
+    }
+
+    #[test]
+    fn maximum_is_per_timer() {
+        let now = Cell::new(0);
+        let mut t =
+            CountdownTimer::with_max(|| now.get(), 7200);
+        t.add_secs(u64::MAX);
+        assert_eq!(t.duration_ms(), 7200 * 1000);
+        let mut t = timer(&now);
+        t.set_duration_ms(7200 * 1000);
+        assert_eq!(t.duration_ms(), MAX_SECS * 1000);
+    }
+
+    #[test]
+    fn duration_is_fixed_once_started() {
+        let now = Cell::new(0);
+        let mut t = timer(&now);
+        for state in [
+            TimerState::Running,
+            TimerState::Paused,
+            TimerState::Finished,
+        ] {
+            t.reset();
+            t.start();
+            match state {
+                TimerState::Paused => {
+                    t.pause();
+                }
+                TimerState::Finished => {
+                    now.set(now.get() + 10_000);
+                    t.update();
+                }
+                _ => {}
+            }
+            assert_eq!(t.state(), state);
+            assert!(!t.add_secs(10));
+            assert!(!t.set_duration_ms(60_000));
+            assert_eq!(t.duration_ms(), 10_000);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Panic handler that puts the crash on the display and
+//! into flash.
+//!
+//! Replaces `esp-backtrace`'s `panic-handler` feature. By
+//! the time we get here the display and its SPI bus may be
+//! in any state (or owned by a task that will never run
+//! again), so everything is stolen and set up from scratch.
+
+use core::{
+    cell::RefCell,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use display_interface_spi::SPIInterface;
+use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
+use embassy_sync::blocking_mutex::NoopMutex;
+use esp_hal::{
+    clock::ClockControl,
+    delay::Delay,
+    gpio::{Io, Level, Output, NO_PIN},
+    peripherals::Peripherals,
+    prelude::*,
+    reset::software_reset,
+    rtc_cntl::Rtc,
+    spi::{master::Spi, SpiMode},
+    system::SystemControl,
+};
+use esp_println::println;
+use mipidsi::{
+    models::ILI9341Rgb565,
+    options::{ColorOrder, Orientation, Rotation},
+    Builder,
+};
+
+use super::{draw_panic_screen, CrashReport};
+use crate::storage;
+
+/// How long the crash stays on screen before we reboot.
+const SHOW_SECS: u32 = 10;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // A panic while reporting a panic: give up quietly
+    // instead of recursing.
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+
+    let backtrace = esp_backtrace::arch::backtrace();
+    let report = CrashReport::from_panic(
+        info,
+        backtrace.iter().flatten().map(|&addr| addr as u32),
+    );
+
+    println!("");
+    super::dump_to_serial(&report);
+
+    if report.save(&mut storage::open()).is_err() {
+        println!("could not save crash report to flash");
+    }
+
+    // SAFETY: we never return, so nobody else gets to use
+    // the peripherals again.
+    let peripherals = unsafe { Peripherals::steal() };
+    let system = SystemControl::new(peripherals.SYSTEM);
+    let clocks =
+        ClockControl::boot_defaults(system.clock_control)
+            .freeze();
+
+    // The apps arm the RWDT with a 2s timeout. Keep it from
+    // cutting the report short.
+    let mut rtc = Rtc::new(peripherals.LPWR);
+    rtc.rwdt.disable();
+
+    let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
+    let mut backlight =
+        Output::new(io.pins.gpio21, Level::Low);
+    let spi = Spi::new(
+        peripherals.SPI2,
+        10.MHz(),
+        SpiMode::Mode0,
+        &clocks,
+    )
+    .with_pins(
+        Some(io.pins.gpio14),
+        Some(io.pins.gpio13),
+        Some(io.pins.gpio12),
+        NO_PIN,
+    );
+    let spi_bus = NoopMutex::new(RefCell::new(spi));
+    let di = SPIInterface::new(
+        SpiDevice::new(
+            &spi_bus,
+            Output::new(io.pins.gpio15, Level::Low),
+        ),
+        Output::new(io.pins.gpio2, Level::Low),
+    );
+
+    let mut delay = Delay::new(&clocks);
+    if let Ok(mut display) = Builder::new(ILI9341Rgb565, di)
+        .orientation(Orientation {
+            rotation: Rotation::Deg90,
+            mirrored: true,
+        })
+        .color_order(ColorOrder::Bgr)
+        .init(&mut delay)
+    {
+        draw_panic_screen(
+            &mut display,
+            &report,
+            "saved to flash - restarting in 10s",
+        )
+        .ok();
+        backlight.set_high();
+    }
+
+    delay.delay_millis(SHOW_SECS * 1000);
+    software_reset();
+    loop {
+        core::hint::spin_loop();
+    }
+}
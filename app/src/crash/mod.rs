@@ -0,0 +1,562 @@
+//! Crash reports: what we know about a panic, in a form
+//! that can be drawn on the display, dumped over serial and
+//! survive a reboot in flash.
+//!
+//! Only the formatting and (de)serialization live here so
+//! they can be tested on the host. The panic handler itself
+//! is in `handler.rs` and the screens are in `screen.rs`.
+
+use core::fmt::{self, Write};
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::storage::{RecordStore, Slot, StorageError};
+
+#[cfg(all(
+    feature = "crash-handler",
+    feature = "backtrace-panic-handler"
+))]
+compile_error!(
+    "`crash-handler` and `backtrace-panic-handler` both \
+     install a panic handler, enable only one"
+);
+#[cfg(all(target_os = "none", feature = "crash-handler"))]
+mod handler;
+mod screen;
+
+pub use screen::{
+    draw_panic_screen,
+    report_page,
+    ReportAction,
+};
+
+/// Longest panic message we keep. Longer ones are cut off.
+pub const MESSAGE_LEN: usize = 160;
+/// Longest source file path we keep.
+pub const FILE_LEN: usize = 64;
+/// Number of backtrace addresses we keep.
+pub const BACKTRACE_LEN: usize = 10;
+/// Longest line [`CrashReport::lines`] produces.
+pub const LINE_LEN: usize = 64;
+/// Upper bound for [`CrashReport::encode`].
+pub const ENCODED_LEN: usize = 1
+    + MESSAGE_LEN
+    + 1
+    + 1
+    + FILE_LEN
+    + 8
+    + 1
+    + 4 * BACKTRACE_LEN;
+
+pub type Line = heapless::String<LINE_LEN>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: heapless::String<FILE_LEN>,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    pub message: heapless::String<MESSAGE_LEN>,
+    pub location: Option<Location>,
+    pub backtrace: heapless::Vec<u32, BACKTRACE_LEN>,
+}
+
+impl CrashReport {
+    /// Builds a report, truncating anything that doesn't
+    /// fit.
+    pub fn new(
+        message: impl fmt::Display,
+        location: Option<(&str, u32, u32)>,
+        backtrace: impl IntoIterator<Item = u32>,
+    ) -> Self {
+        let mut report = Self {
+            message: heapless::String::new(),
+            location: location.map(
+                |(file, line, column)| {
+                    let mut loc = Location {
+                        file: heapless::String::new(),
+                        line,
+                        column,
+                    };
+                    write!(
+                        Truncating(&mut loc.file),
+                        "{}",
+                        file
+                    )
+                    .ok();
+                    loc
+                },
+            ),
+            backtrace: backtrace
+                .into_iter()
+                .take(BACKTRACE_LEN)
+                .collect(),
+        };
+        write!(
+            Truncating(&mut report.message),
+            "{}",
+            message
+        )
+        .ok();
+        report
+    }
+
+    /// Builds a report from what the panic handler gets.
+    pub fn from_panic(
+        info: &core::panic::PanicInfo,
+        backtrace: impl IntoIterator<Item = u32>,
+    ) -> Self {
+        Self::new(
+            info.message(),
+            info.location()
+                .map(|l| (l.file(), l.line(), l.column())),
+            backtrace,
+        )
+    }
+
+    /// Lays the report out as lines of at most `columns`
+    /// characters, word-wrapping the message. Stops after
+    /// `N` lines.
+    pub fn lines<const N: usize>(
+        &self,
+        columns: usize,
+    ) -> heapless::Vec<Line, N> {
+        let columns = columns.clamp(1, LINE_LEN);
+        let mut out = LineWriter {
+            lines: heapless::Vec::new(),
+            columns,
+        };
+
+        out.wrap("PANIC");
+        out.wrap(&self.message);
+        if let Some(loc) = &self.location {
+            let mut line = heapless::String::<
+                { FILE_LEN + 24 },
+            >::new();
+            write!(
+                line,
+                "at {}:{}:{}",
+                loc.file, loc.line, loc.column
+            )
+            .ok();
+            out.wrap(&line);
+        }
+        if !self.backtrace.is_empty() {
+            out.wrap("backtrace:");
+            let mut line = Line::new();
+            for addr in &self.backtrace {
+                if !line.is_empty()
+                    && line.len() + 11 > columns
+                {
+                    out.push(&line);
+                    line.clear();
+                }
+                if !line.is_empty() {
+                    line.push(' ').ok();
+                }
+                write!(line, "0x{:08x}", addr).ok();
+            }
+            out.push(&line);
+        }
+        out.lines
+    }
+
+    /// Writes the report in the format the "send over
+    /// serial" dump uses.
+    ///
+    /// The backtrace is printed the way `esp-backtrace`
+    /// does, so `espflash monitor` can resolve the
+    /// addresses.
+    pub fn dump(&self, w: &mut impl Write) -> fmt::Result {
+        writeln!(w, "==== CRASH REPORT BEGIN ====")?;
+        writeln!(w, "message: {}", self.message)?;
+        match &self.location {
+            Some(loc) => writeln!(
+                w,
+                "location: {}:{}:{}",
+                loc.file, loc.line, loc.column
+            )?,
+            None => writeln!(w, "location: unknown")?,
+        }
+        writeln!(w, "backtrace:")?;
+        for addr in &self.backtrace {
+            writeln!(w, "0x{:08x}", addr)?;
+        }
+        writeln!(w, "==== CRASH REPORT END ====")
+    }
+
+    /// Serializes the report into `buf`, returning the
+    /// number of bytes used. `buf` should be
+    /// [`ENCODED_LEN`] bytes long.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut w = ByteWriter { buf, pos: 0 };
+        w.bytes_with_len(self.message.as_bytes())?;
+        match &self.location {
+            Some(loc) => {
+                w.byte(1)?;
+                w.bytes_with_len(loc.file.as_bytes())?;
+                w.bytes(&loc.line.to_le_bytes())?;
+                w.bytes(&loc.column.to_le_bytes())?;
+            }
+            None => w.byte(0)?,
+        }
+        w.byte(self.backtrace.len() as u8)?;
+        for addr in &self.backtrace {
+            w.bytes(&addr.to_le_bytes())?;
+        }
+        Some(w.pos)
+    }
+
+    /// Inverse of [`CrashReport::encode`].
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let mut r = ByteReader { buf };
+        let message = r.str_with_len()?;
+        let location = match r.byte()? {
+            0 => None,
+            1 => Some(Location {
+                file: r.str_with_len()?,
+                line: r.u32()?,
+                column: r.u32()?,
+            }),
+            _ => return None,
+        };
+        let count = r.byte()? as usize;
+        if count > BACKTRACE_LEN {
+            return None;
+        }
+        let mut backtrace = heapless::Vec::new();
+        for _ in 0..count {
+            backtrace.push(r.u32()?).ok()?;
+        }
+        Some(Self {
+            message,
+            location,
+            backtrace,
+        })
+    }
+
+    /// Persists the report in its storage slot.
+    pub fn save<F: NorFlash>(
+        &self,
+        store: &mut RecordStore<F>,
+    ) -> Result<(), StorageError<F::Error>> {
+        let mut buf = [0u8; ENCODED_LEN];
+        let len = self
+            .encode(&mut buf)
+            .ok_or(StorageError::TooLarge)?;
+        store.store(Slot::CrashReport, &buf[..len])
+    }
+
+    /// Loads the report left behind by the last crash, if
+    /// any.
+    pub fn load<F: NorFlash>(
+        store: &mut RecordStore<F>,
+    ) -> Result<Option<Self>, StorageError<F::Error>> {
+        let mut buf = [0u8; ENCODED_LEN];
+        Ok(store
+            .load(Slot::CrashReport, &mut buf)?
+            .and_then(Self::decode))
+    }
+}
+
+/// Loads the report of the previous crash from flash.
+///
+/// Call this once at boot; show the result with
+/// [`report_page`].
+#[cfg(target_os = "none")]
+pub fn stored_report() -> Option<CrashReport> {
+    CrashReport::load(&mut crate::storage::open())
+        .ok()
+        .flatten()
+}
+
+/// Forgets the stored crash report.
+#[cfg(target_os = "none")]
+pub fn clear_stored_report() {
+    crate::storage::open().clear(Slot::CrashReport).ok();
+}
+
+/// Prints the report over the serial console.
+#[cfg(target_os = "none")]
+pub fn dump_to_serial(report: &CrashReport) {
+    report.dump(&mut esp_println::Printer).ok();
+}
+
+/// `fmt::Write` adapter that silently drops whatever
+/// doesn't fit instead of failing the whole write.
+struct Truncating<'a, const N: usize>(
+    &'a mut heapless::String<N>,
+);
+
+impl<const N: usize> Write for Truncating<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct LineWriter<const N: usize> {
+    lines: heapless::Vec<Line, N>,
+    columns: usize,
+}
+
+impl<const N: usize> LineWriter<N> {
+    fn push(&mut self, line: &str) {
+        let mut l = Line::new();
+        l.push_str(line).ok();
+        self.lines.push(l).ok();
+    }
+
+    /// Greedy word wrap. Words longer than a line are
+    /// split, filling up the current line first.
+    fn wrap(&mut self, text: &str) {
+        let mut line = Line::new();
+        for word in text.split_whitespace() {
+            let mut word = word;
+            while !word.is_empty() {
+                let sep = !line.is_empty() as usize;
+                let word_len = word.chars().count();
+                let room = self
+                    .columns
+                    .saturating_sub(line.len() + sep);
+                if word_len <= room {
+                    if sep == 1 {
+                        line.push(' ').ok();
+                    }
+                    line.push_str(word).ok();
+                    break;
+                }
+                if word_len <= self.columns || room == 0 {
+                    self.push(&line);
+                    line.clear();
+                    continue;
+                }
+                let split = word
+                    .char_indices()
+                    .nth(room)
+                    .map_or(word.len(), |(i, _)| i);
+                if sep == 1 {
+                    line.push(' ').ok();
+                }
+                line.push_str(&word[..split]).ok();
+                self.push(&line);
+                line.clear();
+                word = &word[split..];
+            }
+        }
+        if !line.is_empty() || text.trim().is_empty() {
+            self.push(&line);
+        }
+    }
+}
+
+struct ByteWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl ByteWriter<'_> {
+    fn bytes(&mut self, data: &[u8]) -> Option<()> {
+        let end = self.pos + data.len();
+        self.buf
+            .get_mut(self.pos..end)?
+            .copy_from_slice(data);
+        self.pos = end;
+        Some(())
+    }
+
+    fn byte(&mut self, b: u8) -> Option<()> {
+        self.bytes(&[b])
+    }
+
+    fn bytes_with_len(
+        &mut self,
+        data: &[u8],
+    ) -> Option<()> {
+        self.byte(u8::try_from(data.len()).ok()?)?;
+        self.bytes(data)
+    }
+}
+
+struct ByteReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.buf.len() {
+            return None;
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Some(head)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(
+            self.bytes(4)?.try_into().ok()?,
+        ))
+    }
+
+    fn str_with_len<const N: usize>(
+        &mut self,
+    ) -> Option<heapless::String<N>> {
+        let len = self.byte()? as usize;
+        let s =
+            core::str::from_utf8(self.bytes(len)?).ok()?;
+        heapless::String::try_from(s).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::MockFlash;
+
+    fn sample() -> CrashReport {
+        CrashReport::new(
+            "Running Touch driver failed: Spi",
+            Some(("src/bin/timer.rs", 87, 14)),
+            [0x400D_1234, 0x400D_5678, 0x4008_0ABC],
+        )
+    }
+
+    #[test]
+    fn truncates_long_message() {
+        let long = "x".repeat(MESSAGE_LEN + 20);
+        let report = CrashReport::new(&long, None, []);
+        assert_eq!(report.message.len(), MESSAGE_LEN);
+    }
+
+    #[test]
+    fn keeps_only_first_backtrace_addresses() {
+        let report =
+            CrashReport::new("boom", None, 0..100u32);
+        assert_eq!(report.backtrace.len(), BACKTRACE_LEN);
+        assert_eq!(report.backtrace[0], 0);
+    }
+
+    #[test]
+    fn lines_wrap_message_and_list_backtrace() {
+        let lines = sample().lines::<16>(20);
+        let lines: Vec<&str> =
+            lines.iter().map(|l| l.as_str()).collect();
+        assert_eq!(
+            lines,
+            [
+                "PANIC",
+                "Running Touch driver",
+                "failed: Spi",
+                "at src/bin/timer.rs:",
+                "87:14",
+                "backtrace:",
+                "0x400d1234",
+                "0x400d5678",
+                "0x40080abc",
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_pack_addresses_on_wide_screens() {
+        let lines = sample().lines::<16>(53);
+        assert_eq!(
+            lines.last().unwrap().as_str(),
+            "0x400d1234 0x400d5678 0x40080abc"
+        );
+        assert_eq!(
+            lines[2].as_str(),
+            "at src/bin/timer.rs:87:14"
+        );
+    }
+
+    #[test]
+    fn lines_stop_at_capacity() {
+        let lines = sample().lines::<3>(10);
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn lines_without_location_or_backtrace() {
+        let report = CrashReport::new("oops", None, []);
+        let lines = report.lines::<8>(40);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].as_str(), "oops");
+    }
+
+    #[test]
+    fn dump_format() {
+        let mut out = String::new();
+        sample().dump(&mut out).unwrap();
+        assert_eq!(
+            out,
+            "==== CRASH REPORT BEGIN ====\n\
+             message: Running Touch driver failed: Spi\n\
+             location: src/bin/timer.rs:87:14\n\
+             backtrace:\n\
+             0x400d1234\n\
+             0x400d5678\n\
+             0x40080abc\n\
+             ==== CRASH REPORT END ====\n"
+        );
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let mut buf = [0u8; ENCODED_LEN];
+        for report in
+            [sample(), CrashReport::new("", None, [])]
+        {
+            let len = report.encode(&mut buf).unwrap();
+            assert_eq!(
+                CrashReport::decode(&buf[..len]),
+                Some(report)
+            );
+        }
+    }
+
+    #[test]
+    fn encoded_len_is_an_upper_bound() {
+        let file = "f".repeat(FILE_LEN);
+        let report = CrashReport::new(
+            "m".repeat(MESSAGE_LEN),
+            Some((&file, u32::MAX, u32::MAX)),
+            [u32::MAX; BACKTRACE_LEN],
+        );
+        let mut buf = [0u8; ENCODED_LEN];
+        assert_eq!(
+            report.encode(&mut buf),
+            Some(ENCODED_LEN)
+        );
+    }
+
+    #[test]
+    fn decode_rejects_garbage() {
+        assert_eq!(CrashReport::decode(&[]), None);
+        assert_eq!(CrashReport::decode(&[5, b'a']), None);
+        assert_eq!(CrashReport::decode(&[0, 2]), None);
+    }
+
+    #[test]
+    fn save_and_load_through_flash() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(CrashReport::load(&mut store), Ok(None));
+        sample().save(&mut store).unwrap();
+        assert_eq!(
+            CrashReport::load(&mut store),
+            Ok(Some(sample()))
+        );
+    }
+}
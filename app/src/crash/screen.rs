@@ -0,0 +1,123 @@
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyle},
+    pixelcolor::Rgb565,
+    prelude::*,
+    text::{Baseline, Text},
+};
+use kolibri_embedded_gui::{
+    button::Button,
+    label::Label,
+    smartstate::SmartstateProvider,
+    ui::Ui,
+};
+
+use super::CrashReport;
+
+/// Report lines shown on the post-reboot page. Together
+/// with the title and the two buttons this stays well below
+/// the apps' 20 smartstates.
+const PAGE_LINES: usize = 10;
+const PAGE_COLUMNS: usize = 50;
+
+/// What the user picked on the [`report_page`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportAction {
+    Dismiss,
+    SendOverSerial,
+}
+
+/// Draws the report straight onto `display`, without going
+/// through Kolibri. This is what the panic handler shows,
+/// so it must not depend on any UI state.
+pub fn draw_panic_screen<D: DrawTarget<Color = Rgb565>>(
+    display: &mut D,
+    report: &CrashReport,
+    footer: &str,
+) -> Result<(), D::Error> {
+    let font = &ascii::FONT_6X10;
+    let size = display.bounding_box().size;
+    let columns = (size.width / font.character_size.width)
+        .saturating_sub(1) as usize;
+    let line_height = font.character_size.height as i32 + 1;
+
+    display.clear(Rgb565::CSS_DARK_RED)?;
+    let style = MonoTextStyle::new(font, Rgb565::WHITE);
+    let mut y = 3;
+    for line in report.lines::<20>(columns).iter() {
+        Text::with_baseline(
+            line,
+            Point::new(3, y),
+            style,
+            Baseline::Top,
+        )
+        .draw(display)?;
+        y += line_height;
+    }
+
+    let style =
+        MonoTextStyle::new(font, Rgb565::CSS_LIGHT_GRAY);
+    Text::with_baseline(
+        footer,
+        Point::new(3, size.height as i32 - 3),
+        style,
+        Baseline::Bottom,
+    )
+    .draw(display)?;
+    Ok(())
+}
+
+/// Kolibri page that shows the report of the previous crash
+/// after a reboot.
+///
+/// Returns the button the user pressed this frame, if any.
+pub fn report_page<
+    D: DrawTarget<Color = Rgb565>,
+    const N: usize,
+>(
+    ui: &mut Ui<D, Rgb565>,
+    sm: &mut SmartstateProvider<N>,
+    report: &CrashReport,
+) -> Option<ReportAction> {
+    ui.sub_ui(|ui| {
+        ui.style_mut().default_font = ascii::FONT_9X18_BOLD;
+        ui.add(
+            Label::new("Crashed before last reboot")
+                .smartstate(sm.next()),
+        );
+        Ok(())
+    })
+    .ok();
+
+    // skip the "PANIC" header, the title says it already
+    for line in report
+        .lines::<{ PAGE_LINES + 1 }>(PAGE_COLUMNS)
+        .iter()
+        .skip(1)
+    {
+        ui.add(
+            Label::new(line)
+                .with_font(ascii::FONT_6X10)
+                .smartstate(sm.next()),
+        );
+    }
+
+    let mut action = None;
+    if ui
+        .add_horizontal(
+            Button::new("Dismiss").smartstate(sm.next()),
+        )
+        .clicked()
+    {
+        action = Some(ReportAction::Dismiss);
+    }
+    if ui
+        .add(
+            Button::new("Send over serial")
+                .smartstate(sm.next()),
+        )
+        .clicked()
+    {
+        action = Some(ReportAction::SendOverSerial);
+    }
+    action
+}
@@ -1,37 +1,335 @@
-/// Basic debouncer that checks 16 samples, and only if all of them are on / off, it will return
-/// the value.
-#[derive(Debug)]
-pub struct Debouncer(u16, bool);
+/// A change of the debounced value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Pressed,
+    Released,
+}
+
+/// Debouncer over a window of the last `N` samples (at most
+/// 64).
+///
+/// It switches on once `press_threshold` samples in the
+/// window are set, and off again once `release_threshold`
+/// samples are clear. Both default to `N`, so by default
+/// all samples in the window have to agree; lower
+/// thresholds trade noise immunity for latency, and
+/// different ones give hysteresis.
+#[derive(Debug, Clone)]
+pub struct Debouncer<const N: usize = 16> {
+    history: u64,
+    state: bool,
+    press_threshold: u32,
+    release_threshold: u32,
+}
+
+impl<const N: usize> Debouncer<N> {
+    const MASK: u64 = {
+        assert!(
+            N > 0 && N <= 64,
+            "window must be 1 to 64 samples"
+        );
+        if N == 64 {
+            u64::MAX
+        } else {
+            (1 << N) - 1
+        }
+    };
+
+    pub const fn new() -> Self {
+        Self::with_thresholds(N as u32, N as u32)
+    }
 
-impl Debouncer {
-    pub fn new() -> Self {
-        Self(0, false)
+    /// Creates a debouncer that needs `press` set samples
+    /// in the window to switch on and `release` clear
+    /// samples to switch off.
+    ///
+    /// Panics if either threshold is 0 or larger than `N`,
+    /// or if they don't add up to more than `N` (the
+    /// value could flip on every sample then).
+    pub const fn with_thresholds(
+        press: u32,
+        release: u32,
+    ) -> Self {
+        let _ = Self::MASK;
+        assert!(press > 0 && press <= N as u32);
+        assert!(release > 0 && release <= N as u32);
+        assert!(press + release > N as u32);
+        Self {
+            history: 0,
+            state: false,
+            press_threshold: press,
+            release_threshold: release,
+        }
     }
 
     pub fn read(&self) -> bool {
-        self.1
+        self.state
     }
 
     /// Completely fill the debouncer, settng it "true".
     pub fn fill(&mut self) {
-        self.0 = u16::MAX;
-        self.1 = true;
+        self.history = Self::MASK;
+        self.state = true;
     }
 
     /// Completely empty the debouncer, settng it "false".
     pub fn empty(&mut self) {
-        self.0 = 0;
-        self.1 = false;
+        self.history = 0;
+        self.state = false;
     }
 
+    /// Adds a sample and returns the debounced value.
     pub fn update(&mut self, val: bool) -> bool {
-        self.0 = (self.0 << 1) | (val as u16);
-        if self.0 == u16::MAX {
-            self.1 = true;
-        } else if self.0 == 0 {
-            self.1 = false;
+        self.sample(val);
+        self.state
+    }
+
+    /// Adds a sample and returns the edge it caused, if
+    /// any.
+    pub fn sample(&mut self, val: bool) -> Option<Edge> {
+        self.history =
+            ((self.history << 1) | val as u64) & Self::MASK;
+        let set = self.history.count_ones();
+        let clear = N as u32 - set;
+        if !self.state && set >= self.press_threshold {
+            self.state = true;
+            Some(Edge::Pressed)
+        } else if self.state
+            && clear >= self.release_threshold
+        {
+            self.state = false;
+            Some(Edge::Released)
+        } else {
+            None
+        }
+    }
+}
+
+impl<const N: usize> Default for Debouncer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Time based debouncer: the value switches once the input
+/// has been stable for `press_ms` (to switch on) or
+/// `release_ms` (to switch off) milliseconds. Timestamps
+/// are the caller's, e.g. `Instant::now().as_millis()`.
+#[derive(Debug, Clone)]
+pub struct TimedDebouncer {
+    press_ms: u64,
+    release_ms: u64,
+    state: bool,
+    /// Since when the input has differed from `state`.
+    changed_at: Option<u64>,
+}
+
+impl TimedDebouncer {
+    pub const fn new(
+        press_ms: u64,
+        release_ms: u64,
+    ) -> Self {
+        Self {
+            press_ms,
+            release_ms,
+            state: false,
+            changed_at: None,
+        }
+    }
+
+    pub fn read(&self) -> bool {
+        self.state
+    }
+
+    /// Adds a sample taken at `now_ms` and returns the edge
+    /// it caused, if any.
+    pub fn update(
+        &mut self,
+        now_ms: u64,
+        val: bool,
+    ) -> Option<Edge> {
+        if val == self.state {
+            self.changed_at = None;
+            return None;
+        }
+        let since = *self.changed_at.get_or_insert(now_ms);
+        let needed = if val {
+            self.press_ms
+        } else {
+            self.release_ms
+        };
+        if now_ms.saturating_sub(since) < needed {
+            return None;
+        }
+        self.state = val;
+        self.changed_at = None;
+        Some(if val {
+            Edge::Pressed
+        } else {
+            Edge::Released
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed<const N: usize>(
+        d: &mut Debouncer<N>,
+        samples: &str,
+    ) -> Vec<Option<Edge>> {
+        samples
+            .chars()
+            .map(|c| d.sample(c == '1'))
+            .collect()
+    }
+
+    #[test]
+    fn default_needs_a_full_window() {
+        let mut d = Debouncer::<16>::new();
+        for _ in 0..15 {
+            assert!(!d.update(true));
+        }
+        assert_eq!(d.sample(true), Some(Edge::Pressed));
+        assert!(d.read());
+
+        // a single clear sample doesn't release
+        assert!(d.update(false));
+        for _ in 0..14 {
+            assert!(d.update(false));
         }
+        assert_eq!(d.sample(false), Some(Edge::Released));
+        assert!(!d.read());
+    }
+
+    #[test]
+    fn small_window() {
+        let mut d = Debouncer::<3>::new();
+        let edges = feed(&mut d, "110111000");
+        assert_eq!(
+            edges,
+            [
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(Edge::Pressed),
+                None,
+                None,
+                Some(Edge::Released),
+            ]
+        );
+    }
+
+    #[test]
+    fn full_64_sample_window() {
+        let mut d = Debouncer::<64>::new();
+        for _ in 0..63 {
+            assert_eq!(d.sample(true), None);
+        }
+        assert_eq!(d.sample(true), Some(Edge::Pressed));
+        d.empty();
+        assert!(!d.read());
+        d.fill();
+        assert!(d.read());
+        assert_eq!(d.sample(true), None);
+    }
+
+    #[test]
+    fn hysteresis() {
+        // on with 2 of 4 set, off only with 4 of 4 clear
+        let mut d = Debouncer::<4>::with_thresholds(2, 4);
+        let edges = feed(&mut d, "0101000");
+        assert_eq!(
+            edges,
+            [
+                None,
+                None,
+                None,
+                Some(Edge::Pressed),
+                None,
+                None,
+                None,
+            ]
+        );
+        // the lone set sample is still in the window
+        assert!(d.read());
+        assert_eq!(d.sample(false), Some(Edge::Released));
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut d = Debouncer::<4>::with_thresholds(3, 3);
+        let edges = feed(&mut d, "10101010");
+        assert!(edges.iter().all(Option::is_none));
+        assert!(!d.read());
+    }
+
+    #[test]
+    fn edges_come_once() {
+        let mut d = Debouncer::<2>::new();
+        let edges = feed(&mut d, "11111");
+        assert_eq!(edges.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn thresholds_that_could_oscillate_are_rejected() {
+        let _ = Debouncer::<4>::with_thresholds(2, 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_threshold_is_rejected() {
+        let _ = Debouncer::<4>::with_thresholds(0, 4);
+    }
+
+    #[test]
+    fn timed_switches_after_stable_period() {
+        let mut d = TimedDebouncer::new(5, 20);
+        assert_eq!(d.update(0, true), None);
+        assert_eq!(d.update(4, true), None);
+        assert_eq!(d.update(5, true), Some(Edge::Pressed));
+        assert!(d.read());
+        assert_eq!(d.update(6, true), None);
+
+        assert_eq!(d.update(10, false), None);
+        assert_eq!(d.update(29, false), None);
+        assert_eq!(
+            d.update(30, false),
+            Some(Edge::Released)
+        );
+        assert!(!d.read());
+    }
+
+    #[test]
+    fn timed_restarts_on_bounce() {
+        let mut d = TimedDebouncer::new(0, 20);
+        assert_eq!(d.update(0, true), Some(Edge::Pressed));
+        // a short release mid-drag, as the touch controller
+        // sometimes reports
+        assert_eq!(d.update(10, false), None);
+        assert_eq!(d.update(25, false), None);
+        assert_eq!(d.update(26, true), None);
+        assert_eq!(d.update(40, false), None);
+        assert_eq!(d.update(59, false), None);
+        assert!(d.read());
+        assert_eq!(
+            d.update(60, false),
+            Some(Edge::Released)
+        );
+    }
 
-        self.1
+    #[test]
+    fn timed_zero_delay_follows_input() {
+        let mut d = TimedDebouncer::new(0, 0);
+        assert_eq!(d.update(0, true), Some(Edge::Pressed));
+        assert_eq!(
+            d.update(1, false),
+            Some(Edge::Released)
+        );
+        assert_eq!(d.update(2, false), None);
     }
 }
@@ -0,0 +1,295 @@
+//! Keyboard-style focus for Kolibri apps, driven by the
+//! [buttons](crate::buttons).
+//!
+//! Kolibri only knows touch, so [`Focus`] remembers where
+//! the widgets of the last frame were, moves a focus ring
+//! between them and presses the focused one by feeding
+//! Kolibri a click and a release at its centre.
+
+use embedded_graphics::{
+    prelude::*,
+    primitives::{
+        PrimitiveStyleBuilder,
+        Rectangle,
+        StrokeAlignment,
+        StyledDrawable,
+    },
+};
+use heapless::Vec;
+use kolibri_embedded_gui::ui::{Interaction, Response};
+
+use crate::buttons::Action;
+
+/// Most widgets a page can have in the focus order.
+pub const MAX_WIDGETS: usize = 24;
+
+/// Width of the focus ring. It is drawn on the inside of
+/// the widget's area, so the widget covers it again when it
+/// is redrawn.
+const RING_WIDTH: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Click {
+    None,
+    Press(Point),
+    Release(Point),
+}
+
+/// Focus order and state of one page. Widgets take part by
+/// passing their [`Response`] through
+/// [`track`](Self::track) every frame, in the order focus
+/// should visit them.
+#[derive(Debug, Clone)]
+pub struct Focus {
+    index: Option<usize>,
+    /// The tracked widgets of the frame being drawn.
+    areas: Vec<Rectangle, MAX_WIDGETS>,
+    /// The tracked widgets of the last complete frame.
+    last_areas: Vec<Rectangle, MAX_WIDGETS>,
+    click: Click,
+    moved: bool,
+}
+
+impl Focus {
+    pub const fn new() -> Self {
+        Self {
+            index: None,
+            areas: Vec::new(),
+            last_areas: Vec::new(),
+            click: Click::None,
+            moved: false,
+        }
+    }
+
+    /// Handles a button action. Returns `false` for
+    /// [`Action::Back`], which is up to the app.
+    pub fn apply(&mut self, action: Action) -> bool {
+        let count = self.last_areas.len();
+        match action {
+            Action::FocusNext if count > 0 => {
+                self.index = Some(
+                    self.index
+                        .map_or(0, |i| (i + 1) % count),
+                );
+                self.moved = true;
+            }
+            Action::FocusPrev if count > 0 => {
+                self.index = Some(
+                    self.index.map_or(count - 1, |i| {
+                        (i + count - 1) % count
+                    }),
+                );
+                self.moved = true;
+            }
+            Action::Activate => {
+                if let Some(area) = self.focused() {
+                    self.click =
+                        Click::Press(area.center());
+                }
+            }
+            Action::Back => return false,
+            _ => {}
+        }
+        true
+    }
+
+    /// The interaction to pass to Kolibri this frame
+    /// instead of the touch one, while the focused widget
+    /// is being pressed.
+    pub fn interaction(&mut self) -> Option<Interaction> {
+        match self.click {
+            Click::None => None,
+            Click::Press(p) => {
+                self.click = Click::Release(p);
+                Some(Interaction::Click(p))
+            }
+            Click::Release(p) => {
+                self.click = Click::None;
+                Some(Interaction::Release(p))
+            }
+        }
+    }
+
+    /// Adds a widget to the focus order and hands its
+    /// response back.
+    pub fn track(
+        &mut self,
+        response: Response,
+    ) -> Response {
+        self.add(response.internal.area);
+        response
+    }
+
+    /// Adds a widget's area to the focus order.
+    pub fn add(&mut self, area: Rectangle) {
+        self.areas.push(area).ok();
+    }
+
+    /// Ends the frame: the widgets tracked since the last
+    /// call are the ones focus moves between from now on.
+    pub fn end_frame(&mut self) {
+        self.last_areas = core::mem::take(&mut self.areas);
+        if self
+            .index
+            .is_some_and(|i| i >= self.last_areas.len())
+        {
+            self.index = None;
+        }
+    }
+
+    /// Drops the focus and forgets the widgets, e.g. when
+    /// the app switches pages.
+    pub fn reset(&mut self) {
+        self.index = None;
+        self.areas.clear();
+        self.last_areas.clear();
+        self.click = Click::None;
+        self.moved = true;
+    }
+
+    /// The area of the focused widget.
+    pub fn focused(&self) -> Option<Rectangle> {
+        self.last_areas.get(self.index?).copied()
+    }
+
+    /// Whether the focus moved since the last call. The old
+    /// ring stays on screen until its widget is redrawn, so
+    /// force a redraw then.
+    pub fn take_moved(&mut self) -> bool {
+        core::mem::take(&mut self.moved)
+    }
+
+    /// Draws the ring around the focused widget. Call it
+    /// after the frame has been drawn.
+    pub fn draw<D: DrawTarget>(
+        &self,
+        target: &mut D,
+        color: D::Color,
+    ) -> Result<(), D::Error> {
+        match self.focused() {
+            Some(area) => area.draw_styled(
+                &PrimitiveStyleBuilder::new()
+                    .stroke_color(color)
+                    .stroke_width(RING_WIDTH)
+                    .stroke_alignment(
+                        StrokeAlignment::Inside,
+                    )
+                    .build(),
+                target,
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for Focus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(x: i32) -> Rectangle {
+        Rectangle::new(Point::new(x, 10), Size::new(20, 10))
+    }
+
+    fn frame(f: &mut Focus, n: i32) {
+        for i in 0..n {
+            f.add(area(i * 30));
+        }
+        f.end_frame();
+    }
+
+    #[test]
+    fn next_and_prev_wrap_around() {
+        let mut f = Focus::new();
+        frame(&mut f, 3);
+        assert_eq!(f.focused(), None);
+        f.apply(Action::FocusNext);
+        assert_eq!(f.focused(), Some(area(0)));
+        f.apply(Action::FocusNext);
+        f.apply(Action::FocusNext);
+        assert_eq!(f.focused(), Some(area(60)));
+        f.apply(Action::FocusNext);
+        assert_eq!(f.focused(), Some(area(0)));
+        f.apply(Action::FocusPrev);
+        assert_eq!(f.focused(), Some(area(60)));
+        assert!(f.take_moved());
+        assert!(!f.take_moved());
+    }
+
+    #[test]
+    fn prev_starts_at_the_end() {
+        let mut f = Focus::new();
+        frame(&mut f, 4);
+        f.apply(Action::FocusPrev);
+        assert_eq!(f.focused(), Some(area(90)));
+    }
+
+    #[test]
+    fn nothing_to_focus() {
+        let mut f = Focus::new();
+        frame(&mut f, 0);
+        f.apply(Action::FocusNext);
+        f.apply(Action::Activate);
+        assert_eq!(f.focused(), None);
+        assert_eq!(f.interaction(), None);
+    }
+
+    #[test]
+    fn activate_clicks_the_centre() {
+        let mut f = Focus::new();
+        frame(&mut f, 2);
+        f.apply(Action::FocusNext);
+        f.apply(Action::FocusNext);
+        assert!(f.apply(Action::Activate));
+        let centre = area(30).center();
+        assert_eq!(
+            f.interaction(),
+            Some(Interaction::Click(centre))
+        );
+        assert_eq!(
+            f.interaction(),
+            Some(Interaction::Release(centre))
+        );
+        assert_eq!(f.interaction(), None);
+    }
+
+    #[test]
+    fn reset_forgets_the_page() {
+        let mut f = Focus::new();
+        frame(&mut f, 3);
+        f.apply(Action::FocusNext);
+        f.take_moved();
+        // the new page switches halfway through a frame
+        f.add(area(0));
+        f.reset();
+        assert!(f.take_moved());
+        frame(&mut f, 1);
+        f.apply(Action::FocusNext);
+        f.apply(Action::FocusNext);
+        assert_eq!(f.focused(), Some(area(0)));
+    }
+
+    #[test]
+    fn back_is_left_to_the_app() {
+        let mut f = Focus::new();
+        frame(&mut f, 2);
+        assert!(!f.apply(Action::Back));
+    }
+
+    #[test]
+    fn focus_is_dropped_when_the_page_shrinks() {
+        let mut f = Focus::new();
+        frame(&mut f, 3);
+        f.apply(Action::FocusPrev);
+        frame(&mut f, 2);
+        assert_eq!(f.focused(), None);
+        f.apply(Action::FocusNext);
+        frame(&mut f, 2);
+        assert_eq!(f.focused(), Some(area(0)));
+    }
+}
@@ -0,0 +1,702 @@
+//! Gesture recognition on top of the raw touch stream.
+//!
+//! [`GestureRecognizer`] takes timestamped touch samples or
+//! the [`TouchEvent`]s of the touch task and turns them
+//! into the Kolibri [`Interaction`] for the frame plus
+//! higher level [`Gesture`]s: taps, double taps, long
+//! presses, swipes and the start and end of drags.
+
+use embedded_graphics::prelude::Point;
+use heapless::Vec;
+use kolibri_embedded_gui::ui::Interaction;
+
+use crate::touch::event::{TouchEvent, TouchKind};
+
+/// Thresholds of the [`GestureRecognizer`]. Times are in
+/// milliseconds, distances in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureConfig {
+    /// Presses longer than this are no taps.
+    pub tap_max_ms: u64,
+    /// A second tap within this time after the first one
+    /// makes a double tap. Taps are only reported once
+    /// it has passed, set to 0 to report them right
+    /// away and disable double taps.
+    pub double_tap_ms: u64,
+    /// Holding still for this long is a long press.
+    pub long_press_ms: u64,
+    /// Moving less than this is still holding still.
+    pub move_tolerance: u32,
+    /// Minimal distance between start and end of a swipe.
+    pub swipe_min_distance: u32,
+    /// Minimal average speed of a swipe, in pixels per
+    /// second.
+    pub swipe_min_velocity: f32,
+}
+
+impl GestureConfig {
+    pub const DEFAULT: Self = Self {
+        tap_max_ms: 250,
+        double_tap_ms: 300,
+        long_press_ms: 600,
+        move_tolerance: 10,
+        swipe_min_distance: 60,
+        swipe_min_velocity: 300.0,
+    };
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A swipe back has to start this close to the left edge
+/// of the screen, in pixels, so that dragging a slider to
+/// the right doesn't leave the page.
+pub const BACK_SWIPE_EDGE: i32 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    Tap(Point),
+    DoubleTap(Point),
+    /// Reported while the finger is still down. The rest
+    /// of the press is not passed on to Kolibri, so
+    /// the widget below doesn't see a click as well.
+    LongPress(Point),
+    Swipe {
+        start: Point,
+        end: Point,
+        direction: SwipeDirection,
+        /// Average speed in pixels per second.
+        velocity: f32,
+    },
+    /// The finger moved further than
+    /// [`GestureConfig::move_tolerance`]. Carries the
+    /// point the press started at.
+    DragStart(Point),
+    /// A drag ended; carries the last touched point.
+    DragEnd(Point),
+}
+
+/// What one call to [`GestureRecognizer::update`] or
+/// [`GestureRecognizer::poll`] produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Events {
+    /// Pass this to `Ui::interact`.
+    pub interaction: Interaction,
+    /// At most a tap that timed out, a drag end and a
+    /// swipe come in together.
+    pub gestures: Vec<Gesture, 3>,
+}
+
+impl Events {
+    fn new(interaction: Interaction) -> Self {
+        Self {
+            interaction,
+            gestures: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, gesture: Gesture) {
+        self.gestures.push(gesture).ok();
+    }
+
+    /// Where a [`Gesture::Tap`] among the gestures was,
+    /// if any.
+    pub fn tap(&self) -> Option<Point> {
+        self.gestures.iter().find_map(|g| match g {
+            Gesture::Tap(p) => Some(*p),
+            _ => None,
+        })
+    }
+
+    /// Whether a [`Gesture::LongPress`] is among the
+    /// gestures.
+    pub fn long_press(&self) -> Option<Point> {
+        self.gestures.iter().find_map(|g| match g {
+            Gesture::LongPress(p) => Some(*p),
+            _ => None,
+        })
+    }
+
+    /// The direction of a [`Gesture::Swipe`] among the
+    /// gestures, if any.
+    pub fn swipe(&self) -> Option<SwipeDirection> {
+        self.gestures.iter().find_map(|g| match g {
+            Gesture::Swipe { direction, .. } => {
+                Some(*direction)
+            }
+            _ => None,
+        })
+    }
+
+    /// Whether a swipe to the right that started within
+    /// [`BACK_SWIPE_EDGE`] of the left edge is among the
+    /// gestures, the way to go back a page.
+    pub fn back_swipe(&self) -> bool {
+        self.gestures.iter().any(|g| {
+            matches!(
+                g,
+                Gesture::Swipe {
+                    start,
+                    direction: SwipeDirection::Right,
+                    ..
+                } if start.x < BACK_SWIPE_EDGE
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Press {
+    start_ms: u64,
+    start: Point,
+    last: Point,
+    dragging: bool,
+    long_pressed: bool,
+}
+
+/// Turns touch samples into Kolibri interactions and
+/// [`Gesture`]s.
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    press: Option<Press>,
+    /// A tap waiting for a possible second one.
+    pending_tap: Option<(u64, Point)>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            press: None,
+            pending_tap: None,
+        }
+    }
+
+    pub fn config(&self) -> &GestureConfig {
+        &self.config
+    }
+
+    /// Feeds a new touch sample taken at `now_ms`: the
+    /// touch point, or `None` when the screen isn't
+    /// touched.
+    pub fn update(
+        &mut self,
+        now_ms: u64,
+        touch: Option<Point>,
+    ) -> Events {
+        let consumed = self.consumed();
+        let mut events = Events::new(Interaction::None);
+        self.check_timeouts(now_ms, &mut events);
+
+        match (touch, self.press.as_mut()) {
+            (Some(point), None) => {
+                self.press = Some(Press {
+                    start_ms: now_ms,
+                    start: point,
+                    last: point,
+                    dragging: false,
+                    long_pressed: false,
+                });
+                events.interaction =
+                    Interaction::Click(point);
+            }
+            (Some(point), Some(press)) => {
+                press.last = point;
+                if !press.dragging
+                    && distance(press.start, point)
+                        > self.config.move_tolerance as f32
+                {
+                    press.dragging = true;
+                    events.push(Gesture::DragStart(
+                        press.start,
+                    ));
+                }
+                if !consumed {
+                    events.interaction =
+                        Interaction::Drag(point);
+                }
+            }
+            (None, Some(press)) => {
+                let press = *press;
+                self.press = None;
+                if !consumed {
+                    events.interaction =
+                        Interaction::Release(press.last);
+                }
+                self.released(now_ms, press, &mut events);
+            }
+            (None, None) => {}
+        }
+        events
+    }
+
+    /// Feeds an event from the
+    /// [`TouchChannel`](crate::touch::event::TouchChannel),
+    /// at the time it was taken.
+    pub fn event(&mut self, event: TouchEvent) -> Events {
+        let touch = match event.kind {
+            TouchKind::Press | TouchKind::Move => {
+                Some(event.point)
+            }
+            TouchKind::Release => None,
+        };
+        self.update(event.t_ms, touch)
+    }
+
+    /// Advances time without a new sample, e.g. on frames
+    /// where the touch task didn't report anything.
+    /// Long presses and delayed taps fire from here,
+    /// too.
+    pub fn poll(&mut self, now_ms: u64) -> Events {
+        let consumed = self.consumed();
+        let mut events = Events::new(Interaction::None);
+        self.check_timeouts(now_ms, &mut events);
+        if let (Some(press), false) = (self.press, consumed)
+        {
+            // still down: keep the widget below pressed
+            events.interaction =
+                Interaction::Drag(press.last);
+        }
+        events
+    }
+
+    /// Whether the current press has been taken over by a
+    /// long press.
+    fn consumed(&self) -> bool {
+        self.press.is_some_and(|p| p.long_pressed)
+    }
+
+    fn check_timeouts(
+        &mut self,
+        now_ms: u64,
+        events: &mut Events,
+    ) {
+        if let Some((at, point)) = self.pending_tap {
+            if now_ms.saturating_sub(at)
+                > self.config.double_tap_ms
+            {
+                self.pending_tap = None;
+                events.push(Gesture::Tap(point));
+            }
+        }
+        if let Some(press) = self.press.as_mut() {
+            if !press.dragging
+                && !press.long_pressed
+                && now_ms.saturating_sub(press.start_ms)
+                    >= self.config.long_press_ms
+            {
+                press.long_pressed = true;
+                self.pending_tap = None;
+                events
+                    .push(Gesture::LongPress(press.start));
+            }
+        }
+    }
+
+    fn released(
+        &mut self,
+        now_ms: u64,
+        press: Press,
+        events: &mut Events,
+    ) {
+        let duration_ms =
+            now_ms.saturating_sub(press.start_ms);
+        if press.dragging {
+            events.push(Gesture::DragEnd(press.last));
+            let dist = distance(press.start, press.last);
+            let velocity =
+                dist * 1000.0 / duration_ms.max(1) as f32;
+            if dist >= self.config.swipe_min_distance as f32
+                && velocity
+                    >= self.config.swipe_min_velocity
+            {
+                events.push(Gesture::Swipe {
+                    start: press.start,
+                    end: press.last,
+                    direction: direction(
+                        press.start,
+                        press.last,
+                    ),
+                    velocity,
+                });
+            }
+        } else if !press.long_pressed
+            && duration_ms <= self.config.tap_max_ms
+        {
+            let point = press.start;
+            match self.pending_tap.take() {
+                Some((_, first))
+                    if distance(first, point)
+                        <= self.config.move_tolerance
+                            as f32
+                            * 2.0 =>
+                {
+                    events.push(Gesture::DoubleTap(point));
+                }
+                other => {
+                    // a tap somewhere else: the first one
+                    // was a single tap after all
+                    if let Some((_, first)) = other {
+                        events.push(Gesture::Tap(first));
+                    }
+                    if self.config.double_tap_ms == 0 {
+                        events.push(Gesture::Tap(point));
+                    } else {
+                        self.pending_tap =
+                            Some((now_ms, point));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        Self::new(GestureConfig::DEFAULT)
+    }
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    let d = b - a;
+    libm::sqrtf((d.x * d.x + d.y * d.y) as f32)
+}
+
+fn direction(start: Point, end: Point) -> SwipeDirection {
+    let d = end - start;
+    if d.x.abs() >= d.y.abs() {
+        if d.x >= 0 {
+            SwipeDirection::Right
+        } else {
+            SwipeDirection::Left
+        }
+    } else if d.y >= 0 {
+        // screen coordinates grow downwards
+        SwipeDirection::Down
+    } else {
+        SwipeDirection::Up
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `(time, sample)` pairs and collects all
+    /// gestures.
+    fn run(
+        r: &mut GestureRecognizer,
+        samples: &[(u64, Option<(i32, i32)>)],
+    ) -> std::vec::Vec<Gesture> {
+        samples
+            .iter()
+            .flat_map(|&(t, s)| {
+                r.update(
+                    t,
+                    s.map(|(x, y)| Point::new(x, y)),
+                )
+                .gestures
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interactions_follow_the_touch() {
+        let mut r = GestureRecognizer::default();
+        let p = Point::new(10, 10);
+        let q = Point::new(12, 11);
+        assert_eq!(
+            r.update(0, Some(p)).interaction,
+            Interaction::Click(p)
+        );
+        assert_eq!(
+            r.update(10, Some(q)).interaction,
+            Interaction::Drag(q)
+        );
+        assert_eq!(
+            r.poll(20).interaction,
+            Interaction::Drag(q)
+        );
+        assert_eq!(
+            r.update(30, None).interaction,
+            Interaction::Release(q)
+        );
+        assert_eq!(
+            r.update(40, None).interaction,
+            Interaction::None
+        );
+        assert_eq!(
+            r.poll(50).interaction,
+            Interaction::None
+        );
+    }
+
+    #[test]
+    fn tap_is_reported_after_double_tap_window() {
+        let mut r = GestureRecognizer::default();
+        let g =
+            run(&mut r, &[(0, Some((50, 50))), (80, None)]);
+        assert!(g.is_empty());
+        assert!(r.poll(300).gestures.is_empty());
+        assert_eq!(
+            r.poll(400).gestures.as_slice(),
+            &[Gesture::Tap(Point::new(50, 50))]
+        );
+    }
+
+    #[test]
+    fn tap_is_immediate_without_double_tap() {
+        let mut r = GestureRecognizer::new(GestureConfig {
+            double_tap_ms: 0,
+            ..GestureConfig::DEFAULT
+        });
+        let g =
+            run(&mut r, &[(0, Some((50, 50))), (80, None)]);
+        assert_eq!(g, [Gesture::Tap(Point::new(50, 50))]);
+    }
+
+    #[test]
+    fn double_tap() {
+        let mut r = GestureRecognizer::default();
+        let g = run(
+            &mut r,
+            &[
+                (0, Some((50, 50))),
+                (80, None),
+                (200, Some((53, 48))),
+                (260, None),
+            ],
+        );
+        assert_eq!(
+            g,
+            [Gesture::DoubleTap(Point::new(53, 48))]
+        );
+        assert!(r.poll(1000).gestures.is_empty());
+    }
+
+    #[test]
+    fn taps_at_different_places_are_two_taps() {
+        let mut r = GestureRecognizer::default();
+        let g = run(
+            &mut r,
+            &[
+                (0, Some((50, 50))),
+                (80, None),
+                (200, Some((200, 50))),
+                (260, None),
+            ],
+        );
+        assert_eq!(g, [Gesture::Tap(Point::new(50, 50))]);
+        assert_eq!(
+            r.poll(1000).gestures.as_slice(),
+            &[Gesture::Tap(Point::new(200, 50))]
+        );
+    }
+
+    #[test]
+    fn slow_press_is_no_tap() {
+        let mut r = GestureRecognizer::default();
+        run(&mut r, &[(0, Some((50, 50))), (400, None)]);
+        assert!(r.poll(2000).gestures.is_empty());
+    }
+
+    #[test]
+    fn long_press_fires_while_held_and_swallows_release() {
+        let mut r = GestureRecognizer::default();
+        let p = Point::new(100, 100);
+        r.update(0, Some(p));
+        assert!(r.poll(500).gestures.is_empty());
+
+        let events = r.poll(600);
+        assert_eq!(events.long_press(), Some(p));
+        // the frame it fires on still reports the press, so
+        // the widget below can be hit-tested
+        assert_eq!(
+            events.interaction,
+            Interaction::Drag(p)
+        );
+
+        assert_eq!(
+            r.poll(700).interaction,
+            Interaction::None
+        );
+        let events = r.update(800, None);
+        assert_eq!(events.interaction, Interaction::None);
+        assert!(events.gestures.is_empty());
+        assert!(r.poll(2000).gestures.is_empty());
+    }
+
+    #[test]
+    fn moving_prevents_long_press() {
+        let mut r = GestureRecognizer::default();
+        let g = run(
+            &mut r,
+            &[
+                (0, Some((100, 100))),
+                (100, Some((130, 100))),
+                (700, Some((131, 100))),
+                (2000, None),
+            ],
+        );
+        assert_eq!(
+            g,
+            [
+                Gesture::DragStart(Point::new(100, 100)),
+                Gesture::DragEnd(Point::new(131, 100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn fast_move_is_a_swipe() {
+        let mut r = GestureRecognizer::default();
+        let g = run(
+            &mut r,
+            &[
+                (0, Some((40, 120))),
+                (50, Some((100, 125))),
+                (100, Some((140, 120))),
+                (110, None),
+            ],
+        );
+        assert_eq!(g.len(), 3);
+        assert_eq!(
+            g[0],
+            Gesture::DragStart(Point::new(40, 120))
+        );
+        assert_eq!(
+            g[1],
+            Gesture::DragEnd(Point::new(140, 120))
+        );
+        let Gesture::Swipe {
+            start,
+            end,
+            direction,
+            velocity,
+        } = g[2]
+        else {
+            panic!("no swipe: {g:?}");
+        };
+        assert_eq!(start, Point::new(40, 120));
+        assert_eq!(end, Point::new(140, 120));
+        assert_eq!(direction, SwipeDirection::Right);
+        assert!(
+            (velocity - 909.09).abs() < 0.1,
+            "{velocity}"
+        );
+    }
+
+    #[test]
+    fn swipe_directions() {
+        for (end, dir) in [
+            ((30, 100), SwipeDirection::Left),
+            ((170, 100), SwipeDirection::Right),
+            ((100, 20), SwipeDirection::Up),
+            ((110, 190), SwipeDirection::Down),
+        ] {
+            let mut r = GestureRecognizer::default();
+            r.update(0, Some(Point::new(100, 100)));
+            r.update(50, Some(Point::new(end.0, end.1)));
+            assert_eq!(
+                r.update(60, None).swipe(),
+                Some(dir)
+            );
+        }
+    }
+
+    #[test]
+    fn only_swipes_from_the_edge_go_back() {
+        for (start, back) in [(10, true), (100, false)] {
+            let mut r = GestureRecognizer::default();
+            r.update(0, Some(Point::new(start, 100)));
+            r.update(
+                50,
+                Some(Point::new(start + 100, 100)),
+            );
+            let events = r.update(60, None);
+            assert_eq!(
+                events.swipe(),
+                Some(SwipeDirection::Right)
+            );
+            assert_eq!(events.back_swipe(), back);
+        }
+        let mut r = GestureRecognizer::default();
+        r.update(0, Some(Point::new(100, 100)));
+        r.update(50, Some(Point::new(10, 100)));
+        assert!(!r.update(60, None).back_swipe());
+    }
+
+    #[test]
+    fn a_tap_can_time_out_as_a_swipe_ends() {
+        let mut r = GestureRecognizer::default();
+        r.update(0, Some(Point::new(200, 100)));
+        assert!(r.update(50, None).gestures.is_empty());
+        r.update(100, Some(Point::new(10, 100)));
+        r.update(150, Some(Point::new(110, 100)));
+        let events = r.update(360, None);
+        assert_eq!(
+            events.tap(),
+            Some(Point::new(200, 100))
+        );
+        assert!(events.gestures.contains(
+            &Gesture::DragEnd(Point::new(110, 100))
+        ));
+        assert!(events.back_swipe());
+    }
+
+    #[test]
+    fn slow_or_short_drags_are_no_swipes() {
+        let mut r = GestureRecognizer::default();
+        r.update(0, Some(Point::new(40, 120)));
+        r.update(100, Some(Point::new(140, 120)));
+        // 100px in 1s
+        assert_eq!(r.update(1000, None).swipe(), None);
+
+        r.update(2000, Some(Point::new(40, 120)));
+        r.update(2010, Some(Point::new(70, 120)));
+        assert_eq!(r.update(2020, None).swipe(), None);
+    }
+
+    #[test]
+    fn queued_events_keep_their_timing() {
+        let mut r = GestureRecognizer::new(GestureConfig {
+            double_tap_ms: 0,
+            ..GestureConfig::DEFAULT
+        });
+        let p = Point::new(50, 50);
+        // a quick tap, only handled a few frames later
+        let press = r.event(TouchEvent::new(
+            TouchKind::Press,
+            p,
+            100,
+        ));
+        assert_eq!(
+            press.interaction,
+            Interaction::Click(p)
+        );
+        let release = r.event(TouchEvent::new(
+            TouchKind::Release,
+            p,
+            180,
+        ));
+        assert_eq!(
+            release.interaction,
+            Interaction::Release(p)
+        );
+        assert_eq!(
+            release.gestures.as_slice(),
+            &[Gesture::Tap(p)]
+        );
+    }
+}
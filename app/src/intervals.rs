@@ -0,0 +1,656 @@
+//! Interval programs for the timer app: named phases run
+//! one after the other, and the whole sequence is repeated,
+//! e.g. "work 25m / break 5m x4".
+//!
+//! An [`IntervalProgram`] is written and stored as one line
+//! of text:
+//!
+//! ```text
+//! Pomodoro: work 25m / break 5m x4
+//! ```
+//!
+//! The name before the colon and the repeat count after the
+//! last phase are optional. Durations are written as `1h`,
+//! `25m`, `40s` or combined, like `1m30s`.
+//!
+//! An [`IntervalRunner`] counts the phases down with a
+//! [`CountdownTimer`] and reports every phase change, so
+//! the app can beep and blink.
+
+use core::fmt;
+
+use heapless::Vec;
+
+use crate::{
+    countdown::{
+        Clock,
+        CountdownTimer,
+        TimerEvent,
+        TimerState,
+        MIN_SECS,
+    },
+    storage::{parse_lines, write_lines, Slot, TextRecord},
+    timers::{truncated, TimerLabel, MAX_TIMER_SECS},
+};
+
+/// Most phases in one program.
+pub const MAX_PHASES: usize = 6;
+/// Most programs kept in flash.
+pub const MAX_PROGRAMS: usize = 6;
+/// Most rounds of a program.
+pub const MAX_REPEATS: u8 = 99;
+/// Longest line a program can be written as.
+pub const LINE_LEN: usize = 192;
+
+/// Programs the app starts with until others are stored.
+pub const DEFAULT_PROGRAMS: [&str; 4] = [
+    "Pomodoro: work 25m / break 5m x4",
+    "HIIT: work 40s / rest 20s x8",
+    "Tabata: work 20s / rest 10s x8",
+    "Stretch: hold 30s / switch 10s x6",
+];
+
+/// One step of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phase {
+    name: TimerLabel,
+    secs: u32,
+}
+
+impl Phase {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn secs(&self) -> u32 {
+        self.secs
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{} {}", self.name, Secs(self.secs))
+    }
+}
+
+/// Why a line is not a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// No phases.
+    Empty,
+    /// A phase without a name or a duration.
+    Phase,
+    /// A duration that isn't one, or is outside
+    /// [`MIN_SECS`] ..= [`MAX_TIMER_SECS`].
+    Duration,
+    /// More than [`MAX_PHASES`].
+    TooManyPhases,
+    /// A repeat count of zero or above [`MAX_REPEATS`].
+    Repeats,
+}
+
+/// Named phases, run in order, [`repeats`](Self::repeats)
+/// times.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntervalProgram {
+    name: TimerLabel,
+    phases: Vec<Phase, MAX_PHASES>,
+    repeats: u8,
+}
+
+impl IntervalProgram {
+    /// Reads a program written like its [`Display`]
+    /// output. Names are cut to
+    /// [`LABEL_LEN`](crate::timers::LABEL_LEN) bytes.
+    ///
+    /// [`Display`]: fmt::Display
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let (name, rest) = match line.split_once(':') {
+            Some((name, rest)) => (name.trim(), rest),
+            None => ("", line),
+        };
+        let mut rest = rest.trim();
+        let mut repeats = 1;
+        if let Some((phases, count)) = rest.rsplit_once(' ')
+        {
+            if let Some(count) = count
+                .strip_prefix('x')
+                .or_else(|| count.strip_prefix('×'))
+            {
+                repeats = count
+                    .parse()
+                    .ok()
+                    .filter(|r| {
+                        (1..=MAX_REPEATS).contains(r)
+                    })
+                    .ok_or(ParseError::Repeats)?;
+                rest = phases;
+            }
+        }
+        let mut phases = Vec::new();
+        for phase in rest.split('/') {
+            let phase = phase.trim();
+            if phase.is_empty() {
+                continue;
+            }
+            let (name, duration) =
+                phase
+                    .rsplit_once(' ')
+                    .ok_or(ParseError::Phase)?;
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(ParseError::Phase);
+            }
+            let phase = Phase {
+                name: truncated(name),
+                secs: parse_secs(duration)?,
+            };
+            phases
+                .push(phase)
+                .map_err(|_| ParseError::TooManyPhases)?;
+        }
+        if phases.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        Ok(Self {
+            name: truncated(name),
+            phases,
+            repeats,
+        })
+    }
+
+    /// The name, or `""` if it has none.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    pub fn repeats(&self) -> u8 {
+        self.repeats
+    }
+
+    /// Length of one round, in seconds.
+    pub fn round_secs(&self) -> u64 {
+        self.phases.iter().map(|p| p.secs as u64).sum()
+    }
+
+    /// Length of all rounds, in seconds.
+    pub fn total_secs(&self) -> u64 {
+        self.round_secs() * self.repeats as u64
+    }
+}
+
+impl fmt::Display for IntervalProgram {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        if !self.name.is_empty() {
+            write!(f, "{}: ", self.name)?;
+        }
+        for (i, phase) in self.phases.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" / ")?;
+            }
+            write!(f, "{}", phase)?;
+        }
+        if self.repeats > 1 {
+            write!(f, " x{}", self.repeats)?;
+        }
+        Ok(())
+    }
+}
+
+/// `25m`, `40s`, `1h30m`, ... A number without a unit is
+/// seconds.
+fn parse_secs(text: &str) -> Result<u32, ParseError> {
+    let mut secs = 0u64;
+    let mut number = None::<u64>;
+    for c in text.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                let digit = c as u64 - '0' as u64;
+                number = Some(
+                    number
+                        .unwrap_or(0)
+                        .saturating_mul(10)
+                        .saturating_add(digit),
+                );
+                continue;
+            }
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(ParseError::Duration),
+        };
+        let n =
+            number.take().ok_or(ParseError::Duration)?;
+        secs = secs.saturating_add(n.saturating_mul(unit));
+    }
+    if let Some(n) = number {
+        secs = secs.saturating_add(n);
+    }
+    if !(MIN_SECS..=MAX_TIMER_SECS).contains(&secs) {
+        return Err(ParseError::Duration);
+    }
+    Ok(secs as u32)
+}
+
+/// Writes seconds the way [`parse_secs`] reads them.
+struct Secs(u32);
+
+impl fmt::Display for Secs {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        let (h, m, s) =
+            (self.0 / 3600, self.0 / 60 % 60, self.0 % 60);
+        if h > 0 {
+            write!(f, "{}h", h)?;
+        }
+        if m > 0 {
+            write!(f, "{}m", m)?;
+        }
+        if s > 0 {
+            write!(f, "{}s", s)?;
+        }
+        Ok(())
+    }
+}
+
+/// The programs of the timer app, kept in flash as their
+/// lines of text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Programs {
+    pub list: Vec<IntervalProgram, MAX_PROGRAMS>,
+}
+
+impl Programs {
+    pub const ENCODED_LEN: usize =
+        1 + MAX_PROGRAMS * (LINE_LEN + 1);
+
+    /// [`DEFAULT_PROGRAMS`].
+    pub fn defaults() -> Self {
+        let list = DEFAULT_PROGRAMS
+            .iter()
+            .filter_map(|l| IntervalProgram::parse(l).ok())
+            .collect();
+        Self { list }
+    }
+}
+
+impl TextRecord<{ Programs::ENCODED_LEN }> for Programs {
+    const SLOT: Slot = Slot::IntervalPrograms;
+    const FORMAT_VERSION: u8 = 1;
+
+    fn write_lines(
+        &self,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        write_lines(out, &self.list)
+    }
+
+    fn from_lines<'a>(
+        lines: impl Iterator<Item = &'a str>,
+    ) -> Option<Self> {
+        let list =
+            parse_lines(lines, IntervalProgram::parse)?;
+        Some(Self { list })
+    }
+}
+
+impl Default for Programs {
+    fn default() -> Self {
+        Self::defaults()
+    }
+}
+
+/// Loads the stored programs, falling back to
+/// [`DEFAULT_PROGRAMS`] if there are none.
+#[cfg(target_os = "none")]
+pub fn stored_programs() -> Programs {
+    Programs::stored()
+        .filter(|p| !p.list.is_empty())
+        .unwrap_or_default()
+}
+
+/// What [`IntervalRunner::update`] noticed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntervalEvent {
+    /// The next phase started; both count from zero.
+    Phase { round: u8, phase: usize },
+    /// The last phase of the last round ended.
+    Finished,
+}
+
+/// Runs an [`IntervalProgram`]. Like the [`CountdownTimer`]
+/// it is built on, it can be paused and has to be
+/// [updated](Self::update) regularly.
+///
+/// A phase starts when [`update`](Self::update) notices the
+/// previous one ended, so every phase can start up to one
+/// update late.
+#[derive(Debug, Clone)]
+pub struct IntervalRunner<C: Clock> {
+    program: IntervalProgram,
+    timer: CountdownTimer<C>,
+    round: u8,
+    phase: usize,
+}
+
+impl<C: Clock> IntervalRunner<C> {
+    /// A runner at the start of `program`, not started.
+    pub fn new(program: IntervalProgram, clock: C) -> Self {
+        let mut runner = Self {
+            program,
+            timer: CountdownTimer::with_max(
+                clock,
+                MAX_TIMER_SECS,
+            ),
+            round: 0,
+            phase: 0,
+        };
+        runner.reset();
+        runner
+    }
+
+    pub fn program(&self) -> &IntervalProgram {
+        &self.program
+    }
+
+    /// Replaces the program and goes back to its start.
+    pub fn set_program(
+        &mut self,
+        program: IntervalProgram,
+    ) {
+        self.program = program;
+        self.reset();
+    }
+
+    /// `Finished` only after the last phase.
+    pub fn state(&self) -> TimerState {
+        self.timer.state()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.timer.is_running()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.timer.is_finished()
+    }
+
+    /// At the start of the program, not started.
+    pub fn is_reset(&self) -> bool {
+        self.timer.is_stopped()
+    }
+
+    /// Starts from the beginning once finished.
+    pub fn start(&mut self) -> Option<TimerEvent> {
+        if self.timer.is_finished() {
+            self.reset();
+        }
+        self.timer.start()
+    }
+
+    pub fn pause(&mut self) -> Option<TimerEvent> {
+        self.timer.pause()
+    }
+
+    /// Stops and goes back to the first phase of the
+    /// first round.
+    pub fn reset(&mut self) {
+        self.round = 0;
+        self.enter(0);
+    }
+
+    /// Current round, from zero.
+    pub fn round(&self) -> u8 {
+        self.round
+    }
+
+    /// Index of the current phase.
+    pub fn phase_index(&self) -> usize {
+        self.phase
+    }
+
+    pub fn phase(&self) -> &Phase {
+        &self.program.phases[self.phase]
+    }
+
+    /// The phase after the current one, if there is one.
+    pub fn next_phase(&self) -> Option<&Phase> {
+        let phases = self.program.phases();
+        if self.phase + 1 < phases.len() {
+            Some(&phases[self.phase + 1])
+        } else if self.round + 1 < self.program.repeats {
+            Some(&phases[0])
+        } else {
+            None
+        }
+    }
+
+    /// What is left of the current phase.
+    pub fn phase_remaining_ms(&self) -> u64 {
+        self.timer.remaining_ms()
+    }
+
+    /// Time spent in the program so far.
+    pub fn elapsed_ms(&self) -> u64 {
+        if self.timer.is_finished() {
+            return self.total_ms();
+        }
+        let rounds = self.round as u64
+            * self.program.round_secs()
+            * 1000;
+        let phases: u64 = self.program.phases[..self.phase]
+            .iter()
+            .map(|p| p.secs as u64 * 1000)
+            .sum();
+        rounds + phases + self.timer.duration_ms()
+            - self.timer.remaining_ms()
+    }
+
+    pub fn total_ms(&self) -> u64 {
+        self.program.total_secs() * 1000
+    }
+
+    /// Moves on to the next phase when the current one has
+    /// run out.
+    pub fn update(&mut self) -> Option<IntervalEvent> {
+        if self.timer.update() != Some(TimerEvent::Finished)
+        {
+            return None;
+        }
+        let next = self.phase + 1;
+        if next < self.program.phases.len() {
+            self.enter(next);
+        } else if self.round + 1 < self.program.repeats {
+            self.round += 1;
+            self.enter(0);
+        } else {
+            return Some(IntervalEvent::Finished);
+        }
+        self.timer.start();
+        Some(IntervalEvent::Phase {
+            round: self.round,
+            phase: self.phase,
+        })
+    }
+
+    /// Stops the timer and sets it to phase `phase`.
+    fn enter(&mut self, phase: usize) {
+        self.phase = phase;
+        self.timer.reset();
+        self.timer.set_duration_ms(
+            self.program.phases[phase].secs as u64 * 1000,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::storage::{tests::MockFlash, RecordStore};
+
+    fn program(line: &str) -> IntervalProgram {
+        IntervalProgram::parse(line).unwrap()
+    }
+
+    #[test]
+    fn programs_are_read_and_written_as_text() {
+        let p = program("Pomodoro: work 25m / break 5m ×4");
+        assert_eq!(p.name(), "Pomodoro");
+        assert_eq!(p.repeats(), 4);
+        assert_eq!(p.phases()[0].name(), "work");
+        assert_eq!(p.phases()[1].secs(), 300);
+        assert_eq!(p.total_secs(), 4 * 30 * 60);
+        assert_eq!(
+            p.to_string(),
+            "Pomodoro: work 25m / break 5m x4"
+        );
+        let p = program("cool down 1m30s");
+        assert_eq!(p.name(), "");
+        assert_eq!(p.repeats(), 1);
+        assert_eq!(p.phases()[0].name(), "cool down");
+        assert_eq!(p.to_string(), "cool down 1m30s");
+        for line in DEFAULT_PROGRAMS {
+            assert_eq!(program(line).to_string(), line);
+        }
+    }
+
+    #[test]
+    fn broken_lines_are_rejected() {
+        for (line, error) in [
+            ("Empty: ", ParseError::Empty),
+            ("work", ParseError::Phase),
+            ("work 25x", ParseError::Duration),
+            ("work 5s", ParseError::Duration),
+            ("work m", ParseError::Duration),
+            ("work 100h", ParseError::Duration),
+            ("work 1m x0", ParseError::Repeats),
+            ("work 1m x100", ParseError::Repeats),
+            (
+                "a 1m / b 1m / c 1m / d 1m / e 1m / f 1m \
+                 / g 1m",
+                ParseError::TooManyPhases,
+            ),
+        ] {
+            assert_eq!(
+                IntervalProgram::parse(line),
+                Err(error),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn phases_run_in_order_for_every_round() {
+        let now = Cell::new(0);
+        let mut r = IntervalRunner::new(
+            program("work 40s / rest 20s x2"),
+            || now.get(),
+        );
+        assert!(r.is_reset());
+        assert_eq!(r.phase().name(), "work");
+        assert_eq!(r.update(), None);
+        r.start();
+        now.set(40_000);
+        assert_eq!(
+            r.update(),
+            Some(IntervalEvent::Phase {
+                round: 0,
+                phase: 1
+            })
+        );
+        assert_eq!(r.phase().name(), "rest");
+        assert_eq!(r.next_phase().unwrap().name(), "work");
+        assert_eq!(r.elapsed_ms(), 40_000);
+        now.set(50_000);
+        assert_eq!(r.phase_remaining_ms(), 10_000);
+        now.set(60_000);
+        assert_eq!(
+            r.update(),
+            Some(IntervalEvent::Phase {
+                round: 1,
+                phase: 0
+            })
+        );
+        now.set(100_000);
+        r.update();
+        assert_eq!(r.next_phase(), None);
+        now.set(120_000);
+        assert_eq!(
+            r.update(),
+            Some(IntervalEvent::Finished)
+        );
+        assert!(r.is_finished());
+        assert_eq!(r.elapsed_ms(), r.total_ms());
+        assert_eq!(r.update(), None);
+        // starting again begins from the first phase
+        r.start();
+        assert_eq!((r.round(), r.phase_index()), (0, 0));
+        assert!(r.is_running());
+    }
+
+    #[test]
+    fn pausing_holds_the_phase() {
+        let now = Cell::new(0);
+        let mut r = IntervalRunner::new(
+            program("work 30s / rest 10s"),
+            || now.get(),
+        );
+        r.start();
+        now.set(20_000);
+        r.pause();
+        now.set(90_000);
+        assert_eq!(r.update(), None);
+        assert_eq!(r.phase_remaining_ms(), 10_000);
+        r.start();
+        now.set(100_000);
+        assert_eq!(
+            r.update(),
+            Some(IntervalEvent::Phase {
+                round: 0,
+                phase: 1
+            })
+        );
+        r.reset();
+        assert!(r.is_reset());
+        assert_eq!(r.phase_index(), 0);
+        assert_eq!(r.phase_remaining_ms(), 30_000);
+    }
+
+    #[test]
+    fn programs_survive_a_reboot() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(Programs::load(&mut store), Ok(None));
+        let mut p = Programs::defaults();
+        p.list.push(program("Run: run 1h x3")).unwrap();
+        p.save(&mut store).unwrap();
+        assert_eq!(Programs::load(&mut store), Ok(Some(p)));
+    }
+
+    #[test]
+    fn broken_programs_are_rejected() {
+        let mut bytes =
+            Programs::defaults().to_bytes().unwrap();
+        bytes[0] = 0;
+        assert_eq!(Programs::from_bytes(&bytes), None);
+        let mut bytes =
+            Programs::defaults().to_bytes().unwrap();
+        bytes.extend_from_slice(b"work 1\n").unwrap();
+        assert_eq!(Programs::from_bytes(&bytes), None);
+    }
+}
@@ -0,0 +1,140 @@
+//! Typing a duration on a numeric keypad, the way
+//! microwaves and kitchen timers do it: digits come in from
+//! the right, so `1`, `2`, `0`, `0` reads `00:12:00`.
+
+use core::fmt;
+
+/// Digits of `hhmmss`.
+pub const MAX_DIGITS: usize = 6;
+
+/// A duration being typed in. Shown as `hh:mm:ss`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DurationEntry {
+    /// The digits typed so far, as a number: `1200` is
+    /// `00:12:00`.
+    digits: u32,
+    len: u8,
+}
+
+impl DurationEntry {
+    pub const fn new() -> Self {
+        Self { digits: 0, len: 0 }
+    }
+
+    /// Appends a digit. Returns `false` if `digit` is not
+    /// one, or the entry is full.
+    pub fn push(&mut self, digit: u8) -> bool {
+        if digit > 9 || self.len as usize >= MAX_DIGITS {
+            return false;
+        }
+        // leading zeros change nothing
+        if self.digits == 0 && digit == 0 {
+            return true;
+        }
+        self.digits = self.digits * 10 + digit as u32;
+        self.len += 1;
+        true
+    }
+
+    /// Removes the last digit.
+    pub fn backspace(&mut self) {
+        self.digits /= 10;
+        self.len = self.len.saturating_sub(1);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digits == 0
+    }
+
+    /// Hours, minutes and seconds as typed. Minutes and
+    /// seconds can be 60 or more, like on a microwave.
+    pub fn fields(&self) -> (u32, u32, u32) {
+        (
+            self.digits / 10_000,
+            self.digits / 100 % 100,
+            self.digits % 100,
+        )
+    }
+
+    /// The duration typed so far. `00:90` is 90 seconds.
+    pub fn secs(&self) -> u64 {
+        let (h, m, s) = self.fields();
+        h as u64 * 3600 + m as u64 * 60 + s as u64
+    }
+}
+
+impl fmt::Display for DurationEntry {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        let (h, m, s) = self.fields();
+        write!(f, "{:02}:{:02}:{:02}", h, m, s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(digits: &[u8]) -> DurationEntry {
+        let mut e = DurationEntry::new();
+        for &d in digits {
+            e.push(d);
+        }
+        e
+    }
+
+    #[test]
+    fn digits_come_in_from_the_right() {
+        let e = typed(&[1, 2, 0, 0]);
+        assert_eq!(e.to_string(), "00:12:00");
+        assert_eq!(e.secs(), 12 * 60);
+        let e = typed(&[1, 3, 0, 0, 0, 0]);
+        assert_eq!(e.to_string(), "13:00:00");
+        assert_eq!(e.secs(), 13 * 3600);
+    }
+
+    #[test]
+    fn overflowing_fields_are_added_up() {
+        assert_eq!(typed(&[9, 0]).secs(), 90);
+        assert_eq!(typed(&[9, 9, 9, 9]).secs(), 99 * 61);
+    }
+
+    #[test]
+    fn full_entry_ignores_more_digits() {
+        let mut e = typed(&[1, 2, 3, 4, 5, 6]);
+        assert!(!e.push(7));
+        assert_eq!(e.to_string(), "12:34:56");
+        assert!(!e.push(10));
+    }
+
+    #[test]
+    fn leading_zeros_are_dropped() {
+        let mut e = typed(&[0, 0, 5]);
+        assert_eq!(e.secs(), 5);
+        for d in [0, 0, 0, 0, 0] {
+            assert!(e.push(d));
+        }
+        assert_eq!(e.to_string(), "50:00:00");
+    }
+
+    #[test]
+    fn backspace_and_clear() {
+        let mut e = typed(&[4, 5]);
+        e.backspace();
+        assert_eq!(e.secs(), 4);
+        e.backspace();
+        assert!(e.is_empty());
+        e.backspace();
+        assert_eq!(e, DurationEntry::new());
+        let mut e = typed(&[1, 2, 3]);
+        e.clear();
+        assert!(e.is_empty());
+        assert_eq!(e.to_string(), "00:00:00");
+    }
+}
@@ -0,0 +1,290 @@
+use core::{fmt, str::FromStr};
+
+/// Warmest colour temperature a tunable white lamp takes,
+/// in kelvin.
+pub const MIN_KELVIN: u16 = 2000;
+/// Coldest colour temperature a tunable white lamp takes,
+/// in kelvin.
+pub const MAX_KELVIN: u16 = 6500;
+
+/// What a lamp can do besides switching on and off. The
+/// apps only offer what the lamp can do, and the backends
+/// turn down the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LampKind {
+    /// Only on and off.
+    Switch,
+    Dimmable,
+    /// Dimmable, from warm to cold white.
+    TunableWhite,
+    /// Dimmable, in any colour.
+    Rgb,
+}
+
+impl LampKind {
+    pub fn is_dimmable(self) -> bool {
+        self != LampKind::Switch
+    }
+
+    /// Whether a lamp of this kind can show `colour`.
+    pub fn supports(self, colour: Colour) -> bool {
+        matches!(
+            (self, colour),
+            (
+                LampKind::TunableWhite,
+                Colour::Temperature(_)
+            ) | (LampKind::Rgb, Colour::Hs { .. })
+        )
+    }
+
+    /// The colour a lamp of this kind has before anyone
+    /// sets one, `None` if it has no colour.
+    pub fn default_colour(self) -> Option<Colour> {
+        match self {
+            LampKind::Switch | LampKind::Dimmable => None,
+            LampKind::TunableWhite => {
+                Some(Colour::WARM_WHITE)
+            }
+            LampKind::Rgb => Some(Colour::WHITE),
+        }
+    }
+}
+
+/// The colour of a lamp, at full brightness.
+///
+/// Written as `CT <kelvin>` or `HS <hue> <saturation>`,
+/// e.g. in the serial protocol and in scenes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colour {
+    /// A white, from [`MIN_KELVIN`] to [`MAX_KELVIN`].
+    Temperature(u16),
+    /// `hue` in degrees below 360, with red at 0, and
+    /// `saturation` from white at 0 to the pure colour at
+    /// 255.
+    Hs { hue: u16, saturation: u8 },
+}
+
+/// The white of a blackbody, every 500 K from
+/// [`MIN_KELVIN`].
+const BLACKBODY: [[u8; 3]; 10] = [
+    [255, 137, 14],
+    [255, 161, 72],
+    [255, 180, 107],
+    [255, 196, 137],
+    [255, 209, 163],
+    [255, 219, 186],
+    [255, 228, 206],
+    [255, 236, 224],
+    [255, 243, 239],
+    [255, 249, 253],
+];
+
+impl Colour {
+    pub const WARM_WHITE: Self = Colour::Temperature(2700);
+    pub const WHITE: Self = Colour::Hs {
+        hue: 0,
+        saturation: 0,
+    };
+
+    /// A white of `kelvin`, clamped to [`MIN_KELVIN`] and
+    /// [`MAX_KELVIN`].
+    pub fn temperature(kelvin: u16) -> Self {
+        Colour::Temperature(
+            kelvin.clamp(MIN_KELVIN, MAX_KELVIN),
+        )
+    }
+
+    /// A colour of `hue`, taken modulo 360.
+    pub fn hs(hue: u16, saturation: u8) -> Self {
+        Colour::Hs {
+            hue: hue % 360,
+            saturation,
+        }
+    }
+
+    /// The red, green and blue of the colour at full
+    /// brightness, e.g. for the channels of an RGB LED.
+    pub fn to_rgb(self) -> [u8; 3] {
+        match self {
+            Colour::Temperature(kelvin) => {
+                let k = kelvin
+                    .clamp(MIN_KELVIN, MAX_KELVIN)
+                    - MIN_KELVIN;
+                let i = (k / 500) as usize;
+                let t = (k % 500) as u32;
+                let lo = BLACKBODY[i];
+                let hi = BLACKBODY[(i + 1).min(9)];
+                core::array::from_fn(|c| {
+                    let (lo, hi) =
+                        (lo[c] as u32, hi[c] as u32);
+                    ((lo * (500 - t) + hi * t + 250) / 500)
+                        as u8
+                })
+            }
+            Colour::Hs { hue, saturation } => {
+                hsv(hue, saturation)
+            }
+        }
+    }
+
+    /// Reads a colour written like its [`Display`] output
+    /// from `words`, `None` if it isn't one. Leaves the
+    /// words after it.
+    ///
+    /// [`Display`]: fmt::Display
+    pub(crate) fn from_words<'a>(
+        words: &mut impl Iterator<Item = &'a str>,
+    ) -> Option<Self> {
+        let colour = match words.next()? {
+            "CT" => Colour::Temperature(number(words)?),
+            "HS" => Colour::Hs {
+                hue: number(words)?,
+                saturation: number(words)?,
+            },
+            _ => return None,
+        };
+        colour.is_valid().then_some(colour)
+    }
+
+    fn is_valid(self) -> bool {
+        match self {
+            Colour::Temperature(kelvin) => {
+                (MIN_KELVIN..=MAX_KELVIN).contains(&kelvin)
+            }
+            Colour::Hs { hue, .. } => hue < 360,
+        }
+    }
+}
+
+fn number<'a, T: FromStr>(
+    words: &mut impl Iterator<Item = &'a str>,
+) -> Option<T> {
+    words.next()?.parse().ok()
+}
+
+impl fmt::Display for Colour {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            Colour::Temperature(kelvin) => {
+                write!(f, "CT {}", kelvin)
+            }
+            Colour::Hs { hue, saturation } => {
+                write!(f, "HS {} {}", hue, saturation)
+            }
+        }
+    }
+}
+
+/// The red, green and blue of `hue` and `saturation` at
+/// full value, in integers only, as it runs for every
+/// pixel of the [hue wheel](crate::lighting::HueWheel).
+pub fn hsv(hue: u16, saturation: u8) -> [u8; 3] {
+    let hue = (hue % 360) as u32;
+    let s = saturation as u32;
+    // how far into the sixth of the circle, 0 to 255
+    let f = (hue % 60) * 255 / 60;
+    let p = (255 - s) as u8;
+    let q = (255 - s * f / 255) as u8;
+    let t = (255 - s * (255 - f) / 255) as u8;
+    match hue / 60 {
+        0 => [255, t, p],
+        1 => [q, 255, p],
+        2 => [p, 255, t],
+        3 => [p, q, 255],
+        4 => [t, p, 255],
+        _ => [255, p, q],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_take_their_colours() {
+        use LampKind::*;
+        let white = Colour::Temperature(4000);
+        let red = Colour::hs(0, 255);
+        assert!(TunableWhite.supports(white));
+        assert!(!TunableWhite.supports(red));
+        assert!(Rgb.supports(red));
+        assert!(!Rgb.supports(white));
+        assert!(!Dimmable.supports(white));
+        assert!(!Switch.is_dimmable());
+        assert_eq!(Dimmable.default_colour(), None);
+        assert_eq!(
+            Rgb.default_colour(),
+            Some(Colour::WHITE)
+        );
+    }
+
+    #[test]
+    fn colours_are_converted_to_rgb() {
+        assert_eq!(hsv(0, 255), [255, 0, 0]);
+        assert_eq!(hsv(120, 255), [0, 255, 0]);
+        assert_eq!(hsv(240, 255), [0, 0, 255]);
+        assert_eq!(hsv(60, 255), [255, 255, 0]);
+        assert_eq!(hsv(30, 255), [255, 127, 0]);
+        assert_eq!(hsv(200, 0), [255, 255, 255]);
+        assert_eq!(hsv(360 + 180, 255), [0, 255, 255]);
+        assert_eq!(
+            Colour::Temperature(MIN_KELVIN).to_rgb(),
+            [255, 137, 14]
+        );
+        assert_eq!(
+            Colour::Temperature(2250).to_rgb(),
+            [255, 149, 43]
+        );
+        assert_eq!(
+            Colour::Temperature(MAX_KELVIN).to_rgb(),
+            [255, 249, 253]
+        );
+        assert_eq!(
+            Colour::temperature(10_000),
+            Colour::Temperature(MAX_KELVIN)
+        );
+        assert_eq!(
+            Colour::hs(400, 7),
+            Colour::Hs {
+                hue: 40,
+                saturation: 7
+            }
+        );
+    }
+
+    #[test]
+    fn colours_are_written_as_words() {
+        for text in ["CT 2700", "HS 359 255", "HS 0 0"] {
+            let mut words = text.split_whitespace();
+            let colour =
+                Colour::from_words(&mut words).unwrap();
+            assert_eq!(colour.to_string(), text);
+            assert_eq!(words.next(), None);
+        }
+        let mut words = "HS 1 2 rest".split_whitespace();
+        Colour::from_words(&mut words).unwrap();
+        assert_eq!(words.next(), Some("rest"));
+        for text in [
+            "",
+            "CT",
+            "CT 1999",
+            "CT 6501",
+            "HS 360 0",
+            "HS 10",
+            "HS 10 256",
+            "RGB 1 2 3",
+        ] {
+            assert_eq!(
+                Colour::from_words(
+                    &mut text.split_whitespace()
+                ),
+                None,
+                "{}",
+                text
+            );
+        }
+    }
+}
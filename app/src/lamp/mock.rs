@@ -0,0 +1,277 @@
+use heapless::Deque;
+
+use super::{
+    Colour,
+    Confirmed,
+    LampBackend,
+    LampEvent,
+    LampKind,
+    LampState,
+    MAX_LAMPS,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockError {
+    /// There is no lamp with this index.
+    NoLamp,
+    /// The lamp can't show the colour.
+    Unsupported,
+    /// Set up with [`MockBackend::fail_next`].
+    Failed,
+}
+
+/// Lamps in memory, all off at the start.
+///
+/// By default every request is confirmed right away. With
+/// [`hold`](Self::hold) they wait for
+/// [`confirm`](Self::confirm), like a controller that takes
+/// a while to answer.
+#[derive(Debug, Clone)]
+pub struct MockBackend {
+    confirmed: Confirmed,
+    /// Requests not confirmed yet, while holding.
+    pending: Deque<LampEvent, 16>,
+    hold: bool,
+    fail_next: bool,
+}
+
+impl MockBackend {
+    /// `lamps` dimmable lamps.
+    pub fn new(lamps: usize) -> Self {
+        Self::with_kinds(
+            &[LampKind::Dimmable; MAX_LAMPS]
+                [..lamps.min(MAX_LAMPS)],
+        )
+    }
+
+    /// A lamp of each of `kinds`.
+    pub fn with_kinds(kinds: &[LampKind]) -> Self {
+        Self {
+            confirmed: Confirmed::new(kinds, true),
+            pending: Deque::new(),
+            hold: false,
+            fail_next: false,
+        }
+    }
+
+    /// Whether requests wait for
+    /// [`confirm`](Self::confirm).
+    pub fn hold(&mut self, hold: bool) {
+        self.hold = hold;
+    }
+
+    /// Makes the next request fail.
+    pub fn fail_next(&mut self) {
+        self.fail_next = true;
+    }
+
+    /// Confirms the oldest request that is held back.
+    /// Returns `false` if there is none.
+    pub fn confirm(&mut self) -> bool {
+        match self.pending.pop_front() {
+            Some(LampEvent { lamp, state }) => {
+                self.confirmed.confirm(lamp, state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes `lamp` without a request from the app, like
+    /// a wall switch would.
+    pub fn change(
+        &mut self,
+        lamp: usize,
+        state: LampState,
+    ) {
+        self.confirmed.confirm(lamp, state);
+    }
+
+    /// Applies `change` to what `lamp` will do once all
+    /// requests are confirmed.
+    fn request(
+        &mut self,
+        lamp: usize,
+        change: impl FnOnce(&mut LampState),
+    ) -> Result<(), MockError> {
+        if lamp >= self.confirmed.len() {
+            return Err(MockError::NoLamp);
+        }
+        if core::mem::take(&mut self.fail_next) {
+            return Err(MockError::Failed);
+        }
+        let mut state = self
+            .pending
+            .iter()
+            .rev()
+            .find(|e| e.lamp == lamp)
+            .map(|e| e.state)
+            .or(self.confirmed.get(lamp))
+            .unwrap_or_default();
+        change(&mut state);
+        if self.hold {
+            self.pending
+                .push_back(LampEvent { lamp, state })
+                .map_err(|_| MockError::Failed)
+        } else {
+            self.confirmed.confirm(lamp, state);
+            Ok(())
+        }
+    }
+}
+
+impl LampBackend for MockBackend {
+    type Error = MockError;
+
+    fn lamp_count(&self) -> usize {
+        self.confirmed.len()
+    }
+
+    fn kind(&self, lamp: usize) -> Option<LampKind> {
+        self.confirmed.kind(lamp)
+    }
+
+    fn set_on(
+        &mut self,
+        lamp: usize,
+        on: bool,
+    ) -> Result<(), MockError> {
+        self.request(lamp, |state| state.on = on)
+    }
+
+    fn set_brightness(
+        &mut self,
+        lamp: usize,
+        brightness: u8,
+    ) -> Result<(), MockError> {
+        self.request(lamp, |state| {
+            state.brightness = brightness
+        })
+    }
+
+    fn set_colour(
+        &mut self,
+        lamp: usize,
+        colour: Colour,
+    ) -> Result<(), MockError> {
+        match self.confirmed.kind(lamp) {
+            None => return Err(MockError::NoLamp),
+            Some(kind) if !kind.supports(colour) => {
+                return Err(MockError::Unsupported)
+            }
+            Some(_) => {}
+        }
+        self.request(lamp, |state| {
+            state.colour = Some(colour)
+        })
+    }
+
+    fn state(&self, lamp: usize) -> Option<LampState> {
+        self.confirmed.get(lamp)
+    }
+
+    fn poll(&mut self) -> Option<LampEvent> {
+        self.confirmed.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_confirmed_right_away() {
+        let mut b = MockBackend::new(2);
+        assert_eq!(b.lamp_count(), 2);
+        assert_eq!(b.state(0), Some(LampState::OFF));
+        b.set_on(0, true).unwrap();
+        b.set_brightness(0, 40).unwrap();
+        let state = LampState {
+            on: true,
+            brightness: 40,
+            colour: None,
+        };
+        assert_eq!(b.state(0), Some(state));
+        assert_eq!(b.poll().unwrap().state.brightness, 255);
+        assert_eq!(
+            b.poll(),
+            Some(LampEvent { lamp: 0, state })
+        );
+        assert_eq!(b.poll(), None);
+        assert_eq!(
+            b.set_on(2, true),
+            Err(MockError::NoLamp)
+        );
+    }
+
+    #[test]
+    fn held_requests_wait_for_confirmation() {
+        let mut b = MockBackend::new(1);
+        b.hold(true);
+        b.set_on(0, true).unwrap();
+        b.set_brightness(0, 10).unwrap();
+        assert_eq!(b.state(0), Some(LampState::OFF));
+        assert_eq!(b.poll(), None);
+        assert!(b.confirm());
+        assert!(b.state(0).unwrap().on);
+        assert!(b.confirm());
+        assert_eq!(b.state(0).unwrap().brightness, 10);
+        assert!(!b.confirm());
+    }
+
+    #[test]
+    fn failures_and_outside_changes() {
+        let mut b = MockBackend::new(1);
+        b.fail_next();
+        assert_eq!(
+            b.set_on(0, true),
+            Err(MockError::Failed)
+        );
+        assert_eq!(b.poll(), None);
+        b.set_on(0, true).unwrap();
+        b.poll();
+        let off = LampState {
+            on: false,
+            brightness: 255,
+            colour: None,
+        };
+        b.change(0, off);
+        assert_eq!(
+            b.poll(),
+            Some(LampEvent {
+                lamp: 0,
+                state: off
+            })
+        );
+    }
+
+    #[test]
+    fn colours_follow_the_kinds() {
+        let mut b = MockBackend::with_kinds(&[
+            LampKind::Switch,
+            LampKind::Rgb,
+        ]);
+        assert_eq!(b.kind(1), Some(LampKind::Rgb));
+        assert_eq!(b.kind(2), None);
+        assert_eq!(b.state(0).unwrap().colour, None);
+        let red = Colour::hs(0, 255);
+        assert_eq!(
+            b.set_colour(0, red),
+            Err(MockError::Unsupported)
+        );
+        assert_eq!(
+            b.set_colour(1, Colour::WARM_WHITE),
+            Err(MockError::Unsupported)
+        );
+        assert_eq!(
+            b.set_colour(2, red),
+            Err(MockError::NoLamp)
+        );
+        assert_eq!(b.poll(), None);
+        b.set_colour(1, red).unwrap();
+        assert_eq!(
+            b.poll().unwrap().state.colour,
+            Some(red)
+        );
+    }
+}
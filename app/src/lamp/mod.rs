@@ -0,0 +1,321 @@
+//! The lamps behind the light-control apps, apart from the
+//! UI.
+//!
+//! A [`LampBackend`] switches and dims the lamps, and sets
+//! the [`Colour`] of those whose [`LampKind`] has one. The
+//! apps only ask it to; what they show comes from the
+//! [`LampEvent`]s it reports back once a change is done, so
+//! a request the lamp never carried out doesn't show up as
+//! done, and changes made elsewhere (e.g. with a wall
+//! switch on an external controller) do.
+//!
+//! - [`PwmBackend`] dims PWM channels, e.g. the CYD's RGB
+//!   LED as one colour lamp.
+//! - [`SerialBackend`] talks to an external controller with
+//!   a line-based [protocol](serial).
+//! - [`MockBackend`] keeps the lamps in memory, for tests.
+
+mod colour;
+mod mock;
+mod pwm;
+pub mod serial;
+
+use core::fmt;
+
+pub use colour::{
+    hsv,
+    Colour,
+    LampKind,
+    MAX_KELVIN,
+    MIN_KELVIN,
+};
+use heapless::{Deque, Vec};
+pub use mock::{MockBackend, MockError};
+pub use pwm::{PwmBackend, PwmError};
+pub use serial::{SerialBackend, SerialError};
+
+/// Most lamps a backend keeps track of.
+pub const MAX_LAMPS: usize = 8;
+
+/// What a lamp does. Written as e.g. `ON 80`, or
+/// `ON 80 CT 2700` with a colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LampState {
+    pub on: bool,
+    /// From 0 to 255, also kept while the lamp is off.
+    pub brightness: u8,
+    /// `None` for lamps without a colour, also kept while
+    /// the lamp is off.
+    pub colour: Option<Colour>,
+}
+
+impl LampState {
+    /// Off, for a lamp without a colour.
+    pub const OFF: Self = Self {
+        on: false,
+        brightness: 255,
+        colour: None,
+    };
+
+    /// Off, with the default colour of `kind`.
+    pub fn off(kind: LampKind) -> Self {
+        Self {
+            colour: kind.default_colour(),
+            ..Self::OFF
+        }
+    }
+
+    /// Reads a state written like its [`Display`] output
+    /// from `words`, `None` if it isn't one. Leaves the
+    /// words after it.
+    ///
+    /// [`Display`]: fmt::Display
+    pub(crate) fn from_words<'a>(
+        words: &mut impl Iterator<Item = &'a str>,
+    ) -> Option<Self> {
+        let on = match words.next()? {
+            "ON" => true,
+            "OFF" => false,
+            _ => return None,
+        };
+        let brightness = words.next()?.parse().ok()?;
+        let colour = match words.next() {
+            Some(word) => Some(Colour::from_words(
+                &mut core::iter::once(word).chain(words),
+            )?),
+            None => None,
+        };
+        Some(Self {
+            on,
+            brightness,
+            colour,
+        })
+    }
+}
+
+impl fmt::Display for LampState {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        let on = if self.on { "ON" } else { "OFF" };
+        write!(f, "{} {}", on, self.brightness)?;
+        if let Some(colour) = self.colour {
+            write!(f, " {}", colour)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for LampState {
+    fn default() -> Self {
+        Self::OFF
+    }
+}
+
+/// A lamp changed, at the request of the app or not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LampEvent {
+    /// Index of the lamp, from 0.
+    pub lamp: usize,
+    pub state: LampState,
+}
+
+/// Switches, dims and colours lamps, numbered from 0.
+///
+/// Requests don't block: they are sent off, and [`poll`]
+/// reports the change once the lamp confirms it.
+///
+/// [`poll`]: LampBackend::poll
+pub trait LampBackend {
+    type Error: core::fmt::Debug;
+
+    fn lamp_count(&self) -> usize;
+
+    /// What `lamp` can do, `None` if there is no such
+    /// lamp.
+    fn kind(&self, lamp: usize) -> Option<LampKind>;
+
+    fn set_on(
+        &mut self,
+        lamp: usize,
+        on: bool,
+    ) -> Result<(), Self::Error>;
+
+    fn set_brightness(
+        &mut self,
+        lamp: usize,
+        brightness: u8,
+    ) -> Result<(), Self::Error>;
+
+    /// Fails for a colour the [`LampKind`] of `lamp`
+    /// doesn't [support](LampKind::supports).
+    fn set_colour(
+        &mut self,
+        lamp: usize,
+        colour: Colour,
+    ) -> Result<(), Self::Error>;
+
+    /// The last confirmed state of `lamp`, `None` while it
+    /// isn't known yet.
+    fn state(&self, lamp: usize) -> Option<LampState>;
+
+    /// The next change, oldest first. Call every frame
+    /// until it returns `None`.
+    fn poll(&mut self) -> Option<LampEvent>;
+}
+
+/// The kinds and confirmed states of the lamps of a
+/// backend, and the changes not polled yet.
+#[derive(Debug, Clone)]
+struct Confirmed {
+    kinds: Vec<LampKind, MAX_LAMPS>,
+    states: Vec<Option<LampState>, MAX_LAMPS>,
+    events: Deque<LampEvent, 16>,
+}
+
+impl Confirmed {
+    /// A lamp of each of `kinds`, cut to [`MAX_LAMPS`];
+    /// switched off if `known`, otherwise unknown.
+    fn new(kinds: &[LampKind], known: bool) -> Self {
+        let kinds: Vec<_, MAX_LAMPS> =
+            kinds.iter().copied().take(MAX_LAMPS).collect();
+        let states = kinds
+            .iter()
+            .map(|&kind| {
+                known.then(|| LampState::off(kind))
+            })
+            .collect();
+        Self {
+            kinds,
+            states,
+            events: Deque::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    fn kind(&self, lamp: usize) -> Option<LampKind> {
+        self.kinds.get(lamp).copied()
+    }
+
+    fn get(&self, lamp: usize) -> Option<LampState> {
+        self.states.get(lamp).copied().flatten()
+    }
+
+    /// Records the state `lamp` confirmed, and queues an
+    /// event if it changed. When the queue is full, the
+    /// oldest event is dropped; the newer one is what the
+    /// lamp does now.
+    fn confirm(&mut self, lamp: usize, state: LampState) {
+        let Some(slot) = self.states.get_mut(lamp) else {
+            return;
+        };
+        if *slot == Some(state) {
+            return;
+        }
+        *slot = Some(state);
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        self.events
+            .push_back(LampEvent { lamp, state })
+            .ok();
+    }
+
+    fn poll(&mut self) -> Option<LampEvent> {
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changes_are_reported() {
+        let mut c = Confirmed::new(
+            &[LampKind::Dimmable, LampKind::Rgb],
+            false,
+        );
+        let on = LampState {
+            on: true,
+            brightness: 100,
+            colour: None,
+        };
+        c.confirm(1, on);
+        c.confirm(1, on);
+        c.confirm(5, on);
+        assert_eq!(
+            c.poll(),
+            Some(LampEvent { lamp: 1, state: on })
+        );
+        assert_eq!(c.poll(), None);
+        assert_eq!(c.get(0), None);
+        assert_eq!(c.get(1), Some(on));
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest() {
+        let mut c =
+            Confirmed::new(&[LampKind::Dimmable], false);
+        for brightness in 0..20 {
+            c.confirm(
+                0,
+                LampState {
+                    on: true,
+                    brightness,
+                    colour: None,
+                },
+            );
+        }
+        assert_eq!(c.poll().unwrap().state.brightness, 4);
+        assert_eq!(
+            core::iter::from_fn(|| c.poll()).last(),
+            Some(LampEvent {
+                lamp: 0,
+                state: LampState {
+                    on: true,
+                    brightness: 19,
+                    colour: None
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn states_are_written_as_words() {
+        for text in
+            ["ON 80", "OFF 0 CT 2700", "ON 255 HS 9 1"]
+        {
+            let state = LampState::from_words(
+                &mut text.split_whitespace(),
+            )
+            .unwrap();
+            assert_eq!(state.to_string(), text);
+        }
+        assert_eq!(
+            LampState::off(LampKind::TunableWhite).colour,
+            Some(Colour::WARM_WHITE)
+        );
+        for text in [
+            "",
+            "on 1",
+            "ON 256",
+            "ON",
+            "ON 1 2",
+            "ON 1 CT 1",
+        ] {
+            assert_eq!(
+                LampState::from_words(
+                    &mut text.split_whitespace()
+                ),
+                None,
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use embedded_hal::pwm::SetDutyCycle;
+
+use super::{
+    Colour,
+    Confirmed,
+    LampBackend,
+    LampEvent,
+    LampKind,
+    LampState,
+    MAX_KELVIN,
+    MIN_KELVIN,
+};
+use crate::output::Polarity;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PwmError<E> {
+    /// There is no lamp with this index.
+    NoLamp,
+    /// The lamp can't show the colour.
+    Unsupported,
+    Pwm(E),
+}
+
+/// Lamps on PWM channels, dimmed with the duty cycle. A
+/// switched or dimmable lamp takes one channel, a tunable
+/// white one two (warm, then cold white) and an RGB lamp
+/// three (red, green, blue). The first lamps get the `C`
+/// channels; any more lamps are only kept in memory.
+///
+/// The PWM takes effect at once, so every request is
+/// confirmed as soon as the duty cycle is set.
+#[derive(Debug)]
+pub struct PwmBackend<P, const C: usize> {
+    channels: [P; C],
+    polarity: Polarity,
+    confirmed: Confirmed,
+}
+
+/// How many channels a lamp of `kind` takes.
+fn width(kind: LampKind) -> usize {
+    match kind {
+        LampKind::Switch | LampKind::Dimmable => 1,
+        LampKind::TunableWhite => 2,
+        LampKind::Rgb => 3,
+    }
+}
+
+/// The levels of the channels of a lamp of `kind` in
+/// `state`, from 0 to 255.
+fn levels(kind: LampKind, state: LampState) -> [u8; 3] {
+    let level = match (state.on, kind.is_dimmable()) {
+        (false, _) => 0,
+        (true, true) => state.brightness as u32,
+        (true, false) => 255,
+    };
+    let mix = match state.colour {
+        Some(Colour::Temperature(kelvin)) => {
+            let cold = (kelvin
+                .clamp(MIN_KELVIN, MAX_KELVIN)
+                - MIN_KELVIN) as u32
+                * 255
+                / (MAX_KELVIN - MIN_KELVIN) as u32;
+            [255 - cold, cold, 0]
+        }
+        Some(colour) => colour.to_rgb().map(u32::from),
+        None => [255; 3],
+    };
+    mix.map(|m| ((m * level + 127) / 255) as u8)
+}
+
+impl<P: SetDutyCycle, const C: usize> PwmBackend<P, C> {
+    /// A lamp of each of `kinds`, all switched off.
+    pub fn new(
+        channels: [P; C],
+        polarity: Polarity,
+        kinds: &[LampKind],
+    ) -> Result<Self, P::Error> {
+        let mut backend = Self {
+            channels,
+            polarity,
+            confirmed: Confirmed::new(kinds, true),
+        };
+        for channel in 0..C {
+            backend.set_level(channel, 0)?;
+        }
+        Ok(backend)
+    }
+
+    /// The channels of `lamp`, if it has them.
+    fn channels(
+        &self,
+        lamp: usize,
+    ) -> Option<core::ops::Range<usize>> {
+        let mut first = 0;
+        for i in 0..lamp {
+            first += width(self.confirmed.kind(i)?);
+        }
+        let last =
+            first + width(self.confirmed.kind(lamp)?);
+        (last <= C).then_some(first..last)
+    }
+
+    fn set_level(
+        &mut self,
+        channel: usize,
+        level: u8,
+    ) -> Result<(), P::Error> {
+        let channel = &mut self.channels[channel];
+        let max = channel.max_duty_cycle() as u32;
+        let duty = (max * level as u32 + 127) / 255;
+        let duty = if self.polarity.is_high(true) {
+            duty
+        } else {
+            max - duty
+        };
+        channel.set_duty_cycle(duty as u16)
+    }
+
+    /// Sets the duty cycles of the channels of `lamp`, if
+    /// it has any.
+    fn apply(
+        &mut self,
+        lamp: usize,
+        state: LampState,
+    ) -> Result<(), P::Error> {
+        let (Some(kind), Some(channels)) = (
+            self.confirmed.kind(lamp),
+            self.channels(lamp),
+        ) else {
+            return Ok(());
+        };
+        let levels = levels(kind, state);
+        for (channel, level) in channels.zip(levels) {
+            self.set_level(channel, level)?;
+        }
+        Ok(())
+    }
+
+    fn request(
+        &mut self,
+        lamp: usize,
+        change: impl FnOnce(&mut LampState),
+    ) -> Result<(), PwmError<P::Error>> {
+        let mut state = self
+            .confirmed
+            .get(lamp)
+            .ok_or(PwmError::NoLamp)?;
+        change(&mut state);
+        self.apply(lamp, state).map_err(PwmError::Pwm)?;
+        self.confirmed.confirm(lamp, state);
+        Ok(())
+    }
+}
+
+impl<P: SetDutyCycle, const C: usize> LampBackend
+    for PwmBackend<P, C>
+{
+    type Error = PwmError<P::Error>;
+
+    fn lamp_count(&self) -> usize {
+        self.confirmed.len()
+    }
+
+    fn kind(&self, lamp: usize) -> Option<LampKind> {
+        self.confirmed.kind(lamp)
+    }
+
+    fn set_on(
+        &mut self,
+        lamp: usize,
+        on: bool,
+    ) -> Result<(), Self::Error> {
+        self.request(lamp, |state| state.on = on)
+    }
+
+    fn set_brightness(
+        &mut self,
+        lamp: usize,
+        brightness: u8,
+    ) -> Result<(), Self::Error> {
+        self.request(lamp, |state| {
+            state.brightness = brightness
+        })
+    }
+
+    fn set_colour(
+        &mut self,
+        lamp: usize,
+        colour: Colour,
+    ) -> Result<(), Self::Error> {
+        match self.confirmed.kind(lamp) {
+            None => return Err(PwmError::NoLamp),
+            Some(kind) if !kind.supports(colour) => {
+                return Err(PwmError::Unsupported)
+            }
+            Some(_) => {}
+        }
+        self.request(lamp, |state| {
+            state.colour = Some(colour)
+        })
+    }
+
+    fn state(&self, lamp: usize) -> Option<LampState> {
+        self.confirmed.get(lamp)
+    }
+
+    fn poll(&mut self) -> Option<LampEvent> {
+        self.confirmed.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use embedded_hal::pwm::ErrorType;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct FakePwm {
+        duty: u16,
+    }
+
+    impl ErrorType for FakePwm {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for FakePwm {
+        fn max_duty_cycle(&self) -> u16 {
+            1000
+        }
+
+        fn set_duty_cycle(
+            &mut self,
+            duty: u16,
+        ) -> Result<(), Infallible> {
+            self.duty = duty;
+            Ok(())
+        }
+    }
+
+    fn duties<const C: usize>(
+        b: &PwmBackend<FakePwm, C>,
+    ) -> [u16; C] {
+        core::array::from_fn(|i| b.channels[i].duty)
+    }
+
+    #[test]
+    fn duty_cycle_follows_the_lamps() {
+        let mut b = PwmBackend::new(
+            [FakePwm::default(), FakePwm::default()],
+            Polarity::ActiveHigh,
+            &[LampKind::Dimmable; 3],
+        )
+        .unwrap();
+        assert_eq!(b.lamp_count(), 3);
+        b.set_brightness(0, 51).unwrap();
+        // dimming an off lamp keeps it off
+        assert_eq!(duties(&b), [0, 0]);
+        b.set_on(0, true).unwrap();
+        b.set_on(1, true).unwrap();
+        assert_eq!(duties(&b), [200, 1000]);
+        assert_eq!(
+            b.poll(),
+            Some(LampEvent {
+                lamp: 0,
+                state: LampState {
+                    on: false,
+                    brightness: 51,
+                    colour: None
+                }
+            })
+        );
+        // the lamp without a channel is kept in memory
+        b.set_on(2, true).unwrap();
+        assert!(b.state(2).unwrap().on);
+        assert_eq!(
+            b.set_on(3, true),
+            Err(PwmError::NoLamp)
+        );
+    }
+
+    #[test]
+    fn active_low_channels_are_inverted() {
+        let mut b = PwmBackend::new(
+            [FakePwm::default()],
+            Polarity::ActiveLow,
+            &[LampKind::Dimmable],
+        )
+        .unwrap();
+        // off from the start
+        assert_eq!(duties(&b), [1000]);
+        b.set_on(0, true).unwrap();
+        assert_eq!(duties(&b), [0]);
+        b.set_brightness(0, 0).unwrap();
+        assert_eq!(duties(&b), [1000]);
+    }
+
+    #[test]
+    fn colour_lamps_take_several_channels() {
+        let mut b = PwmBackend::new(
+            core::array::from_fn::<_, 6, _>(|_| {
+                FakePwm::default()
+            }),
+            Polarity::ActiveHigh,
+            &[
+                LampKind::Rgb,
+                LampKind::TunableWhite,
+                LampKind::Switch,
+                LampKind::Dimmable,
+            ],
+        )
+        .unwrap();
+        b.set_on(0, true).unwrap();
+        // white from the start
+        assert_eq!(duties(&b), [1000, 1000, 1000, 0, 0, 0]);
+        b.set_brightness(0, 51).unwrap();
+        b.set_colour(0, Colour::hs(60, 255)).unwrap();
+        assert_eq!(duties(&b), [200, 200, 0, 0, 0, 0]);
+        assert_eq!(
+            b.set_colour(0, Colour::WARM_WHITE),
+            Err(PwmError::Unsupported)
+        );
+
+        b.set_on(1, true).unwrap();
+        b.set_colour(1, Colour::Temperature(MAX_KELVIN))
+            .unwrap();
+        assert_eq!(duties(&b)[3..5], [0, 1000]);
+        b.set_colour(1, Colour::Temperature(4250)).unwrap();
+        assert_eq!(duties(&b)[3..5], [502, 498]);
+
+        // a switch ignores the brightness
+        b.set_brightness(2, 0).unwrap();
+        b.set_on(2, true).unwrap();
+        assert_eq!(duties(&b)[5], 1000);
+        // the last lamp has no channel left
+        b.set_on(3, true).unwrap();
+        assert!(b.state(3).unwrap().on);
+    }
+}
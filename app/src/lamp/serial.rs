@@ -0,0 +1,374 @@
+//! Lamps on an external controller, e.g. a relay board
+//! with its own microcontroller, on a serial line.
+//!
+//! The protocol is plain text, a line per message, with the
+//! lamps numbered from 0. The app sends:
+//!
+//! ```text
+//! SET <n> ON|OFF          switches lamp n
+//! DIM <n> <0-255>         dims lamp n
+//! CT <n> <2000-6500>      sets its white, in kelvin
+//! HS <n> <0-359> <0-255>  sets its hue and saturation
+//! GET <n>                 asks for the state of lamp n
+//! ```
+//!
+//! The controller answers every request with the state of
+//! the lamp, or an error if it couldn't carry it out, and
+//! also sends the state whenever a lamp changes on its own:
+//!
+//! ```text
+//! STATE <n> ON|OFF <0-255> [CT <K> | HS <hue> <sat>]
+//! ERR <n>
+//! ```
+//!
+//! A controller that doesn't know about colours can leave
+//! them out; the lamp then keeps the colour it had.
+//!
+//! The controller should ignore any other lines, so the
+//! protocol can share a line with the serial console.
+
+use core::fmt::Write;
+
+use super::{
+    Colour,
+    Confirmed,
+    LampBackend,
+    LampEvent,
+    LampKind,
+    LampState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    /// There is no lamp with this index.
+    NoLamp,
+    /// The lamp can't show the colour.
+    Unsupported,
+    /// Writing the request failed.
+    Write,
+}
+
+/// A line of the controller that isn't a valid reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError;
+
+/// A line from the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    State(LampEvent),
+    /// The controller couldn't carry out a request for
+    /// this lamp.
+    Error(usize),
+}
+
+impl Reply {
+    /// Parses a line from the controller. Returns `None` if
+    /// it isn't meant for the lamps at all.
+    pub fn parse(
+        line: &str,
+    ) -> Option<Result<Self, ParseError>> {
+        let mut words = line.split_whitespace();
+        let state = match words.next()? {
+            "STATE" => true,
+            "ERR" => false,
+            _ => return None,
+        };
+        let mut parse = || {
+            let lamp = words
+                .next()
+                .and_then(|w| w.parse().ok())
+                .ok_or(ParseError)?;
+            if !state {
+                return Ok(Reply::Error(lamp));
+            }
+            let state = LampState::from_words(&mut words)
+                .ok_or(ParseError)?;
+            Ok(Reply::State(LampEvent { lamp, state }))
+        };
+        let reply = parse();
+        Some(if words.next().is_some() {
+            Err(ParseError)
+        } else {
+            reply
+        })
+    }
+}
+
+/// Lamps on an external controller, which gets its requests
+/// written to `out`. The replies are passed in with
+/// [`receive`](Self::receive), by whoever reads the line.
+///
+/// The states are unknown until the controller sends them;
+/// [`sync`](Self::sync) asks for all of them.
+#[derive(Debug)]
+pub struct SerialBackend<W> {
+    out: W,
+    confirmed: Confirmed,
+}
+
+impl<W: Write> SerialBackend<W> {
+    /// A lamp of each of `kinds` on the controller.
+    pub fn new(out: W, kinds: &[LampKind]) -> Self {
+        Self {
+            out,
+            confirmed: Confirmed::new(kinds, false),
+        }
+    }
+
+    /// Asks for the state of every lamp.
+    pub fn sync(&mut self) -> Result<(), SerialError> {
+        for lamp in 0..self.confirmed.len() {
+            writeln!(self.out, "GET {}", lamp)
+                .map_err(|_| SerialError::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Handles a line from the controller. Returns `None`
+    /// if it isn't meant for the lamps, so it can be
+    /// passed on to e.g. the console commands.
+    pub fn receive(
+        &mut self,
+        line: &str,
+    ) -> Option<Result<Reply, ParseError>> {
+        let reply = Reply::parse(line)?;
+        if let Ok(Reply::State(LampEvent {
+            lamp,
+            mut state,
+        })) = reply
+        {
+            if state.colour.is_none() {
+                state.colour = self
+                    .confirmed
+                    .get(lamp)
+                    .and_then(|s| s.colour)
+                    .or_else(|| {
+                        self.confirmed
+                            .kind(lamp)?
+                            .default_colour()
+                    });
+            }
+            self.confirmed.confirm(lamp, state);
+        }
+        Some(reply)
+    }
+
+    fn check(
+        &self,
+        lamp: usize,
+    ) -> Result<(), SerialError> {
+        if lamp < self.confirmed.len() {
+            Ok(())
+        } else {
+            Err(SerialError::NoLamp)
+        }
+    }
+}
+
+impl<W: Write> LampBackend for SerialBackend<W> {
+    type Error = SerialError;
+
+    fn lamp_count(&self) -> usize {
+        self.confirmed.len()
+    }
+
+    fn kind(&self, lamp: usize) -> Option<LampKind> {
+        self.confirmed.kind(lamp)
+    }
+
+    fn set_on(
+        &mut self,
+        lamp: usize,
+        on: bool,
+    ) -> Result<(), SerialError> {
+        self.check(lamp)?;
+        let on = if on { "ON" } else { "OFF" };
+        writeln!(self.out, "SET {} {}", lamp, on)
+            .map_err(|_| SerialError::Write)
+    }
+
+    fn set_brightness(
+        &mut self,
+        lamp: usize,
+        brightness: u8,
+    ) -> Result<(), SerialError> {
+        self.check(lamp)?;
+        writeln!(self.out, "DIM {} {}", lamp, brightness)
+            .map_err(|_| SerialError::Write)
+    }
+
+    fn set_colour(
+        &mut self,
+        lamp: usize,
+        colour: Colour,
+    ) -> Result<(), SerialError> {
+        self.check(lamp)?;
+        if !self
+            .confirmed
+            .kind(lamp)
+            .is_some_and(|k| k.supports(colour))
+        {
+            return Err(SerialError::Unsupported);
+        }
+        match colour {
+            Colour::Temperature(kelvin) => {
+                writeln!(self.out, "CT {} {}", lamp, kelvin)
+            }
+            Colour::Hs { hue, saturation } => writeln!(
+                self.out,
+                "HS {} {} {}",
+                lamp, hue, saturation
+            ),
+        }
+        .map_err(|_| SerialError::Write)
+    }
+
+    fn state(&self, lamp: usize) -> Option<LampState> {
+        self.confirmed.get(lamp)
+    }
+
+    fn poll(&mut self) -> Option<LampEvent> {
+        self.confirmed.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use super::*;
+
+    fn state(
+        lamp: usize,
+        on: bool,
+        brightness: u8,
+    ) -> Reply {
+        Reply::State(LampEvent {
+            lamp,
+            state: LampState {
+                on,
+                brightness,
+                colour: None,
+            },
+        })
+    }
+
+    #[test]
+    fn parses_replies() {
+        assert_eq!(
+            Reply::parse("STATE 2 ON 128"),
+            Some(Ok(state(2, true, 128)))
+        );
+        assert_eq!(
+            Reply::parse(" STATE 0 OFF 255 "),
+            Some(Ok(state(0, false, 255)))
+        );
+        assert_eq!(
+            Reply::parse("STATE 1 ON 9 HS 120 255"),
+            Some(Ok(Reply::State(LampEvent {
+                lamp: 1,
+                state: LampState {
+                    on: true,
+                    brightness: 9,
+                    colour: Some(Colour::hs(120, 255)),
+                },
+            })))
+        );
+        assert_eq!(
+            Reply::parse("ERR 3"),
+            Some(Ok(Reply::Error(3)))
+        );
+        assert_eq!(Reply::parse("time 12:00"), None);
+        assert_eq!(Reply::parse(""), None);
+        for line in [
+            "STATE",
+            "STATE x ON 1",
+            "STATE 1 on 1",
+            "STATE 1 ON 256",
+            "STATE 1 ON",
+            "STATE 1 ON 1 2",
+            "STATE 1 ON 1 CT 9000",
+            "STATE 1 ON 1 CT 2700 2",
+            "ERR",
+            "ERR 1 2",
+        ] {
+            assert_eq!(
+                Reply::parse(line),
+                Some(Err(ParseError)),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn requests_are_written_as_lines() {
+        let mut b = SerialBackend::new(
+            String::<96>::new(),
+            &[LampKind::Rgb, LampKind::TunableWhite],
+        );
+        b.set_on(1, true).unwrap();
+        b.set_brightness(0, 7).unwrap();
+        b.set_on(0, false).unwrap();
+        b.set_colour(0, Colour::hs(300, 20)).unwrap();
+        b.set_colour(1, Colour::Temperature(4000)).unwrap();
+        assert_eq!(
+            b.set_on(2, true),
+            Err(SerialError::NoLamp)
+        );
+        assert_eq!(
+            b.set_colour(1, Colour::WHITE),
+            Err(SerialError::Unsupported)
+        );
+        b.sync().unwrap();
+        assert_eq!(
+            b.out,
+            "SET 1 ON\nDIM 0 7\nSET 0 OFF\nHS 0 300 20\n\
+             CT 1 4000\nGET 0\nGET 1\n"
+        );
+        // the state only changes with the reply
+        assert_eq!(b.state(1), None);
+        assert_eq!(b.poll(), None);
+    }
+
+    #[test]
+    fn replies_update_the_state() {
+        let mut b = SerialBackend::new(
+            String::<64>::new(),
+            &[LampKind::Dimmable, LampKind::TunableWhite],
+        );
+        assert_eq!(b.receive("help"), None);
+        assert_eq!(
+            b.receive("STATE 1 ON 9"),
+            Some(Ok(state(1, true, 9)))
+        );
+        // without a colour in the reply, the lamp gets
+        // the default one
+        assert_eq!(
+            b.state(1),
+            Some(LampState {
+                on: true,
+                brightness: 9,
+                colour: Some(Colour::WARM_WHITE),
+            })
+        );
+        assert!(b.poll().is_some());
+        assert_eq!(
+            b.receive("ERR 1"),
+            Some(Ok(Reply::Error(1)))
+        );
+        assert_eq!(b.poll(), None);
+        // lamps it doesn't have are ignored
+        b.receive("STATE 5 ON 9");
+        assert_eq!(b.poll(), None);
+        // a full output reports a write error
+        let mut b = SerialBackend::new(
+            String::<8>::new(),
+            &[LampKind::Dimmable],
+        );
+        assert_eq!(
+            b.set_brightness(0, 100),
+            Err(SerialError::Write)
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Touch-to-photon latency distributions.
+//!
+//! The measurement itself is done by the profiler display's
+//! `LatencyTag`: the apps report every touch and every UI
+//! change it causes, and the display stamps the first pixel
+//! written after that. [`LatencyStats`] collects the
+//! results of one app and prints them as a line like
+//!
+//! ```text
+//! latency kolibri/timer: n=20 min=18 p50=25 p90=40 p99=45 max=45 mean=27 ms
+//! ```
+//!
+//! so the logs of the Kolibri, Slint and LVGL versions of
+//! an app can be compared directly. Percentiles are the
+//! upper edge of their [`BUCKET_MS`] wide bucket.
+
+use core::fmt;
+
+/// Width of the histogram buckets.
+pub const BUCKET_MS: u64 = 5;
+/// Number of buckets. Latencies of `BUCKETS * BUCKET_MS`
+/// and more all go into the last one.
+pub const BUCKETS: usize = 100;
+/// Print the distribution after this many measurements.
+pub const REPORT_EVERY: u32 = 10;
+
+/// Histogram of the latencies measured in one app.
+#[derive(Debug, Clone)]
+pub struct LatencyStats {
+    framework: &'static str,
+    app: &'static str,
+    buckets: [u32; BUCKETS],
+    count: u32,
+    sum_ms: u64,
+    min_ms: u64,
+    max_ms: u64,
+}
+
+impl LatencyStats {
+    /// `framework` and `app` name the report, e.g.
+    /// `"kolibri"` and `"timer"`.
+    pub const fn new(
+        framework: &'static str,
+        app: &'static str,
+    ) -> Self {
+        Self {
+            framework,
+            app,
+            buckets: [0; BUCKETS],
+            count: 0,
+            sum_ms: 0,
+            min_ms: u64::MAX,
+            max_ms: 0,
+        }
+    }
+
+    /// Adds a measurement. Returns `true` every
+    /// [`REPORT_EVERY`] measurements, when it is time to
+    /// print the stats.
+    pub fn add(&mut self, latency_ms: u64) -> bool {
+        let bucket = ((latency_ms / BUCKET_MS) as usize)
+            .min(BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += latency_ms;
+        self.min_ms = self.min_ms.min(latency_ms);
+        self.max_ms = self.max_ms.max(latency_ms);
+        self.count % REPORT_EVERY == 0
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The latency `percent` % of the measurements are at
+    /// or below, or `None` without measurements.
+    pub fn percentile(&self, percent: u32) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        // rank of the measurement, rounded up
+        let rank = (self.count as u64
+            * percent.min(100) as u64)
+            .div_ceil(100)
+            .max(1);
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n as u64;
+            if seen >= rank && i < BUCKETS - 1 {
+                let upper = (i as u64 + 1) * BUCKET_MS;
+                return Some(upper.min(self.max_ms));
+            }
+        }
+        // the last bucket has no upper edge
+        Some(self.max_ms)
+    }
+
+    pub fn mean(&self) -> Option<u64> {
+        (self.count > 0)
+            .then(|| self.sum_ms / self.count as u64)
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "latency {}/{}: ",
+            self.framework, self.app
+        )?;
+        if self.count == 0 {
+            return write!(f, "n=0");
+        }
+        let p =
+            |percent| self.percentile(percent).unwrap_or(0);
+        write!(
+            f,
+            "n={} min={} p50={} p90={} p99={} max={} mean={} ms",
+            self.count,
+            self.min_ms,
+            p(50),
+            p(90),
+            p(99),
+            self.max_ms,
+            self.mean().unwrap_or(0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let s = LatencyStats::new("kolibri", "timer");
+        assert_eq!(s.percentile(50), None);
+        assert_eq!(s.mean(), None);
+        assert_eq!(
+            s.to_string(),
+            "latency kolibri/timer: n=0"
+        );
+    }
+
+    #[test]
+    fn percentiles_use_bucket_edges() {
+        let mut s = LatencyStats::new("slint", "timer");
+        for ms in [12, 14, 22, 23, 31, 33, 34, 41, 48, 97] {
+            s.add(ms);
+        }
+        assert_eq!(s.percentile(0), Some(15));
+        assert_eq!(s.percentile(50), Some(35));
+        assert_eq!(s.percentile(90), Some(50));
+        // never more than the largest measurement
+        assert_eq!(s.percentile(100), Some(97));
+        assert_eq!(s.mean(), Some(35));
+        assert_eq!(
+            s.to_string(),
+            "latency slint/timer: n=10 min=12 p50=35 p90=50 p99=97 max=97 mean=35 ms"
+        );
+    }
+
+    #[test]
+    fn slow_frames_go_into_the_last_bucket() {
+        let mut s =
+            LatencyStats::new("lvgl", "light-control");
+        s.add(10);
+        s.add(10_000);
+        assert_eq!(s.percentile(50), Some(15));
+        assert_eq!(s.percentile(99), Some(10_000));
+    }
+
+    #[test]
+    fn reports_every_few_measurements() {
+        let mut s = LatencyStats::new("kolibri", "timer");
+        let reports = (0..25).filter(|_| s.add(20)).count();
+        assert_eq!(reports, 2);
+        assert_eq!(s.count(), 25);
+    }
+}
@@ -1,4 +1,27 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub mod alarm;
+pub mod buttons;
+pub mod buzzer;
+pub mod calibration;
+pub mod console;
+pub mod countdown;
+pub mod crash;
 mod debouncer;
-pub use debouncer::Debouncer;
\ No newline at end of file
+pub mod focus;
+pub mod gesture;
+pub mod intervals;
+pub mod keypad;
+pub mod lamp;
+pub mod latency;
+pub mod lighting;
+pub mod microwave;
+pub mod mqtt;
+pub mod output;
+pub mod stopwatch;
+pub mod storage;
+pub mod timers;
+pub mod touch;
+pub mod wallclock;
+
+pub use debouncer::{Debouncer, Edge, TimedDebouncer};
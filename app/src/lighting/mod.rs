@@ -0,0 +1,424 @@
+//! The lamps of the light-control app, with groups and
+//! scenes.
+//!
+//! A [`Group`] is a few lamps switched together, e.g.
+//! "Downstairs" with the living room and the front door. A
+//! [`Scene`] is a snapshot of how some of the lamps were
+//! switched, dimmed and coloured, to go back to with one
+//! tap. Both refer to the lamps by number (from 0), and are
+//! kept in flash as their lines of text:
+//!
+//! ```text
+//! group Downstairs: 0 1
+//! scene Evening: 0 ON 80, 1 OFF 255, 4 ON 255 CT 2700
+//! ```
+//!
+//! Scenes are captured from the lamps on the
+//! [`SceneEditor`] page or with a [`LightingCommand`] on
+//! the serial console, which also sets the groups:
+//!
+//! ```text
+//! group                        lists groups and scenes
+//! group set Downstairs: 0 1    adds or replaces a group
+//! group delete Downstairs
+//! scene save Evening           captures all lamps
+//! scene save Evening: 0 4      captures lamps 0 and 4
+//! scene set Evening: 0 ON 80, 1 OFF 255
+//! scene delete Evening
+//! ```
+//!
+//! [`Home`] only asks the [`LampBackend`] for changes; what
+//! the lamps do is what the backend confirms.
+//!
+//! Lamps with a colour get it from a colour temperature
+//! slider or a [`HueWheel`] on their page.
+
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+
+use crate::{
+    lamp::{
+        LampBackend,
+        LampEvent,
+        LampKind,
+        LampState,
+        MAX_LAMPS,
+    },
+    timers::{truncated, TimerLabel},
+};
+
+mod screen;
+mod setup;
+mod wheel;
+
+pub use screen::{SceneAction, SceneEditor};
+#[cfg(target_os = "none")]
+pub use setup::stored_setup;
+pub use setup::{
+    Group,
+    LightingCommand,
+    ParseError,
+    Scene,
+    Setup,
+    ALL_LAMPS,
+    LINE_LEN,
+    MAX_GROUPS,
+    MAX_SCENES,
+};
+pub use wheel::{HueWheel, WheelState, WHEEL_SIZE};
+
+/// A lamp as shown, as last confirmed by the lamp backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lamp {
+    /// Cut to [`LABEL_LEN`](crate::timers::LABEL_LEN)
+    /// bytes.
+    pub name: TimerLabel,
+    /// What the app offers for the lamp.
+    pub kind: LampKind,
+    pub state: LampState,
+}
+
+/// The lamps and their groups and scenes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Home {
+    pub lamps: Vec<Lamp, MAX_LAMPS>,
+    pub setup: Setup,
+}
+
+impl Home {
+    /// No lamps, groups or scenes.
+    pub const fn new() -> Self {
+        Self {
+            lamps: Vec::new(),
+            setup: Setup::new(),
+        }
+    }
+
+    /// Adds the next lamp, switched off until the backend
+    /// tells otherwise. Returns `false` if there are
+    /// [`MAX_LAMPS`] already.
+    pub fn add_lamp(
+        &mut self,
+        name: &str,
+        kind: LampKind,
+    ) -> bool {
+        self.lamps
+            .push(Lamp {
+                name: truncated(name),
+                kind,
+                state: LampState::off(kind),
+            })
+            .is_ok()
+    }
+
+    /// The kinds of the lamps, e.g. to set up the backend
+    /// with.
+    pub fn kinds(&self) -> Vec<LampKind, MAX_LAMPS> {
+        self.lamps.iter().map(|l| l.kind).collect()
+    }
+
+    /// Shows a change the lamp backend reported.
+    pub fn apply(&mut self, event: LampEvent) {
+        if let Some(lamp) = self.lamps.get_mut(event.lamp) {
+            lamp.state = event.state;
+        }
+    }
+
+    /// Whether any lamp of `group` is on.
+    pub fn is_on(&self, group: &Group) -> bool {
+        group.lamps().any(|i| {
+            self.lamps.get(i).is_some_and(|l| l.state.on)
+        })
+    }
+
+    /// Asks `lamps` to switch, dim and colour as `scene`
+    /// says. Goes on with the other lamps if one fails, and
+    /// returns the first error.
+    pub fn activate<B: LampBackend>(
+        &self,
+        scene: &Scene,
+        lamps: &mut B,
+    ) -> Result<(), B::Error> {
+        let mut result = Ok(());
+        for (i, state) in scene.states() {
+            result =
+                result.and(self.request(lamps, i, state));
+        }
+        result
+    }
+
+    /// Asks `lamps` to switch every lamp off.
+    pub fn all_off<B: LampBackend>(
+        &self,
+        lamps: &mut B,
+    ) -> Result<(), B::Error> {
+        let mut result = Ok(());
+        for i in 0..self.lamps.len() {
+            result =
+                result.and(self.set_on(lamps, i, false));
+        }
+        result
+    }
+
+    /// Asks `lamps` to switch the lamps of `group`: all off
+    /// if any is on, otherwise all on.
+    pub fn toggle<B: LampBackend>(
+        &self,
+        group: &Group,
+        lamps: &mut B,
+    ) -> Result<(), B::Error> {
+        let on = !self.is_on(group);
+        let mut result = Ok(());
+        for i in group.lamps() {
+            result = result.and(self.set_on(lamps, i, on));
+        }
+        result
+    }
+
+    /// A scene called `name` with the states the lamps in
+    /// `members` (a bit per lamp) have now.
+    pub fn capture(
+        &self,
+        name: &str,
+        members: u8,
+    ) -> Scene {
+        let mut scene = Scene::new(name);
+        for (i, lamp) in self.lamps.iter().enumerate() {
+            if members & 1 << i != 0 {
+                scene.set_state(i, Some(lamp.state));
+            }
+        }
+        scene
+    }
+
+    /// Captures all lamps in a new scene, called `Scene n`
+    /// with the lowest `n` that isn't taken. Returns its
+    /// index, or `None` if there are [`MAX_SCENES`]
+    /// already.
+    pub fn add_scene(&mut self) -> Option<usize> {
+        if self.setup.scenes.is_full() {
+            return None;
+        }
+        let mut name = String::<16>::new();
+        for n in 1.. {
+            name.clear();
+            // can't fail, `n` is at most `MAX_SCENES + 1`
+            write!(name, "Scene {}", n).ok();
+            if self.setup.scene(&name).is_none() {
+                break;
+            }
+        }
+        let scene = self.capture(&name, ALL_LAMPS);
+        self.setup.scenes.push(scene).ok()?;
+        Some(self.setup.scenes.len() - 1)
+    }
+
+    fn set_on<B: LampBackend>(
+        &self,
+        lamps: &mut B,
+        i: usize,
+        on: bool,
+    ) -> Result<(), B::Error> {
+        match self.lamps.get(i) {
+            Some(lamp) if lamp.state.on != on => {
+                lamps.set_on(i, on)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Asks for what differs from the state lamp `i` has.
+    /// A lamp that goes on is dimmed and coloured first, so
+    /// it doesn't flash up as it was.
+    fn request<B: LampBackend>(
+        &self,
+        lamps: &mut B,
+        i: usize,
+        state: LampState,
+    ) -> Result<(), B::Error> {
+        let Some(lamp) = self.lamps.get(i) else {
+            return Ok(());
+        };
+        let dim = |lamps: &mut B| {
+            if lamp.state.brightness != state.brightness {
+                lamps
+                    .set_brightness(i, state.brightness)?;
+            }
+            match state.colour {
+                Some(colour)
+                    if lamp.state.colour
+                        != Some(colour) =>
+                {
+                    lamps.set_colour(i, colour)
+                }
+                _ => Ok(()),
+            }
+        };
+        if state.on {
+            dim(lamps)?;
+        }
+        self.set_on(lamps, i, state.on)?;
+        if !state.on {
+            dim(lamps)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lamp::{Colour, MockBackend, MockError};
+
+    fn home() -> (Home, MockBackend) {
+        let mut home = Home::new();
+        let kinds = [
+            LampKind::Dimmable,
+            LampKind::Dimmable,
+            LampKind::Rgb,
+        ];
+        for (name, kind) in
+            ["Front Door", "Living Room", "Porch"]
+                .into_iter()
+                .zip(kinds)
+        {
+            assert!(home.add_lamp(name, kind));
+        }
+        assert_eq!(home.kinds(), kinds);
+        (home, MockBackend::with_kinds(&kinds))
+    }
+
+    fn sync(home: &mut Home, lamps: &mut MockBackend) {
+        while let Some(event) = lamps.poll() {
+            home.apply(event);
+        }
+    }
+
+    fn on(brightness: u8) -> LampState {
+        LampState {
+            on: true,
+            brightness,
+            colour: None,
+        }
+    }
+
+    #[test]
+    fn scenes_are_captured_and_restored() {
+        let (mut home, mut lamps) = home();
+        lamps.set_on(0, true).unwrap();
+        lamps.set_brightness(0, 80).unwrap();
+        lamps.set_on(2, true).unwrap();
+        let red = Colour::hs(0, 255);
+        lamps.set_colour(2, red).unwrap();
+        sync(&mut home, &mut lamps);
+        let evening = home.capture("Evening", 0b101);
+        assert_eq!(
+            evening.to_string(),
+            "Evening: 0 ON 80, 2 ON 255 HS 0 255"
+        );
+
+        home.all_off(&mut lamps).unwrap();
+        sync(&mut home, &mut lamps);
+        assert!(home.lamps.iter().all(|l| !l.state.on));
+        // the brightness stays
+        assert_eq!(home.lamps[0].state.brightness, 80);
+
+        lamps.set_on(1, true).unwrap();
+        lamps.set_colour(2, Colour::WHITE).unwrap();
+        sync(&mut home, &mut lamps);
+        home.activate(&evening, &mut lamps).unwrap();
+        sync(&mut home, &mut lamps);
+        let states: std::vec::Vec<_> =
+            home.lamps.iter().map(|l| l.state).collect();
+        // lamps outside the scene stay as they are
+        assert_eq!(
+            states,
+            [
+                on(80),
+                on(255),
+                LampState {
+                    colour: Some(red),
+                    ..on(255)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn only_changes_are_requested() {
+        let (mut home, mut lamps) = home();
+        let scene = home.capture("Off", ALL_LAMPS);
+        lamps.hold(true);
+        // everything is off already
+        home.activate(&scene, &mut lamps).unwrap();
+        assert!(!lamps.confirm());
+
+        // a brightness and a switch, and a colour; there
+        // is no lamp 7
+        let scene = Scene::parse(
+            "Dim: 0 ON 10, 2 OFF 255 HS 0 0, 7 ON 10",
+        )
+        .unwrap();
+        home.activate(&scene, &mut lamps).unwrap();
+        assert!(lamps.confirm() && lamps.confirm());
+        assert!(!lamps.confirm());
+        let scene = Scene::parse("Red: 2 OFF 255 HS 0 255")
+            .unwrap();
+        home.activate(&scene, &mut lamps).unwrap();
+        assert!(lamps.confirm() && !lamps.confirm());
+        lamps.hold(false);
+        sync(&mut home, &mut lamps);
+        assert_eq!(home.lamps[0].state, on(10));
+
+        // the others are still asked for after a failure
+        let scene =
+            Scene::parse("Both: 0 OFF 10, 2 ON 255")
+                .unwrap();
+        lamps.fail_next();
+        assert_eq!(
+            home.activate(&scene, &mut lamps),
+            Err(MockError::Failed)
+        );
+        sync(&mut home, &mut lamps);
+        assert!(home.lamps[0].state.on);
+        assert!(home.lamps[2].state.on);
+    }
+
+    #[test]
+    fn groups_switch_together() {
+        let (mut home, mut lamps) = home();
+        let downstairs =
+            Group::parse("Downstairs: 0 1").unwrap();
+        assert!(!home.is_on(&downstairs));
+        lamps.set_on(1, true).unwrap();
+        sync(&mut home, &mut lamps);
+        assert!(home.is_on(&downstairs));
+        // one lamp on: all off
+        home.toggle(&downstairs, &mut lamps).unwrap();
+        sync(&mut home, &mut lamps);
+        assert!(!home.is_on(&downstairs));
+        home.toggle(&downstairs, &mut lamps).unwrap();
+        sync(&mut home, &mut lamps);
+        assert!(home.lamps[0].state.on);
+        assert!(home.lamps[1].state.on);
+        assert!(!home.lamps[2].state.on);
+    }
+
+    #[test]
+    fn new_scenes_get_free_names() {
+        let (mut home, _) = home();
+        assert_eq!(home.add_scene(), Some(0));
+        home.setup
+            .scenes
+            .push(Scene::new("Scene 3"))
+            .unwrap();
+        assert_eq!(home.add_scene(), Some(2));
+        assert_eq!(home.setup.scenes[2].name(), "Scene 2");
+        assert_eq!(home.setup.scenes[2].members(), 0b111);
+        while home.setup.scenes.len() < MAX_SCENES {
+            home.add_scene().unwrap();
+        }
+        assert_eq!(home.add_scene(), None);
+    }
+}
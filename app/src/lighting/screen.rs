@@ -0,0 +1,169 @@
+use embedded_graphics::{
+    mono_font::ascii,
+    pixelcolor::Rgb565,
+    prelude::*,
+};
+use kolibri_embedded_gui::{
+    button::Button,
+    checkbox::Checkbox,
+    label::Label,
+    smartstate::SmartstateProvider,
+    spacer::Spacer,
+    ui::Ui,
+};
+
+use super::Home;
+use crate::focus::Focus;
+
+/// What the user picked on the [`SceneEditor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneAction {
+    /// Closes the page and keeps the scene as it was.
+    Back,
+    /// Replaces the scene with how the lamps in `members`
+    /// (a bit per lamp) are now.
+    Capture {
+        members: u8,
+    },
+    Delete,
+}
+
+/// Kolibri page to edit a scene: pick the lamps it sets
+/// and capture how they are now. Renaming is left to the
+/// serial console.
+#[derive(Debug, Clone)]
+pub struct SceneEditor {
+    scene: usize,
+    members: u8,
+}
+
+impl SceneEditor {
+    /// Starts with the lamps scene `scene` sets now.
+    pub fn new(home: &Home, scene: usize) -> Self {
+        let members = home
+            .setup
+            .scenes
+            .get(scene)
+            .map_or(0, |s| s.members());
+        Self { scene, members }
+    }
+
+    /// The index of the scene.
+    pub fn scene(&self) -> usize {
+        self.scene
+    }
+
+    /// Draws the page. Returns the button the user pressed
+    /// this frame, if it closes the page.
+    pub fn show<
+        D: DrawTarget<Color = Rgb565>,
+        const N: usize,
+    >(
+        &mut self,
+        ui: &mut Ui<D, Rgb565>,
+        sm: &mut SmartstateProvider<N>,
+        focus: &mut Focus,
+        home: &Home,
+    ) -> Option<SceneAction> {
+        let Some(scene) = home.setup.scenes.get(self.scene)
+        else {
+            return Some(SceneAction::Back);
+        };
+        ui.add(Spacer::new(Size::new(0, 10)));
+        ui.add_centered(
+            Label::new(scene.name())
+                .smartstate(sm.next())
+                .with_font(ascii::FONT_10X20),
+        );
+        ui.add_centered(
+            Label::new("lamps in the scene:")
+                .smartstate(sm.next())
+                .with_font(ascii::FONT_6X10),
+        );
+        ui.add(Spacer::new(Size::new(0, 10)));
+
+        let before = self.members;
+        for (i, lamp) in home.lamps.iter().enumerate() {
+            let mut member = self.members & 1 << i != 0;
+            ui.add_horizontal(Spacer::new(Size::new(
+                20, 0,
+            )));
+            focus.track(
+                ui.add_horizontal(
+                    Checkbox::new(&mut member)
+                        .smartstate(sm.next()),
+                ),
+            );
+            ui.add_horizontal(
+                Label::new(&lamp.name)
+                    .smartstate(sm.next()),
+            );
+            if member {
+                self.members |= 1 << i;
+            } else {
+                self.members &= !(1 << i);
+            }
+            // two lamps per row
+            if i % 2 == 1 {
+                ui.new_row();
+            } else {
+                let width = 9 * lamp.name.len() as u32;
+                ui.add_horizontal(Spacer::new(Size::new(
+                    150u32.saturating_sub(width),
+                    0,
+                )));
+            }
+        }
+        ui.new_row();
+        if self.members != before {
+            // the checkbox is drawn already, show the
+            // new state next frame
+            sm.force_redraw_all();
+        }
+
+        let mut action = None;
+        ui.add(Spacer::new(Size::new(0, 10)));
+        ui.add_horizontal(Spacer::new(Size::new(30, 0)));
+        if focus
+            .track(ui.add_horizontal(
+                Button::new("Back").smartstate(sm.next()),
+            ))
+            .clicked()
+        {
+            action = Some(SceneAction::Back);
+        }
+        ui.add_horizontal(Spacer::new(Size::new(10, 0)));
+        if focus
+            .track(
+                ui.add_horizontal(
+                    Button::new("Capture")
+                        .smartstate(sm.next()),
+                ),
+            )
+            .clicked()
+        {
+            action = Some(SceneAction::Capture {
+                members: self.members,
+            });
+        }
+        ui.add_horizontal(Spacer::new(Size::new(10, 0)));
+        if focus
+            .track(ui.add(
+                Button::new("Delete").smartstate(sm.next()),
+            ))
+            .clicked()
+        {
+            action = Some(SceneAction::Delete);
+        }
+
+        ui.add(Spacer::new(Size::new(0, 10)));
+        ui.add_centered(
+            Label::new(
+                "Capture saves how these lamps are now",
+            )
+            .smartstate(sm.next())
+            .with_font(ascii::FONT_6X10),
+        );
+        action
+    }
+}
@@ -0,0 +1,763 @@
+use core::fmt;
+
+use heapless::Vec;
+
+use crate::{
+    lamp::{LampState, MAX_LAMPS},
+    storage::{Slot, TextRecord},
+    timers::{truncated, TimerLabel},
+};
+
+/// Most groups, as many as fit into a row on the home page.
+pub const MAX_GROUPS: usize = 3;
+/// Most scenes, as many as fit into a row on the home page.
+pub const MAX_SCENES: usize = 3;
+/// Longest line a group or scene can be written as,
+/// including `group ` or `scene `.
+pub const LINE_LEN: usize = 200;
+/// The members of a scene that captures every lamp.
+pub const ALL_LAMPS: u8 = u8::MAX;
+
+/// Why a line is not a [`Group`] or [`Scene`], or not a
+/// [`LightingCommand`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// No name before the colon.
+    Name,
+    /// A lamp that isn't a number below
+    /// [`MAX_LAMPS`], or is there twice.
+    Lamp,
+    /// A lamp state that isn't `ON` or `OFF` and a
+    /// brightness of 0 to 255, maybe with a colour.
+    State,
+    /// A `group` or `scene` command that doesn't exist.
+    Command,
+}
+
+/// `NAME: rest`, with the name cut to
+/// [`LABEL_LEN`](crate::timers::LABEL_LEN) bytes.
+fn split_name(
+    line: &str,
+) -> Result<(TimerLabel, &str), ParseError> {
+    let (name, rest) =
+        line.split_once(':').ok_or(ParseError::Name)?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ParseError::Name);
+    }
+    // no space left at the end where the name is cut
+    Ok((truncated(truncated(name).trim_end()), rest.trim()))
+}
+
+/// The bit of lamp `text` in a set of lamps, if it is a
+/// lamp that isn't in `lamps` yet.
+fn lamp_bit(
+    text: &str,
+    lamps: u8,
+) -> Result<u8, ParseError> {
+    let lamp = text
+        .parse::<usize>()
+        .ok()
+        .filter(|&l| l < MAX_LAMPS)
+        .ok_or(ParseError::Lamp)?;
+    let bit = 1 << lamp;
+    if lamps & bit != 0 {
+        return Err(ParseError::Lamp);
+    }
+    Ok(bit)
+}
+
+/// The lamps of a set, a bit per lamp.
+fn bits(lamps: u8) -> impl Iterator<Item = usize> {
+    (0..MAX_LAMPS).filter(move |i| lamps & 1 << i != 0)
+}
+
+/// Lamps that are switched together. Written and stored
+/// as one line of text, with the lamps by number:
+///
+/// ```text
+/// Downstairs: 0 1
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    name: TimerLabel,
+    /// A bit per lamp.
+    lamps: u8,
+}
+
+impl Group {
+    /// Reads a group written like its [`Display`] output.
+    ///
+    /// [`Display`]: fmt::Display
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let (name, rest) = split_name(line)?;
+        let mut lamps = 0;
+        for lamp in rest.split_whitespace() {
+            lamps |= lamp_bit(lamp, lamps)?;
+        }
+        Ok(Self { name, lamps })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn lamps(&self) -> impl Iterator<Item = usize> {
+        bits(self.lamps)
+    }
+}
+
+impl fmt::Display for Group {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{}:", self.name)?;
+        for lamp in self.lamps() {
+            write!(f, " {}", lamp)?;
+        }
+        Ok(())
+    }
+}
+
+/// How some of the lamps are switched, dimmed and
+/// coloured. Written and stored as one line of text, with
+/// the lamps by number and their [`LampState`]s:
+///
+/// ```text
+/// Evening: 0 ON 80, 1 OFF 255, 2 ON 200 CT 2700
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scene {
+    name: TimerLabel,
+    /// `None` for the lamps the scene leaves alone.
+    states: [Option<LampState>; MAX_LAMPS],
+}
+
+impl Scene {
+    /// A scene that leaves all lamps alone.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: truncated(name),
+            states: [None; MAX_LAMPS],
+        }
+    }
+
+    /// Reads a scene written like its [`Display`] output.
+    ///
+    /// [`Display`]: fmt::Display
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let (name, rest) = split_name(line)?;
+        let mut scene = Self {
+            name,
+            states: [None; MAX_LAMPS],
+        };
+        let mut lamps = 0;
+        for lamp in rest.split(',').map(str::trim) {
+            if lamp.is_empty() && rest.is_empty() {
+                break;
+            }
+            let mut words = lamp.split_whitespace();
+            let bit = lamp_bit(
+                words.next().unwrap_or(""),
+                lamps,
+            )?;
+            lamps |= bit;
+            let state = LampState::from_words(&mut words)
+                .ok_or(ParseError::State)?;
+            if words.next().is_some() {
+                return Err(ParseError::State);
+            }
+            scene.states[bit.trailing_zeros() as usize] =
+                Some(state);
+        }
+        Ok(scene)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What the scene does with `lamp`, if anything.
+    pub fn state(&self, lamp: usize) -> Option<LampState> {
+        self.states.get(lamp).copied().flatten()
+    }
+
+    /// Sets what the scene does with `lamp`, or leaves it
+    /// alone with `None`.
+    pub fn set_state(
+        &mut self,
+        lamp: usize,
+        state: Option<LampState>,
+    ) {
+        if let Some(s) = self.states.get_mut(lamp) {
+            *s = state;
+        }
+    }
+
+    /// The lamps the scene sets, with their states.
+    pub fn states(
+        &self,
+    ) -> impl Iterator<Item = (usize, LampState)> + '_ {
+        self.states
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.map(|s| (i, s)))
+    }
+
+    /// The lamps the scene sets, a bit per lamp.
+    pub fn members(&self) -> u8 {
+        self.states().fold(0, |m, (i, _)| m | 1 << i)
+    }
+}
+
+impl fmt::Display for Scene {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{}:", self.name)?;
+        for (n, (lamp, state)) in self.states().enumerate()
+        {
+            let sep = if n == 0 { "" } else { "," };
+            write!(f, "{} {} {}", sep, lamp, state)?;
+        }
+        Ok(())
+    }
+}
+
+/// The groups and scenes, kept in flash as their lines of
+/// text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Setup {
+    pub groups: Vec<Group, MAX_GROUPS>,
+    pub scenes: Vec<Scene, MAX_SCENES>,
+}
+
+impl Setup {
+    pub const ENCODED_LEN: usize =
+        1 + (MAX_GROUPS + MAX_SCENES) * (LINE_LEN + 1);
+
+    /// No groups or scenes.
+    pub const fn new() -> Self {
+        Self {
+            groups: Vec::new(),
+            scenes: Vec::new(),
+        }
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.name() == name)
+    }
+
+    pub fn scene(&self, name: &str) -> Option<&Scene> {
+        self.scenes.iter().find(|s| s.name() == name)
+    }
+
+    /// Adds `group`, or replaces the one with the same
+    /// name. Returns `false` if there are [`MAX_GROUPS`]
+    /// already.
+    pub fn set_group(&mut self, group: Group) -> bool {
+        match self
+            .groups
+            .iter_mut()
+            .find(|g| g.name == group.name)
+        {
+            Some(old) => *old = group,
+            None => return self.groups.push(group).is_ok(),
+        }
+        true
+    }
+
+    /// Adds `scene`, or replaces the one with the same
+    /// name. Returns `false` if there are [`MAX_SCENES`]
+    /// already.
+    pub fn set_scene(&mut self, scene: Scene) -> bool {
+        match self
+            .scenes
+            .iter_mut()
+            .find(|s| s.name == scene.name)
+        {
+            Some(old) => *old = scene,
+            None => return self.scenes.push(scene).is_ok(),
+        }
+        true
+    }
+
+    /// Deletes the group called `name`. Returns whether
+    /// there was one.
+    pub fn remove_group(&mut self, name: &str) -> bool {
+        let len = self.groups.len();
+        self.groups.retain(|g| g.name() != name);
+        self.groups.len() != len
+    }
+
+    /// Deletes the scene called `name`. Returns whether
+    /// there was one.
+    pub fn remove_scene(&mut self, name: &str) -> bool {
+        let len = self.scenes.len();
+        self.scenes.retain(|s| s.name() != name);
+        self.scenes.len() != len
+    }
+}
+
+impl TextRecord<{ Setup::ENCODED_LEN }> for Setup {
+    const SLOT: Slot = Slot::Lighting;
+    const FORMAT_VERSION: u8 = 1;
+
+    fn write_lines(
+        &self,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        write!(out, "{}", self)
+    }
+
+    /// Rejects lines that aren't groups or scenes.
+    fn from_lines<'a>(
+        lines: impl Iterator<Item = &'a str>,
+    ) -> Option<Self> {
+        let mut setup = Self::new();
+        for line in lines {
+            if let Some(group) = line.strip_prefix("group ")
+            {
+                let group = Group::parse(group).ok()?;
+                setup.groups.push(group).ok()?;
+            } else if let Some(scene) =
+                line.strip_prefix("scene ")
+            {
+                let scene = Scene::parse(scene).ok()?;
+                setup.scenes.push(scene).ok()?;
+            } else {
+                return None;
+            }
+        }
+        Some(setup)
+    }
+}
+
+/// One line per group, then one per scene, as they are
+/// stored.
+impl fmt::Display for Setup {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        for group in &self.groups {
+            writeln!(f, "group {}", group)?;
+        }
+        for scene in &self.scenes {
+            writeln!(f, "scene {}", scene)?;
+        }
+        Ok(())
+    }
+}
+
+/// Loads the stored groups and scenes, or none if none are
+/// stored.
+#[cfg(target_os = "none")]
+pub fn stored_setup() -> Setup {
+    Setup::stored().unwrap_or_default()
+}
+
+#[cfg(target_os = "none")]
+impl super::Home {
+    /// Runs `line` from the serial console if it is a
+    /// [`LightingCommand`], stores the groups and scenes if
+    /// they changed and prints the result. Returns whether
+    /// it was one.
+    pub fn run_command(&mut self, line: &str) -> bool {
+        use esp_println::{print, println};
+
+        let done = match LightingCommand::parse(line) {
+            None => return false,
+            Some(Ok(LightingCommand::List)) => Ok(false),
+            Some(Ok(LightingCommand::SetGroup(group))) => {
+                if self.setup.set_group(group) {
+                    Ok(true)
+                } else {
+                    Err("there are too many groups")
+                }
+            }
+            Some(Ok(LightingCommand::DeleteGroup(
+                name,
+            ))) => {
+                if self.setup.remove_group(&name) {
+                    Ok(true)
+                } else {
+                    Err("there is no such group")
+                }
+            }
+            Some(Ok(LightingCommand::SaveScene {
+                name,
+                lamps,
+            })) => {
+                let scene = self.capture(&name, lamps);
+                if self.setup.set_scene(scene) {
+                    Ok(true)
+                } else {
+                    Err("there are too many scenes")
+                }
+            }
+            Some(Ok(LightingCommand::SetScene(scene))) => {
+                if self.setup.set_scene(scene) {
+                    Ok(true)
+                } else {
+                    Err("there are too many scenes")
+                }
+            }
+            Some(Ok(LightingCommand::DeleteScene(
+                name,
+            ))) => {
+                if self.setup.remove_scene(&name) {
+                    Ok(true)
+                } else {
+                    Err("there is no such scene")
+                }
+            }
+            Some(Err(e)) => {
+                println!("lighting: {:?}", e);
+                println!(
+                    "usage: group [set NAME: 0 1 ...] \
+                     [delete NAME]"
+                );
+                println!(
+                    "usage: scene [save NAME[: 0 1 ...]] \
+                     [set NAME: 0 ON 255, ...] [delete NAME]"
+                );
+                return true;
+            }
+        };
+        match done {
+            Ok(true) => {
+                self.setup
+                    .save(&mut crate::storage::open())
+                    .ok();
+            }
+            Ok(false) => {}
+            Err(e) => {
+                println!("lighting: {}", e);
+                return true;
+            }
+        }
+        print!("{}", self.setup);
+        true
+    }
+}
+
+/// What can be typed on the serial console.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightingCommand {
+    /// `group` or `scene`
+    List,
+    /// `group set Downstairs: 0 1`
+    SetGroup(Group),
+    /// `group delete Downstairs`
+    DeleteGroup(TimerLabel),
+    /// `scene save Evening` for all lamps, or
+    /// `scene save Evening: 0 4` for some
+    SaveScene {
+        name: TimerLabel,
+        /// A bit per lamp.
+        lamps: u8,
+    },
+    /// `scene set Evening: 0 ON 80, 1 OFF 255`
+    SetScene(Scene),
+    /// `scene delete Evening`
+    DeleteScene(TimerLabel),
+}
+
+impl LightingCommand {
+    /// Longest command, `scene set ` and a scene that
+    /// takes [`LINE_LEN`] written as `scene ...`.
+    pub const LINE_LEN: usize = LINE_LEN + "set ".len();
+
+    /// `None` if `line` is no `group` or `scene` command
+    /// at all.
+    pub fn parse(
+        line: &str,
+    ) -> Option<Result<Self, ParseError>> {
+        let line = line.trim();
+        let (word, rest) =
+            line.split_once(' ').unwrap_or((line, ""));
+        let group = match word {
+            "group" => true,
+            "scene" => false,
+            _ => return None,
+        };
+        let rest = rest.trim_start();
+        let (command, arg) =
+            rest.split_once(' ').unwrap_or((rest, ""));
+        let arg = arg.trim();
+        let name = || match arg {
+            "" => Err(ParseError::Name),
+            name => Ok(truncated(name)),
+        };
+        Some(match (group, command) {
+            (_, "") => Ok(Self::List),
+            (true, "set") => {
+                Group::parse(arg).map(Self::SetGroup)
+            }
+            (true, "delete") => {
+                name().map(Self::DeleteGroup)
+            }
+            (false, "save") if !arg.contains(':') => name()
+                .map(|name| Self::SaveScene {
+                    name,
+                    lamps: ALL_LAMPS,
+                }),
+            (false, "save") => {
+                Group::parse(arg).map(|g| Self::SaveScene {
+                    name: g.name,
+                    lamps: g.lamps,
+                })
+            }
+            (false, "set") => {
+                Scene::parse(arg).map(Self::SetScene)
+            }
+            (false, "delete") => {
+                name().map(Self::DeleteScene)
+            }
+            _ => Err(ParseError::Command),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{tests::MockFlash, RecordStore};
+
+    #[test]
+    fn groups_and_scenes_round_trip() {
+        for line in [
+            "Downstairs: 0 1",
+            "Nothing:",
+            "Everything: 0 1 2 3 4 5 6 7",
+        ] {
+            assert_eq!(
+                Group::parse(line).unwrap().to_string(),
+                line
+            );
+        }
+        for line in [
+            "Evening: 0 ON 80, 1 OFF 255",
+            "Night: 7 OFF 0",
+            "Party: 1 ON 80 HS 300 255, 2 OFF 9 CT 6500",
+            "Empty:",
+        ] {
+            assert_eq!(
+                Scene::parse(line).unwrap().to_string(),
+                line
+            );
+        }
+        // written in any order and spacing, cut to length
+        let group =
+            Group::parse(" A very long name : 3  1 ")
+                .unwrap();
+        assert_eq!(group.to_string(), "A very long: 1 3");
+        let scene =
+            Scene::parse("Late:4 ON 9 ,2 OFF 0").unwrap();
+        assert_eq!(
+            scene.to_string(),
+            "Late: 2 OFF 0, 4 ON 9"
+        );
+        assert_eq!(scene.members(), 0b10100);
+        assert_eq!(
+            scene.state(4),
+            Some(LampState {
+                on: true,
+                brightness: 9,
+                colour: None
+            })
+        );
+        assert_eq!(scene.state(0), None);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        for (line, error) in [
+            ("Downstairs 0 1", ParseError::Name),
+            (" : 0 1", ParseError::Name),
+            ("Downstairs: 0 8", ParseError::Lamp),
+            ("Downstairs: 0 0", ParseError::Lamp),
+            ("Downstairs: x", ParseError::Lamp),
+        ] {
+            assert_eq!(
+                Group::parse(line),
+                Err(error),
+                "{}",
+                line
+            );
+        }
+        for (line, error) in [
+            ("Evening 0 ON 1", ParseError::Name),
+            ("Evening: 0 on 1", ParseError::State),
+            ("Evening: 0 ON 256", ParseError::State),
+            ("Evening: 0 ON", ParseError::State),
+            ("Evening: 0 ON 1 2", ParseError::State),
+            ("Evening: 0 ON 1 CT", ParseError::State),
+            ("Evening: 0 ON 1 HS 1 1 1", ParseError::State),
+            ("Evening: 0 ON 1,", ParseError::Lamp),
+            ("Evening: 0 ON 1, 0 OFF 1", ParseError::Lamp),
+            ("Evening: 9 ON 1", ParseError::Lamp),
+        ] {
+            assert_eq!(
+                Scene::parse(line),
+                Err(error),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn setup_replaces_by_name() {
+        let mut setup = Setup::new();
+        for n in 0..MAX_GROUPS {
+            let group = Group::parse(&std::format!(
+                "G{}: {}", n, n
+            ))
+            .unwrap();
+            assert!(setup.set_group(group));
+        }
+        assert!(!setup
+            .set_group(Group::parse("More: 1").unwrap()));
+        assert!(setup
+            .set_group(Group::parse("G1: 5 6").unwrap()));
+        assert_eq!(
+            setup.group("G1").unwrap().to_string(),
+            "G1: 5 6"
+        );
+        assert!(setup.remove_group("G1"));
+        assert!(!setup.remove_group("G1"));
+        assert!(setup.set_scene(Scene::new("S")));
+        assert!(setup
+            .set_scene(Scene::parse("S: 1 ON 1").unwrap()));
+        assert_eq!(setup.scenes.len(), 1);
+        assert!(setup.remove_scene("S"));
+        assert!(setup.scene("S").is_none());
+    }
+
+    #[test]
+    fn setup_survives_a_reboot() {
+        let mut setup = Setup::new();
+        setup
+            .groups
+            .push(Group::parse("Downstairs: 0 1").unwrap())
+            .unwrap();
+        // the longest lines there are
+        for n in 0..MAX_SCENES {
+            let mut line =
+                std::format!("Longest name {}:", n);
+            for lamp in 0..MAX_LAMPS {
+                let sep = if lamp == 0 { "" } else { "," };
+                line += &std::format!(
+                    "{} {} OFF 255 HS 359 255",
+                    sep,
+                    lamp
+                );
+            }
+            assert!(line.len() + 6 <= LINE_LEN);
+            setup
+                .scenes
+                .push(Scene::parse(&line).unwrap())
+                .unwrap();
+        }
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(Setup::load(&mut store), Ok(None));
+        setup.save(&mut store).unwrap();
+        assert_eq!(
+            Setup::load(&mut store),
+            Ok(Some(setup.clone()))
+        );
+        assert!(setup.to_string().starts_with(
+            "group Downstairs: 0 1\nscene Longest name: 0 OFF 255 HS"
+        ));
+
+        let mut bytes = setup.to_bytes().unwrap();
+        bytes[0] = 2;
+        assert_eq!(Setup::from_bytes(&bytes), None);
+        assert_eq!(Setup::from_bytes(b"\x01lamp 1"), None);
+        assert_eq!(
+            Setup::from_bytes(b"\x01"),
+            Some(Setup::new())
+        );
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(LightingCommand::parse("time"), None);
+        assert_eq!(LightingCommand::parse("groups"), None);
+        assert_eq!(
+            LightingCommand::parse(" group "),
+            Some(Ok(LightingCommand::List))
+        );
+        assert_eq!(
+            LightingCommand::parse("scene"),
+            Some(Ok(LightingCommand::List))
+        );
+        assert_eq!(
+            LightingCommand::parse(
+                "group set Downstairs: 1 0"
+            ),
+            Some(Ok(LightingCommand::SetGroup(
+                Group::parse("Downstairs: 0 1").unwrap()
+            )))
+        );
+        assert_eq!(
+            LightingCommand::parse(
+                "group delete Downstairs"
+            ),
+            Some(Ok(LightingCommand::DeleteGroup(
+                truncated("Downstairs")
+            )))
+        );
+        assert_eq!(
+            LightingCommand::parse(
+                "scene save Movie night"
+            ),
+            Some(Ok(LightingCommand::SaveScene {
+                name: truncated("Movie night"),
+                lamps: ALL_LAMPS
+            }))
+        );
+        assert_eq!(
+            LightingCommand::parse("scene save Movie: 4 1"),
+            Some(Ok(LightingCommand::SaveScene {
+                name: truncated("Movie"),
+                lamps: 0b10010
+            }))
+        );
+        assert_eq!(
+            LightingCommand::parse(
+                "scene set Off: 0 OFF 255"
+            ),
+            Some(Ok(LightingCommand::SetScene(
+                Scene::parse("Off: 0 OFF 255").unwrap()
+            )))
+        );
+        assert_eq!(
+            LightingCommand::parse("scene delete Off"),
+            Some(Ok(LightingCommand::DeleteScene(
+                truncated("Off")
+            )))
+        );
+        for line in [
+            "group delete",
+            "scene save",
+            "group save Downstairs",
+            "scene set Off",
+            "scene on Off",
+        ] {
+            assert!(
+                matches!(
+                    LightingCommand::parse(line),
+                    Some(Err(_))
+                ),
+                "{}",
+                line
+            );
+        }
+    }
+}
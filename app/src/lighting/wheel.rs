@@ -0,0 +1,365 @@
+//! A colour wheel for the lamps, drawn pixel by pixel with
+//! embedded-graphics.
+//!
+//! The hue goes around the wheel, counterclockwise from red
+//! on the right, and the saturation grows from white in the
+//! middle to the pure colour on the rim. The wheel is
+//! [`WHEEL_SIZE`] pixels square, so it fits into Kolibri's
+//! 200×100 pixel buffer and goes to the display in one
+//! transfer. Its pixels are worked out with integers only,
+//! as they are streamed into the buffer, and once the wheel
+//! is on the screen, moving the marker only redraws the
+//! small squares around its old and new place.
+
+use embedded_graphics::{
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::Rectangle,
+};
+use kolibri_embedded_gui::{
+    smartstate::{Container, Smartstate},
+    ui::{GuiResult, Interaction, Response, Ui, Widget},
+};
+
+use crate::lamp::hsv;
+
+/// Width and height of the [`HueWheel`], in pixels.
+pub const WHEEL_SIZE: u32 = 96;
+
+const RADIUS: i32 = WHEEL_SIZE as i32 / 2 - 1;
+/// Outer radius of the ring that marks the picked colour.
+const MARKER: i32 = 5;
+
+/// What a [`HueWheel`] has on the screen. Keep it while the
+/// wheel is shown, so a moved marker can be redrawn alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WheelState {
+    /// The hue and saturation the marker is drawn at.
+    drawn: Option<(u16, u8)>,
+}
+
+impl WheelState {
+    pub const fn new() -> Self {
+        Self { drawn: None }
+    }
+}
+
+/// Kolibri widget to pick a hue and saturation by touching
+/// or dragging on a colour wheel. The response is
+/// [`changed`](Response::changed) when the user picked a
+/// new colour.
+pub struct HueWheel<'a> {
+    hue: &'a mut u16,
+    saturation: &'a mut u8,
+    state: &'a mut WheelState,
+    smartstate: Container<'a, Smartstate>,
+}
+
+impl<'a> HueWheel<'a> {
+    pub fn new(
+        hue: &'a mut u16,
+        saturation: &'a mut u8,
+        state: &'a mut WheelState,
+    ) -> Self {
+        Self {
+            hue,
+            saturation,
+            state,
+            smartstate: Container::empty(),
+        }
+    }
+
+    /// Redraws the whole wheel when `smartstate` is reset,
+    /// e.g. after the page was cleared.
+    pub fn smartstate(
+        mut self,
+        smartstate: &'a mut Smartstate,
+    ) -> Self {
+        self.smartstate.set(smartstate);
+        self
+    }
+}
+
+impl Widget<Rgb565> for HueWheel<'_> {
+    fn draw<D: DrawTarget<Color = Rgb565>>(
+        &mut self,
+        ui: &mut Ui<D, Rgb565>,
+    ) -> GuiResult<Response> {
+        let iresponse =
+            ui.allocate_space(Size::new_equal(WHEEL_SIZE))?;
+        let area = iresponse.area;
+        let centre = area.top_left
+            + Point::new_equal(WHEEL_SIZE as i32 / 2);
+
+        let before = (*self.hue, *self.saturation);
+        if let Interaction::Click(p)
+        | Interaction::Drag(p) = iresponse.interaction
+        {
+            (*self.hue, *self.saturation) =
+                pick(p - centre);
+        }
+        let now = (*self.hue, *self.saturation);
+
+        let prevstate = self.smartstate.clone_inner();
+        self.smartstate
+            .modify(|st| *st = Smartstate::state(1));
+        let pixels = Pixels {
+            area,
+            centre,
+            marker: centre + marker_offset(now),
+            background: ui.style().background_color,
+        };
+        if !self.smartstate.eq_option(&prevstate) {
+            pixels.draw_into(ui, area)?;
+        } else if self.state.drawn != Some(now) {
+            if let Some(old) = self.state.drawn {
+                let old = centre + marker_offset(old);
+                pixels.draw_into(ui, around(old, area))?;
+            }
+            pixels.draw_into(
+                ui,
+                around(pixels.marker, area),
+            )?;
+        }
+        self.state.drawn = Some(now);
+
+        Ok(Response::new(iresponse)
+            .set_changed(now != before))
+    }
+}
+
+/// The square the marker at `marker` covers, within `area`.
+fn around(marker: Point, area: Rectangle) -> Rectangle {
+    Rectangle::with_center(
+        marker,
+        Size::new_equal(2 * MARKER as u32 + 1),
+    )
+    .intersection(&area)
+}
+
+/// The wheel with its marker, as a drawable for a part of
+/// it.
+struct Pixels {
+    area: Rectangle,
+    centre: Point,
+    marker: Point,
+    background: Rgb565,
+}
+
+impl Pixels {
+    /// Draws the part `part` of the wheel through Kolibri's
+    /// buffer.
+    fn draw_into<D: DrawTarget<Color = Rgb565>>(
+        &self,
+        ui: &mut Ui<D, Rgb565>,
+        part: Rectangle,
+    ) -> GuiResult<()> {
+        ui.start_drawing(&part);
+        ui.draw(&Pixels {
+            area: part,
+            ..*self
+        })?;
+        ui.finalize()
+    }
+}
+
+impl Drawable for Pixels {
+    type Color = Rgb565;
+    type Output = ();
+
+    fn draw<D: DrawTarget<Color = Rgb565>>(
+        &self,
+        target: &mut D,
+    ) -> Result<(), D::Error> {
+        // the distance from the centre, carried from pixel
+        // to pixel, where it changes by one at most
+        let mut r = 0;
+        let colours = self.area.points().map(|p| {
+            let m = p - self.marker;
+            let ring = m.x * m.x + m.y * m.y;
+            if ring <= MARKER * MARKER && ring > 16 {
+                return Rgb565::WHITE;
+            }
+            if (9..=16).contains(&ring) {
+                return Rgb565::BLACK;
+            }
+            let d = p - self.centre;
+            let d2 = d.x * d.x + d.y * d.y;
+            if d2 > RADIUS * RADIUS {
+                return self.background;
+            }
+            while r * r > d2 {
+                r -= 1;
+            }
+            while (r + 1) * (r + 1) <= d2 {
+                r += 1;
+            }
+            let [red, green, blue] =
+                hsv(angle(d.x, d.y), saturation(r));
+            Rgb565::new(red >> 3, green >> 2, blue >> 3)
+        });
+        target.fill_contiguous(&self.area, colours)
+    }
+}
+
+/// The saturation at `r` pixels from the centre.
+fn saturation(r: i32) -> u8 {
+    (r.min(RADIUS) * 255 / RADIUS) as u8
+}
+
+/// The hue and saturation at `offset` from the centre, on
+/// the rim for points outside the wheel.
+fn pick(offset: Point) -> (u16, u8) {
+    let d2 =
+        (offset.x * offset.x + offset.y * offset.y) as f32;
+    let r = libm::roundf(libm::sqrtf(d2)) as i32;
+    (angle(offset.x, offset.y), saturation(r))
+}
+
+/// Where the marker of `hue` and `saturation` is, from the
+/// centre.
+fn marker_offset((hue, saturation): (u16, u8)) -> Point {
+    let r = saturation as f32 * RADIUS as f32 / 255.0;
+    let a = hue as f32 * core::f32::consts::PI / 180.0;
+    Point::new(
+        libm::roundf(r * libm::cosf(a)) as i32,
+        -libm::roundf(r * libm::sinf(a)) as i32,
+    )
+}
+
+/// The angle of `(x, y)` on the screen, in degrees
+/// counterclockwise from the right. Within an octant it is
+/// 45° t + 15.6° t (1 - t) for t = min / max, which needs
+/// no floats and is less than a degree off once rounded.
+fn angle(x: i32, y: i32) -> u16 {
+    let (ax, ay) = (x.unsigned_abs(), y.unsigned_abs());
+    let (lo, hi) = (ax.min(ay), ax.max(ay));
+    if hi == 0 {
+        return 0;
+    }
+    let t = lo * 256 / hi;
+    // in tenths of a degree
+    let mut a = (450 * t + 156 * t * (256 - t) / 256) / 256;
+    if ay > ax {
+        a = 900 - a;
+    }
+    if x < 0 {
+        a = 1800 - a;
+    }
+    // the screen's y grows downwards
+    if y > 0 {
+        a = 3600 - a;
+    }
+    ((a + 5) / 10 % 360) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::mock_display::MockDisplay;
+
+    use super::*;
+
+    #[test]
+    fn angles_are_close_enough() {
+        assert_eq!(angle(0, 0), 0);
+        assert_eq!(angle(10, 0), 0);
+        assert_eq!(angle(0, -10), 90);
+        assert_eq!(angle(-10, 0), 180);
+        assert_eq!(angle(0, 10), 270);
+        assert_eq!(angle(7, -7), 45);
+        for x in -RADIUS..=RADIUS {
+            for y in -RADIUS..=RADIUS {
+                if x == 0 && y == 0 {
+                    continue;
+                }
+                let exact = (-(y as f64))
+                    .atan2(x as f64)
+                    .to_degrees()
+                    .rem_euclid(360.0);
+                let off =
+                    (angle(x, y) as f64 - exact).abs();
+                let off = off.min(360.0 - off);
+                assert!(off < 1.0, "{} {}: {}", x, y, off);
+            }
+        }
+    }
+
+    #[test]
+    fn markers_land_where_they_were_picked() {
+        for hue in (0..360).step_by(15) {
+            for saturation in [64, 128, 255] {
+                let (h, s) =
+                    pick(marker_offset((hue, saturation)));
+                let off = (h as i32 - hue as i32)
+                    .rem_euclid(360)
+                    .min(
+                        (hue as i32 - h as i32)
+                            .rem_euclid(360),
+                    );
+                assert!(off <= 4, "{} {}", hue, h);
+                assert!(
+                    (s as i32 - saturation as i32).abs()
+                        <= 6
+                );
+            }
+        }
+        assert_eq!(pick(Point::zero()), (0, 0));
+        // outside the wheel is on the rim
+        assert_eq!(pick(Point::new(0, -60)), (90, 255));
+    }
+
+    #[test]
+    fn the_wheel_is_drawn_with_its_marker() {
+        // a corner of the wheel, which fits the mock
+        let pixels = Pixels {
+            area: Rectangle::new(
+                Point::zero(),
+                Size::new_equal(64),
+            ),
+            centre: Point::new_equal(48),
+            marker: Point::new(48, 20),
+            background: Rgb565::BLUE,
+        };
+        let mut display = MockDisplay::new();
+        pixels.draw(&mut display).unwrap();
+        // outside the wheel
+        assert_eq!(
+            display.get_pixel(Point::zero()),
+            Some(Rgb565::BLUE)
+        );
+        // white in the middle
+        assert_eq!(
+            display.get_pixel(Point::new(48, 48)),
+            Some(Rgb565::WHITE)
+        );
+        // pure cyan on the rim on the left
+        assert_eq!(
+            display.get_pixel(Point::new(1, 48)),
+            Some(Rgb565::CYAN)
+        );
+        // the marker ring
+        assert_eq!(
+            display.get_pixel(Point::new(48, 23)),
+            Some(Rgb565::BLACK)
+        );
+        assert_eq!(
+            display.get_pixel(Point::new(48, 25)),
+            Some(Rgb565::WHITE)
+        );
+    }
+
+    #[test]
+    fn the_marker_square_stays_on_the_wheel() {
+        let area = Rectangle::new(
+            Point::new(10, 10),
+            Size::new_equal(WHEEL_SIZE),
+        );
+        assert_eq!(
+            around(Point::new(12, 60), area),
+            Rectangle::new(
+                Point::new(10, 55),
+                Size::new(8, 11)
+            )
+        );
+    }
+}
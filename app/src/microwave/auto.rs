@@ -0,0 +1,655 @@
+use core::fmt;
+
+use heapless::Vec;
+
+use super::{
+    program::{parse_mmss, parse_watts, Mmss},
+    PowerLevel,
+    Stage,
+};
+use crate::{
+    storage::{parse_lines, write_lines, Slot, TextRecord},
+    timers::{truncated, TimerLabel},
+};
+
+/// Most foods in the table.
+pub const MAX_FOODS: usize = 6;
+/// Most weights a food's time is given for.
+pub const MAX_POINTS: usize = 5;
+/// What one step of the weight stepper adds or takes away.
+pub const WEIGHT_STEP_G: u16 = 50;
+/// Longest line a food can be written as.
+pub const LINE_LEN: usize = 96;
+
+/// The table the app starts with until another one is
+/// stored.
+pub const DEFAULT_TABLE: [&str; 5] = [
+    "Potatoes: 800W 250g 4:00 / 500g 7:00 / 1000g 12:30",
+    "Vegetables: 800W 100g 2:00 / 500g 6:00 / 1000g 10:00",
+    "Beverage: 800W 150g 1:00 / 300g 1:50 / 500g 3:00",
+    "Fish: 620W 100g 1:30 / 500g 5:00",
+    "Meat defrost: 180W 100g 2:30 / 500g 10:00 / 1000g 18:00",
+];
+
+/// How long a food takes at one weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeightPoint {
+    pub grams: u16,
+    pub secs: u32,
+}
+
+impl fmt::Display for WeightPoint {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{}g {}", self.grams, Mmss(self.secs))
+    }
+}
+
+/// Why a line is not a [`Food`], or not a
+/// [`TableCommand`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// No name before the colon.
+    Name,
+    /// A power that isn't one of the [`PowerLevel`]s.
+    Power,
+    /// A point that isn't a weight and a duration.
+    Point,
+    /// A weight that isn't one, or isn't heavier than the
+    /// one before.
+    Weight,
+    /// A duration that isn't `m:ss`, or is outside
+    /// [`MIN_SECS`](crate::countdown::MIN_SECS) ..=
+    /// [`MAX_STAGE_SECS`](super::MAX_STAGE_SECS).
+    Duration,
+    /// Less than two points.
+    TooFewPoints,
+    /// More than [`MAX_POINTS`].
+    TooManyPoints,
+    /// An `auto` command that doesn't exist.
+    Command,
+}
+
+/// One row of the [`AutoTable`]: the power a food is cooked
+/// at, and the time it takes for a few weights. Weights in
+/// between are interpolated.
+///
+/// Written and stored as one line of text:
+///
+/// ```text
+/// Potatoes: 800W 250g 4:00 / 500g 7:00 / 1000g 12:30
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Food {
+    name: TimerLabel,
+    level: PowerLevel,
+    points: Vec<WeightPoint, MAX_POINTS>,
+}
+
+impl Food {
+    /// Reads a food written like its [`Display`] output.
+    /// Names are cut to
+    /// [`LABEL_LEN`](crate::timers::LABEL_LEN) bytes.
+    ///
+    /// [`Display`]: fmt::Display
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let (name, rest) =
+            line.split_once(':').ok_or(ParseError::Name)?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ParseError::Name);
+        }
+        let (power, rest) = rest
+            .trim()
+            .split_once(' ')
+            .ok_or(ParseError::Power)?;
+        let level =
+            parse_watts(power).ok_or(ParseError::Power)?;
+        let mut points =
+            Vec::<WeightPoint, MAX_POINTS>::new();
+        for point in rest.split('/') {
+            let point = parse_point(point.trim())?;
+            if let Some(last) = points.last() {
+                if point.grams <= last.grams {
+                    return Err(ParseError::Weight);
+                }
+            }
+            points
+                .push(point)
+                .map_err(|_| ParseError::TooManyPoints)?;
+        }
+        if points.len() < 2 {
+            return Err(ParseError::TooFewPoints);
+        }
+        Ok(Self {
+            name: truncated(name),
+            level,
+            points,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn level(&self) -> PowerLevel {
+        self.level
+    }
+
+    pub fn points(&self) -> &[WeightPoint] {
+        &self.points
+    }
+
+    /// Lightest weight in the table.
+    pub fn min_grams(&self) -> u16 {
+        self.points[0].grams
+    }
+
+    /// Heaviest weight in the table.
+    pub fn max_grams(&self) -> u16 {
+        self.points[self.points.len() - 1].grams
+    }
+
+    /// The time for `grams`, interpolated linearly between
+    /// the two points around it and rounded to the second.
+    /// Weights outside the table are clamped to it.
+    pub fn secs_for(&self, grams: u16) -> u32 {
+        let grams =
+            grams.clamp(self.min_grams(), self.max_grams());
+        let i = self
+            .points
+            .windows(2)
+            .position(|w| grams <= w[1].grams)
+            .unwrap_or(0);
+        let (a, b) = (self.points[i], self.points[i + 1]);
+        let span = (b.grams - a.grams) as i64;
+        let delta = (b.secs as i64 - a.secs as i64)
+            * (grams - a.grams) as i64;
+        // round half away from zero
+        let offset = (2 * delta + delta.signum() * span)
+            / (2 * span);
+        (a.secs as i64 + offset) as u32
+    }
+}
+
+impl fmt::Display for Food {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ",
+            self.name,
+            self.level.label()
+        )?;
+        for (i, point) in self.points.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" / ")?;
+            }
+            write!(f, "{}", point)?;
+        }
+        Ok(())
+    }
+}
+
+/// `250g 4:00`.
+fn parse_point(
+    text: &str,
+) -> Result<WeightPoint, ParseError> {
+    let (weight, duration) =
+        text.split_once(' ').ok_or(ParseError::Point)?;
+    let grams = weight
+        .strip_suffix('g')
+        .and_then(|g| g.parse().ok())
+        .filter(|&g| g > 0)
+        .ok_or(ParseError::Weight)?;
+    let secs =
+        parse_mmss(duration).ok_or(ParseError::Duration)?;
+    Ok(WeightPoint { grams, secs })
+}
+
+/// The foods of the auto mode, kept in flash as their lines
+/// of text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoTable {
+    pub foods: Vec<Food, MAX_FOODS>,
+}
+
+impl AutoTable {
+    pub const ENCODED_LEN: usize =
+        1 + MAX_FOODS * (LINE_LEN + 1);
+
+    /// [`DEFAULT_TABLE`].
+    pub fn defaults() -> Self {
+        let foods = DEFAULT_TABLE
+            .iter()
+            .filter_map(|l| Food::parse(l).ok())
+            .collect();
+        Self { foods }
+    }
+
+    /// Adds `food`, or replaces the one with the same name.
+    /// Returns `false` if the table is full.
+    pub fn set(&mut self, food: Food) -> bool {
+        match self
+            .foods
+            .iter_mut()
+            .find(|f| f.name() == food.name())
+        {
+            Some(old) => *old = food,
+            None => return self.foods.push(food).is_ok(),
+        }
+        true
+    }
+}
+
+impl TextRecord<{ AutoTable::ENCODED_LEN }> for AutoTable {
+    const SLOT: Slot = Slot::AutoCookTable;
+    const FORMAT_VERSION: u8 = 1;
+
+    fn write_lines(
+        &self,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        write_lines(out, &self.foods)
+    }
+
+    fn from_lines<'a>(
+        lines: impl Iterator<Item = &'a str>,
+    ) -> Option<Self> {
+        let foods = parse_lines(lines, Food::parse)?;
+        Some(Self { foods })
+    }
+}
+
+impl Default for AutoTable {
+    fn default() -> Self {
+        Self::defaults()
+    }
+}
+
+/// Loads the stored table, falling back to
+/// [`DEFAULT_TABLE`] if there is none.
+#[cfg(target_os = "none")]
+pub fn stored_auto_table() -> AutoTable {
+    AutoTable::stored()
+        .filter(|t| !t.foods.is_empty())
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "none")]
+impl AutoTable {
+    /// Runs `line` from the serial console if it is a
+    /// [`TableCommand`], stores the table if it changed and
+    /// prints the result. Returns whether it was one.
+    pub fn run_command(&mut self, line: &str) -> bool {
+        use esp_println::println;
+
+        match TableCommand::parse(line) {
+            None => return false,
+            Some(Ok(TableCommand::List)) => {}
+            Some(Ok(TableCommand::Set(food))) => {
+                if !self.set(food) {
+                    println!("auto: the table is full");
+                    return true;
+                }
+                self.save(&mut crate::storage::open()).ok();
+            }
+            Some(Ok(TableCommand::Reset)) => {
+                *self = Self::defaults();
+                self.save(&mut crate::storage::open()).ok();
+            }
+            Some(Err(e)) => {
+                println!("auto: {:?}", e);
+                println!(
+                    "usage: auto [set NAME: 800W 100g 1:00 / \
+                     500g 4:00 / ...] [reset]"
+                );
+                return true;
+            }
+        }
+        for food in &self.foods {
+            println!("{}", food);
+        }
+        true
+    }
+}
+
+/// What can be typed on the serial console.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableCommand {
+    /// `auto`
+    List,
+    /// `auto set Rice: 620W 100g 8:00 / 300g 14:00`
+    Set(Food),
+    /// `auto reset`, back to [`DEFAULT_TABLE`].
+    Reset,
+}
+
+impl TableCommand {
+    /// Longest command, `auto set ` and a food of
+    /// [`LINE_LEN`].
+    pub const LINE_LEN: usize =
+        "auto set ".len() + LINE_LEN;
+
+    /// `None` if `line` is no `auto` command at all.
+    pub fn parse(
+        line: &str,
+    ) -> Option<Result<Self, ParseError>> {
+        let line = line.trim();
+        let rest = line.strip_prefix("auto")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+        let rest = rest.trim_start();
+        let (command, arg) =
+            rest.split_once(' ').unwrap_or((rest, ""));
+        Some(match (command, arg.trim()) {
+            ("", _) => Ok(Self::List),
+            ("set", food) => {
+                Food::parse(food).map(Self::Set)
+            }
+            ("reset", "") => Ok(Self::Reset),
+            _ => Err(ParseError::Command),
+        })
+    }
+}
+
+/// What the user picked in the auto mode: a food of the
+/// [`AutoTable`] and its weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoCook {
+    food: usize,
+    grams: u16,
+}
+
+impl AutoCook {
+    /// The first food, at its lightest weight.
+    pub fn new(table: &AutoTable) -> Self {
+        let mut auto = Self { food: 0, grams: 0 };
+        auto.select(table, 0);
+        auto
+    }
+
+    /// The food picked. The table must not be empty.
+    pub fn food<'a>(
+        &self,
+        table: &'a AutoTable,
+    ) -> &'a Food {
+        &table.foods[self.food.min(table.foods.len() - 1)]
+    }
+
+    pub fn grams(&self) -> u16 {
+        self.grams
+    }
+
+    /// The next food, after the last one the first.
+    pub fn next_food(&mut self, table: &AutoTable) {
+        self.select(
+            table,
+            (self.food + 1) % table.foods.len(),
+        );
+    }
+
+    /// The food before, before the first one the last.
+    pub fn prev_food(&mut self, table: &AutoTable) {
+        let len = table.foods.len();
+        self.select(table, (self.food + len - 1) % len);
+    }
+
+    /// [`WEIGHT_STEP_G`] more, up to the heaviest weight of
+    /// the food.
+    pub fn heavier(&mut self, table: &AutoTable) {
+        let food = self.food(table);
+        self.grams = self
+            .grams
+            .saturating_add(WEIGHT_STEP_G)
+            .min(food.max_grams());
+    }
+
+    /// [`WEIGHT_STEP_G`] less, down to the lightest weight
+    /// of the food.
+    pub fn lighter(&mut self, table: &AutoTable) {
+        let food = self.food(table);
+        self.grams = self
+            .grams
+            .saturating_sub(WEIGHT_STEP_G)
+            .max(food.min_grams());
+    }
+
+    /// Power and time for the food and weight picked.
+    pub fn setting(&self, table: &AutoTable) -> Stage {
+        let food = self.food(table);
+        Stage {
+            level: food.level(),
+            secs: food.secs_for(self.grams),
+        }
+    }
+
+    /// Picks food `food` at its lightest weight.
+    fn select(&mut self, table: &AutoTable, food: usize) {
+        self.food = food.min(table.foods.len() - 1);
+        self.grams = self.food(table).min_grams();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{tests::MockFlash, RecordStore};
+
+    fn food(line: &str) -> Food {
+        Food::parse(line).unwrap()
+    }
+
+    #[test]
+    fn foods_are_read_and_written_as_text() {
+        let f = food("Rice: 620w 100g 8:00/300g 14:00");
+        assert_eq!(f.name(), "Rice");
+        assert_eq!(f.level().label(), "620W");
+        assert_eq!(
+            f.points()[1],
+            WeightPoint {
+                grams: 300,
+                secs: 840
+            }
+        );
+        assert_eq!(
+            f.to_string(),
+            "Rice: 620W 100g 8:00 / 300g 14:00"
+        );
+        for line in DEFAULT_TABLE {
+            assert_eq!(food(line).to_string(), line);
+        }
+    }
+
+    #[test]
+    fn broken_lines_are_rejected() {
+        for (line, error) in [
+            ("800W 100g", ParseError::Name),
+            ("Rice: 100g 1:00", ParseError::Power),
+            ("Rice: 750W 100g 1:00", ParseError::Power),
+            ("Rice: 800W 100g", ParseError::Point),
+            (
+                "Rice: 800W 0g 1:00 / 1g 1:00",
+                ParseError::Weight,
+            ),
+            (
+                "Rice: 800W 100g 1:00 / 100g 2:00",
+                ParseError::Weight,
+            ),
+            (
+                "Rice: 800W 100g 1:00 / 200 2:00",
+                ParseError::Weight,
+            ),
+            (
+                "Rice: 800W 100g 1:00 / 200g 2",
+                ParseError::Duration,
+            ),
+            (
+                "Rice: 800W 100g 1:00",
+                ParseError::TooFewPoints,
+            ),
+            (
+                "Rice: 800W 1g 1:00 / 2g 1:00 / 3g 1:00 \
+                 / 4g 1:00 / 5g 1:00 / 6g 1:00",
+                ParseError::TooManyPoints,
+            ),
+        ] {
+            assert_eq!(
+                Food::parse(line),
+                Err(error),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn times_are_interpolated_between_weights() {
+        let f = food("Potatoes: 800W 250g 4:00 / 500g 7:00 / 1000g 12:30");
+        // on the points
+        assert_eq!(f.secs_for(250), 240);
+        assert_eq!(f.secs_for(500), 420);
+        assert_eq!(f.secs_for(1000), 750);
+        // in between
+        assert_eq!(f.secs_for(300), 276);
+        assert_eq!(f.secs_for(750), 585);
+        // 420 + 330 * 1 / 500 = 420.66
+        assert_eq!(f.secs_for(501), 421);
+        // outside the table
+        assert_eq!(f.secs_for(0), 240);
+        assert_eq!(f.secs_for(u16::MAX), 750);
+    }
+
+    #[test]
+    fn falling_times_are_interpolated_too() {
+        // e.g. thin slices that cook faster per gram
+        let f = food("Odd: 360W 100g 3:00 / 200g 1:00");
+        assert_eq!(f.secs_for(150), 120);
+        // 180 - 120 * 1 / 100 = 178.8
+        assert_eq!(f.secs_for(101), 179);
+        assert_eq!(f.secs_for(199), 61);
+    }
+
+    #[test]
+    fn the_stepper_stays_within_the_table() {
+        let table = AutoTable::defaults();
+        let mut auto = AutoCook::new(&table);
+        assert_eq!(auto.food(&table).name(), "Potatoes");
+        assert_eq!(auto.grams(), 250);
+        auto.lighter(&table);
+        assert_eq!(auto.grams(), 250);
+        auto.heavier(&table);
+        assert_eq!(auto.grams(), 300);
+        assert_eq!(
+            auto.setting(&table),
+            Stage {
+                level: PowerLevel::MAX,
+                secs: 276
+            }
+        );
+        for _ in 0..20 {
+            auto.heavier(&table);
+        }
+        assert_eq!(auto.grams(), 1000);
+        // another food starts at its lightest weight
+        auto.prev_food(&table);
+        assert_eq!(
+            auto.food(&table).name(),
+            "Meat defrost"
+        );
+        assert_eq!(auto.grams(), 100);
+        assert_eq!(
+            auto.setting(&table).level,
+            PowerLevel::MIN
+        );
+        auto.next_food(&table);
+        auto.next_food(&table);
+        assert_eq!(auto.food(&table).name(), "Vegetables");
+    }
+
+    #[test]
+    fn a_stored_table_overrides_the_defaults() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(AutoTable::load(&mut store), Ok(None));
+        let mut table = AutoTable::defaults();
+        assert!(table
+            .set(food("Fish: 480W 100g 2:00 / 500g 6:00")));
+        assert!(table.set(food(
+            "Rice: 620W 100g 8:00 / 300g 14:00"
+        )));
+        assert!(!table
+            .set(food("Soup: 800W 250g 2:00 / 500g 3:30")));
+        assert_eq!(table.foods[3].level().label(), "480W");
+        table.save(&mut store).unwrap();
+        assert_eq!(
+            AutoTable::load(&mut store),
+            Ok(Some(table))
+        );
+    }
+
+    #[test]
+    fn console_commands() {
+        assert_eq!(TableCommand::parse("cook"), None);
+        assert_eq!(TableCommand::parse("automatic"), None);
+        assert_eq!(
+            TableCommand::parse("auto"),
+            Some(Ok(TableCommand::List))
+        );
+        assert_eq!(
+            TableCommand::parse(" auto reset "),
+            Some(Ok(TableCommand::Reset))
+        );
+        assert_eq!(
+            TableCommand::parse(
+                "auto set Tea: 800W 200g 1:20 / 400g 2:30"
+            ),
+            Some(Ok(TableCommand::Set(food(
+                "Tea: 800W 200g 1:20 / 400g 2:30"
+            ))))
+        );
+        assert_eq!(
+            TableCommand::parse("auto set Tea: 800W"),
+            Some(Err(ParseError::Power))
+        );
+        assert_eq!(
+            TableCommand::parse("auto reset now"),
+            Some(Err(ParseError::Command))
+        );
+    }
+
+    #[test]
+    fn the_longest_food_can_be_typed() {
+        use crate::console::LineBuffer;
+
+        let mut line =
+            std::string::String::from("Longest name: 800W");
+        for i in 0..MAX_POINTS {
+            let sep = if i == 0 { "" } else { " /" };
+            line += &std::format!(
+                "{} {}g 99:59",
+                sep,
+                60000 + i * 1000
+            );
+        }
+        assert!(line.len() <= LINE_LEN);
+        let longest = food(&line);
+        assert_eq!(longest.to_string(), line);
+
+        let mut buf = LineBuffer::new();
+        let typed = std::format!("auto set {}\r", line)
+            .bytes()
+            .find_map(|b| buf.push(b))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            TableCommand::parse(&typed),
+            Some(Ok(TableCommand::Set(longest)))
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! The microwave behind the microwave apps, apart from the
+//! UI.
+//!
+//! [`PowerController`] turns the set [`PowerLevel`] and the
+//! state of the cook timer into the [`Outputs`] to drive:
+//! the magnetron, switched on and off in a
+//! time-proportional duty cycle, the turntable and the
+//! lamp.
+//!
+//! [`Safety`] stands between the user and the cook timer:
+//! it keeps the oven from running with the door open, and
+//! has a child lock.
+//!
+//! A [`CookProgram`] cooks in stages, each with its own
+//! power level, e.g. defrosting at 180 W before heating at
+//! 800 W. There are a few [built-in
+//! programs](BUILT_IN_PROGRAMS); the user adds more with a
+//! [`ProgramCommand`] on the serial console:
+//!
+//! ```text
+//! cook                               lists the programs
+//! cook add Soup: 800W 2:00 / 480W 3:00
+//! cook delete Soup
+//! ```
+//!
+//! The auto mode works out power and time from a food and
+//! its weight, with an [`AutoTable`] that can be changed
+//! with a [`TableCommand`].
+
+mod auto;
+mod power;
+mod program;
+mod safety;
+mod screen;
+
+#[cfg(target_os = "none")]
+mod task;
+
+#[cfg(target_os = "none")]
+pub use auto::stored_auto_table;
+pub use auto::{
+    AutoCook,
+    AutoTable,
+    Food,
+    TableCommand,
+    WeightPoint,
+    DEFAULT_TABLE,
+    MAX_FOODS,
+    MAX_POINTS,
+    WEIGHT_STEP_G,
+};
+pub use power::{
+    Outputs,
+    PowerConfig,
+    PowerController,
+    PowerLevel,
+};
+#[cfg(target_os = "none")]
+pub use program::stored_cook_programs;
+pub use program::{
+    AddError,
+    CookProgram,
+    CookPrograms,
+    ParseError,
+    ProgramCommand,
+    ProgramRun,
+    Stage,
+    BUILT_IN_PROGRAMS,
+    LIBRARY_LEN,
+    MAX_STAGES,
+    MAX_STAGE_SECS,
+    MAX_USER_PROGRAMS,
+};
+pub use safety::{
+    Command,
+    CookState,
+    DoorChannel,
+    DoorEvent,
+    DoorSensor,
+    Input,
+    Refused,
+    Safety,
+    DOOR_SAMPLE_MS,
+    UNLOCK_HOLD_MS,
+};
+pub use screen::{
+    auto_page,
+    program_page,
+    AutoAction,
+    ProgramAction,
+};
+#[cfg(target_os = "none")]
+pub use task::door_task;
+
+pub use crate::output::Polarity;
@@ -0,0 +1,305 @@
+use crate::countdown::TimerState;
+
+/// Output power of the levels, in watts.
+const WATTS: [u16; PowerLevel::COUNT as usize] =
+    [180, 220, 360, 480, 620, 800];
+const LABELS: [&str; PowerLevel::COUNT as usize] =
+    ["180W", "220W", "360W", "480W", "620W", "800W"];
+
+/// One of the microwave's power settings, from `0` (lowest)
+/// to [`PowerLevel::MAX`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub struct PowerLevel(u8);
+
+impl PowerLevel {
+    pub const COUNT: u8 = 6;
+    pub const MIN: Self = Self(0);
+    pub const MAX: Self = Self(Self::COUNT - 1);
+
+    /// `None` for levels above [`PowerLevel::MAX`].
+    pub const fn new(level: u8) -> Option<Self> {
+        if level < Self::COUNT {
+            Some(Self(level))
+        } else {
+            None
+        }
+    }
+
+    /// Like [`new`](Self::new), but clamps levels above
+    /// [`PowerLevel::MAX`].
+    pub fn saturating(level: u8) -> Self {
+        Self(level.min(Self::MAX.0))
+    }
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    /// One level up, if there is one.
+    pub fn up(self) -> Self {
+        Self::saturating(self.0 + 1)
+    }
+
+    /// One level down, if there is one.
+    pub fn down(self) -> Self {
+        Self(self.0.saturating_sub(1))
+    }
+
+    /// The level of exactly `watts`, if there is one.
+    pub fn from_watts(watts: u16) -> Option<Self> {
+        WATTS
+            .iter()
+            .position(|&w| w == watts)
+            .map(|i| Self(i as u8))
+    }
+
+    pub fn watts(self) -> u16 {
+        WATTS[self.0 as usize]
+    }
+
+    /// The power as shown to the user, e.g. `"800W"`.
+    pub fn label(self) -> &'static str {
+        LABELS[self.0 as usize]
+    }
+
+    /// Share of the full power, in percent. The magnetron
+    /// only knows on and off, so this is the share of the
+    /// time it is on.
+    pub fn duty_percent(self) -> u8 {
+        (self.watts() as u32 * 100
+            / Self::MAX.watts() as u32) as u8
+    }
+}
+
+impl Default for PowerLevel {
+    fn default() -> Self {
+        Self::MAX
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerConfig {
+    /// Length of one on/off cycle of the magnetron. Real
+    /// ovens use 20 to 30 seconds, so the relay doesn't
+    /// switch too often.
+    pub window_ms: u64,
+    /// Shorter on or off times are skipped: the magnetron
+    /// stays off (or on) for the whole window instead.
+    pub min_switch_ms: u64,
+}
+
+impl PowerConfig {
+    pub const DEFAULT: Self = Self {
+        window_ms: 20_000,
+        min_switch_ms: 1000,
+    };
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// What should be switched on right now.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Outputs {
+    pub magnetron: bool,
+    pub turntable: bool,
+    pub lamp: bool,
+}
+
+/// Runs the magnetron, turntable and lamp from the state
+/// of the cook timer.
+///
+/// While cooking, every window of
+/// [`PowerConfig::window_ms`] starts with the magnetron on
+/// for [`PowerLevel::duty_percent`] of the window, and ends
+/// with it off. A pause ends the window; resuming starts a
+/// new one. The turntable turns while cooking, the lamp is
+/// on while cooking or paused.
+#[derive(Debug, Clone)]
+pub struct PowerController {
+    config: PowerConfig,
+    level: PowerLevel,
+    /// Start of the current window, while cooking.
+    window_start: Option<u64>,
+}
+
+impl PowerController {
+    pub const fn new(config: PowerConfig) -> Self {
+        Self {
+            config,
+            level: PowerLevel::MAX,
+            window_start: None,
+        }
+    }
+
+    pub fn level(&self) -> PowerLevel {
+        self.level
+    }
+
+    /// Takes effect within the current window.
+    pub fn set_level(&mut self, level: PowerLevel) {
+        self.level = level;
+    }
+
+    /// How long the magnetron is on per window.
+    pub fn on_ms(&self) -> u64 {
+        let window = self.config.window_ms;
+        let on =
+            window * self.level.duty_percent() as u64 / 100;
+        if on < self.config.min_switch_ms {
+            0
+        } else if window - on < self.config.min_switch_ms {
+            window
+        } else {
+            on
+        }
+    }
+
+    /// Call every frame with the state of the cook timer.
+    pub fn update(
+        &mut self,
+        now: u64,
+        state: TimerState,
+    ) -> Outputs {
+        if state != TimerState::Running {
+            self.window_start = None;
+            return Outputs {
+                lamp: state == TimerState::Paused,
+                ..Outputs::default()
+            };
+        }
+        let start = *self.window_start.get_or_insert(now);
+        let window = self.config.window_ms.max(1);
+        let phase = now.saturating_sub(start) % window;
+        Outputs {
+            magnetron: phase < self.on_ms(),
+            turntable: true,
+            lamp: true,
+        }
+    }
+}
+
+impl Default for PowerController {
+    fn default() -> Self {
+        Self::new(PowerConfig::DEFAULT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How long the magnetron is on from `from` to `to`,
+    /// checked every 10 ms like a frame loop would.
+    fn on_time(
+        c: &mut PowerController,
+        from: u64,
+        to: u64,
+    ) -> u64 {
+        (from..to)
+            .step_by(10)
+            .filter(|&t| {
+                c.update(t, TimerState::Running).magnetron
+            })
+            .count() as u64
+            * 10
+    }
+
+    #[test]
+    fn levels_are_clamped() {
+        assert_eq!(
+            PowerLevel::new(5),
+            Some(PowerLevel::MAX)
+        );
+        assert_eq!(PowerLevel::new(6), None);
+        assert_eq!(
+            PowerLevel::saturating(200),
+            PowerLevel::MAX
+        );
+        assert_eq!(PowerLevel::MAX.up(), PowerLevel::MAX);
+        assert_eq!(PowerLevel::MIN.down(), PowerLevel::MIN);
+        assert_eq!(PowerLevel::MIN.up().label(), "220W");
+        assert_eq!(PowerLevel::MAX.duty_percent(), 100);
+        assert_eq!(PowerLevel::MIN.duty_percent(), 22);
+        assert_eq!(
+            PowerLevel::from_watts(360),
+            PowerLevel::new(2)
+        );
+        assert_eq!(PowerLevel::from_watts(300), None);
+    }
+
+    #[test]
+    fn duty_cycle_follows_the_level() {
+        let mut c = PowerController::default();
+        assert_eq!(on_time(&mut c, 0, 40_000), 40_000);
+        c.set_level(PowerLevel::new(2).unwrap());
+        let mut c2 = c.clone();
+        // 360 W of 800 W: 45 % of every window
+        assert_eq!(on_time(&mut c, 40_000, 80_000), 18_000);
+        // on first, then off
+        assert!(
+            c2.update(100_000, TimerState::Running)
+                .magnetron
+        );
+        assert!(
+            c2.update(108_990, TimerState::Running)
+                .magnetron
+        );
+        assert!(
+            !c2.update(109_000, TimerState::Running)
+                .magnetron
+        );
+        assert!(
+            c2.update(120_000, TimerState::Running)
+                .magnetron
+        );
+    }
+
+    #[test]
+    fn short_switching_times_are_skipped() {
+        let config = PowerConfig {
+            window_ms: 2000,
+            min_switch_ms: 500,
+        };
+        let mut c = PowerController::new(config);
+        c.set_level(PowerLevel::MIN);
+        // 22 % of 2 s is below 500 ms
+        assert_eq!(c.on_ms(), 0);
+        c.set_level(PowerLevel::new(4).unwrap());
+        // 77 %, off for less than 500 ms
+        assert_eq!(c.on_ms(), 2000);
+    }
+
+    #[test]
+    fn outputs_follow_the_cook_state() {
+        let mut c = PowerController::default();
+        c.set_level(PowerLevel::MIN);
+        let off = Outputs::default();
+        assert_eq!(c.update(0, TimerState::Stopped), off);
+        assert_eq!(
+            c.update(1000, TimerState::Running),
+            Outputs {
+                magnetron: true,
+                turntable: true,
+                lamp: true
+            }
+        );
+        assert_eq!(
+            c.update(10_000, TimerState::Paused),
+            Outputs { lamp: true, ..off }
+        );
+        // resuming starts a new window, magnetron first
+        assert!(
+            c.update(30_000, TimerState::Running).magnetron
+        );
+        assert_eq!(
+            c.update(40_000, TimerState::Finished),
+            off
+        );
+    }
+}
@@ -0,0 +1,680 @@
+use core::fmt;
+
+use heapless::Vec;
+
+use super::PowerLevel;
+use crate::{
+    countdown::{MAX_SECS, MIN_SECS},
+    storage::{parse_lines, write_lines, Slot, TextRecord},
+    timers::{truncated, TimerLabel},
+};
+
+/// Most stages in one program.
+pub const MAX_STAGES: usize = 4;
+/// Most programs the user can add to the built-in ones.
+pub const MAX_USER_PROGRAMS: usize = 5;
+/// Most programs in the [library](CookPrograms::library).
+pub const LIBRARY_LEN: usize =
+    BUILT_IN_PROGRAMS.len() + MAX_USER_PROGRAMS;
+/// Longest stage, `99:59`.
+pub const MAX_STAGE_SECS: u32 = 99 * 60 + 59;
+/// Longest line a program can be written as.
+pub const LINE_LEN: usize = 96;
+
+/// Programs every oven has. They can't be changed or
+/// deleted.
+pub const BUILT_IN_PROGRAMS: [&str; 3] = [
+    "Popcorn: 800W 2:30",
+    "Reheat: 620W 1:30 / 360W 1:00",
+    "Defrost: 180W 3:00 / 800W 1:30",
+];
+
+/// One step of a program: a power level for a while.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stage {
+    pub level: PowerLevel,
+    pub secs: u32,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.level.label(),
+            Mmss(self.secs)
+        )
+    }
+}
+
+/// Why a line is not a program, or not a
+/// [`ProgramCommand`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// No name before the colon.
+    Name,
+    /// No stages.
+    Empty,
+    /// A stage that isn't a power and a duration.
+    Stage,
+    /// A power that isn't one of the [`PowerLevel`]s.
+    Power,
+    /// A duration that isn't `m:ss`, or is outside
+    /// [`MIN_SECS`] ..= [`MAX_STAGE_SECS`].
+    Duration,
+    /// More than [`MAX_STAGES`].
+    TooManyStages,
+    /// All stages together are longer than the cook timer
+    /// goes, [`MAX_SECS`].
+    TooLong,
+    /// A `cook` command that doesn't exist.
+    Command,
+}
+
+/// Named stages, cooked one after the other.
+///
+/// Written and stored as one line of text:
+///
+/// ```text
+/// Defrost: 180W 3:00 / 800W 1:30
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookProgram {
+    name: TimerLabel,
+    stages: Vec<Stage, MAX_STAGES>,
+}
+
+impl CookProgram {
+    /// Reads a program written like its [`Display`]
+    /// output. Names are cut to
+    /// [`LABEL_LEN`](crate::timers::LABEL_LEN) bytes.
+    ///
+    /// [`Display`]: fmt::Display
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let (name, rest) =
+            line.split_once(':').ok_or(ParseError::Name)?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ParseError::Name);
+        }
+        let mut stages = Vec::new();
+        for stage in rest.split('/') {
+            let stage = stage.trim();
+            if stage.is_empty() {
+                continue;
+            }
+            stages
+                .push(parse_stage(stage)?)
+                .map_err(|_| ParseError::TooManyStages)?;
+        }
+        if stages.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        let program = Self {
+            name: truncated(name),
+            stages,
+        };
+        if program.total_secs() > MAX_SECS {
+            return Err(ParseError::TooLong);
+        }
+        Ok(program)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Length of all stages, in seconds.
+    pub fn total_secs(&self) -> u64 {
+        self.stages.iter().map(|s| s.secs as u64).sum()
+    }
+
+    /// Index of the stage that runs `elapsed_ms` into the
+    /// program. Past the end, that is the last one.
+    pub fn stage_at(&self, elapsed_ms: u64) -> usize {
+        let mut end = 0;
+        for (i, stage) in self.stages.iter().enumerate() {
+            end += stage.secs as u64 * 1000;
+            if elapsed_ms < end {
+                return i;
+            }
+        }
+        self.stages.len() - 1
+    }
+}
+
+impl fmt::Display for CookProgram {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{}: ", self.name)?;
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" / ")?;
+            }
+            write!(f, "{}", stage)?;
+        }
+        Ok(())
+    }
+}
+
+/// `800W 1:30`.
+fn parse_stage(text: &str) -> Result<Stage, ParseError> {
+    let (power, duration) =
+        text.split_once(' ').ok_or(ParseError::Stage)?;
+    let level =
+        parse_watts(power).ok_or(ParseError::Power)?;
+    let secs =
+        parse_mmss(duration).ok_or(ParseError::Duration)?;
+    Ok(Stage { level, secs })
+}
+
+/// `800W`, the level of exactly that power.
+pub(super) fn parse_watts(
+    text: &str,
+) -> Option<PowerLevel> {
+    let watts =
+        text.strip_suffix(['W', 'w'])?.parse().ok()?;
+    PowerLevel::from_watts(watts)
+}
+
+/// `m:ss`, with two digits for the seconds. `None` if the
+/// duration is outside [`MIN_SECS`] ..= [`MAX_STAGE_SECS`].
+pub(super) fn parse_mmss(text: &str) -> Option<u32> {
+    let (m, s) = text.trim().split_once(':')?;
+    if s.len() != 2 {
+        return None;
+    }
+    let secs = match (m.parse::<u32>(), s.parse::<u32>()) {
+        (Ok(m), Ok(s)) if s < 60 => {
+            m.saturating_mul(60).saturating_add(s)
+        }
+        _ => return None,
+    };
+    (MIN_SECS as u32..=MAX_STAGE_SECS)
+        .contains(&secs)
+        .then_some(secs)
+}
+
+/// Writes seconds as `m:ss`, the way [`parse_mmss`] reads
+/// them.
+pub(super) struct Mmss(pub u32);
+
+impl fmt::Display for Mmss {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+/// Why a program can't be added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddError {
+    /// There are [`MAX_USER_PROGRAMS`] already.
+    Full,
+    /// A built-in program has that name.
+    BuiltIn,
+}
+
+/// The programs the user added, kept in flash as their
+/// lines of text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookPrograms {
+    pub list: Vec<CookProgram, MAX_USER_PROGRAMS>,
+}
+
+impl CookPrograms {
+    pub const ENCODED_LEN: usize =
+        1 + MAX_USER_PROGRAMS * (LINE_LEN + 1);
+
+    /// No programs but the built-in ones.
+    pub const fn new() -> Self {
+        Self { list: Vec::new() }
+    }
+
+    /// The [`BUILT_IN_PROGRAMS`], then the user's.
+    pub fn library(&self) -> Vec<CookProgram, LIBRARY_LEN> {
+        let mut library: Vec<_, LIBRARY_LEN> =
+            BUILT_IN_PROGRAMS
+                .iter()
+                .filter_map(|l| CookProgram::parse(l).ok())
+                .collect();
+        // can't fail, the library has room for all
+        library.extend(self.list.iter().cloned());
+        library
+    }
+
+    /// Adds `program`, or replaces the one with the same
+    /// name.
+    pub fn add(
+        &mut self,
+        program: CookProgram,
+    ) -> Result<(), AddError> {
+        if BUILT_IN_PROGRAMS.iter().any(|l| {
+            l.split_once(':').is_some_and(|(name, _)| {
+                name == program.name()
+            })
+        }) {
+            return Err(AddError::BuiltIn);
+        }
+        match self
+            .list
+            .iter_mut()
+            .find(|p| p.name() == program.name())
+        {
+            Some(old) => *old = program,
+            None => self
+                .list
+                .push(program)
+                .map_err(|_| AddError::Full)?,
+        }
+        Ok(())
+    }
+
+    /// Deletes the program called `name`. Returns whether
+    /// there was one.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.list.len();
+        self.list.retain(|p| p.name() != name);
+        self.list.len() != len
+    }
+}
+
+impl TextRecord<{ CookPrograms::ENCODED_LEN }>
+    for CookPrograms
+{
+    const SLOT: Slot = Slot::CookPrograms;
+    const FORMAT_VERSION: u8 = 1;
+
+    fn write_lines(
+        &self,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        write_lines(out, &self.list)
+    }
+
+    fn from_lines<'a>(
+        lines: impl Iterator<Item = &'a str>,
+    ) -> Option<Self> {
+        let list = parse_lines(lines, CookProgram::parse)?;
+        Some(Self { list })
+    }
+}
+
+/// Loads the user's programs, or none if none are stored.
+#[cfg(target_os = "none")]
+pub fn stored_cook_programs() -> CookPrograms {
+    CookPrograms::stored().unwrap_or_default()
+}
+
+#[cfg(target_os = "none")]
+impl CookPrograms {
+    /// Runs `line` from the serial console if it is a
+    /// [`ProgramCommand`], stores the programs if they
+    /// changed and prints the result. Returns whether it
+    /// was one.
+    pub fn run_command(&mut self, line: &str) -> bool {
+        use esp_println::println;
+
+        match ProgramCommand::parse(line) {
+            None => return false,
+            Some(Ok(ProgramCommand::List)) => {}
+            Some(Ok(ProgramCommand::Add(program))) => {
+                if let Err(e) = self.add(program) {
+                    println!("cook: can't add: {:?}", e);
+                    return true;
+                }
+                self.save(&mut crate::storage::open()).ok();
+            }
+            Some(Ok(ProgramCommand::Delete(name))) => {
+                if !self.remove(&name) {
+                    println!("cook: no program {}", name);
+                    return true;
+                }
+                self.save(&mut crate::storage::open()).ok();
+            }
+            Some(Err(e)) => {
+                println!("cook: {:?}", e);
+                println!(
+                    "usage: cook [add NAME: 800W 1:30 / ...] \
+                     [delete NAME]"
+                );
+                return true;
+            }
+        }
+        for program in self.library() {
+            println!("{}", program);
+        }
+        true
+    }
+}
+
+/// What can be typed on the serial console.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgramCommand {
+    /// `cook`
+    List,
+    /// `cook add Soup: 800W 2:00 / 480W 3:00`
+    Add(CookProgram),
+    /// `cook delete Soup`
+    Delete(TimerLabel),
+}
+
+impl ProgramCommand {
+    /// Longest command, `cook add ` and a program of
+    /// [`LINE_LEN`].
+    pub const LINE_LEN: usize =
+        "cook add ".len() + LINE_LEN;
+
+    /// `None` if `line` is no `cook` command at all.
+    pub fn parse(
+        line: &str,
+    ) -> Option<Result<Self, ParseError>> {
+        let line = line.trim();
+        let rest = line.strip_prefix("cook")?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+        let rest = rest.trim_start();
+        if rest.is_empty() {
+            return Some(Ok(ProgramCommand::List));
+        }
+        let (command, arg) =
+            rest.split_once(' ').unwrap_or((rest, ""));
+        let arg = arg.trim();
+        Some(match command {
+            "add" => CookProgram::parse(arg).map(Self::Add),
+            "delete" if arg.is_empty() => {
+                Err(ParseError::Name)
+            }
+            "delete" => Ok(Self::Delete(truncated(arg))),
+            _ => Err(ParseError::Command),
+        })
+    }
+}
+
+/// Follows a [`CookProgram`] while the cook timer counts
+/// it down as a whole, so pausing, resuming and the door
+/// interlock work as for a plain countdown.
+#[derive(Debug, Clone)]
+pub struct ProgramRun {
+    program: CookProgram,
+    stage: usize,
+}
+
+impl ProgramRun {
+    pub fn new(program: CookProgram) -> Self {
+        Self { program, stage: 0 }
+    }
+
+    pub fn program(&self) -> &CookProgram {
+        &self.program
+    }
+
+    /// Index of the current stage.
+    pub fn stage_index(&self) -> usize {
+        self.stage
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.program.stages[self.stage]
+    }
+
+    /// What to set the cook timer to.
+    pub fn duration_ms(&self) -> u64 {
+        self.program.total_secs() * 1000
+    }
+
+    /// Call every frame with the time the cook timer has
+    /// run. Returns the index of the new stage when it
+    /// changed.
+    pub fn update(
+        &mut self,
+        elapsed_ms: u64,
+    ) -> Option<usize> {
+        let stage = self.program.stage_at(elapsed_ms);
+        if stage == self.stage {
+            return None;
+        }
+        self.stage = stage;
+        Some(stage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write;
+
+    use super::*;
+    use crate::storage::{tests::MockFlash, RecordStore};
+
+    fn program(line: &str) -> CookProgram {
+        CookProgram::parse(line).unwrap()
+    }
+
+    #[test]
+    fn programs_are_read_and_written_as_text() {
+        let p = program("Defrost: 180w 3:00/800W 1:30");
+        assert_eq!(p.name(), "Defrost");
+        assert_eq!(
+            p.stages(),
+            [
+                Stage {
+                    level: PowerLevel::MIN,
+                    secs: 180,
+                },
+                Stage {
+                    level: PowerLevel::MAX,
+                    secs: 90,
+                },
+            ]
+        );
+        assert_eq!(p.total_secs(), 270);
+        assert_eq!(
+            p.to_string(),
+            "Defrost: 180W 3:00 / 800W 1:30"
+        );
+        for line in BUILT_IN_PROGRAMS {
+            assert_eq!(program(line).to_string(), line);
+        }
+    }
+
+    #[test]
+    fn broken_lines_are_rejected() {
+        for (line, error) in [
+            ("800W", ParseError::Name),
+            (" : 800W 1:30", ParseError::Name),
+            ("Soup: ", ParseError::Empty),
+            ("Soup: 800W", ParseError::Stage),
+            ("Soup: 900W 1:30", ParseError::Power),
+            ("Soup: 800 1:30", ParseError::Power),
+            ("Soup: 800W 90", ParseError::Duration),
+            ("Soup: 800W 1:3", ParseError::Duration),
+            ("Soup: 800W 1:60", ParseError::Duration),
+            ("Soup: 800W 0:05", ParseError::Duration),
+            ("Soup: 800W 100:00", ParseError::Duration),
+            (
+                "Soup: 800W 1:00 / 800W 1:00 / 800W 1:00 \
+                 / 800W 1:00 / 800W 1:00",
+                ParseError::TooManyStages,
+            ),
+            (
+                "Soup: 800W 60:00 / 180W 40:00",
+                ParseError::TooLong,
+            ),
+        ] {
+            assert_eq!(
+                CookProgram::parse(line),
+                Err(error),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn programs_fit_into_the_cook_timer() {
+        use core::cell::Cell;
+
+        use crate::countdown::CountdownTimer;
+
+        assert_eq!(
+            CookProgram::parse(
+                "Stew: 800W 60:00 / 180W 39:59 / 180W 0:10"
+            ),
+            Err(ParseError::TooLong)
+        );
+        let run = ProgramRun::new(program(
+            "Stew: 800W 60:00 / 180W 39:59",
+        ));
+        let now = Cell::new(0);
+        let mut timer = CountdownTimer::new(|| now.get());
+        timer.set_duration_ms(run.duration_ms());
+        assert_eq!(timer.remaining_ms(), MAX_SECS * 1000);
+    }
+
+    #[test]
+    fn user_programs_extend_the_library() {
+        let mut programs = CookPrograms::new();
+        assert_eq!(
+            programs.library().len(),
+            BUILT_IN_PROGRAMS.len()
+        );
+        assert_eq!(
+            programs.add(program("Popcorn: 620W 3:00")),
+            Err(AddError::BuiltIn)
+        );
+        programs.add(program("Soup: 800W 2:00")).unwrap();
+        programs.add(program("Soup: 620W 2:30")).unwrap();
+        assert_eq!(programs.list.len(), 1);
+        let library = programs.library();
+        assert_eq!(library[0].name(), "Popcorn");
+        assert_eq!(library[3].total_secs(), 150);
+        for i in 1..MAX_USER_PROGRAMS {
+            let mut line = heapless::String::<32>::new();
+            write!(line, "P{}: 360W 1:00", i).unwrap();
+            programs.add(program(&line)).unwrap();
+        }
+        assert_eq!(
+            programs.add(program("Tea: 800W 1:00")),
+            Err(AddError::Full)
+        );
+        assert_eq!(programs.library().len(), LIBRARY_LEN);
+        assert!(programs.remove("Soup"));
+        assert!(!programs.remove("Soup"));
+    }
+
+    #[test]
+    fn user_programs_survive_a_reboot() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(
+            CookPrograms::load(&mut store),
+            Ok(None)
+        );
+        let mut programs = CookPrograms::new();
+        programs
+            .add(program(
+                "Pizza slice: 800W 1:00 / 360W 0:45",
+            ))
+            .unwrap();
+        programs.save(&mut store).unwrap();
+        assert_eq!(
+            CookPrograms::load(&mut store),
+            Ok(Some(programs))
+        );
+    }
+
+    #[test]
+    fn console_commands() {
+        assert_eq!(ProgramCommand::parse("time"), None);
+        assert_eq!(ProgramCommand::parse("cooking"), None);
+        assert_eq!(
+            ProgramCommand::parse(" cook "),
+            Some(Ok(ProgramCommand::List))
+        );
+        assert_eq!(
+            ProgramCommand::parse(
+                "cook add Tea: 800W 1:00"
+            ),
+            Some(Ok(ProgramCommand::Add(program(
+                "Tea: 800W 1:00"
+            ))))
+        );
+        assert_eq!(
+            ProgramCommand::parse("cook add Tea"),
+            Some(Err(ParseError::Name))
+        );
+        assert_eq!(
+            ProgramCommand::parse("cook delete  Iced tea"),
+            Some(Ok(ProgramCommand::Delete(truncated(
+                "Iced tea"
+            ))))
+        );
+        assert_eq!(
+            ProgramCommand::parse("cook delete"),
+            Some(Err(ParseError::Name))
+        );
+        assert_eq!(
+            ProgramCommand::parse("cook burn"),
+            Some(Err(ParseError::Command))
+        );
+    }
+
+    #[test]
+    fn the_longest_program_can_be_typed() {
+        use crate::console::LineBuffer;
+
+        let stages = ["800W 24:59"; MAX_STAGES].join(" / ");
+        let line = std::format!("Longest name: {}", stages);
+        let longest = program(&line);
+        assert_eq!(longest.to_string(), line);
+        // however it is spaced, up to the longest line
+        let spaced = std::format!(
+            "Longest name:{:>1$}",
+            stages,
+            LINE_LEN - "Longest name:".len()
+        );
+        assert_eq!(spaced.len(), LINE_LEN);
+
+        let mut buf = LineBuffer::new();
+        let typed = std::format!("cook add {}\r", spaced)
+            .bytes()
+            .find_map(|b| buf.push(b))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ProgramCommand::parse(&typed),
+            Some(Ok(ProgramCommand::Add(longest)))
+        );
+    }
+
+    #[test]
+    fn a_run_follows_the_elapsed_time() {
+        let mut run = ProgramRun::new(program(
+            "Reheat: 620W 1:30 / 360W 1:00",
+        ));
+        assert_eq!(run.duration_ms(), 150_000);
+        assert_eq!(run.stage().level.label(), "620W");
+        assert_eq!(run.update(0), None);
+        assert_eq!(run.update(89_999), None);
+        assert_eq!(run.update(90_000), Some(1));
+        assert_eq!(run.stage().level.label(), "360W");
+        assert_eq!(run.update(120_000), None);
+        // added time goes to the last stage
+        assert_eq!(run.update(200_000), None);
+        assert_eq!(run.stage_index(), 1);
+        // a reset timer starts over
+        assert_eq!(run.update(0), Some(0));
+    }
+}
@@ -0,0 +1,433 @@
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::Channel,
+};
+
+use crate::{Debouncer, Edge};
+
+/// Time between two samples of the door switch.
+pub const DOOR_SAMPLE_MS: u64 = 5;
+/// Holding the screen this long ends the child lock.
+pub const UNLOCK_HOLD_MS: u64 = 3000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoorEvent {
+    Opened,
+    Closed,
+}
+
+/// Carries the door events from the door task to the UI.
+pub type DoorChannel =
+    Channel<CriticalSectionRawMutex, DoorEvent, 4>;
+
+/// Debounces the door switch. The first sample is taken as
+/// it is and reported, so the app learns how the door is
+/// right from the start.
+#[derive(Debug, Clone, Default)]
+pub struct DoorSensor {
+    debouncer: Debouncer<4>,
+    started: bool,
+}
+
+impl DoorSensor {
+    pub const fn new() -> Self {
+        Self {
+            debouncer: Debouncer::new(),
+            started: false,
+        }
+    }
+
+    /// Adds a sample, `true` while the door is open.
+    pub fn update(
+        &mut self,
+        open: bool,
+    ) -> Option<DoorEvent> {
+        if !self.started {
+            self.started = true;
+            if open {
+                self.debouncer.fill();
+                return Some(DoorEvent::Opened);
+            }
+            self.debouncer.empty();
+            return Some(DoorEvent::Closed);
+        }
+        match self.debouncer.sample(open)? {
+            Edge::Pressed => Some(DoorEvent::Opened),
+            Edge::Released => Some(DoorEvent::Closed),
+        }
+    }
+}
+
+/// Where the cooking is, as far as safety goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookState {
+    Idle,
+    Cooking,
+    /// Paused by the user.
+    Paused,
+    /// Paused because the door was opened. Closing it
+    /// doesn't resume; the user has to press start.
+    Interrupted,
+    /// The time ran out; the alarm rings until it is
+    /// dismissed or the door is opened.
+    Finished,
+}
+
+/// What the user, the door and the cook timer tell the
+/// [`Safety`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Start,
+    Pause,
+    /// Stop and reset, or dismiss the alarm.
+    Cancel,
+    Door(DoorEvent),
+    /// The cook timer ran out.
+    Finished,
+    /// Turn the child lock on. Only a hold of
+    /// [`UNLOCK_HOLD_MS`] turns it off again.
+    Lock,
+}
+
+/// What the app has to do to the cook timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Start,
+    Resume,
+    Pause,
+    /// Reset the timer, which also ends its alarm.
+    Reset,
+}
+
+/// Why an [`Input`] was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refused {
+    DoorOpen,
+    Locked,
+}
+
+/// The door interlock and child lock of the microwave.
+///
+/// All user input goes through [`handle`](Self::handle),
+/// which refuses to start while the door is open and
+/// ignores everything while the child lock is on. The door
+/// is always listened to: opening it stops the magnetron
+/// by pausing the cook timer, and ends the alarm of a
+/// finished one.
+#[derive(Debug, Clone)]
+pub struct Safety {
+    state: CookState,
+    door_open: bool,
+    locked: bool,
+    /// Since when the screen is held, while locked.
+    hold_since: Option<u64>,
+}
+
+impl Safety {
+    /// Assumes the door is open until told otherwise.
+    pub const fn new() -> Self {
+        Self {
+            state: CookState::Idle,
+            door_open: true,
+            locked: false,
+            hold_since: None,
+        }
+    }
+
+    pub fn state(&self) -> CookState {
+        self.state
+    }
+
+    pub fn is_door_open(&self) -> bool {
+        self.door_open
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether start would be accepted right now.
+    pub fn can_start(&self) -> bool {
+        !self.door_open
+            && !self.locked
+            && self.state != CookState::Cooking
+    }
+
+    /// Handles `input` and returns what to do to the cook
+    /// timer, if anything.
+    pub fn handle(
+        &mut self,
+        input: Input,
+    ) -> Result<Option<Command>, Refused> {
+        use CookState::*;
+
+        match input {
+            Input::Door(event) => {
+                return Ok(self.door(event))
+            }
+            Input::Finished => {
+                // the timer may have run out just before
+                // it was paused
+                if matches!(
+                    self.state,
+                    Cooking | Paused | Interrupted
+                ) {
+                    self.state = Finished;
+                }
+                return Ok(None);
+            }
+            _ if self.locked => {
+                return Err(Refused::Locked)
+            }
+            _ => {}
+        }
+
+        let command = match (input, self.state) {
+            (Input::Lock, _) => {
+                self.locked = true;
+                self.hold_since = None;
+                None
+            }
+            (Input::Start, Cooking) => None,
+            (Input::Start, _) if self.door_open => {
+                return Err(Refused::DoorOpen);
+            }
+            (Input::Start, Idle | Finished) => {
+                self.state = Cooking;
+                Some(Command::Start)
+            }
+            (Input::Start, Paused | Interrupted) => {
+                self.state = Cooking;
+                Some(Command::Resume)
+            }
+            (Input::Pause, Cooking) => {
+                self.state = Paused;
+                Some(Command::Pause)
+            }
+            (Input::Pause, _) => None,
+            (Input::Cancel, _) => {
+                self.state = Idle;
+                Some(Command::Reset)
+            }
+            // handled above
+            (Input::Door(_) | Input::Finished, _) => None,
+        };
+        Ok(command)
+    }
+
+    fn door(
+        &mut self,
+        event: DoorEvent,
+    ) -> Option<Command> {
+        self.door_open = event == DoorEvent::Opened;
+        if !self.door_open {
+            return None;
+        }
+        match self.state {
+            CookState::Cooking => {
+                self.state = CookState::Interrupted;
+                Some(Command::Pause)
+            }
+            CookState::Finished => {
+                self.state = CookState::Idle;
+                Some(Command::Reset)
+            }
+            _ => None,
+        }
+    }
+
+    /// Call every frame while locked, with whether the
+    /// screen is held. Returns `true` once it has been held
+    /// for [`UNLOCK_HOLD_MS`] and the lock is off.
+    pub fn hold(&mut self, now: u64, held: bool) -> bool {
+        if !self.locked || !held {
+            self.hold_since = None;
+            return false;
+        }
+        let since = *self.hold_since.get_or_insert(now);
+        if now.saturating_sub(since) < UNLOCK_HOLD_MS {
+            return false;
+        }
+        self.locked = false;
+        self.hold_since = None;
+        true
+    }
+}
+
+impl Default for Safety {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPEN: Input = Input::Door(DoorEvent::Opened);
+    const CLOSE: Input = Input::Door(DoorEvent::Closed);
+
+    fn closed() -> Safety {
+        let mut s = Safety::new();
+        s.handle(CLOSE).unwrap();
+        s
+    }
+
+    fn cooking() -> Safety {
+        let mut s = closed();
+        assert_eq!(
+            s.handle(Input::Start),
+            Ok(Some(Command::Start))
+        );
+        s
+    }
+
+    #[test]
+    fn door_sensor_reports_the_first_sample_then_edges() {
+        let mut d = DoorSensor::new();
+        assert_eq!(
+            d.update(false),
+            Some(DoorEvent::Closed)
+        );
+        assert_eq!(d.update(true), None);
+        assert_eq!(d.update(true), None);
+        assert_eq!(d.update(true), None);
+        assert_eq!(d.update(true), Some(DoorEvent::Opened));
+        let mut d = DoorSensor::new();
+        assert_eq!(d.update(true), Some(DoorEvent::Opened));
+        assert_eq!(d.update(false), None);
+    }
+
+    #[test]
+    fn refuses_to_start_with_the_door_open() {
+        let mut s = Safety::new();
+        assert!(s.is_door_open());
+        assert_eq!(
+            s.handle(Input::Start),
+            Err(Refused::DoorOpen)
+        );
+        assert_eq!(s.state(), CookState::Idle);
+        s.handle(CLOSE).unwrap();
+        assert!(s.can_start());
+        assert_eq!(
+            s.handle(Input::Start),
+            Ok(Some(Command::Start))
+        );
+        assert_eq!(s.handle(Input::Start), Ok(None));
+    }
+
+    #[test]
+    fn opening_the_door_interrupts_until_resumed() {
+        let mut s = cooking();
+        assert_eq!(
+            s.handle(OPEN),
+            Ok(Some(Command::Pause))
+        );
+        assert_eq!(s.state(), CookState::Interrupted);
+        assert_eq!(
+            s.handle(Input::Start),
+            Err(Refused::DoorOpen)
+        );
+        // closing alone doesn't resume
+        assert_eq!(s.handle(CLOSE), Ok(None));
+        assert_eq!(s.state(), CookState::Interrupted);
+        assert_eq!(
+            s.handle(Input::Start),
+            Ok(Some(Command::Resume))
+        );
+        assert_eq!(s.state(), CookState::Cooking);
+    }
+
+    #[test]
+    fn pause_resume_and_cancel() {
+        let mut s = cooking();
+        assert_eq!(
+            s.handle(Input::Pause),
+            Ok(Some(Command::Pause))
+        );
+        assert_eq!(s.handle(Input::Pause), Ok(None));
+        // a paused oven may be opened and closed again
+        s.handle(OPEN).unwrap();
+        assert_eq!(s.state(), CookState::Paused);
+        s.handle(CLOSE).unwrap();
+        assert_eq!(
+            s.handle(Input::Start),
+            Ok(Some(Command::Resume))
+        );
+        assert_eq!(
+            s.handle(Input::Cancel),
+            Ok(Some(Command::Reset))
+        );
+        assert_eq!(s.state(), CookState::Idle);
+    }
+
+    #[test]
+    fn opening_the_door_after_finishing_ends_the_alarm() {
+        let mut s = cooking();
+        s.handle(Input::Finished).unwrap();
+        assert_eq!(s.state(), CookState::Finished);
+        assert_eq!(
+            s.handle(OPEN),
+            Ok(Some(Command::Reset))
+        );
+        assert_eq!(s.state(), CookState::Idle);
+        // finishing while not cooking changes nothing
+        s.handle(Input::Finished).unwrap();
+        assert_eq!(s.state(), CookState::Idle);
+        // and a finished oven can start over
+        let mut s = cooking();
+        s.handle(Input::Finished).unwrap();
+        assert_eq!(
+            s.handle(Input::Start),
+            Ok(Some(Command::Start))
+        );
+    }
+
+    #[test]
+    fn child_lock_ignores_input_but_not_the_door() {
+        let mut s = cooking();
+        assert_eq!(s.handle(Input::Lock), Ok(None));
+        assert!(!s.can_start());
+        for input in [
+            Input::Start,
+            Input::Pause,
+            Input::Cancel,
+            Input::Lock,
+        ] {
+            assert_eq!(
+                s.handle(input),
+                Err(Refused::Locked)
+            );
+        }
+        assert_eq!(s.state(), CookState::Cooking);
+        assert_eq!(
+            s.handle(OPEN),
+            Ok(Some(Command::Pause))
+        );
+        s.handle(CLOSE).unwrap();
+        assert_eq!(
+            s.handle(Input::Start),
+            Err(Refused::Locked)
+        );
+        assert_eq!(s.state(), CookState::Interrupted);
+    }
+
+    #[test]
+    fn only_a_long_hold_unlocks() {
+        let mut s = closed();
+        assert!(!s.hold(0, true));
+        s.handle(Input::Lock).unwrap();
+        assert!(!s.hold(1000, true));
+        assert!(!s.hold(3000, true));
+        // letting go starts over
+        assert!(!s.hold(3100, false));
+        assert!(!s.hold(4000, true));
+        assert!(!s.hold(6999, true));
+        assert!(s.hold(7000, true));
+        assert!(!s.is_locked());
+        assert_eq!(
+            s.handle(Input::Start),
+            Ok(Some(Command::Start))
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use core::fmt::Write;
+
+use embedded_graphics::{
+    mono_font::ascii,
+    pixelcolor::Rgb565,
+    prelude::*,
+};
+use heapless::String;
+use kolibri_embedded_gui::{
+    button::Button,
+    iconbutton::IconButton,
+    icons::size32px,
+    label::Label,
+    smartstate::SmartstateProvider,
+    spacer::Spacer,
+    ui::Ui,
+};
+
+use super::{
+    program::Mmss,
+    AutoCook,
+    AutoTable,
+    CookProgram,
+    Stage,
+};
+use crate::{focus::Focus, timers::LABEL_LEN};
+
+/// What the user picked on the [`program_page`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramAction {
+    /// The program at this index of the library.
+    Pick(usize),
+    /// Back to setting power and time by hand.
+    Manual,
+    /// To the [`auto_page`].
+    Auto,
+    Cancel,
+}
+
+/// Kolibri page to pick a cook program from `library`, two
+/// per row. With the title and the three buttons below, the
+/// full library takes 12 smartstates.
+///
+/// Returns the button the user pressed this frame, if any.
+pub fn program_page<
+    D: DrawTarget<Color = Rgb565>,
+    const N: usize,
+>(
+    ui: &mut Ui<D, Rgb565>,
+    sm: &mut SmartstateProvider<N>,
+    focus: &mut Focus,
+    library: &[CookProgram],
+) -> Option<ProgramAction> {
+    ui.add_centered(
+        Label::new("Cook programs")
+            .smartstate(sm.next())
+            .with_font(ascii::FONT_10X20),
+    );
+    ui.add(Spacer::new(Size::new(0, 10)));
+
+    let mut action = None;
+    for (i, program) in library.iter().enumerate() {
+        let button = Button::new(program.name())
+            .smartstate(sm.next());
+        let response = if i % 2 == 0 {
+            ui.add_horizontal(Spacer::new(Size::new(
+                20, 0,
+            )));
+            ui.add_horizontal(button)
+        } else {
+            ui.add(button)
+        };
+        if focus.track(response).clicked() {
+            action = Some(ProgramAction::Pick(i));
+        }
+    }
+    if library.len() % 2 == 1 {
+        ui.new_row();
+    }
+
+    ui.add(Spacer::new(Size::new(0, 10)));
+    ui.add_horizontal(Spacer::new(Size::new(20, 0)));
+    if focus
+        .track(ui.add_horizontal(
+            Button::new("Manual").smartstate(sm.next()),
+        ))
+        .clicked()
+    {
+        action = Some(ProgramAction::Manual);
+    }
+    if focus
+        .track(ui.add_horizontal(
+            Button::new("Auto").smartstate(sm.next()),
+        ))
+        .clicked()
+    {
+        action = Some(ProgramAction::Auto);
+    }
+    if focus
+        .track(
+            ui.add(
+                Button::new("Back").smartstate(sm.next()),
+            ),
+        )
+        .clicked()
+    {
+        action = Some(ProgramAction::Cancel);
+    }
+    action
+}
+
+/// What the user picked on the [`auto_page`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoAction {
+    /// Cook at this power for this long.
+    Set(Stage),
+    Cancel,
+}
+
+/// Kolibri page of the auto mode: pick a food of `table`
+/// and its weight, and see the power and time they take.
+///
+/// Returns the button the user pressed this frame, if it
+/// closes the page.
+pub fn auto_page<
+    D: DrawTarget<Color = Rgb565>,
+    const N: usize,
+>(
+    ui: &mut Ui<D, Rgb565>,
+    sm: &mut SmartstateProvider<N>,
+    focus: &mut Focus,
+    table: &AutoTable,
+    auto: &mut AutoCook,
+) -> Option<AutoAction> {
+    ui.add_centered(
+        Label::new("Auto cook")
+            .smartstate(sm.next())
+            .with_font(ascii::FONT_10X20),
+    );
+    ui.add(Spacer::new(Size::new(0, 10)));
+
+    // padded, so the buttons don't move with the text
+    let mut text = String::<LABEL_LEN>::new();
+    write!(
+        text,
+        "{:<1$}",
+        auto.food(table).name(),
+        LABEL_LEN
+    )
+    .ok();
+    match stepper(ui, sm, focus, &text) {
+        Some(true) => auto.next_food(table),
+        Some(false) => auto.prev_food(table),
+        None => {}
+    }
+    text.clear();
+    write!(text, "{:>5} g", auto.grams()).ok();
+    match stepper(ui, sm, focus, &text) {
+        Some(true) => auto.heavier(table),
+        Some(false) => auto.lighter(table),
+        None => {}
+    }
+
+    let setting = auto.setting(table);
+    text.clear();
+    write!(
+        text,
+        "{} {}",
+        setting.level.label(),
+        Mmss(setting.secs)
+    )
+    .ok();
+    ui.add(Spacer::new(Size::new(0, 10)));
+    ui.add_centered(
+        Label::new(&text)
+            .smartstate(sm.next())
+            .with_font(ascii::FONT_10X20),
+    );
+
+    let mut action = None;
+    ui.add(Spacer::new(Size::new(0, 10)));
+    ui.add_horizontal(Spacer::new(Size::new(80, 0)));
+    if focus
+        .track(ui.add_horizontal(
+            Button::new("Back").smartstate(sm.next()),
+        ))
+        .clicked()
+    {
+        action = Some(AutoAction::Cancel);
+    }
+    ui.add_horizontal(Spacer::new(Size::new(20, 0)));
+    if focus
+        .track(
+            ui.add(
+                Button::new("Set").smartstate(sm.next()),
+            ),
+        )
+        .clicked()
+    {
+        action = Some(AutoAction::Set(setting));
+    }
+    action
+}
+
+/// A minus button, `text` and a plus button, in one row,
+/// like the microwave's power setting. Returns `Some(true)`
+/// for plus and `Some(false)` for minus.
+fn stepper<
+    D: DrawTarget<Color = Rgb565>,
+    const N: usize,
+>(
+    ui: &mut Ui<D, Rgb565>,
+    sm: &mut SmartstateProvider<N>,
+    focus: &mut Focus,
+    text: &str,
+) -> Option<bool> {
+    let mut step = None;
+    ui.add_horizontal(Spacer::new(Size::new(20, 0)));
+    if focus
+        .track(
+            ui.add_horizontal(
+                IconButton::new(
+                    size32px::actions::MinusCircle,
+                )
+                .smartstate(sm.next()),
+            ),
+        )
+        .clicked()
+    {
+        step = Some(false);
+    }
+    ui.add_horizontal(
+        Label::new(text)
+            .smartstate(sm.next())
+            .with_font(ascii::FONT_10X20),
+    );
+    if focus
+        .track(
+            ui.add(
+                IconButton::new(
+                    size32px::actions::AddCircle,
+                )
+                .smartstate(sm.next()),
+            ),
+        )
+        .clicked()
+    {
+        step = Some(true);
+    }
+    if step.is_some() {
+        // the labels are drawn already, show the new
+        // values next frame
+        sm.force_redraw_all();
+    }
+    step
+}
@@ -0,0 +1,35 @@
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::{AnyInput, GpioPin, Pull};
+use esp_println::println;
+
+use super::{DoorChannel, DoorSensor, DOOR_SAMPLE_MS};
+
+/// Samples the door switch on GPIO35 every
+/// [`DOOR_SAMPLE_MS`] and sends its
+/// [`DoorEvent`](super::DoorEvent)s, starting with the
+/// state it is in.
+///
+/// The switch closes to ground while the door is shut.
+/// GPIO35 has no internal pull-up, so it needs an external
+/// one (10 kΩ to 3.3 V); a broken wire then reads as an
+/// open door.
+#[embassy_executor::task]
+pub async fn door_task(
+    pin: GpioPin<35>,
+    events: &'static DoorChannel,
+) -> ! {
+    let input = AnyInput::new(pin, Pull::None);
+    let mut sensor = DoorSensor::new();
+
+    loop {
+        if let Some(event) = sensor.update(input.is_high())
+        {
+            println!("door: {:?}", event);
+            // the door must never be missed, so wait for
+            // room rather than dropping the event
+            events.send(event).await;
+        }
+        Timer::after(Duration::from_millis(DOOR_SAMPLE_MS))
+            .await;
+    }
+}
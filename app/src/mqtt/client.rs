@@ -0,0 +1,1083 @@
+use core::fmt::Write;
+
+use heapless::{Deque, String, Vec};
+
+use super::packet::{self, Packet, PacketError, Will};
+use crate::lamp::{
+    Colour,
+    LampEvent,
+    LampKind,
+    LampState,
+    MAX_KELVIN,
+    MAX_LAMPS,
+    MIN_KELVIN,
+};
+
+/// Seconds the broker waits for a packet before it drops
+/// the client; the client pings twice as often.
+pub const KEEP_ALIVE_SECS: u16 = 30;
+/// How long the broker has to accept the connection.
+pub const CONNECT_TIMEOUT_MS: u64 = 10_000;
+/// Size of the buffer for [`Client::next_packet`], enough
+/// for every packet the client sends.
+pub const PACKET_LEN: usize = 768;
+/// Longest node id, which starts all topics.
+pub const NODE_LEN: usize = 24;
+/// Longest lamp name.
+pub const NAME_LEN: usize = 32;
+
+/// Longest packet the client takes from the broker.
+const RX_LEN: usize = 256;
+const TOPIC_LEN: usize = 96;
+/// Where Home Assistant looks for lights.
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Packet(PacketError),
+    /// The broker didn't accept the connection, with this
+    /// code.
+    Refused(u8),
+    /// The broker didn't accept a subscription.
+    Subscribe,
+    /// A packet from the broker is longer than
+    /// [`Client`] takes.
+    TooLong,
+}
+
+impl From<PacketError> for Error {
+    fn from(e: PacketError) -> Self {
+        Error::Packet(e)
+    }
+}
+
+/// A change of a lamp, asked for over MQTT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    SetOn { lamp: usize, on: bool },
+    SetBrightness { lamp: usize, brightness: u8 },
+    SetColour { lamp: usize, colour: Colour },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Session {
+    Disconnected,
+    /// Waiting for the broker to accept the connection,
+    /// once CONNECT is `sent`.
+    Connecting {
+        sent: bool,
+    },
+    Connected,
+}
+
+/// The MQTT session of the lamps, without the network: the
+/// transport passes in what it receives and sends out what
+/// [`next_packet`](Self::next_packet) gives it.
+///
+/// Once connected, the client subscribes to the command
+/// topics, announces the lamps to Home Assistant and
+/// publishes their states; after that, whatever changes.
+/// Lamp `n` of node `cyd` uses these topics, the ones for
+/// the brightness and colour only if its [`LampKind`] has
+/// them:
+///
+/// ```text
+/// cyd/status                    online or offline (retained)
+/// cyd/light/n/set               ON or OFF, from Home Assistant
+/// cyd/light/n/brightness/set    0 to 255, from Home Assistant
+/// cyd/light/n/color_temp/set    mireds, from Home Assistant
+/// cyd/light/n/hs/set            hue,saturation in %, likewise
+/// cyd/light/n/state             ON or OFF (retained)
+/// cyd/light/n/brightness        0 to 255 (retained)
+/// cyd/light/n/color_temp        mireds (retained)
+/// cyd/light/n/hs                hue,saturation (retained)
+/// homeassistant/light/cyd/n/config
+/// ```
+#[derive(Debug, Clone)]
+pub struct Client {
+    node: String<NODE_LEN>,
+    names: Vec<String<NAME_LEN>, MAX_LAMPS>,
+    kinds: Vec<LampKind, MAX_LAMPS>,
+    states: Vec<Option<LampState>, MAX_LAMPS>,
+    session: Session,
+    rx: Vec<u8, RX_LEN>,
+    commands: Deque<Command, 8>,
+    packet_id: u16,
+    // what is left to send, one bit per lamp
+    subscribe: bool,
+    online: bool,
+    discovery: u8,
+    state: u8,
+    brightness: u8,
+    colour: u8,
+    last_received: u64,
+    last_ping: u64,
+}
+
+impl Client {
+    /// `node` names the device in the topics, so it should
+    /// be unique on the broker. Longer ones are cut off.
+    pub fn new(node: &str) -> Self {
+        let mut id = String::new();
+        for c in node.chars().take(NODE_LEN) {
+            id.push(c).ok();
+        }
+        Self {
+            node: id,
+            names: Vec::new(),
+            kinds: Vec::new(),
+            states: Vec::new(),
+            session: Session::Disconnected,
+            rx: Vec::new(),
+            commands: Deque::new(),
+            packet_id: 0,
+            subscribe: false,
+            online: false,
+            discovery: 0,
+            state: 0,
+            brightness: 0,
+            colour: 0,
+            last_received: 0,
+            last_ping: 0,
+        }
+    }
+
+    /// Adds the next lamp, with the name it gets in Home
+    /// Assistant and what it can do. Returns `false` if
+    /// there are [`MAX_LAMPS`] already.
+    pub fn add_lamp(
+        &mut self,
+        name: &str,
+        kind: LampKind,
+    ) -> bool {
+        let mut short = String::new();
+        for c in name.chars().take(NAME_LEN) {
+            short.push(c).ok();
+        }
+        self.names.push(short).is_ok()
+            && self.kinds.push(kind).is_ok()
+            && self.states.push(None).is_ok()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.session == Session::Connected
+    }
+
+    /// The transport connected to the broker.
+    pub fn connect(&mut self, now: u64) {
+        self.session = Session::Connecting { sent: false };
+        self.rx.clear();
+        self.last_received = now;
+    }
+
+    /// The connection to the broker is gone.
+    pub fn disconnected(&mut self) {
+        self.session = Session::Disconnected;
+    }
+
+    /// Whether the broker has been quiet for too long, so
+    /// the connection should be dropped.
+    pub fn timed_out(&self, now: u64) -> bool {
+        let quiet = now.saturating_sub(self.last_received);
+        match self.session {
+            Session::Disconnected => false,
+            Session::Connecting { .. } => {
+                quiet > CONNECT_TIMEOUT_MS
+            }
+            Session::Connected => {
+                quiet > KEEP_ALIVE_SECS as u64 * 1500
+            }
+        }
+    }
+
+    /// A lamp changed; its state goes out with the next
+    /// packets.
+    pub fn set_state(&mut self, event: LampEvent) {
+        if let Some(state) = self.states.get_mut(event.lamp)
+        {
+            *state = Some(event.state);
+            self.resend_state(event.lamp);
+        }
+    }
+
+    /// Publishes the state of `lamp` again, e.g. after Home
+    /// Assistant asked for a change that didn't happen.
+    pub fn resend_state(&mut self, lamp: usize) {
+        if lamp < self.states.len() {
+            let bit = 1 << lamp;
+            self.state |= bit;
+            self.brightness |= self.dimmable() & bit;
+            self.colour |= self.coloured() & bit;
+        }
+    }
+
+    /// The next change asked for over MQTT, oldest first.
+    pub fn command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+
+    /// Handles bytes from the broker. An error means the
+    /// connection should be dropped.
+    pub fn receive(
+        &mut self,
+        mut bytes: &[u8],
+        now: u64,
+    ) -> Result<(), Error> {
+        // taken out, so the packets can borrow from it
+        let mut rx = core::mem::take(&mut self.rx);
+        let result = (|| {
+            while !bytes.is_empty() {
+                let free = rx.capacity() - rx.len();
+                if free == 0 {
+                    return Err(Error::TooLong);
+                }
+                let (now_in, later) =
+                    bytes.split_at(free.min(bytes.len()));
+                rx.extend_from_slice(now_in).ok();
+                bytes = later;
+                while let Some((packet, len)) =
+                    Packet::parse(&rx)?
+                {
+                    self.last_received = now;
+                    self.handle(packet, now)?;
+                    rx.copy_within(len.., 0);
+                    rx.truncate(rx.len() - len);
+                }
+            }
+            Ok(())
+        })();
+        self.rx = rx;
+        result
+    }
+
+    fn handle(
+        &mut self,
+        packet: Packet,
+        now: u64,
+    ) -> Result<(), Error> {
+        match packet {
+            Packet::ConnAck { code: 0, .. } => {
+                if self.is_connected() {
+                    return Ok(());
+                }
+                self.session = Session::Connected;
+                self.last_ping = now;
+                self.subscribe = true;
+                self.announce();
+            }
+            Packet::ConnAck { code, .. } => {
+                return Err(Error::Refused(code));
+            }
+            Packet::SubAck { codes, .. } => {
+                if codes.contains(&0x80) {
+                    return Err(Error::Subscribe);
+                }
+            }
+            Packet::Publish { topic, payload, .. } => {
+                self.command_from(topic, payload);
+            }
+            Packet::PingResp => {}
+        }
+        Ok(())
+    }
+
+    /// Queues everything Home Assistant needs to know about
+    /// the lamps.
+    fn announce(&mut self) {
+        let all = (1u16 << self.names.len()) - 1;
+        self.online = true;
+        self.discovery = all as u8;
+        self.state = all as u8;
+        self.brightness = self.dimmable();
+        self.colour = self.coloured();
+    }
+
+    /// The lamps with a brightness, a bit per lamp.
+    fn dimmable(&self) -> u8 {
+        self.lamps_where(LampKind::is_dimmable)
+    }
+
+    /// The lamps with a colour, a bit per lamp.
+    fn coloured(&self) -> u8 {
+        self.lamps_where(|k| k.default_colour().is_some())
+    }
+
+    fn lamps_where(
+        &self,
+        f: impl Fn(LampKind) -> bool,
+    ) -> u8 {
+        self.kinds
+            .iter()
+            .enumerate()
+            .filter(|(_, &kind)| f(kind))
+            .fold(0, |lamps, (i, _)| lamps | 1 << i)
+    }
+
+    fn command_from(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+    ) {
+        if topic.strip_prefix(DISCOVERY_PREFIX)
+            == Some("/status")
+        {
+            // Home Assistant restarted and forgot the lamps
+            if payload == b"online" {
+                self.announce();
+            }
+            return;
+        }
+        let Some(rest) = topic
+            .strip_prefix(self.node.as_str())
+            .and_then(|t| t.strip_prefix("/light/"))
+        else {
+            return;
+        };
+        let (lamp, rest) =
+            rest.split_once('/').unwrap_or((rest, ""));
+        let Some(lamp) = lamp
+            .parse()
+            .ok()
+            .filter(|&lamp| lamp < self.names.len())
+        else {
+            return;
+        };
+        let payload = core::str::from_utf8(payload)
+            .unwrap_or("")
+            .trim();
+        let command = match (rest, payload) {
+            ("set", "ON") => {
+                Command::SetOn { lamp, on: true }
+            }
+            ("set", "OFF") => {
+                Command::SetOn { lamp, on: false }
+            }
+            ("brightness/set", value) => {
+                match value.parse() {
+                    Ok(brightness) => {
+                        Command::SetBrightness {
+                            lamp,
+                            brightness,
+                        }
+                    }
+                    Err(_) => return,
+                }
+            }
+            ("color_temp/set", value) => {
+                match value.parse::<u32>() {
+                    Ok(mireds) if mireds > 0 => {
+                        let kelvin = 1_000_000 / mireds;
+                        Command::SetColour {
+                            lamp,
+                            colour: Colour::temperature(
+                                kelvin.min(u16::MAX as u32)
+                                    as u16,
+                            ),
+                        }
+                    }
+                    _ => return,
+                }
+            }
+            ("hs/set", value) => match parse_hs(value) {
+                Some(colour) => {
+                    Command::SetColour { lamp, colour }
+                }
+                None => return,
+            },
+            _ => return,
+        };
+        if self.commands.is_full() {
+            self.commands.pop_front();
+        }
+        self.commands.push_back(command).ok();
+    }
+
+    /// Writes the next packet to send into `buf`, which
+    /// should have [`PACKET_LEN`] bytes. Returns its
+    /// length, or `None` if there is nothing to send
+    /// right now.
+    pub fn next_packet(
+        &mut self,
+        now: u64,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, Error> {
+        let mut topic = String::<TOPIC_LEN>::new();
+        let len = match self.session {
+            Session::Disconnected
+            | Session::Connecting { sent: true } => {
+                return Ok(None)
+            }
+            Session::Connecting { sent: false } => {
+                self.session =
+                    Session::Connecting { sent: true };
+                write!(topic, "{}/status", self.node).ok();
+                packet::connect(
+                    buf,
+                    &self.node,
+                    KEEP_ALIVE_SECS,
+                    Some(Will {
+                        topic: &topic,
+                        message: b"offline",
+                        retain: true,
+                    }),
+                )?
+            }
+            Session::Connected if self.subscribe => {
+                self.subscribe = false;
+                self.packet_id =
+                    self.packet_id.wrapping_add(1).max(1);
+                let mut filters = [(); 4]
+                    .map(|_| String::<TOPIC_LEN>::new());
+                for (filter, suffix) in
+                    filters.iter_mut().zip([
+                        "set",
+                        "brightness/set",
+                        "color_temp/set",
+                        "hs/set",
+                    ])
+                {
+                    write!(
+                        filter,
+                        "{}/light/+/{}",
+                        self.node, suffix
+                    )
+                    .ok();
+                }
+                write!(
+                    topic,
+                    "{}/status",
+                    DISCOVERY_PREFIX
+                )
+                .ok();
+                let [set, brightness, colour_temp, hs] =
+                    &filters;
+                packet::subscribe(
+                    buf,
+                    self.packet_id,
+                    &[
+                        set,
+                        brightness,
+                        colour_temp,
+                        hs,
+                        &topic,
+                    ],
+                )?
+            }
+            Session::Connected if self.online => {
+                self.online = false;
+                write!(topic, "{}/status", self.node).ok();
+                packet::publish(
+                    buf, &topic, b"online", true,
+                )?
+            }
+            Session::Connected if self.discovery != 0 => {
+                let lamp = take_lowest(&mut self.discovery);
+                write!(
+                    topic,
+                    "{}/light/{}/{}/config",
+                    DISCOVERY_PREFIX, self.node, lamp
+                )
+                .ok();
+                let mut config = String::<
+                    { PACKET_LEN - TOPIC_LEN - 8 },
+                >::new();
+                self.discovery_config(&mut config, lamp)
+                    .map_err(|_| {
+                        PacketError::BufferTooSmall
+                    })?;
+                packet::publish(
+                    buf,
+                    &topic,
+                    config.as_bytes(),
+                    true,
+                )?
+            }
+            Session::Connected
+                if self.known(self.state) != 0 =>
+            {
+                let mut known = self.known(self.state);
+                let lamp = take_lowest(&mut known);
+                self.state &= !(1 << lamp);
+                let on =
+                    self.states[lamp].is_some_and(|s| s.on);
+                write!(
+                    topic,
+                    "{}/light/{}/state",
+                    self.node, lamp
+                )
+                .ok();
+                let payload: &[u8] =
+                    if on { b"ON" } else { b"OFF" };
+                packet::publish(buf, &topic, payload, true)?
+            }
+            Session::Connected
+                if self.known(self.brightness) != 0 =>
+            {
+                let mut known = self.known(self.brightness);
+                let lamp = take_lowest(&mut known);
+                self.brightness &= !(1 << lamp);
+                let brightness = self.states[lamp]
+                    .map_or(0, |s| s.brightness);
+                let mut payload = String::<4>::new();
+                write!(payload, "{}", brightness).ok();
+                write!(
+                    topic,
+                    "{}/light/{}/brightness",
+                    self.node, lamp
+                )
+                .ok();
+                packet::publish(
+                    buf,
+                    &topic,
+                    payload.as_bytes(),
+                    true,
+                )?
+            }
+            Session::Connected
+                if self.known(self.colour) != 0 =>
+            {
+                let mut known = self.known(self.colour);
+                let lamp = take_lowest(&mut known);
+                self.colour &= !(1 << lamp);
+                let mut payload = String::<12>::new();
+                let name = match self.states[lamp]
+                    .and_then(|s| s.colour)
+                    .or(self.kinds[lamp].default_colour())
+                {
+                    Some(Colour::Temperature(kelvin)) => {
+                        write!(
+                            payload,
+                            "{}",
+                            mireds(kelvin)
+                        )
+                        .ok();
+                        "color_temp"
+                    }
+                    Some(Colour::Hs {
+                        hue,
+                        saturation,
+                    }) => {
+                        write!(
+                            payload,
+                            "{},{}",
+                            hue,
+                            (saturation as u32 * 100 + 127)
+                                / 255
+                        )
+                        .ok();
+                        "hs"
+                    }
+                    None => return Ok(None),
+                };
+                write!(
+                    topic,
+                    "{}/light/{}/{}",
+                    self.node, lamp, name
+                )
+                .ok();
+                packet::publish(
+                    buf,
+                    &topic,
+                    payload.as_bytes(),
+                    true,
+                )?
+            }
+            Session::Connected
+                if now.saturating_sub(self.last_ping)
+                    >= KEEP_ALIVE_SECS as u64 * 500 =>
+            {
+                self.last_ping = now;
+                packet::pingreq(buf)?
+            }
+            Session::Connected => return Ok(None),
+        };
+        Ok(Some(len))
+    }
+
+    /// The lamps of `mask` whose state is known.
+    fn known(&self, mask: u8) -> u8 {
+        let known = self
+            .states
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| state.is_some())
+            .fold(0, |known, (i, _)| known | 1 << i);
+        mask & known
+    }
+
+    /// The Home Assistant discovery config of `lamp`, in
+    /// JSON with the abbreviated keys.
+    fn discovery_config(
+        &self,
+        out: &mut impl Write,
+        lamp: usize,
+    ) -> core::fmt::Result {
+        let node = &self.node;
+        write!(out, "{{\"name\":\"")?;
+        json_escape(out, &self.names[lamp])?;
+        write!(
+            out,
+            "\",\"uniq_id\":\"{}_light_{}\"",
+            node, lamp
+        )?;
+        let kind = self.kinds[lamp];
+        let topics: &[(&str, &str)] = match kind {
+            LampKind::Switch => &[],
+            LampKind::Dimmable => &[
+                ("bri_cmd_t", "/brightness/set"),
+                ("bri_stat_t", "/brightness"),
+            ],
+            LampKind::TunableWhite => &[
+                ("bri_cmd_t", "/brightness/set"),
+                ("bri_stat_t", "/brightness"),
+                ("clr_temp_cmd_t", "/color_temp/set"),
+                ("clr_temp_stat_t", "/color_temp"),
+            ],
+            LampKind::Rgb => &[
+                ("bri_cmd_t", "/brightness/set"),
+                ("bri_stat_t", "/brightness"),
+                ("hs_cmd_t", "/hs/set"),
+                ("hs_stat_t", "/hs"),
+            ],
+        };
+        for (key, suffix) in
+            [("cmd_t", "/set"), ("stat_t", "/state")]
+                .iter()
+                .chain(topics)
+        {
+            write!(
+                out,
+                ",\"{}\":\"{}/light/{}{}\"",
+                key, node, lamp, suffix
+            )?;
+        }
+        if kind == LampKind::TunableWhite {
+            write!(
+                out,
+                ",\"min_mirs\":{},\"max_mirs\":{}",
+                mireds(MAX_KELVIN),
+                mireds(MIN_KELVIN)
+            )?;
+        }
+        write!(
+            out,
+            ",\"avty_t\":\"{node}/status\",\"dev\":{{\"ids\":[\"{node}\"],\"name\":\"{node}\"}}}}"
+        )
+    }
+}
+
+/// `kelvin` in the mireds Home Assistant uses.
+fn mireds(kelvin: u16) -> u32 {
+    (1_000_000 + kelvin as u32 / 2) / kelvin as u32
+}
+
+/// A colour from Home Assistant, e.g. `30.5,100.0`, with
+/// the saturation in percent.
+fn parse_hs(payload: &str) -> Option<Colour> {
+    let (hue, saturation) = payload.split_once(',')?;
+    let hue: f32 = hue.trim().parse().ok()?;
+    let saturation: f32 = saturation.trim().parse().ok()?;
+    if !(0.0..=360.0).contains(&hue)
+        || !(0.0..=100.0).contains(&saturation)
+    {
+        return None;
+    }
+    Some(Colour::hs(
+        (hue + 0.5) as u16,
+        (saturation * 255.0 / 100.0 + 0.5) as u8,
+    ))
+}
+
+/// Clears the lowest set bit of `mask`, and returns its
+/// index.
+fn take_lowest(mask: &mut u8) -> usize {
+    let lamp = mask.trailing_zeros() as usize;
+    *mask &= !(1 << lamp);
+    lamp
+}
+
+fn json_escape(
+    out: &mut impl Write,
+    text: &str,
+) -> core::fmt::Result {
+    for c in text.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            c if (c as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", c as u32)?
+            }
+            c => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+/// How long to wait before connecting again: twice as long
+/// after every failure, from [`Backoff::MIN_MS`] up to
+/// [`Backoff::MAX_MS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    next_ms: u64,
+}
+
+impl Backoff {
+    pub const MIN_MS: u64 = 1000;
+    pub const MAX_MS: u64 = 60_000;
+
+    pub const fn new() -> Self {
+        Self {
+            next_ms: Self::MIN_MS,
+        }
+    }
+
+    /// A connection attempt failed, or a connection broke.
+    /// Returns how long to wait before the next one.
+    pub fn failed(&mut self) -> u64 {
+        let delay = self.next_ms;
+        self.next_ms = (delay * 2).min(Self::MAX_MS);
+        delay
+    }
+
+    /// The connection is up again.
+    pub fn reset(&mut self) {
+        self.next_ms = Self::MIN_MS;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The packets the client sends right now.
+    fn sent(
+        c: &mut Client,
+        now: u64,
+    ) -> std::vec::Vec<std::vec::Vec<u8>> {
+        let mut buf = [0; PACKET_LEN];
+        core::iter::from_fn(|| {
+            c.next_packet(now, &mut buf)
+                .unwrap()
+                .map(|len| buf[..len].to_vec())
+        })
+        .collect()
+    }
+
+    /// The topics and payloads of the publishes in
+    /// `packets`.
+    fn publishes(
+        packets: &[std::vec::Vec<u8>],
+    ) -> std::vec::Vec<(&str, &str)> {
+        packets
+            .iter()
+            .filter_map(|p| match Packet::parse(p) {
+                Ok(Some((
+                    Packet::Publish {
+                        topic, payload, ..
+                    },
+                    _,
+                ))) => Some((
+                    topic,
+                    core::str::from_utf8(payload).unwrap(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn connected() -> Client {
+        let mut c = Client::new("cyd");
+        c.add_lamp("Front Door", LampKind::Dimmable);
+        c.add_lamp("Living \"Room\"", LampKind::Dimmable);
+        c.set_state(LampEvent {
+            lamp: 1,
+            state: LampState {
+                on: true,
+                brightness: 80,
+                colour: None,
+            },
+        });
+        c.connect(0);
+        assert_eq!(sent(&mut c, 0).len(), 1);
+        c.receive(b"\x20\x02\x00\x00", 10).unwrap();
+        assert!(c.is_connected());
+        c
+    }
+
+    #[test]
+    fn connects_with_a_will() {
+        let mut c = Client::new("cyd");
+        assert!(sent(&mut c, 0).is_empty());
+        c.connect(0);
+        let packets = sent(&mut c, 0);
+        assert_eq!(
+            packets,
+            [b"\x10\x24\0\x04MQTT\x04\x26\0\x1e\0\x03cyd\
+               \0\x0acyd/status\0\x07offline"
+                .to_vec()]
+        );
+        // nothing more until the broker answers
+        assert!(sent(&mut c, 0).is_empty());
+        assert!(!c.timed_out(CONNECT_TIMEOUT_MS));
+        assert!(c.timed_out(CONNECT_TIMEOUT_MS + 1));
+        assert_eq!(
+            c.receive(b"\x20\x02\x00\x05", 0),
+            Err(Error::Refused(5))
+        );
+    }
+
+    #[test]
+    fn announces_the_lamps_once_connected() {
+        let mut c = connected();
+        let packets = sent(&mut c, 10);
+        assert_eq!(
+            &packets[0],
+            b"\x82\x7a\x00\x01\
+              \x00\x0fcyd/light/+/set\x00\
+              \x00\x1acyd/light/+/brightness/set\x00\
+              \x00\x1acyd/light/+/color_temp/set\x00\
+              \x00\x12cyd/light/+/hs/set\x00\
+              \x00\x14homeassistant/status\x00"
+        );
+        let published = publishes(&packets);
+        assert_eq!(published[0], ("cyd/status", "online"));
+        assert_eq!(
+            published[1],
+            (
+                "homeassistant/light/cyd/0/config",
+                "{\"name\":\"Front Door\",\"uniq_id\":\"cyd_light_0\",\
+                 \"cmd_t\":\"cyd/light/0/set\",\
+                 \"stat_t\":\"cyd/light/0/state\",\
+                 \"bri_cmd_t\":\"cyd/light/0/brightness/set\",\
+                 \"bri_stat_t\":\"cyd/light/0/brightness\",\
+                 \"avty_t\":\"cyd/status\",\
+                 \"dev\":{\"ids\":[\"cyd\"],\"name\":\"cyd\"}}"
+            )
+        );
+        assert!(published[2].1.starts_with(
+            "{\"name\":\"Living \\\"Room\\\"\""
+        ));
+        // only the known state
+        assert_eq!(
+            &published[3..],
+            [
+                ("cyd/light/1/state", "ON"),
+                ("cyd/light/1/brightness", "80")
+            ]
+        );
+        assert!(sent(&mut c, 10).is_empty());
+
+        // changes go out as they happen
+        c.set_state(LampEvent {
+            lamp: 0,
+            state: LampState::OFF,
+        });
+        assert_eq!(
+            publishes(&sent(&mut c, 20)),
+            [
+                ("cyd/light/0/state", "OFF"),
+                ("cyd/light/0/brightness", "255")
+            ]
+        );
+        // Home Assistant restarted
+        let mut buf = [0; 64];
+        let len = packet::publish(
+            &mut buf,
+            "homeassistant/status",
+            b"online",
+            false,
+        )
+        .unwrap();
+        c.receive(&buf[..len], 30).unwrap();
+        assert_eq!(publishes(&sent(&mut c, 30)).len(), 7);
+    }
+
+    #[test]
+    fn takes_commands() {
+        let mut c = connected();
+        sent(&mut c, 10);
+        let mut bytes = std::vec::Vec::new();
+        for (topic, payload) in [
+            ("cyd/light/1/set", "ON"),
+            ("cyd/light/0/brightness/set", "128"),
+            ("cyd/light/0/set", "OFF"),
+            ("cyd/light/1/color_temp/set", "250"),
+            ("cyd/light/1/hs/set", "29.6,50.0"),
+            // not for the lamps
+            ("cyd/light/2/set", "ON"),
+            ("cyd/light/0/set", "on"),
+            ("cyd/light/0/brightness/set", "300"),
+            ("cyd/light/0/color_temp/set", "0"),
+            ("cyd/light/0/hs/set", "10,101"),
+            ("cyd/light/0/hs/set", "10"),
+            ("other/light/0/set", "ON"),
+            ("cyd/light/0/state", "ON"),
+        ] {
+            let mut buf = [0; 64];
+            let len = packet::publish(
+                &mut buf,
+                topic,
+                payload.as_bytes(),
+                false,
+            )
+            .unwrap();
+            bytes.extend_from_slice(&buf[..len]);
+        }
+        // in pieces, like a TCP stream may deliver them
+        for chunk in bytes.chunks(7) {
+            c.receive(chunk, 20).unwrap();
+        }
+        let commands: std::vec::Vec<_> =
+            core::iter::from_fn(|| c.command()).collect();
+        assert_eq!(
+            commands,
+            [
+                Command::SetOn { lamp: 1, on: true },
+                Command::SetBrightness {
+                    lamp: 0,
+                    brightness: 128
+                },
+                Command::SetOn { lamp: 0, on: false },
+                Command::SetColour {
+                    lamp: 1,
+                    colour: Colour::Temperature(4000)
+                },
+                Command::SetColour {
+                    lamp: 1,
+                    colour: Colour::hs(30, 128)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lamps_announce_what_they_can_do() {
+        let mut c = Client::new("cyd");
+        c.add_lamp("Porch", LampKind::Switch);
+        c.add_lamp("Desk", LampKind::TunableWhite);
+        c.add_lamp("Strip", LampKind::Rgb);
+        for lamp in 0..3 {
+            c.set_state(LampEvent {
+                lamp,
+                state: LampState::OFF,
+            });
+        }
+        c.set_state(LampEvent {
+            lamp: 2,
+            state: LampState {
+                colour: Some(Colour::hs(120, 255)),
+                ..LampState::OFF
+            },
+        });
+        c.connect(0);
+        sent(&mut c, 0);
+        c.receive(b"\x20\x02\x00\x00", 0).unwrap();
+        let packets = sent(&mut c, 0);
+        let published = publishes(&packets);
+        let config = |lamp: usize| published[1 + lamp].1;
+        assert!(!config(0).contains("bri_"));
+        assert!(config(1).contains(
+            "\"clr_temp_cmd_t\":\"cyd/light/1/color_temp/set\",\
+             \"clr_temp_stat_t\":\"cyd/light/1/color_temp\",\
+             \"min_mirs\":154,\"max_mirs\":500"
+        ));
+        assert!(config(2).contains(
+            "\"hs_cmd_t\":\"cyd/light/2/hs/set\",\
+             \"hs_stat_t\":\"cyd/light/2/hs\""
+        ));
+        assert!(!config(2).contains("clr_temp"));
+        // no brightness for the switch; the colour lamp
+        // without a colour yet publishes its default one
+        assert_eq!(
+            &published[4..],
+            [
+                ("cyd/light/0/state", "OFF"),
+                ("cyd/light/1/state", "OFF"),
+                ("cyd/light/2/state", "OFF"),
+                ("cyd/light/1/brightness", "255"),
+                ("cyd/light/2/brightness", "255"),
+                ("cyd/light/1/color_temp", "370"),
+                ("cyd/light/2/hs", "120,100"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_connection_alive() {
+        let mut c = connected();
+        sent(&mut c, 10);
+        let half = KEEP_ALIVE_SECS as u64 * 500;
+        assert!(sent(&mut c, half).is_empty());
+        assert_eq!(
+            sent(&mut c, 10 + half),
+            [b"\xc0\x00".to_vec()]
+        );
+        assert!(sent(&mut c, 20 + half).is_empty());
+        c.receive(b"\xd0\x00", 30 + half).unwrap();
+        assert!(!c.timed_out(30 + 4 * half));
+        assert!(c.timed_out(31 + 4 * half));
+        c.disconnected();
+        assert!(!c.timed_out(u64::MAX));
+        assert!(sent(&mut c, 100_000).is_empty());
+        // a long packet, which doesn't fit
+        c.connect(0);
+        let mut buf = [0; PACKET_LEN];
+        let len = packet::publish(
+            &mut buf, "t", &[0; 300], false,
+        )
+        .unwrap();
+        assert_eq!(
+            c.receive(&buf[..len], 0),
+            Err(Error::TooLong)
+        );
+    }
+
+    #[test]
+    fn refused_subscriptions_drop_the_connection() {
+        let mut c = connected();
+        assert_eq!(
+            c.receive(b"\x90\x03\x00\x01\x80", 20),
+            Err(Error::Subscribe)
+        );
+    }
+
+    #[test]
+    fn the_longest_config_fits() {
+        let mut c = Client::new(&"n".repeat(40));
+        for _ in 0..MAX_LAMPS + 1 {
+            c.add_lamp(
+                &"\"".repeat(40),
+                LampKind::TunableWhite,
+            );
+        }
+        c.connect(0);
+        sent(&mut c, 0);
+        c.receive(b"\x20\x02\x00\x00", 0).unwrap();
+        assert_eq!(
+            publishes(&sent(&mut c, 0)).len(),
+            1 + MAX_LAMPS
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_minute() {
+        let mut b = Backoff::new();
+        let delays: std::vec::Vec<_> =
+            (0..8).map(|_| b.failed()).collect();
+        assert_eq!(
+            delays,
+            [
+                1000, 2000, 4000, 8000, 16_000, 32_000,
+                60_000, 60_000
+            ]
+        );
+        b.reset();
+        assert_eq!(b.failed(), 1000);
+    }
+}
@@ -0,0 +1,566 @@
+//! The lamps on an MQTT broker, for Home Assistant.
+//!
+//! [`MqttBackend`] sits between the app and another
+//! [`LampBackend`]: it passes the app's requests on,
+//! publishes every change the lamps confirm, and carries
+//! out the commands Home Assistant publishes. The lamps
+//! announce themselves with [MQTT discovery], so they show
+//! up in Home Assistant without any setup there.
+//!
+//! The MQTT side is a [`Client`] without any networking of
+//! its own, so it runs against a broker on the development
+//! machine as well (see the tests). On the CYD,
+//! [`mqtt_task`] connects it over Wi-Fi and reconnects with
+//! a [`Backoff`] when the connection drops.
+//!
+//! [MQTT discovery]: https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+
+mod client;
+pub mod packet;
+
+#[cfg(all(target_os = "none", feature = "mqtt"))]
+mod task;
+
+use core::{cell::RefCell, str::FromStr};
+
+pub use client::{
+    Backoff,
+    Client,
+    Command,
+    Error,
+    CONNECT_TIMEOUT_MS,
+    KEEP_ALIVE_SECS,
+    NAME_LEN,
+    NODE_LEN,
+    PACKET_LEN,
+};
+use embassy_sync::blocking_mutex::{
+    raw::{CriticalSectionRawMutex, RawMutex},
+    Mutex,
+};
+#[cfg(all(target_os = "none", feature = "mqtt"))]
+pub use task::{mqtt_task, net_task, wifi_task, WifiStack};
+
+use crate::lamp::{
+    serial::{ParseError, Reply},
+    Colour,
+    LampBackend,
+    LampEvent,
+    LampKind,
+    LampState,
+    SerialBackend,
+};
+
+/// Where the broker is, e.g. `192.168.1.10:1883`; the port
+/// defaults to 1883.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Broker {
+    pub ip: [u8; 4],
+    pub port: u16,
+}
+
+impl FromStr for Broker {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (ip, port) = match s.split_once(':') {
+            Some((ip, port)) => {
+                (ip, port.parse().map_err(|_| ())?)
+            }
+            None => (s, 1883),
+        };
+        let mut octets = ip.split('.').map(|o| o.parse());
+        let mut next =
+            || octets.next().and_then(|o| o.ok()).ok_or(());
+        let ip = [next()?, next()?, next()?, next()?];
+        if octets.next().is_some() {
+            return Err(());
+        }
+        Ok(Self { ip, port })
+    }
+}
+
+/// The [`Client`] the app and the transport share.
+pub type SharedClient =
+    Mutex<CriticalSectionRawMutex, RefCell<Client>>;
+
+/// Passes the app's requests on to `inner`, and publishes
+/// what it confirms on `client`, which is shared with the
+/// transport.
+pub struct MqttBackend<'a, B, M: RawMutex> {
+    inner: B,
+    client: &'a Mutex<M, RefCell<Client>>,
+}
+
+impl<'a, B: LampBackend, M: RawMutex>
+    MqttBackend<'a, B, M>
+{
+    /// Publishes the states `inner` knows right away.
+    pub fn new(
+        inner: B,
+        client: &'a Mutex<M, RefCell<Client>>,
+    ) -> Self {
+        client.lock(|c| {
+            let mut c = c.borrow_mut();
+            for lamp in 0..inner.lamp_count() {
+                if let Some(state) = inner.state(lamp) {
+                    c.set_state(LampEvent { lamp, state });
+                }
+            }
+        });
+        Self { inner, client }
+    }
+
+    /// Carries out a command from MQTT. If it fails, the
+    /// state the lamp still has is published again.
+    fn run(&mut self, command: Command) {
+        let (lamp, result) = match command {
+            Command::SetOn { lamp, on } => {
+                (lamp, self.inner.set_on(lamp, on))
+            }
+            Command::SetBrightness { lamp, brightness } => {
+                (
+                    lamp,
+                    self.inner
+                        .set_brightness(lamp, brightness),
+                )
+            }
+            Command::SetColour { lamp, colour } => {
+                (lamp, self.inner.set_colour(lamp, colour))
+            }
+        };
+        if result.is_err() {
+            self.client.lock(|c| {
+                c.borrow_mut().resend_state(lamp)
+            });
+        }
+    }
+}
+
+impl<W: core::fmt::Write, M: RawMutex>
+    MqttBackend<'_, SerialBackend<W>, M>
+{
+    /// See [`SerialBackend::receive`].
+    pub fn receive(
+        &mut self,
+        line: &str,
+    ) -> Option<Result<Reply, ParseError>> {
+        self.inner.receive(line)
+    }
+}
+
+impl<B: LampBackend, M: RawMutex> LampBackend
+    for MqttBackend<'_, B, M>
+{
+    type Error = B::Error;
+
+    fn lamp_count(&self) -> usize {
+        self.inner.lamp_count()
+    }
+
+    fn kind(&self, lamp: usize) -> Option<LampKind> {
+        self.inner.kind(lamp)
+    }
+
+    fn set_on(
+        &mut self,
+        lamp: usize,
+        on: bool,
+    ) -> Result<(), B::Error> {
+        self.inner.set_on(lamp, on)
+    }
+
+    fn set_brightness(
+        &mut self,
+        lamp: usize,
+        brightness: u8,
+    ) -> Result<(), B::Error> {
+        self.inner.set_brightness(lamp, brightness)
+    }
+
+    fn set_colour(
+        &mut self,
+        lamp: usize,
+        colour: Colour,
+    ) -> Result<(), B::Error> {
+        self.inner.set_colour(lamp, colour)
+    }
+
+    fn state(&self, lamp: usize) -> Option<LampState> {
+        self.inner.state(lamp)
+    }
+
+    fn poll(&mut self) -> Option<LampEvent> {
+        while let Some(command) =
+            self.client.lock(|c| c.borrow_mut().command())
+        {
+            self.run(command);
+        }
+        let event = self.inner.poll()?;
+        self.client
+            .lock(|c| c.borrow_mut().set_state(event));
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{ErrorKind, Read, Write},
+        net::TcpStream,
+        time::{Duration, Instant},
+    };
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::{packet::Packet, *};
+    use crate::lamp::{MockBackend, MockError};
+
+    #[test]
+    fn parses_brokers() {
+        assert_eq!(
+            "192.168.1.10:1884".parse(),
+            Ok(Broker {
+                ip: [192, 168, 1, 10],
+                port: 1884
+            })
+        );
+        assert_eq!(
+            "10.0.0.1".parse(),
+            Ok(Broker {
+                ip: [10, 0, 0, 1],
+                port: 1883
+            })
+        );
+        for broker in [
+            "",
+            "10.0.0",
+            "10.0.0.1.2",
+            "10.0.0.256",
+            "1.2.3.4:x",
+        ] {
+            assert_eq!(broker.parse::<Broker>(), Err(()));
+        }
+    }
+
+    fn client(
+        node: &str,
+    ) -> Mutex<NoopRawMutex, RefCell<Client>> {
+        let mut client = Client::new(node);
+        client.add_lamp("Front Door", LampKind::Dimmable);
+        client.add_lamp("Porch", LampKind::Rgb);
+        Mutex::new(RefCell::new(client))
+    }
+
+    /// Connects `client` as if the broker accepted it, and
+    /// returns what it publishes.
+    fn connect(
+        client: &Mutex<NoopRawMutex, RefCell<Client>>,
+    ) -> std::vec::Vec<(
+        std::string::String,
+        std::string::String,
+    )> {
+        client.lock(|c| {
+            let mut c = c.borrow_mut();
+            c.connect(0);
+            c.receive(b"\x20\x02\x00\x00", 0).unwrap();
+            published(&mut c, 0)
+        })
+    }
+
+    fn published(
+        c: &mut Client,
+        now: u64,
+    ) -> std::vec::Vec<(
+        std::string::String,
+        std::string::String,
+    )> {
+        let mut buf = [0; PACKET_LEN];
+        let mut publishes = std::vec::Vec::new();
+        while let Some(len) =
+            c.next_packet(now, &mut buf).unwrap()
+        {
+            if let Ok(Some((
+                Packet::Publish { topic, payload, .. },
+                _,
+            ))) = Packet::parse(&buf[..len])
+            {
+                publishes.push((
+                    topic.into(),
+                    std::string::String::from_utf8_lossy(
+                        payload,
+                    )
+                    .into(),
+                ));
+            }
+        }
+        publishes
+    }
+
+    fn command(
+        client: &Mutex<NoopRawMutex, RefCell<Client>>,
+        topic: &str,
+        payload: &str,
+    ) {
+        let mut buf = [0; 64];
+        let len = packet::publish(
+            &mut buf,
+            topic,
+            payload.as_bytes(),
+            false,
+        )
+        .unwrap();
+        client.lock(|c| {
+            c.borrow_mut().receive(&buf[..len], 0).unwrap()
+        });
+    }
+
+    #[test]
+    fn bridges_the_backend_and_mqtt() {
+        let client = client("cyd");
+        let mut lamps = MqttBackend::new(
+            MockBackend::with_kinds(&[
+                LampKind::Dimmable,
+                LampKind::Rgb,
+            ]),
+            &client,
+        );
+        let announced = connect(&client);
+        assert_eq!(announced.len(), 1 + 2 + 5);
+        assert_eq!(announced[3].0, "cyd/light/0/state");
+        assert_eq!(announced[3].1, "OFF");
+
+        // the app's changes are published once confirmed
+        lamps.set_on(1, true).unwrap();
+        assert_eq!(lamps.poll().unwrap().lamp, 1);
+        let published = client
+            .lock(|c| published(&mut c.borrow_mut(), 0));
+        assert_eq!(published[0].0, "cyd/light/1/state");
+        assert_eq!(published[0].1, "ON");
+
+        // and Home Assistant's are carried out
+        command(
+            &client,
+            "cyd/light/0/brightness/set",
+            "30",
+        );
+        assert_eq!(
+            lamps.poll(),
+            Some(LampEvent {
+                lamp: 0,
+                state: LampState {
+                    on: false,
+                    brightness: 30,
+                    colour: None
+                }
+            })
+        );
+        assert_eq!(lamps.state(0).unwrap().brightness, 30);
+        command(&client, "cyd/light/1/hs/set", "240,100");
+        assert_eq!(
+            lamps.poll().unwrap().state.colour,
+            Some(Colour::hs(240, 255))
+        );
+    }
+
+    #[test]
+    fn failed_commands_publish_the_state_again() {
+        let client = client("cyd");
+        let mut backend = MockBackend::new(2);
+        backend.fail_next();
+        let mut lamps = MqttBackend::new(backend, &client);
+        connect(&client);
+        command(&client, "cyd/light/1/set", "ON");
+        assert_eq!(lamps.poll(), None);
+        let resent = client
+            .lock(|c| published(&mut c.borrow_mut(), 0));
+        assert_eq!(resent[0].0, "cyd/light/1/state");
+        assert_eq!(resent[0].1, "OFF");
+        // the mock's lamp 1 has no colour to set
+        command(&client, "cyd/light/1/hs/set", "0,100");
+        assert_eq!(lamps.poll(), None);
+        let resent = client
+            .lock(|c| published(&mut c.borrow_mut(), 0));
+        assert_eq!(resent[0].0, "cyd/light/1/state");
+        assert_eq!(
+            lamps.set_on(5, true),
+            Err(MockError::NoLamp)
+        );
+    }
+
+    /// A connection to the broker, driven like the device's
+    /// transport does.
+    struct Connection {
+        stream: TcpStream,
+        start: Instant,
+    }
+
+    impl Connection {
+        fn open(client: &mut Client) -> Self {
+            let stream =
+                TcpStream::connect(("127.0.0.1", 1883))
+                    .expect(
+                        "no MQTT broker on localhost:1883",
+                    );
+            stream
+                .set_read_timeout(Some(
+                    Duration::from_millis(20),
+                ))
+                .unwrap();
+            let start = Instant::now();
+            client.connect(0);
+            Self { stream, start }
+        }
+
+        fn now(&self) -> u64 {
+            self.start.elapsed().as_millis() as u64
+        }
+
+        /// Sends and receives for `ms`.
+        fn pump(&mut self, client: &mut Client, ms: u64) {
+            let until = self.now() + ms;
+            let mut packet = [0; PACKET_LEN];
+            let mut buf = [0; 256];
+            while self.now() < until {
+                while let Some(len) = client
+                    .next_packet(self.now(), &mut packet)
+                    .unwrap()
+                {
+                    self.stream
+                        .write_all(&packet[..len])
+                        .unwrap();
+                }
+                match self.stream.read(&mut buf) {
+                    Ok(0) => panic!(
+                        "the broker closed the connection"
+                    ),
+                    Ok(n) => client
+                        .receive(&buf[..n], self.now())
+                        .unwrap(),
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            ErrorKind::WouldBlock
+                                | ErrorKind::TimedOut
+                        ) => {}
+                    Err(e) => panic!("{}", e),
+                }
+                assert!(!client.timed_out(self.now()));
+            }
+        }
+    }
+
+    /// Runs against a Mosquitto broker on this machine,
+    /// e.g. `mosquitto -p 1883`, with
+    /// `cargo test --lib --target x86_64-unknown-linux-gnu
+    /// -- --ignored`.
+    #[test]
+    #[ignore = "needs an MQTT broker on localhost:1883"]
+    fn talks_to_a_local_broker() {
+        let node =
+            std::format!("cyd-test-{}", std::process::id());
+        let lamp = client(&node);
+        let mut backend =
+            MqttBackend::new(MockBackend::new(2), &lamp);
+        let mut lamp_conn = lamp.lock(|c| {
+            Connection::open(&mut c.borrow_mut())
+        });
+
+        // Home Assistant, as a second client
+        let mut ha =
+            Client::new(&std::format!("{}-ha", node));
+        let mut ha_conn = Connection::open(&mut ha);
+        ha_conn.pump(&mut ha, 300);
+        assert!(ha.is_connected());
+        let mut buf = [0; PACKET_LEN];
+        let filters = [
+            std::format!(
+                "homeassistant/light/{}/+/config",
+                node
+            ),
+            std::format!("{}/#", node),
+        ];
+        let filters: std::vec::Vec<&str> =
+            filters.iter().map(|f| f.as_str()).collect();
+        let len = packet::subscribe(&mut buf, 7, &filters)
+            .unwrap();
+        ha_conn.stream.write_all(&buf[..len]).unwrap();
+
+        lamp.lock(|c| {
+            lamp_conn.pump(&mut c.borrow_mut(), 500)
+        });
+        assert!(lamp.lock(|c| c.borrow().is_connected()));
+
+        // the retained discovery configs and states reach
+        // Home Assistant
+        let mut seen = std::vec::Vec::new();
+        let deadline =
+            Instant::now() + Duration::from_secs(2);
+        ha_conn
+            .stream
+            .set_read_timeout(Some(Duration::from_millis(
+                50,
+            )))
+            .unwrap();
+        let mut rx = std::vec::Vec::new();
+        while Instant::now() < deadline {
+            let mut chunk = [0; 256];
+            if let Ok(n) = ha_conn.stream.read(&mut chunk) {
+                rx.extend_from_slice(&chunk[..n]);
+            }
+            while let Ok(Some((packet, len))) =
+                Packet::parse(&rx)
+            {
+                if let Packet::Publish {
+                    topic,
+                    payload,
+                    ..
+                } = packet
+                {
+                    seen.push((
+                        std::string::String::from(topic),
+                        std::string::String::from_utf8_lossy(payload)
+                            .into_owned(),
+                    ));
+                }
+                rx.drain(..len);
+            }
+        }
+        let config = std::format!(
+            "homeassistant/light/{}/1/config",
+            node
+        );
+        assert!(seen.iter().any(|(topic, payload)| {
+            *topic == config
+                && payload.contains("\"name\":\"Porch\"")
+        }));
+        assert!(seen.contains(&(
+            std::format!("{}/light/1/state", node),
+            "OFF".into()
+        )));
+
+        // a command from Home Assistant switches the lamp
+        let len = packet::publish(
+            &mut buf,
+            &std::format!("{}/light/1/set", node),
+            b"ON",
+            false,
+        )
+        .unwrap();
+        ha_conn.stream.write_all(&buf[..len]).unwrap();
+        lamp.lock(|c| {
+            lamp_conn.pump(&mut c.borrow_mut(), 500)
+        });
+        assert_eq!(
+            backend.poll(),
+            Some(LampEvent {
+                lamp: 1,
+                state: LampState {
+                    on: true,
+                    brightness: 255,
+                    colour: None
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,404 @@
+//! The MQTT 3.1.1 packets the [`Client`](super::Client)
+//! sends and receives. Everything is QoS 0, so there are no
+//! acknowledgements to keep track of.
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketError {
+    /// The packet doesn't fit into the buffer.
+    BufferTooSmall,
+    Malformed,
+    /// A packet of this type, which the client never gets
+    /// from a broker.
+    Unexpected(u8),
+}
+
+/// What the broker publishes for the client when the
+/// connection drops without a goodbye.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub message: &'a [u8],
+    pub retain: bool,
+}
+
+/// Writes a packet into a buffer, starting with the fixed
+/// header.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// A packet of `kind` with `flags` and `len` bytes
+    /// after the fixed header.
+    fn new(
+        buf: &'a mut [u8],
+        kind: u8,
+        flags: u8,
+        mut len: usize,
+    ) -> Result<Self, PacketError> {
+        let mut w = Self { buf, pos: 0 };
+        w.u8(kind << 4 | flags)?;
+        loop {
+            let mut byte = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                byte |= 0x80;
+            }
+            w.u8(byte)?;
+            if len == 0 {
+                return Ok(w);
+            }
+        }
+    }
+
+    fn bytes(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<(), PacketError> {
+        let end = self.pos + bytes.len();
+        self.buf
+            .get_mut(self.pos..end)
+            .ok_or(PacketError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.pos = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, byte: u8) -> Result<(), PacketError> {
+        self.bytes(&[byte])
+    }
+
+    fn u16(
+        &mut self,
+        value: u16,
+    ) -> Result<(), PacketError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// A string or binary field: its length, then the
+    /// bytes.
+    fn field(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<(), PacketError> {
+        let len = u16::try_from(bytes.len())
+            .map_err(|_| PacketError::Malformed)?;
+        self.u16(len)?;
+        self.bytes(bytes)
+    }
+
+    fn finish(self) -> usize {
+        self.pos
+    }
+}
+
+/// Writes a CONNECT with a clean session into `buf`.
+/// Returns its length.
+pub fn connect(
+    buf: &mut [u8],
+    client_id: &str,
+    keep_alive_secs: u16,
+    will: Option<Will>,
+) -> Result<usize, PacketError> {
+    let mut len = 10 + 2 + client_id.len();
+    // clean session
+    let mut flags = 0x02;
+    if let Some(will) = will {
+        len +=
+            2 + will.topic.len() + 2 + will.message.len();
+        flags |= 0x04;
+        if will.retain {
+            flags |= 0x20;
+        }
+    }
+    let mut w = Writer::new(buf, CONNECT, 0, len)?;
+    w.field(b"MQTT")?;
+    // protocol level 3.1.1
+    w.u8(4)?;
+    w.u8(flags)?;
+    w.u16(keep_alive_secs)?;
+    w.field(client_id.as_bytes())?;
+    if let Some(will) = will {
+        w.field(will.topic.as_bytes())?;
+        w.field(will.message)?;
+    }
+    Ok(w.finish())
+}
+
+/// Writes a QoS 0 PUBLISH into `buf`. Returns its length.
+pub fn publish(
+    buf: &mut [u8],
+    topic: &str,
+    payload: &[u8],
+    retain: bool,
+) -> Result<usize, PacketError> {
+    let len = 2 + topic.len() + payload.len();
+    let mut w =
+        Writer::new(buf, PUBLISH, retain as u8, len)?;
+    w.field(topic.as_bytes())?;
+    w.bytes(payload)?;
+    Ok(w.finish())
+}
+
+/// Writes a SUBSCRIBE to `filters` at QoS 0 into `buf`.
+/// Returns its length.
+pub fn subscribe(
+    buf: &mut [u8],
+    packet_id: u16,
+    filters: &[&str],
+) -> Result<usize, PacketError> {
+    let len = 2 + filters
+        .iter()
+        .map(|f| 2 + f.len() + 1)
+        .sum::<usize>();
+    let mut w = Writer::new(buf, SUBSCRIBE, 0b0010, len)?;
+    w.u16(packet_id)?;
+    for filter in filters {
+        w.field(filter.as_bytes())?;
+        w.u8(0)?;
+    }
+    Ok(w.finish())
+}
+
+/// Writes a PINGREQ into `buf`. Returns its length.
+pub fn pingreq(
+    buf: &mut [u8],
+) -> Result<usize, PacketError> {
+    Ok(Writer::new(buf, PINGREQ, 0, 0)?.finish())
+}
+
+/// A packet from the broker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet<'a> {
+    ConnAck {
+        session_present: bool,
+        /// 0 if the connection was accepted.
+        code: u8,
+    },
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+        retain: bool,
+    },
+    SubAck {
+        packet_id: u16,
+        /// The granted QoS per filter, `0x80` for a
+        /// refused one.
+        codes: &'a [u8],
+    },
+    PingResp,
+}
+
+impl<'a> Packet<'a> {
+    /// Parses the packet at the start of `buf`. Returns it
+    /// and its length, or `None` while it isn't complete.
+    pub fn parse(
+        buf: &'a [u8],
+    ) -> Result<Option<(Self, usize)>, PacketError> {
+        let Some(&first) = buf.first() else {
+            return Ok(None);
+        };
+        let mut len = 0usize;
+        let mut header = 1;
+        loop {
+            let Some(&byte) = buf.get(header) else {
+                return Ok(None);
+            };
+            if header == 5 {
+                return Err(PacketError::Malformed);
+            }
+            len |= ((byte & 0x7F) as usize)
+                << (7 * (header - 1));
+            header += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let Some(body) = buf.get(header..header + len)
+        else {
+            return Ok(None);
+        };
+        let u16_at = |i: usize| {
+            body.get(i..i + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .ok_or(PacketError::Malformed)
+        };
+        let packet = match first >> 4 {
+            CONNACK => match *body {
+                [flags, code] => Packet::ConnAck {
+                    session_present: flags & 1 == 1,
+                    code,
+                },
+                _ => return Err(PacketError::Malformed),
+            },
+            PUBLISH => {
+                let topic_len = u16_at(0)? as usize;
+                let topic = body
+                    .get(2..2 + topic_len)
+                    .and_then(|t| {
+                        core::str::from_utf8(t).ok()
+                    })
+                    .ok_or(PacketError::Malformed)?;
+                let qos = (first >> 1) & 0b11;
+                // QoS 1 and 2 have a packet id
+                let start = 2
+                    + topic_len
+                    + if qos > 0 { 2 } else { 0 };
+                Packet::Publish {
+                    topic,
+                    payload: body
+                        .get(start..)
+                        .ok_or(PacketError::Malformed)?,
+                    retain: first & 1 == 1,
+                }
+            }
+            SUBACK => Packet::SubAck {
+                packet_id: u16_at(0)?,
+                codes: &body[2..],
+            },
+            PINGRESP => Packet::PingResp,
+            kind => {
+                return Err(PacketError::Unexpected(kind))
+            }
+        };
+        Ok(Some((packet, header + len)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_with_a_will() {
+        let mut buf = [0; 64];
+        let will = Will {
+            topic: "n/status",
+            message: b"offline",
+            retain: true,
+        };
+        let len =
+            connect(&mut buf, "n", 30, Some(will)).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x10\x20\0\x04MQTT\x04\x26\0\x1e\0\x01n\
+              \0\x08n/status\0\x07offline"
+        );
+        let len = connect(&mut buf, "n", 30, None).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x10\x0d\0\x04MQTT\x04\x02\0\x1e\0\x01n"
+        );
+        assert_eq!(
+            connect(&mut buf[..10], "n", 30, None),
+            Err(PacketError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn publishes_round_trip() {
+        let mut buf = [0; 300];
+        let payload = [b'x'; 200];
+        let len = publish(&mut buf, "a/b", &payload, true)
+            .unwrap();
+        // two bytes of remaining length
+        assert_eq!(&buf[..3], b"\x31\xcd\x01");
+        assert_eq!(len, 3 + 205);
+        assert_eq!(
+            Packet::parse(&buf[..len]),
+            Ok(Some((
+                Packet::Publish {
+                    topic: "a/b",
+                    payload: &payload,
+                    retain: true
+                },
+                len
+            )))
+        );
+        // incomplete
+        assert_eq!(Packet::parse(&buf[..2]), Ok(None));
+        assert_eq!(
+            Packet::parse(&buf[..len - 1]),
+            Ok(None)
+        );
+        assert_eq!(Packet::parse(&[]), Ok(None));
+    }
+
+    #[test]
+    fn parses_broker_packets() {
+        assert_eq!(
+            Packet::parse(b"\x20\x02\x00\x00rest"),
+            Ok(Some((
+                Packet::ConnAck {
+                    session_present: false,
+                    code: 0
+                },
+                4
+            )))
+        );
+        assert_eq!(
+            Packet::parse(b"\x90\x04\x00\x07\x00\x80"),
+            Ok(Some((
+                Packet::SubAck {
+                    packet_id: 7,
+                    codes: &[0, 0x80]
+                },
+                6
+            )))
+        );
+        assert_eq!(
+            Packet::parse(b"\xd0\x00"),
+            Ok(Some((Packet::PingResp, 2)))
+        );
+        // QoS 1 publish, with a packet id
+        assert_eq!(
+            Packet::parse(b"\x32\x07\x00\x01t\x00\x05on"),
+            Ok(Some((
+                Packet::Publish {
+                    topic: "t",
+                    payload: b"on",
+                    retain: false
+                },
+                9
+            )))
+        );
+        assert_eq!(
+            Packet::parse(b"\x20\x01\x00"),
+            Err(PacketError::Malformed)
+        );
+        assert_eq!(
+            Packet::parse(b"\x30\x03\x00\x05t"),
+            Err(PacketError::Malformed)
+        );
+        assert_eq!(
+            Packet::parse(b"\x30\xff\xff\xff\xff\x01"),
+            Err(PacketError::Malformed)
+        );
+        assert_eq!(
+            Packet::parse(b"\x10\x00"),
+            Err(PacketError::Unexpected(1))
+        );
+    }
+
+    #[test]
+    fn subscribe_and_ping() {
+        let mut buf = [0; 32];
+        let len =
+            subscribe(&mut buf, 1, &["a/+", "b"]).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x82\x0c\x00\x01\x00\x03a/+\x00\x00\x01b\x00"
+        );
+        let len = pingreq(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\xc0\x00");
+    }
+}
@@ -0,0 +1,196 @@
+use embassy_net::{tcp::TcpSocket, Ipv4Address, Stack};
+use embassy_time::{
+    with_timeout,
+    Duration,
+    Instant,
+    Timer,
+};
+use esp_println::println;
+use esp_wifi::wifi::{
+    ClientConfiguration,
+    Configuration,
+    WifiController,
+    WifiDevice,
+    WifiEvent,
+    WifiStaDevice,
+    WifiState,
+};
+
+use super::{Backoff, Broker, SharedClient, PACKET_LEN};
+
+/// The network to join, set when building with the `mqtt`
+/// feature.
+const SSID: &str = env!("WIFI_SSID");
+const PASSWORD: &str = env!("WIFI_PASSWORD");
+/// The broker, e.g. `192.168.1.10` or `192.168.1.10:1883`.
+const BROKER: &str = env!("MQTT_BROKER");
+
+/// How long a read waits before the client gets a chance to
+/// send what changed in the meantime.
+const POLL_MS: u64 = 50;
+
+pub type WifiStack =
+    Stack<WifiDevice<'static, WifiStaDevice>>;
+
+/// Keeps the CYD on the Wi-Fi network, and joins it again
+/// with a [`Backoff`] whenever the connection drops.
+#[embassy_executor::task]
+pub async fn wifi_task(
+    mut controller: WifiController<'static>,
+) -> ! {
+    let mut backoff = Backoff::new();
+    loop {
+        if esp_wifi::wifi::get_wifi_state()
+            == WifiState::StaConnected
+        {
+            controller
+                .wait_for_event(WifiEvent::StaDisconnected)
+                .await;
+            println!("wifi: disconnected");
+        }
+        if !matches!(controller.is_started(), Ok(true)) {
+            let config = Configuration::Client(
+                ClientConfiguration {
+                    ssid: SSID.try_into().unwrap(),
+                    password: PASSWORD.try_into().unwrap(),
+                    ..Default::default()
+                },
+            );
+            controller.set_configuration(&config).unwrap();
+            controller.start().await.unwrap();
+        }
+        match controller.connect().await {
+            Ok(()) => {
+                println!("wifi: connected to {}", SSID);
+                backoff.reset();
+            }
+            Err(e) => {
+                let delay = backoff.failed();
+                println!(
+                    "wifi: {:?}, retrying in {} ms",
+                    e, delay
+                );
+                Timer::after(Duration::from_millis(delay))
+                    .await;
+            }
+        }
+    }
+}
+
+/// Runs the network stack.
+#[embassy_executor::task]
+pub async fn net_task(stack: &'static WifiStack) -> ! {
+    stack.run().await
+}
+
+/// Connects `client` to the broker in `$MQTT_BROKER` once
+/// the network is up, and keeps it connected, waiting
+/// longer after every failed attempt.
+#[embassy_executor::task]
+pub async fn mqtt_task(
+    stack: &'static WifiStack,
+    client: &'static SharedClient,
+) -> ! {
+    let broker: Broker =
+        BROKER.parse().expect("invalid MQTT_BROKER");
+    let [a, b, c, d] = broker.ip;
+    let addr = (Ipv4Address::new(a, b, c, d), broker.port);
+    let mut rx_buf = [0; 1024];
+    let mut tx_buf = [0; 1024];
+    let mut backoff = Backoff::new();
+    loop {
+        while !stack.is_config_up() {
+            Timer::after(Duration::from_millis(500)).await;
+        }
+        let mut socket =
+            TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+        match socket.connect(addr).await {
+            Ok(()) => {
+                client.lock(|c| {
+                    c.borrow_mut()
+                        .connect(Instant::now().as_millis())
+                });
+                let reason = session(
+                    &mut socket,
+                    client,
+                    &mut backoff,
+                )
+                .await;
+                println!("mqtt: {}", reason);
+                client.lock(|c| {
+                    c.borrow_mut().disconnected()
+                });
+                socket.abort();
+                socket.flush().await.ok();
+            }
+            Err(e) => println!("mqtt: {:?}", e),
+        }
+        let delay = backoff.failed();
+        println!("mqtt: reconnecting in {} ms", delay);
+        Timer::after(Duration::from_millis(delay)).await;
+    }
+}
+
+/// Passes packets between `client` and the broker until the
+/// connection fails. Returns why it did.
+async fn session(
+    socket: &mut TcpSocket<'_>,
+    client: &SharedClient,
+    backoff: &mut Backoff,
+) -> &'static str {
+    let mut packet = [0; PACKET_LEN];
+    let mut buf = [0; 256];
+    loop {
+        let now = Instant::now().as_millis();
+        loop {
+            let next = client.lock(|c| {
+                c.borrow_mut().next_packet(now, &mut packet)
+            });
+            let len = match next {
+                Ok(Some(len)) => len,
+                Ok(None) => break,
+                Err(_) => return "packet too long",
+            };
+            let mut sent = 0;
+            while sent < len {
+                match socket.write(&packet[sent..len]).await
+                {
+                    Ok(0) | Err(_) => {
+                        return "connection lost"
+                    }
+                    Ok(n) => sent += n,
+                }
+            }
+        }
+
+        let read = with_timeout(
+            Duration::from_millis(POLL_MS),
+            socket.read(&mut buf),
+        )
+        .await;
+        let now = Instant::now().as_millis();
+        match read {
+            Ok(Ok(0)) => return "closed by the broker",
+            Ok(Ok(n)) => {
+                if let Err(e) = client.lock(|c| {
+                    c.borrow_mut().receive(&buf[..n], now)
+                }) {
+                    println!("mqtt: {:?}", e);
+                    return "broker error";
+                }
+            }
+            Ok(Err(_)) => return "connection lost",
+            Err(_) => {}
+        }
+        let (connected, timed_out) = client.lock(|c| {
+            let c = c.borrow();
+            (c.is_connected(), c.timed_out(now))
+        });
+        if timed_out {
+            return "timed out";
+        }
+        if connected {
+            backoff.reset();
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! What the apps' digital and PWM outputs share, like the
+//! microwave's relays and the lamps.
+
+/// Whether an output is switched on by driving it high or
+/// low.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    /// E.g. the CYD's RGB LED, or relay boards with an
+    /// inverting driver.
+    ActiveLow,
+}
+
+impl Polarity {
+    /// Whether to drive the pin high to get `on`.
+    pub fn is_high(self, on: bool) -> bool {
+        match self {
+            Polarity::ActiveHigh => on,
+            Polarity::ActiveLow => !on,
+        }
+    }
+}
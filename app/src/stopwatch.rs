@@ -0,0 +1,233 @@
+//! A stopwatch with lap times, for the timer app.
+
+use heapless::Vec;
+
+use crate::countdown::Clock;
+
+/// Most laps a [`Stopwatch`] records. Laps after that are
+/// not recorded.
+pub const MAX_LAPS: usize = 50;
+
+/// Counts up from zero; can be stopped and started again.
+/// [`lap`](Self::lap) records the time since the previous
+/// lap.
+#[derive(Debug, Clone)]
+pub struct Stopwatch<C: Clock> {
+    clock: C,
+    /// When it was last started, while it runs.
+    started_ms: Option<u64>,
+    /// Time counted before it was last started.
+    banked_ms: u64,
+    laps: Vec<u64, MAX_LAPS>,
+    /// The elapsed time at the end of the last lap.
+    lap_start_ms: u64,
+}
+
+impl<C: Clock> Stopwatch<C> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            started_ms: None,
+            banked_ms: 0,
+            laps: Vec::new(),
+            lap_start_ms: 0,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started_ms.is_some()
+    }
+
+    /// Stopped at zero, without laps.
+    pub fn is_reset(&self) -> bool {
+        !self.is_running()
+            && self.banked_ms == 0
+            && self.laps.is_empty()
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        match self.started_ms {
+            Some(started) => self.banked_ms.saturating_add(
+                self.clock.now_ms().saturating_sub(started),
+            ),
+            None => self.banked_ms,
+        }
+    }
+
+    /// Starts or continues counting. Returns `false` if it
+    /// was running already.
+    pub fn start(&mut self) -> bool {
+        if self.is_running() {
+            return false;
+        }
+        self.started_ms = Some(self.clock.now_ms());
+        true
+    }
+
+    /// Stops counting. Returns `false` if it was not
+    /// running.
+    pub fn stop(&mut self) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+        self.banked_ms = self.elapsed_ms();
+        self.started_ms = None;
+        true
+    }
+
+    /// Stops and goes back to zero, forgetting the laps.
+    pub fn reset(&mut self) {
+        self.started_ms = None;
+        self.banked_ms = 0;
+        self.laps.clear();
+        self.lap_start_ms = 0;
+    }
+
+    /// Ends the current lap and returns its time. Only
+    /// while running, and only [`MAX_LAPS`] times.
+    pub fn lap(&mut self) -> Option<u64> {
+        if !self.is_running() || self.laps.is_full() {
+            return None;
+        }
+        let now = self.elapsed_ms();
+        let lap = now - self.lap_start_ms;
+        self.laps.push(lap).ok()?;
+        self.lap_start_ms = now;
+        Some(lap)
+    }
+
+    /// Time since the end of the last lap.
+    pub fn current_lap_ms(&self) -> u64 {
+        self.elapsed_ms() - self.lap_start_ms
+    }
+
+    /// The recorded lap times, first lap first.
+    pub fn laps(&self) -> &[u64] {
+        &self.laps
+    }
+
+    /// Index of the fastest lap, once there are two laps
+    /// to compare. The first one wins a tie.
+    pub fn best(&self) -> Option<usize> {
+        if self.laps.len() < 2 {
+            return None;
+        }
+        // `min_by_key` keeps the first of equal elements
+        (0..self.laps.len()).min_by_key(|&i| self.laps[i])
+    }
+
+    /// Index of the slowest lap, once there are two laps
+    /// to compare. The first one wins a tie.
+    pub fn worst(&self) -> Option<usize> {
+        if self.laps.len() < 2 {
+            return None;
+        }
+        // `max_by_key` keeps the last of equal elements
+        (0..self.laps.len())
+            .rev()
+            .max_by_key(|&i| self.laps[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+
+    fn stopwatch(
+        now: &Cell<u64>,
+    ) -> Stopwatch<impl Clock + '_> {
+        Stopwatch::new(move || now.get())
+    }
+
+    #[test]
+    fn counts_up_while_running() {
+        let now = Cell::new(5_000);
+        let mut s = stopwatch(&now);
+        assert!(s.is_reset());
+        assert!(s.start());
+        assert!(!s.start());
+        now.set(6_250);
+        assert_eq!(s.elapsed_ms(), 1_250);
+        assert!(s.stop());
+        assert!(!s.stop());
+        now.set(60_000);
+        assert_eq!(s.elapsed_ms(), 1_250);
+        assert!(!s.is_reset());
+        s.start();
+        now.set(61_000);
+        assert_eq!(s.elapsed_ms(), 2_250);
+    }
+
+    #[test]
+    fn laps_measure_the_time_since_the_last_lap() {
+        let now = Cell::new(0);
+        let mut s = stopwatch(&now);
+        assert_eq!(s.lap(), None);
+        s.start();
+        now.set(30_000);
+        assert_eq!(s.lap(), Some(30_000));
+        now.set(40_000);
+        s.stop();
+        // the pause does not count
+        now.set(90_000);
+        s.start();
+        now.set(115_000);
+        assert_eq!(s.current_lap_ms(), 35_000);
+        assert_eq!(s.lap(), Some(35_000));
+        assert_eq!(s.laps(), [30_000, 35_000]);
+        assert_eq!(s.elapsed_ms(), 65_000);
+    }
+
+    #[test]
+    fn best_and_worst_lap() {
+        let now = Cell::new(0);
+        let mut s = stopwatch(&now);
+        s.start();
+        now.set(31_000);
+        s.lap();
+        assert_eq!(s.best(), None);
+        assert_eq!(s.worst(), None);
+        for t in [60_000, 95_000, 124_000, 159_000] {
+            now.set(t);
+            s.lap();
+        }
+        // 31, 29, 35, 29, 35
+        assert_eq!(s.best(), Some(1));
+        assert_eq!(s.worst(), Some(2));
+    }
+
+    #[test]
+    fn reset_forgets_everything() {
+        let now = Cell::new(0);
+        let mut s = stopwatch(&now);
+        s.start();
+        now.set(1_000);
+        s.lap();
+        now.set(2_000);
+        s.reset();
+        assert!(s.is_reset());
+        assert_eq!(s.elapsed_ms(), 0);
+        assert!(s.laps().is_empty());
+        now.set(3_000);
+        assert_eq!(s.elapsed_ms(), 0);
+        s.start();
+        now.set(3_500);
+        assert_eq!(s.lap(), Some(500));
+    }
+
+    #[test]
+    fn laps_stop_being_recorded_when_full() {
+        let now = Cell::new(0);
+        let mut s = stopwatch(&now);
+        s.start();
+        for i in 1..=MAX_LAPS as u64 {
+            now.set(i * 1_000);
+            assert_eq!(s.lap(), Some(1_000));
+        }
+        now.set(99_000);
+        assert_eq!(s.lap(), None);
+        assert_eq!(s.laps().len(), MAX_LAPS);
+    }
+}
@@ -0,0 +1,632 @@
+//! Small record store on top of a NOR flash region.
+//!
+//! Every [`Slot`] owns one erase sector and holds at most
+//! one record: a header (magic, length, CRC-32) followed by
+//! the payload. Writing a slot erases its sector first, so
+//! a record is either fully there or reported as
+//! missing after a power cut.
+//!
+//! On the CYD the region is the `storage` partition from
+//! `partitions.csv` at the repository root.
+
+use core::fmt;
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::Vec;
+
+/// Flash offset of the `storage` partition (see
+/// `partitions.csv`).
+pub const STORAGE_PARTITION_OFFSET: u32 = 0x3C_0000;
+/// Size of the `storage` partition.
+pub const STORAGE_PARTITION_SIZE: u32 = 0x4_0000;
+/// Size of one slot. Matches the ESP32's flash erase
+/// granularity.
+pub const SECTOR_SIZE: u32 = 4096;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"CYDR");
+const HEADER_LEN: usize = 12;
+const CHUNK_LEN: usize = 64;
+
+/// Largest payload a slot can hold.
+pub const MAX_RECORD_LEN: usize =
+    SECTOR_SIZE as usize - HEADER_LEN;
+
+/// What a sector in the storage partition is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Slot {
+    CrashReport      = 0,
+    Calibration      = 1,
+    TimerPresets     = 2,
+    IntervalPrograms = 3,
+    WallClock        = 4,
+    CookPrograms     = 5,
+    AutoCookTable    = 6,
+    Lighting         = 7,
+}
+
+impl Slot {
+    fn offset(self) -> u32 {
+        self as u32 * SECTOR_SIZE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageError<E> {
+    Flash(E),
+    /// The record doesn't fit into a slot.
+    TooLarge,
+    /// The caller's buffer can't hold the stored record.
+    BufferTooSmall,
+}
+
+pub struct RecordStore<F> {
+    flash: F,
+    base: u32,
+}
+
+impl<F: NorFlash> RecordStore<F> {
+    /// Creates a store using the flash region starting at
+    /// `base`.
+    pub fn new(flash: F, base: u32) -> Self {
+        Self { flash, base }
+    }
+
+    /// Reads the record in `slot` into `buf`, returning the
+    /// filled part.
+    ///
+    /// Returns `Ok(None)` for empty, erased or corrupted
+    /// slots.
+    pub fn load<'b>(
+        &mut self,
+        slot: Slot,
+        buf: &'b mut [u8],
+    ) -> Result<Option<&'b [u8]>, StorageError<F::Error>>
+    {
+        let start = self.base + slot.offset();
+        let mut header = [0u8; HEADER_LEN];
+        self.read(start, &mut header)?;
+
+        let magic = u32::from_le_bytes(
+            header[0..4].try_into().unwrap(),
+        );
+        let len = u32::from_le_bytes(
+            header[4..8].try_into().unwrap(),
+        ) as usize;
+        let crc = u32::from_le_bytes(
+            header[8..12].try_into().unwrap(),
+        );
+        if magic != MAGIC || len > MAX_RECORD_LEN {
+            return Ok(None);
+        }
+        if len > buf.len() {
+            return Err(StorageError::BufferTooSmall);
+        }
+
+        let data = &mut buf[..len];
+        self.read(start + HEADER_LEN as u32, data)?;
+        if crc32(data) != crc {
+            return Ok(None);
+        }
+        Ok(Some(data))
+    }
+
+    /// Replaces the record in `slot` with `data`.
+    pub fn store(
+        &mut self,
+        slot: Slot,
+        data: &[u8],
+    ) -> Result<(), StorageError<F::Error>> {
+        if data.len() > MAX_RECORD_LEN {
+            return Err(StorageError::TooLarge);
+        }
+        self.clear(slot)?;
+
+        let mut header = [0u8; HEADER_LEN];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(
+            &(data.len() as u32).to_le_bytes(),
+        );
+        header[8..12]
+            .copy_from_slice(&crc32(data).to_le_bytes());
+
+        // NOR flash writes need to be aligned, so stream
+        // header and payload through a padded chunk buffer.
+        let mut offset = self.base + slot.offset();
+        let mut bytes = header.iter().chain(data).copied();
+        loop {
+            let mut chunk = [0xFFu8; CHUNK_LEN];
+            let mut filled = 0usize;
+            for (dst, src) in
+                chunk.iter_mut().zip(&mut bytes)
+            {
+                *dst = src;
+                filled += 1;
+            }
+            if filled == 0 {
+                break;
+            }
+            let len =
+                filled.next_multiple_of(F::WRITE_SIZE);
+            self.flash
+                .write(offset, &chunk[..len])
+                .map_err(StorageError::Flash)?;
+            offset += len as u32;
+        }
+        Ok(())
+    }
+
+    /// Erases `slot`.
+    pub fn clear(
+        &mut self,
+        slot: Slot,
+    ) -> Result<(), StorageError<F::Error>> {
+        let start = self.base + slot.offset();
+        self.flash
+            .erase(start, start + SECTOR_SIZE)
+            .map_err(StorageError::Flash)
+    }
+
+    /// Reads `buf.len()` bytes at `offset`, honouring the
+    /// flash's read alignment.
+    fn read(
+        &mut self,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<(), StorageError<F::Error>> {
+        let mut chunk = [0u8; CHUNK_LEN];
+        let mut done = 0;
+        while done < buf.len() {
+            let len = (buf.len() - done).min(CHUNK_LEN);
+            let aligned =
+                len.next_multiple_of(F::READ_SIZE);
+            self.flash
+                .read(
+                    offset + done as u32,
+                    &mut chunk[..aligned],
+                )
+                .map_err(StorageError::Flash)?;
+            buf[done..done + len]
+                .copy_from_slice(&chunk[..len]);
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+/// Opens the CYD's `storage` partition.
+#[cfg(target_os = "none")]
+pub fn open() -> RecordStore<esp_storage::FlashStorage> {
+    RecordStore::new(
+        esp_storage::FlashStorage::new(),
+        STORAGE_PARTITION_OFFSET,
+    )
+}
+
+/// Settings kept in a [`Slot`] as their lines of text,
+/// after a version byte, e.g. the groups and scenes of the
+/// light-control app. The record takes `N` bytes at most.
+pub trait TextRecord<const N: usize>: Sized {
+    const SLOT: Slot;
+    /// Stored in front of the lines. Records of other
+    /// versions are ignored.
+    const FORMAT_VERSION: u8;
+
+    /// Writes the settings, a line per entry.
+    fn write_lines(
+        &self,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result;
+
+    /// Inverse of [`write_lines`](Self::write_lines),
+    /// without the blank lines. `None` if one of them isn't
+    /// valid.
+    fn from_lines<'a>(
+        lines: impl Iterator<Item = &'a str>,
+    ) -> Option<Self>;
+
+    /// The version, then the lines. Fails if they take
+    /// more than `N` bytes.
+    fn to_bytes(&self) -> Result<Vec<u8, N>, fmt::Error> {
+        let mut buf = Vec::new();
+        buf.push(Self::FORMAT_VERSION)
+            .map_err(|_| fmt::Error)?;
+        self.write_lines(&mut Bytes(&mut buf))?;
+        Ok(buf)
+    }
+
+    /// Inverse of [`to_bytes`](Self::to_bytes). Rejects
+    /// other format versions and invalid lines.
+    fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let (&version, text) = buf.split_first()?;
+        if version != Self::FORMAT_VERSION {
+            return None;
+        }
+        let text = core::str::from_utf8(text).ok()?;
+        Self::from_lines(
+            text.lines().filter(|l| !l.trim().is_empty()),
+        )
+    }
+
+    fn save<F: NorFlash>(
+        &self,
+        store: &mut RecordStore<F>,
+    ) -> Result<(), StorageError<F::Error>> {
+        let bytes = self
+            .to_bytes()
+            .map_err(|_| StorageError::TooLarge)?;
+        store.store(Self::SLOT, &bytes)
+    }
+
+    fn load<F: NorFlash>(
+        store: &mut RecordStore<F>,
+    ) -> Result<Option<Self>, StorageError<F::Error>> {
+        let mut buf = [0u8; N];
+        Ok(store
+            .load(Self::SLOT, &mut buf)?
+            .and_then(Self::from_bytes))
+    }
+
+    /// What the CYD's `storage` partition holds, if
+    /// anything valid.
+    #[cfg(target_os = "none")]
+    fn stored() -> Option<Self> {
+        Self::load(&mut open()).ok().flatten()
+    }
+}
+
+/// Writes `items` a line each, for
+/// [`TextRecord::write_lines`].
+pub fn write_lines<T: fmt::Display>(
+    out: &mut impl fmt::Write,
+    items: &[T],
+) -> fmt::Result {
+    for item in items {
+        writeln!(out, "{}", item)?;
+    }
+    Ok(())
+}
+
+/// Reads `lines` with `parse`, for
+/// [`TextRecord::from_lines`]. `None` if one of them fails
+/// or there are more than `M`.
+pub fn parse_lines<'a, T, E, const M: usize>(
+    lines: impl Iterator<Item = &'a str>,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> Option<Vec<T, M>> {
+    let mut items = Vec::new();
+    for line in lines {
+        items.push(parse(line).ok()?).ok()?;
+    }
+    Some(items)
+}
+
+/// Text written into a byte buffer.
+struct Bytes<'a, const N: usize>(&'a mut Vec<u8, N>);
+
+impl<const N: usize> fmt::Write for Bytes<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0
+            .extend_from_slice(s.as_bytes())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+/// CRC-32 (IEEE 802.3), bitwise to avoid a 1 KiB table.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use embedded_storage::nor_flash::{
+        ErrorType,
+        NorFlashError,
+        NorFlashErrorKind,
+        ReadNorFlash,
+    };
+
+    use super::*;
+
+    /// RAM-backed flash with the ESP32's geometry.
+    pub(crate) struct MockFlash {
+        pub data: [u8; 8 * SECTOR_SIZE as usize],
+    }
+
+    impl MockFlash {
+        pub fn new() -> Self {
+            Self {
+                data: [0xFF; 8 * SECTOR_SIZE as usize],
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct MockError(NorFlashErrorKind);
+
+    impl NorFlashError for MockError {
+        fn kind(&self) -> NorFlashErrorKind {
+            self.0
+        }
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = MockError;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 4;
+
+        fn read(
+            &mut self,
+            offset: u32,
+            bytes: &mut [u8],
+        ) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if offset % Self::READ_SIZE != 0
+                || bytes.len() % Self::READ_SIZE != 0
+            {
+                return Err(MockError(
+                    NorFlashErrorKind::NotAligned,
+                ));
+            }
+            bytes.copy_from_slice(
+                &self.data[offset..offset + bytes.len()],
+            );
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+        fn erase(
+            &mut self,
+            from: u32,
+            to: u32,
+        ) -> Result<(), Self::Error> {
+            if from % SECTOR_SIZE != 0
+                || to % SECTOR_SIZE != 0
+            {
+                return Err(MockError(
+                    NorFlashErrorKind::NotAligned,
+                ));
+            }
+            self.data[from as usize..to as usize]
+                .fill(0xFF);
+            Ok(())
+        }
+
+        fn write(
+            &mut self,
+            offset: u32,
+            bytes: &[u8],
+        ) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if offset % Self::WRITE_SIZE != 0
+                || bytes.len() % Self::WRITE_SIZE != 0
+            {
+                return Err(MockError(
+                    NorFlashErrorKind::NotAligned,
+                ));
+            }
+            // NOR flash can only clear bits.
+            for (cell, byte) in self.data
+                [offset..offset + bytes.len()]
+                .iter_mut()
+                .zip(bytes)
+            {
+                *cell &= byte;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn empty_slot_loads_none() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        let mut buf = [0u8; 32];
+        assert_eq!(
+            store.load(Slot::CrashReport, &mut buf),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn store_then_load_roundtrips() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        // Odd length to exercise the padding path.
+        let data: [u8; 101] =
+            core::array::from_fn(|i| i as u8);
+        store.store(Slot::CrashReport, &data).unwrap();
+
+        let mut buf = [0u8; 128];
+        let loaded = store
+            .load(Slot::CrashReport, &mut buf)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded, &data[..]);
+    }
+
+    #[test]
+    fn store_overwrites_previous_record() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), SECTOR_SIZE);
+        store.store(Slot::CrashReport, b"first").unwrap();
+        store.store(Slot::CrashReport, b"2nd").unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            store.load(Slot::CrashReport, &mut buf),
+            Ok(Some(&b"2nd"[..]))
+        );
+    }
+
+    #[test]
+    fn clear_empties_slot() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        store.store(Slot::CrashReport, b"data").unwrap();
+        store.clear(Slot::CrashReport).unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            store.load(Slot::CrashReport, &mut buf),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn corrupted_record_loads_none() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        store.store(Slot::CrashReport, b"payload").unwrap();
+        store.flash.data[HEADER_LEN] = b'P' & 0x0F;
+
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            store.load(Slot::CrashReport, &mut buf),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn small_buffer_is_an_error() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        store.store(Slot::CrashReport, b"payload").unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            store.load(Slot::CrashReport, &mut buf),
+            Err(StorageError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn oversized_record_is_rejected() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        let data = [0u8; MAX_RECORD_LEN + 1];
+        assert_eq!(
+            store.store(Slot::CrashReport, &data),
+            Err(StorageError::TooLarge)
+        );
+    }
+
+    /// Up to three numbers, a line each.
+    #[derive(Debug, PartialEq)]
+    struct Numbers(Vec<u16, 3>);
+
+    impl TextRecord<16> for Numbers {
+        const SLOT: Slot = Slot::CrashReport;
+        const FORMAT_VERSION: u8 = 2;
+
+        fn write_lines(
+            &self,
+            out: &mut impl fmt::Write,
+        ) -> fmt::Result {
+            write_lines(out, &self.0)
+        }
+
+        fn from_lines<'a>(
+            lines: impl Iterator<Item = &'a str>,
+        ) -> Option<Self> {
+            parse_lines(lines, str::parse).map(Numbers)
+        }
+    }
+
+    #[test]
+    fn text_records_are_versioned_lines() {
+        let numbers =
+            Numbers(Vec::from_slice(&[7, 300]).unwrap());
+        assert_eq!(
+            &numbers.to_bytes().unwrap()[..],
+            b"\x027\n300\n"
+        );
+        assert_eq!(
+            Numbers::from_bytes(b"\x02\n 7\n\n300"),
+            None
+        );
+        assert_eq!(
+            Numbers::from_bytes(b"\x027\n\n300"),
+            Some(numbers)
+        );
+        // other versions, bad lines or too many of them
+        for bytes in [
+            &b"\x017\n300\n"[..],
+            b"\x02x\n",
+            b"\x021\n2\n3\n4\n",
+            b"",
+        ] {
+            assert_eq!(Numbers::from_bytes(bytes), None);
+        }
+        assert_eq!(
+            Numbers::from_bytes(b"\x02"),
+            Some(Numbers(Vec::new()))
+        );
+    }
+
+    #[test]
+    fn text_records_are_saved_to_their_slot() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(Numbers::load(&mut store), Ok(None));
+        let numbers =
+            Numbers(Vec::from_slice(&[1]).unwrap());
+        numbers.save(&mut store).unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            store.load(Slot::CrashReport, &mut buf),
+            Ok(Some(&b"\x021\n"[..]))
+        );
+        assert_eq!(
+            Numbers::load(&mut store),
+            Ok(Some(numbers))
+        );
+    }
+
+    #[test]
+    fn text_records_too_long_for_n_are_not_saved() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        let numbers =
+            Numbers(Vec::from_slice(&[1, 2, 3]).unwrap());
+        numbers.save(&mut store).unwrap();
+        // 19 bytes
+        let long = Numbers(
+            Vec::from_slice(&[60000, 60000, 60000])
+                .unwrap(),
+        );
+        assert_eq!(long.to_bytes(), Err(fmt::Error));
+        assert_eq!(
+            long.save(&mut store),
+            Err(StorageError::TooLarge)
+        );
+        assert_eq!(
+            Numbers::load(&mut store),
+            Ok(Some(numbers))
+        );
+    }
+}
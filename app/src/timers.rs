@@ -0,0 +1,478 @@
+//! Several named countdowns running side by side, e.g.
+//! "Pasta 9:00" and "Oven 25:00".
+//!
+//! Each entry of a [`TimerList`] is an independent
+//! [`CountdownTimer`]. The list notices when one of them
+//! finishes and keeps it [alerting](TimerList::alerting)
+//! until the alert is
+//! [acknowledged](TimerList::acknowledge).
+//!
+//! [`Presets`] are the durations on the timer app's keypad
+//! page, kept in flash.
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::{String, Vec};
+
+use crate::{
+    countdown::{
+        Clock,
+        CountdownTimer,
+        TimerEvent,
+        MIN_SECS,
+    },
+    storage::{RecordStore, Slot, StorageError},
+};
+
+/// Most timers that can exist at once. Four rows fit the
+/// list screen.
+pub const MAX_TIMERS: usize = 4;
+/// Longest label, in bytes. Longer labels are cut.
+pub const LABEL_LEN: usize = 12;
+
+/// Longest duration of a timer of the list, 99:59:59, more
+/// than the other countdowns get.
+pub const MAX_TIMER_SECS: u64 = 99 * 3600 + 59 * 60 + 59;
+
+pub type TimerLabel = String<LABEL_LEN>;
+
+/// One countdown of a [`TimerList`].
+#[derive(Debug, Clone)]
+pub struct NamedTimer<C: Clock> {
+    label: TimerLabel,
+    pub timer: CountdownTimer<C>,
+    /// Finished, and nobody has seen it yet.
+    alerting: bool,
+}
+
+impl<C: Clock> NamedTimer<C> {
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn set_label(&mut self, label: &str) {
+        self.label = truncated(label);
+    }
+
+    pub fn is_alerting(&self) -> bool {
+        self.alerting
+    }
+}
+
+/// The timers of the timer app, in the order they were
+/// added.
+#[derive(Debug, Clone)]
+pub struct TimerList<C: Clock + Clone> {
+    clock: C,
+    timers: Vec<NamedTimer<C>, MAX_TIMERS>,
+}
+
+impl<C: Clock + Clone> TimerList<C> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            timers: Vec::new(),
+        }
+    }
+
+    /// Adds a stopped timer set to `secs`, clamped to
+    /// [`MIN_SECS`] ..= [`MAX_TIMER_SECS`]. Returns its
+    /// index, or `None` if the list is full.
+    pub fn add(
+        &mut self,
+        label: &str,
+        secs: u64,
+    ) -> Option<usize> {
+        let mut timer = CountdownTimer::with_max(
+            self.clock.clone(),
+            MAX_TIMER_SECS,
+        );
+        timer.set_duration_ms(secs.saturating_mul(1000));
+        self.timers
+            .push(NamedTimer {
+                label: truncated(label),
+                timer,
+                alerting: false,
+            })
+            .ok()?;
+        Some(self.timers.len() - 1)
+    }
+
+    /// Removes a timer; the ones after it move up.
+    pub fn remove(
+        &mut self,
+        index: usize,
+    ) -> Option<NamedTimer<C>> {
+        (index < self.timers.len())
+            .then(|| self.timers.remove(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.timers.is_full()
+    }
+
+    pub fn get(
+        &self,
+        index: usize,
+    ) -> Option<&NamedTimer<C>> {
+        self.timers.get(index)
+    }
+
+    pub fn get_mut(
+        &mut self,
+        index: usize,
+    ) -> Option<&mut NamedTimer<C>> {
+        self.timers.get_mut(index)
+    }
+
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = &NamedTimer<C>> {
+        self.timers.iter()
+    }
+
+    pub fn iter_mut(
+        &mut self,
+    ) -> impl Iterator<Item = &mut NamedTimer<C>> {
+        self.timers.iter_mut()
+    }
+
+    /// Updates every timer. Returns `true` if one of them
+    /// finished just now, i.e. an alert was raised. A timer
+    /// that was reset or started again stops alerting.
+    pub fn update(&mut self) -> bool {
+        let mut raised = false;
+        for t in self.timers.iter_mut() {
+            if t.timer.update()
+                == Some(TimerEvent::Finished)
+            {
+                t.alerting = true;
+                raised = true;
+            } else if !t.timer.is_finished() {
+                t.alerting = false;
+            }
+        }
+        raised
+    }
+
+    /// The running timer that runs out first.
+    pub fn soonest(&self) -> Option<usize> {
+        self.timers
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.timer.is_running())
+            .min_by_key(|(_, t)| t.timer.remaining_ms())
+            .map(|(i, _)| i)
+    }
+
+    /// Whether a timer finished that has not been
+    /// acknowledged yet.
+    pub fn is_alerting(&self) -> bool {
+        self.timers.iter().any(|t| t.alerting)
+    }
+
+    /// The timers that finished and have not been
+    /// acknowledged yet.
+    pub fn alerting(
+        &self,
+    ) -> impl Iterator<Item = &NamedTimer<C>> {
+        self.timers.iter().filter(|t| t.alerting)
+    }
+
+    /// Silences the alert and resets the timers that raised
+    /// it, so they can be started again.
+    pub fn acknowledge(&mut self) {
+        for t in
+            self.timers.iter_mut().filter(|t| t.alerting)
+        {
+            t.alerting = false;
+            t.timer.reset();
+        }
+    }
+}
+
+/// Number of preset durations.
+pub const PRESET_COUNT: usize = 4;
+
+/// The preset durations of the keypad page, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Presets {
+    pub secs: [u32; PRESET_COUNT],
+}
+
+impl Presets {
+    /// One, three, five and ten minutes.
+    pub const DEFAULT: Self = Self {
+        secs: [60, 3 * 60, 5 * 60, 10 * 60],
+    };
+
+    const FORMAT_VERSION: u8 = 1;
+
+    pub const ENCODED_LEN: usize = 1 + PRESET_COUNT * 4;
+
+    /// Replaces preset `index` with `secs`, clamped like
+    /// the timers' durations.
+    pub fn set(&mut self, index: usize, secs: u64) {
+        if let Some(p) = self.secs.get_mut(index) {
+            *p =
+                secs.clamp(MIN_SECS, MAX_TIMER_SECS) as u32;
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        buf[0] = Self::FORMAT_VERSION;
+        for (chunk, secs) in
+            buf[1..].chunks_exact_mut(4).zip(self.secs)
+        {
+            chunk.copy_from_slice(&secs.to_le_bytes());
+        }
+        buf
+    }
+
+    /// Inverse of [`Presets::to_bytes`]. Rejects other
+    /// format versions and durations a timer can't have.
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() != Self::ENCODED_LEN
+            || buf[0] != Self::FORMAT_VERSION
+        {
+            return None;
+        }
+        let mut secs = [0u32; PRESET_COUNT];
+        for (secs, chunk) in
+            secs.iter_mut().zip(buf[1..].chunks_exact(4))
+        {
+            *secs =
+                u32::from_le_bytes(chunk.try_into().ok()?);
+            if !(MIN_SECS..=MAX_TIMER_SECS)
+                .contains(&(*secs as u64))
+            {
+                return None;
+            }
+        }
+        Some(Self { secs })
+    }
+
+    pub fn save<F: NorFlash>(
+        &self,
+        store: &mut RecordStore<F>,
+    ) -> Result<(), StorageError<F::Error>> {
+        store.store(Slot::TimerPresets, &self.to_bytes())
+    }
+
+    pub fn load<F: NorFlash>(
+        store: &mut RecordStore<F>,
+    ) -> Result<Option<Self>, StorageError<F::Error>> {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        Ok(store
+            .load(Slot::TimerPresets, &mut buf)?
+            .and_then(Self::from_bytes))
+    }
+}
+
+impl Default for Presets {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Loads the stored presets, falling back to
+/// [`Presets::DEFAULT`].
+#[cfg(target_os = "none")]
+pub fn stored_presets() -> Presets {
+    Presets::load(&mut crate::storage::open())
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// `label`, cut to [`LABEL_LEN`] bytes at a character
+/// boundary.
+pub(crate) fn truncated(label: &str) -> TimerLabel {
+    let mut out = TimerLabel::new();
+    for c in label.chars() {
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::storage::tests::MockFlash;
+
+    fn list(
+        now: &Cell<u64>,
+    ) -> TimerList<impl Clock + Clone + '_> {
+        TimerList::new(move || now.get())
+    }
+
+    #[test]
+    fn timers_run_independently() {
+        let now = Cell::new(0);
+        let mut l = list(&now);
+        let pasta = l.add("Pasta", 9 * 60).unwrap();
+        let oven = l.add("Oven", 25 * 60).unwrap();
+        l.get_mut(oven).unwrap().timer.start();
+        now.set(60_000);
+        l.get_mut(pasta).unwrap().timer.start();
+        now.set(120_000);
+        assert_eq!(
+            l.get(pasta).unwrap().timer.remaining_ms(),
+            8 * 60_000
+        );
+        assert_eq!(
+            l.get(oven).unwrap().timer.remaining_ms(),
+            23 * 60_000
+        );
+        l.get_mut(oven).unwrap().timer.pause();
+        now.set(600_000);
+        assert!(l.get(pasta).unwrap().timer.is_running());
+        assert_eq!(
+            l.get(oven).unwrap().timer.remaining_ms(),
+            23 * 60_000
+        );
+    }
+
+    #[test]
+    fn soonest_only_counts_running_timers() {
+        let now = Cell::new(0);
+        let mut l = list(&now);
+        assert_eq!(l.soonest(), None);
+        l.add("Oven", 25 * 60);
+        l.add("Pasta", 9 * 60);
+        l.add("Eggs", 5 * 60);
+        assert_eq!(l.soonest(), None);
+        l.get_mut(0).unwrap().timer.start();
+        l.get_mut(1).unwrap().timer.start();
+        assert_eq!(l.soonest(), Some(1));
+        // paused timers do not run out
+        l.get_mut(1).unwrap().timer.pause();
+        assert_eq!(l.soonest(), Some(0));
+    }
+
+    #[test]
+    fn finishing_raises_an_alert_until_acknowledged() {
+        let now = Cell::new(0);
+        let mut l = list(&now);
+        l.add("Tea", 60);
+        l.add("Rice", 60 * 20);
+        l.get_mut(0).unwrap().timer.start();
+        l.get_mut(1).unwrap().timer.start();
+        assert!(!l.update());
+        assert!(!l.is_alerting());
+
+        now.set(60_000);
+        assert!(l.update());
+        // raised once
+        assert!(!l.update());
+        assert!(l.is_alerting());
+        assert!(l
+            .alerting()
+            .map(|t| t.label())
+            .eq(["Tea"]));
+
+        l.acknowledge();
+        assert!(!l.is_alerting());
+        assert!(l.get(0).unwrap().timer.is_stopped());
+        assert!(l.get(1).unwrap().timer.is_running());
+    }
+
+    #[test]
+    fn restarting_a_timer_ends_its_alert() {
+        let now = Cell::new(0);
+        let mut l = list(&now);
+        l.add("Tea", 60);
+        l.get_mut(0).unwrap().timer.start();
+        now.set(60_000);
+        assert!(l.update());
+        l.get_mut(0).unwrap().timer.start();
+        assert!(!l.update());
+        assert!(!l.is_alerting());
+    }
+
+    #[test]
+    fn the_list_is_bounded() {
+        let now = Cell::new(0);
+        let mut l = list(&now);
+        for i in 0..MAX_TIMERS {
+            assert_eq!(l.add("Timer", 60), Some(i));
+        }
+        assert!(l.is_full());
+        assert_eq!(l.add("Timer", 60), None);
+        assert!(l.remove(MAX_TIMERS).is_none());
+        l.get_mut(1).unwrap().set_label("Second");
+        l.remove(0);
+        assert_eq!(l.get(0).unwrap().label(), "Second");
+        assert_eq!(l.len(), MAX_TIMERS - 1);
+    }
+
+    #[test]
+    fn labels_are_cut_at_a_char_boundary() {
+        let now = Cell::new(0);
+        let mut l = list(&now);
+        l.add("Spaghetti Bolognese", 60);
+        assert_eq!(
+            l.get(0).unwrap().label(),
+            "Spaghetti Bo"
+        );
+        l.get_mut(0).unwrap().set_label("Crème brûlée!");
+        assert_eq!(l.get(0).unwrap().label(), "Crème brûl");
+    }
+
+    #[test]
+    fn presets_are_clamped() {
+        let mut p = Presets::DEFAULT;
+        p.set(0, 2 * 3600);
+        p.set(1, 0);
+        p.set(2, u64::MAX);
+        p.set(PRESET_COUNT, 60);
+        assert_eq!(
+            p.secs,
+            [
+                2 * 3600,
+                MIN_SECS as u32,
+                MAX_TIMER_SECS as u32,
+                600
+            ]
+        );
+    }
+
+    #[test]
+    fn presets_survive_a_reboot() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(Presets::load(&mut store), Ok(None));
+        let mut p = Presets::DEFAULT;
+        p.set(3, 25 * 60);
+        p.save(&mut store).unwrap();
+        assert_eq!(Presets::load(&mut store), Ok(Some(p)));
+    }
+
+    #[test]
+    fn broken_presets_are_rejected() {
+        let mut bytes = Presets::DEFAULT.to_bytes();
+        bytes[0] = 0;
+        assert_eq!(Presets::from_bytes(&bytes), None);
+        let mut bytes = Presets::DEFAULT.to_bytes();
+        bytes[1..5].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(Presets::from_bytes(&bytes), None);
+        assert_eq!(
+            Presets::from_bytes(
+                &Presets::DEFAULT.to_bytes()
+            ),
+            Some(Presets::DEFAULT)
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use embedded_graphics::prelude::Point;
+use embedded_hal::spi::SpiDevice;
+
+/// Control bytes: start bit, channel, 12 bit differential
+/// mode. The low two bits select the power mode after the
+/// conversion: `01` keeps PENIRQ off while we sample, `00`
+/// powers down and re-enables it.
+const READ_X: u8 = 0x91;
+const READ_Y: u8 = 0xD1;
+const READ_Z1: u8 = 0xB1;
+const READ_Z2: u8 = 0xC1;
+const POWER_DOWN: u8 = 0x90;
+
+/// Full scale of the 12 bit ADC.
+pub const ADC_MAX: u16 = 4095;
+
+/// Samples with less [pressure](RawSample::pressure) are
+/// light grazes and shouldn't count as touches.
+pub const MIN_PRESSURE: u16 = 400;
+
+/// One reading of all four channels of the XPT2046.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawSample {
+    pub x: u16,
+    pub y: u16,
+    pub z1: u16,
+    pub z2: u16,
+}
+
+impl RawSample {
+    /// Position in raw ADC units, the input space of the
+    /// [calibration](crate::calibration).
+    pub fn point(&self) -> Point {
+        Point::new(self.x as i32, self.y as i32)
+    }
+
+    /// How firmly the panel is pressed: 0 when it isn't, up
+    /// to about [`ADC_MAX`] for a firm press. `z1`
+    /// rises and `z2` falls with the contact area.
+    pub fn pressure(&self) -> u16 {
+        if self.z1 == 0 {
+            return 0;
+        }
+        (self.z1 + ADC_MAX)
+            .saturating_sub(self.z2)
+            .min(ADC_MAX)
+    }
+
+    /// Touch resistance in ohms, from the datasheet formula
+    /// `R = R_x-plate * x / 4096 * (z2 / z1 - 1)`. Lower is
+    /// a firmer press; `None` when not touched.
+    pub fn resistance(
+        &self,
+        x_plate_ohms: u32,
+    ) -> Option<u32> {
+        if self.z1 == 0 || self.z2 < self.z1 {
+            return None;
+        }
+        let r = x_plate_ohms as u64
+            * self.x as u64
+            * (self.z2 - self.z1) as u64
+            / (4096 * self.z1 as u64);
+        Some(r as u32)
+    }
+}
+
+/// Minimal XPT2046 driver that reads the raw channels,
+/// including the Z1/Z2 pressure channels the `xpt2046`
+/// crate doesn't expose.
+pub struct TouchController<SPI> {
+    spi: SPI,
+    oversample: u8,
+}
+
+impl<SPI: SpiDevice> TouchController<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Self { spi, oversample: 4 }
+    }
+
+    /// Number of conversions averaged per channel (at least
+    /// one).
+    pub fn set_oversample(&mut self, oversample: u8) {
+        self.oversample = oversample.max(1);
+    }
+
+    /// Reads all channels and powers the controller down
+    /// again, so PENIRQ signals the next touch.
+    pub fn sample(
+        &mut self,
+    ) -> Result<RawSample, SPI::Error> {
+        let z1 = self.read_channel(READ_Z1)?;
+        let z2 = self.read_channel(READ_Z2)?;
+        let x = self.read_channel(READ_X)?;
+        let y = self.read_channel(READ_Y)?;
+        self.convert(POWER_DOWN)?;
+        Ok(RawSample { x, y, z1, z2 })
+    }
+
+    fn read_channel(
+        &mut self,
+        command: u8,
+    ) -> Result<u16, SPI::Error> {
+        // the first conversion after switching channels
+        // hasn't settled yet
+        self.convert(command)?;
+        let mut sum = 0u32;
+        for _ in 0..self.oversample {
+            sum += self.convert(command)? as u32;
+        }
+        Ok((sum / self.oversample as u32) as u16)
+    }
+
+    fn convert(
+        &mut self,
+        command: u8,
+    ) -> Result<u16, SPI::Error> {
+        let mut buf = [command, 0, 0];
+        self.spi.transfer_in_place(&mut buf)?;
+        Ok(u16::from_be_bytes([buf[1], buf[2]]) >> 3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(x: u16, z1: u16, z2: u16) -> RawSample {
+        RawSample { x, y: 2000, z1, z2 }
+    }
+
+    #[test]
+    fn no_contact_has_no_pressure() {
+        let s = sample(0, 0, 4095);
+        assert_eq!(s.pressure(), 0);
+        assert_eq!(s.resistance(300), None);
+    }
+
+    #[test]
+    fn firmer_press_has_more_pressure_and_less_resistance()
+    {
+        let graze = sample(2048, 300, 3900);
+        let press = sample(2048, 900, 2400);
+        assert!(press.pressure() > graze.pressure());
+        assert!(
+            press.resistance(300).unwrap()
+                < graze.resistance(300).unwrap()
+        );
+        assert_eq!(graze.pressure(), 495);
+        assert_eq!(press.pressure(), 2595);
+        // 300 * 2048 / 4096 * (2400 / 900 - 1) = 250
+        assert_eq!(press.resistance(300), Some(250));
+    }
+
+    #[test]
+    fn pressure_saturates() {
+        assert_eq!(
+            sample(100, 4095, 0).pressure(),
+            ADC_MAX
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! Touch events and the channel that carries them from the
+//! touch task to the UI.
+//!
+//! A `Signal` only keeps the latest touch point, so a tap
+//! that starts and ends between two frames got lost. The
+//! [`TouchChannel`] instead queues [`TouchEvent`]s in
+//! order. Only consecutive moves are merged, so presses
+//! and releases always come through. When it runs full,
+//! events are dropped and
+//! [counted](TouchChannel::take_overflowed), but always a
+//! whole press at a time, so consumers never see a press
+//! without its release.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{
+    raw::{CriticalSectionRawMutex, RawMutex},
+    Mutex,
+};
+use embedded_graphics::prelude::Point;
+use heapless::Deque;
+use kolibri_embedded_gui::ui::Interaction;
+
+/// Events the [`TouchChannel`] holds by default. Moves are
+/// merged, so this is about eight taps.
+pub const TOUCH_QUEUE_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchKind {
+    Press,
+    Move,
+    Release,
+}
+
+/// One change of the touch state, in screen coordinates.
+/// A release carries the last touched point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TouchEvent {
+    pub kind: TouchKind,
+    pub point: Point,
+    /// When the touch task saw it, e.g.
+    /// `Instant::now().as_millis()`.
+    pub t_ms: u64,
+}
+
+impl TouchEvent {
+    pub const fn new(
+        kind: TouchKind,
+        point: Point,
+        t_ms: u64,
+    ) -> Self {
+        Self { kind, point, t_ms }
+    }
+
+    /// The Kolibri interaction for this event.
+    pub fn interaction(&self) -> Interaction {
+        match self.kind {
+            TouchKind::Press => {
+                Interaction::Click(self.point)
+            }
+            TouchKind::Move => {
+                Interaction::Drag(self.point)
+            }
+            TouchKind::Release => {
+                Interaction::Release(self.point)
+            }
+        }
+    }
+}
+
+/// Turns a stream of touch samples (the point, or `None`
+/// when not touched) into [`TouchEvent`]s.
+#[derive(Debug, Clone, Default)]
+pub struct TouchTracker {
+    last: Option<Point>,
+}
+
+impl TouchTracker {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Feeds a sample taken at `t_ms` and returns the event
+    /// it caused, if any. Samples that repeat the last
+    /// point cause none.
+    pub fn update(
+        &mut self,
+        t_ms: u64,
+        touch: Option<Point>,
+    ) -> Option<TouchEvent> {
+        let kind = match (self.last, touch) {
+            (None, Some(_)) => TouchKind::Press,
+            (Some(last), Some(p)) if last != p => {
+                TouchKind::Move
+            }
+            (Some(_), None) => TouchKind::Release,
+            _ => return None,
+        };
+        let point = touch.or(self.last)?;
+        self.last = touch;
+        Some(TouchEvent::new(kind, point, t_ms))
+    }
+}
+
+/// Bounded queue of [`TouchEvent`]s, without any locking.
+/// See the [module docs](self) for what it keeps when it
+/// runs full.
+#[derive(Debug, Clone)]
+pub struct TouchQueue<const N: usize> {
+    events: Deque<TouchEvent, N>,
+    /// A press was dropped, so its moves and release have
+    /// to go as well.
+    dropping: bool,
+    overflowed: u32,
+}
+
+impl<const N: usize> TouchQueue<N> {
+    pub const fn new() -> Self {
+        Self {
+            events: Deque::new(),
+            dropping: false,
+            overflowed: 0,
+        }
+    }
+
+    /// Queues an event. Returns `false` if it (or an
+    /// earlier one, to make room) had to be dropped.
+    pub fn push(&mut self, event: TouchEvent) -> bool {
+        if self.dropping && event.kind != TouchKind::Press {
+            self.dropping =
+                event.kind != TouchKind::Release;
+            self.overflowed += 1;
+            return false;
+        }
+        self.dropping = false;
+
+        let back = self.events.back().map(|e| e.kind);
+        if event.kind == TouchKind::Move
+            && back == Some(TouchKind::Move)
+        {
+            *self.events.back_mut().unwrap() = event;
+            return true;
+        }
+        if !self.events.is_full() {
+            self.events.push_back(event).ok();
+            return true;
+        }
+
+        match (event.kind, back) {
+            // the release has the newer point anyway
+            (TouchKind::Release, Some(TouchKind::Move)) => {
+                *self.events.back_mut().unwrap() = event;
+                self.overflowed += 1;
+            }
+            // a press nobody has seen yet: drop all of it
+            (
+                TouchKind::Release,
+                Some(TouchKind::Press),
+            ) => {
+                self.events.pop_back();
+                self.overflowed += 2;
+            }
+            (TouchKind::Press, _) => {
+                self.dropping = true;
+                self.overflowed += 1;
+            }
+            _ => self.overflowed += 1,
+        }
+        false
+    }
+
+    pub fn pop(&mut self) -> Option<TouchEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Number of events dropped since the last call.
+    pub fn take_overflowed(&mut self) -> u32 {
+        core::mem::take(&mut self.overflowed)
+    }
+}
+
+impl<const N: usize> Default for TouchQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`TouchQueue`] that the touch task and the UI can
+/// share. The critical section default also works across
+/// interrupt executors, like the Slint apps use.
+pub struct TouchChannel<
+    M: RawMutex = CriticalSectionRawMutex,
+    const N: usize = TOUCH_QUEUE_LEN,
+> {
+    queue: Mutex<M, RefCell<TouchQueue<N>>>,
+}
+
+impl<M: RawMutex, const N: usize> TouchChannel<M, N> {
+    pub const fn new() -> Self {
+        Self {
+            queue: Mutex::new(RefCell::new(
+                TouchQueue::new(),
+            )),
+        }
+    }
+
+    /// See [`TouchQueue::push`].
+    pub fn push(&self, event: TouchEvent) -> bool {
+        self.queue.lock(|q| q.borrow_mut().push(event))
+    }
+
+    /// Takes the oldest event, if there is one.
+    pub fn try_receive(&self) -> Option<TouchEvent> {
+        self.queue.lock(|q| q.borrow_mut().pop())
+    }
+
+    /// Number of events dropped since the last call.
+    pub fn take_overflowed(&self) -> u32 {
+        self.queue
+            .lock(|q| q.borrow_mut().take_overflowed())
+    }
+}
+
+impl<M: RawMutex, const N: usize> Default
+    for TouchChannel<M, N>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps a widget pressed between events: Kolibri expects
+/// an interaction every frame while the finger is down, but
+/// the channel only has one when something changed.
+#[derive(Debug, Clone, Default)]
+pub struct TouchInteraction {
+    pressed: Option<Point>,
+}
+
+impl TouchInteraction {
+    pub const fn new() -> Self {
+        Self { pressed: None }
+    }
+
+    /// The interaction for a frame, given the event
+    /// received for it, if any.
+    pub fn next(
+        &mut self,
+        event: Option<TouchEvent>,
+    ) -> Interaction {
+        match event {
+            Some(event) => {
+                self.pressed = (event.kind
+                    != TouchKind::Release)
+                    .then_some(event.point);
+                event.interaction()
+            }
+            None => self.pressed.map_or(
+                Interaction::None,
+                Interaction::Drag,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use TouchKind::*;
+
+    use super::*;
+
+    fn ev(
+        kind: TouchKind,
+        x: i32,
+        t_ms: u64,
+    ) -> TouchEvent {
+        TouchEvent::new(kind, Point::new(x, 0), t_ms)
+    }
+
+    fn drain<const N: usize>(
+        q: &mut TouchQueue<N>,
+    ) -> Vec<(TouchKind, i32)> {
+        core::iter::from_fn(|| q.pop())
+            .map(|e| (e.kind, e.point.x))
+            .collect()
+    }
+
+    #[test]
+    fn tracker_emits_press_moves_and_release() {
+        let mut t = TouchTracker::new();
+        let p = |x| Some(Point::new(x, 0));
+        let events: Vec<_> =
+            [None, p(1), p(1), p(2), p(3), None, None]
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, s)| t.update(i as u64, s))
+                .collect();
+        assert_eq!(
+            events,
+            [
+                ev(Press, 1, 1),
+                ev(Move, 2, 3),
+                ev(Move, 3, 4),
+                ev(Release, 3, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn quick_tap_between_frames_survives() {
+        let mut q = TouchQueue::<4>::new();
+        assert!(q.push(ev(Press, 5, 0)));
+        assert!(q.push(ev(Release, 5, 8)));
+        assert_eq!(
+            drain(&mut q),
+            [(Press, 5), (Release, 5)]
+        );
+    }
+
+    #[test]
+    fn only_consecutive_moves_coalesce() {
+        let mut q = TouchQueue::<8>::new();
+        for e in [
+            ev(Press, 0, 0),
+            ev(Move, 1, 1),
+            ev(Move, 2, 2),
+            ev(Move, 3, 3),
+            ev(Release, 3, 4),
+            ev(Press, 9, 5),
+            ev(Move, 8, 6),
+            ev(Release, 8, 7),
+        ] {
+            assert!(q.push(e));
+        }
+        assert_eq!(
+            drain(&mut q),
+            [
+                (Press, 0),
+                (Move, 3),
+                (Release, 3),
+                (Press, 9),
+                (Move, 8),
+                (Release, 8),
+            ]
+        );
+        assert_eq!(q.take_overflowed(), 0);
+    }
+
+    #[test]
+    fn overflow_drops_whole_presses() {
+        let mut q = TouchQueue::<4>::new();
+        for x in 0..2 {
+            q.push(ev(Press, x, 0));
+            q.push(ev(Release, x, 0));
+        }
+        // full: this press and all of it go
+        assert!(!q.push(ev(Press, 7, 0)));
+        assert!(!q.push(ev(Move, 8, 0)));
+        assert!(!q.push(ev(Release, 8, 0)));
+        assert_eq!(q.take_overflowed(), 3);
+        assert_eq!(q.take_overflowed(), 0);
+        assert_eq!(
+            drain(&mut q),
+            [
+                (Press, 0),
+                (Release, 0),
+                (Press, 1),
+                (Release, 1)
+            ]
+        );
+
+        // the next press gets through again
+        assert!(q.push(ev(Press, 9, 0)));
+        assert_eq!(drain(&mut q), [(Press, 9)]);
+    }
+
+    #[test]
+    fn release_is_never_lost_when_full() {
+        let mut q = TouchQueue::<3>::new();
+        q.push(ev(Press, 0, 0));
+        q.push(ev(Release, 0, 0));
+        q.push(ev(Press, 1, 0));
+        // no room for a move, the release replaces the
+        // press it can't be delivered with
+        assert!(!q.push(ev(Move, 2, 0)));
+        assert!(!q.push(ev(Release, 2, 0)));
+        assert_eq!(
+            drain(&mut q),
+            [(Press, 0), (Release, 0)]
+        );
+        assert_eq!(q.take_overflowed(), 3);
+
+        let mut q = TouchQueue::<2>::new();
+        q.push(ev(Press, 0, 0));
+        q.push(ev(Move, 1, 0));
+        assert!(!q.push(ev(Release, 2, 0)));
+        assert_eq!(
+            drain(&mut q),
+            [(Press, 0), (Release, 2)]
+        );
+    }
+
+    #[test]
+    fn channel_is_fifo() {
+        let c = TouchChannel::<NoopRawMutex, 4>::new();
+        c.push(ev(Press, 1, 0));
+        c.push(ev(Release, 1, 1));
+        assert_eq!(c.try_receive(), Some(ev(Press, 1, 0)));
+        assert_eq!(
+            c.try_receive(),
+            Some(ev(Release, 1, 1))
+        );
+        assert_eq!(c.try_receive(), None);
+        assert_eq!(c.take_overflowed(), 0);
+    }
+
+    #[test]
+    fn interaction_holds_the_press_between_events() {
+        let mut i = TouchInteraction::new();
+        let p = Point::new(4, 0);
+        assert_eq!(i.next(None), Interaction::None);
+        assert_eq!(
+            i.next(Some(ev(Press, 4, 0))),
+            Interaction::Click(p)
+        );
+        assert_eq!(i.next(None), Interaction::Drag(p));
+        assert_eq!(
+            i.next(Some(ev(Release, 4, 9))),
+            Interaction::Release(p)
+        );
+        assert_eq!(i.next(None), Interaction::None);
+    }
+}
@@ -0,0 +1,480 @@
+//! The filter stage of the touch pipeline, which turns the
+//! noisy calibrated samples into steady points.
+//!
+//! [`TouchFilter`] chains the stages: a [`MedianFilter`]
+//! against outliers, a [`OneEuroFilter`] against jitter and
+//! a [`DeadZone`] that keeps a resting finger still. The
+//! tests run it on synthetic sample streams in `testdata/`.
+
+use embedded_graphics::prelude::Point;
+
+/// Component-wise median over the last `N` points. Rejects
+/// single outliers (the controller now and then reports a
+/// point far off while the finger lands or lifts) without
+/// adding much lag.
+#[derive(Debug, Clone)]
+pub struct MedianFilter<const N: usize> {
+    xs: [i32; N],
+    ys: [i32; N],
+    len: usize,
+    next: usize,
+}
+
+impl<const N: usize> MedianFilter<N> {
+    pub const fn new() -> Self {
+        Self {
+            xs: [0; N],
+            ys: [0; N],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+
+    pub fn filter(&mut self, p: Point) -> Point {
+        self.xs[self.next] = p.x;
+        self.ys[self.next] = p.y;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+        Point::new(
+            median::<N>(&self.xs[..self.len]),
+            median::<N>(&self.ys[..self.len]),
+        )
+    }
+}
+
+impl<const N: usize> Default for MedianFilter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn median<const N: usize>(values: &[i32]) -> i32 {
+    let mut sorted = [0; N];
+    let sorted = &mut sorted[..values.len()];
+    sorted.copy_from_slice(values);
+    sorted.sort_unstable();
+    sorted[sorted.len() / 2]
+}
+
+/// Parameters of the [`OneEuroFilter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OneEuroConfig {
+    /// Cutoff frequency in Hz when the finger doesn't
+    /// move. Lower means less jitter but more lag.
+    pub min_cutoff: f32,
+    /// How much the cutoff rises with speed (in Hz per
+    /// px/s). Higher means less lag on fast moves.
+    pub beta: f32,
+    /// Cutoff frequency for the speed estimate, in Hz.
+    pub d_cutoff: f32,
+}
+
+impl OneEuroConfig {
+    pub const DEFAULT: Self = Self {
+        min_cutoff: 1.5,
+        beta: 0.02,
+        d_cutoff: 1.0,
+    };
+}
+
+impl Default for OneEuroConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EuroState {
+    t_ms: u64,
+    value: [f32; 2],
+    speed: [f32; 2],
+}
+
+/// Adaptive low-pass filter (Casiez et al., "1€ filter"):
+/// strong smoothing while the finger rests, little lag
+/// while it moves.
+#[derive(Debug, Clone)]
+pub struct OneEuroFilter {
+    config: OneEuroConfig,
+    state: Option<EuroState>,
+}
+
+impl OneEuroFilter {
+    pub const fn new(config: OneEuroConfig) -> Self {
+        Self {
+            config,
+            state: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+
+    pub fn filter(&mut self, t_ms: u64, p: Point) -> Point {
+        let input = [p.x as f32, p.y as f32];
+        let state = match self.state {
+            Some(prev) if t_ms > prev.t_ms => {
+                let dt = (t_ms - prev.t_ms) as f32 / 1000.0;
+                let mut next = EuroState {
+                    t_ms,
+                    value: prev.value,
+                    speed: prev.speed,
+                };
+                for (i, &x) in input.iter().enumerate() {
+                    let speed = (x - prev.value[i]) / dt;
+                    next.speed[i] = lerp(
+                        prev.speed[i],
+                        speed,
+                        alpha(self.config.d_cutoff, dt),
+                    );
+                    let cutoff = self.config.min_cutoff
+                        + self.config.beta
+                            * next.speed[i].abs();
+                    next.value[i] = lerp(
+                        prev.value[i],
+                        x,
+                        alpha(cutoff, dt),
+                    );
+                }
+                next
+            }
+            // same timestamp: nothing to learn from it
+            Some(prev) => prev,
+            None => EuroState {
+                t_ms,
+                value: input,
+                speed: [0.0; 2],
+            },
+        };
+        self.state = Some(state);
+        Point::new(
+            libm::roundf(state.value[0]) as i32,
+            libm::roundf(state.value[1]) as i32,
+        )
+    }
+}
+
+fn alpha(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * core::f32::consts::PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+fn lerp(from: f32, to: f32, alpha: f32) -> f32 {
+    from + alpha * (to - from)
+}
+
+/// Holds the output still until the input has moved more
+/// than `radius` pixels away from it, so a resting finger
+/// doesn't make the widget below wobble.
+#[derive(Debug, Clone)]
+pub struct DeadZone {
+    radius: u32,
+    held: Option<Point>,
+}
+
+impl DeadZone {
+    pub const fn new(radius: u32) -> Self {
+        Self { radius, held: None }
+    }
+
+    pub fn reset(&mut self) {
+        self.held = None;
+    }
+
+    pub fn filter(&mut self, p: Point) -> Point {
+        match self.held {
+            Some(held) => {
+                let d = p - held;
+                let r = self.radius as i32;
+                if d.x * d.x + d.y * d.y > r * r {
+                    self.held = Some(p);
+                    p
+                } else {
+                    held
+                }
+            }
+            None => {
+                self.held = Some(p);
+                p
+            }
+        }
+    }
+}
+
+/// Settings of the [`TouchFilter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterConfig {
+    pub one_euro: OneEuroConfig,
+    /// Radius of the [`DeadZone`] in pixels, 0 to disable
+    /// it.
+    pub dead_zone: u32,
+}
+
+impl FilterConfig {
+    pub const DEFAULT: Self = Self {
+        one_euro: OneEuroConfig::DEFAULT,
+        dead_zone: 2,
+    };
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The filter stage of the touch pipeline: median of five
+/// for outliers, then the one-euro low-pass, then the dead
+/// zone. Works on screen coordinates;
+/// [`reset`](Self::reset) it when the finger lifts.
+#[derive(Debug, Clone)]
+pub struct TouchFilter {
+    median: MedianFilter<5>,
+    one_euro: OneEuroFilter,
+    dead_zone: DeadZone,
+}
+
+impl TouchFilter {
+    pub const fn new(config: FilterConfig) -> Self {
+        Self {
+            median: MedianFilter::new(),
+            one_euro: OneEuroFilter::new(config.one_euro),
+            dead_zone: DeadZone::new(config.dead_zone),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.median.reset();
+        self.one_euro.reset();
+        self.dead_zone.reset();
+    }
+
+    pub fn filter(&mut self, t_ms: u64, p: Point) -> Point {
+        let p = self.median.filter(p);
+        let p = self.one_euro.filter(t_ms, p);
+        self.dead_zone.filter(p)
+    }
+}
+
+impl Default for TouchFilter {
+    fn default() -> Self {
+        Self::new(FilterConfig::DEFAULT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a `t_ms x y` stream, one sample per line, `#`
+    /// starts a comment.
+    fn stream(data: &str) -> Vec<(u64, Point)> {
+        data.lines()
+            .map(|l| l.split('#').next().unwrap().trim())
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let v: Vec<i64> = l
+                    .split_whitespace()
+                    .map(|v| v.parse().unwrap())
+                    .collect();
+                (
+                    v[0] as u64,
+                    Point::new(v[1] as i32, v[2] as i32),
+                )
+            })
+            .collect()
+    }
+
+    fn run(
+        f: &mut TouchFilter,
+        s: &[(u64, Point)],
+    ) -> Vec<Point> {
+        s.iter().map(|&(t, p)| f.filter(t, p)).collect()
+    }
+
+    /// Streams of a finger resting on the screen. Captures
+    /// from the CYD (see the README) are added here.
+    const STATIONARY: &[&str] = &[include_str!(
+        "testdata/synthetic_stationary.txt"
+    )];
+    /// Streams of a left to right slider drag that ends
+    /// with the finger resting. Captures from the CYD are
+    /// added here.
+    const SLIDER_DRAG: &[&str] = &[include_str!(
+        "testdata/synthetic_slider_drag.txt"
+    )];
+
+    #[test]
+    fn median_drops_single_outliers() {
+        let mut m = MedianFilter::<5>::new();
+        let out: Vec<Point> = [10, 11, 90, 10, 12, 11]
+            .iter()
+            .map(|&x| m.filter(Point::new(x, 5)))
+            .collect();
+        assert!(out.iter().all(|p| p.x <= 12), "{out:?}");
+    }
+
+    #[test]
+    fn median_restarts_after_reset() {
+        let mut m = MedianFilter::<3>::new();
+        m.filter(Point::new(100, 100));
+        m.filter(Point::new(100, 100));
+        m.reset();
+        assert_eq!(
+            m.filter(Point::new(5, 5)),
+            Point::new(5, 5)
+        );
+    }
+
+    #[test]
+    fn one_euro_converges_on_constant_input() {
+        let mut f =
+            OneEuroFilter::new(OneEuroConfig::DEFAULT);
+        assert_eq!(
+            f.filter(0, Point::new(0, 0)),
+            Point::new(0, 0)
+        );
+        let mut last = Point::zero();
+        for t in 1..=2000 {
+            last = f.filter(t * 2, Point::new(100, 50));
+        }
+        assert_eq!(last, Point::new(100, 50));
+    }
+
+    #[test]
+    fn one_euro_lags_less_when_fast() {
+        let slow = OneEuroConfig {
+            beta: 0.0,
+            ..OneEuroConfig::DEFAULT
+        };
+        let mut a = OneEuroFilter::new(slow);
+        let mut b =
+            OneEuroFilter::new(OneEuroConfig::DEFAULT);
+        let (mut pa, mut pb) =
+            (Point::zero(), Point::zero());
+        for t in 0..100 {
+            // 1000 px/s
+            let p = Point::new(t as i32 * 2, 0);
+            pa = a.filter(t * 2, p);
+            pb = b.filter(t * 2, p);
+        }
+        assert!(pb.x > pa.x, "{pb:?} vs {pa:?}");
+        assert!(198 - pb.x < 20, "lag {}", 198 - pb.x);
+    }
+
+    #[test]
+    fn dead_zone_holds_then_follows() {
+        let mut d = DeadZone::new(2);
+        assert_eq!(
+            d.filter(Point::new(10, 10)),
+            Point::new(10, 10)
+        );
+        assert_eq!(
+            d.filter(Point::new(11, 9)),
+            Point::new(10, 10)
+        );
+        assert_eq!(
+            d.filter(Point::new(12, 10)),
+            Point::new(10, 10)
+        );
+        assert_eq!(
+            d.filter(Point::new(13, 10)),
+            Point::new(13, 10)
+        );
+    }
+
+    #[test]
+    fn stationary_finger_keeps_still() {
+        for data in STATIONARY {
+            assert_keeps_still(&stream(data));
+        }
+    }
+
+    #[test]
+    fn slider_drag_is_smooth_and_keeps_up() {
+        for data in SLIDER_DRAG {
+            assert_smooth_drag(&stream(data));
+        }
+    }
+
+    fn median(mut v: Vec<i32>) -> i32 {
+        v.sort_unstable();
+        v[v.len() / 2]
+    }
+
+    fn assert_keeps_still(s: &[(u64, Point)]) {
+        let out = run(&mut TouchFilter::default(), s);
+        // where the finger rests, whatever the noise
+        let target = Point::new(
+            median(s.iter().map(|(_, p)| p.x).collect()),
+            median(s.iter().map(|(_, p)| p.y).collect()),
+        );
+        // let the filter settle on the first few samples
+        let settled = &out[20..];
+        for p in settled {
+            let d = *p - target;
+            assert!(
+                d.x.abs() <= 2 && d.y.abs() <= 2,
+                "{p:?} vs {target:?}"
+            );
+        }
+        let changes = settled
+            .windows(2)
+            .filter(|w| w[0] != w[1])
+            .count();
+        assert!(
+            changes <= 2,
+            "output moved {changes} times"
+        );
+
+        // raw stream for comparison
+        let raw_changes = s[20..]
+            .windows(2)
+            .filter(|w| w[0].1 != w[1].1)
+            .count();
+        assert!(raw_changes > 50);
+    }
+
+    fn assert_smooth_drag(s: &[(u64, Point)]) {
+        let out = run(&mut TouchFilter::default(), s);
+
+        // the drag goes left to right: never step back, and
+        // the spikes in the stream must not show up
+        for w in out.windows(2) {
+            assert!(
+                w[1].x >= w[0].x,
+                "{:?} -> {:?}",
+                w[0],
+                w[1]
+            );
+            assert!(
+                w[1].x - w[0].x <= 8,
+                "{:?} -> {:?}",
+                w[0],
+                w[1]
+            );
+        }
+        let ys: Vec<i32> =
+            out[20..].iter().map(|p| p.y).collect();
+        let (min, max) = (
+            ys.iter().min().unwrap(),
+            ys.iter().max().unwrap(),
+        );
+        assert!(max - min <= 3, "y wobbles {min}..{max}");
+
+        // the finger rests at the end; the output must
+        // reach it
+        let end = s.last().unwrap().1;
+        let last = *out.last().unwrap();
+        assert!(
+            (last.x - end.x).abs() <= 3,
+            "{last:?} vs {end:?}"
+        );
+    }
+}
@@ -0,0 +1,31 @@
+//! Touch input shared by all Kolibri apps.
+//!
+//! [`touch_task`] sleeps until the XPT2046 reports a touch
+//! on its PENIRQ line, reads it with its own
+//! [driver](driver::TouchController) while pressed and runs
+//! every sample through the pipeline:
+//!
+//! 1. samples with too little pressure are dropped,
+//! 2. the stored [calibration](crate::calibration) maps
+//!    them to screen coordinates,
+//! 3. the [filter](filter::TouchFilter) removes outliers
+//!    and jitter,
+//! 4. releases are debounced with a
+//!    [`TimedDebouncer`](crate::TimedDebouncer), so a drag
+//!    doesn't break up into several presses.
+//!
+//! The result goes out as press, move and release
+//! [events](event::TouchEvent) on a
+//! [`TouchChannel`](event::TouchChannel). Those events can
+//! be recorded and replayed as [scripts](script).
+
+pub mod driver;
+pub mod event;
+pub mod filter;
+pub mod replay;
+pub mod script;
+#[cfg(target_os = "none")]
+mod task;
+
+#[cfg(target_os = "none")]
+pub use task::touch_task;
@@ -0,0 +1,168 @@
+use core::fmt;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+#[cfg(target_os = "none")]
+use embassy_time::{Duration, Instant, Timer};
+#[cfg(target_os = "none")]
+use esp_println::println;
+
+use super::{
+    event::TouchChannel,
+    script::{Script, START_DELAY_MS},
+};
+
+/// Plays a [script](super::script) into `touch_events`,
+/// with each event at its time after a short
+/// [start delay](START_DELAY_MS). Events are stamped with
+/// the time they are sent. A script that doesn't validate
+/// isn't played at all.
+#[cfg(target_os = "none")]
+pub async fn play(
+    script: &str,
+    touch_events: &TouchChannel,
+) {
+    let count = match Script::validate(script) {
+        Ok(count) => count,
+        Err(e) => {
+            println!(
+                "touch replay: invalid script: {:?}",
+                e
+            );
+            return;
+        }
+    };
+    println!("touch replay: {} events", count);
+
+    Timer::after(Duration::from_millis(START_DELAY_MS))
+        .await;
+    let start = Instant::now();
+    for mut event in Script::new(script).flatten() {
+        Timer::at(
+            start + Duration::from_millis(event.t_ms),
+        )
+        .await;
+        event.t_ms = Instant::now().as_millis();
+        if !touch_events.push(event) {
+            println!("touch replay: event dropped");
+        }
+    }
+    println!("touch replay: done");
+}
+
+/// Like `play`, for apps that replay on a thread of their
+/// own, like the LVGL ones under ESP-IDF. `sleep_ms` blocks
+/// for that many milliseconds, `now_ms` is the clock events
+/// are stamped with and `print` gets the messages `play`
+/// prints.
+pub fn play_blocking<M: RawMutex, const N: usize>(
+    script: &str,
+    touch_events: &TouchChannel<M, N>,
+    mut sleep_ms: impl FnMut(u64),
+    now_ms: impl Fn() -> u64,
+    mut print: impl FnMut(fmt::Arguments),
+) {
+    let count = match Script::validate(script) {
+        Ok(count) => count,
+        Err(e) => {
+            print(format_args!(
+                "touch replay: invalid script: {:?}",
+                e
+            ));
+            return;
+        }
+    };
+    print(format_args!("touch replay: {} events", count));
+
+    sleep_ms(START_DELAY_MS);
+    let start = now_ms();
+    for mut event in Script::new(script).flatten() {
+        sleep_ms(
+            (start + event.t_ms).saturating_sub(now_ms()),
+        );
+        event.t_ms = now_ms();
+        if !touch_events.push(event) {
+            print(format_args!(
+                "touch replay: event dropped"
+            ));
+        }
+    }
+    print(format_args!("touch replay: done"));
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embedded_graphics::prelude::Point;
+
+    use super::*;
+    use crate::touch::event::{TouchEvent, TouchKind::*};
+
+    fn replay(
+        script: &str,
+        touch_events: &TouchChannel<NoopRawMutex, 16>,
+    ) -> Vec<String> {
+        let clock = Cell::new(500);
+        let mut printed = Vec::new();
+        play_blocking(
+            script,
+            touch_events,
+            |ms| clock.set(clock.get() + ms),
+            || clock.get(),
+            |args| printed.push(format!("{}", args)),
+        );
+        printed
+    }
+
+    #[test]
+    fn blocking_replay_stamps_events_with_the_clock() {
+        let channel = TouchChannel::new();
+        let printed = replay(
+            "0 p 10 20\n40 m 12 20\n90 r 12 20\n",
+            &channel,
+        );
+        assert_eq!(
+            printed,
+            [
+                "touch replay: 3 events",
+                "touch replay: done"
+            ]
+        );
+        let start = 500 + START_DELAY_MS;
+        let events: Vec<_> =
+            core::iter::from_fn(|| channel.try_receive())
+                .collect();
+        assert_eq!(
+            events,
+            [
+                TouchEvent::new(
+                    Press,
+                    Point::new(10, 20),
+                    start
+                ),
+                TouchEvent::new(
+                    Move,
+                    Point::new(12, 20),
+                    start + 40
+                ),
+                TouchEvent::new(
+                    Release,
+                    Point::new(12, 20),
+                    start + 90
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_scripts_are_not_played() {
+        let channel = TouchChannel::new();
+        let printed =
+            replay("0 p 10 20\n0 p 10 20\n", &channel);
+        assert_eq!(printed.len(), 1);
+        assert!(printed[0]
+            .starts_with("touch replay: invalid script"));
+        assert_eq!(channel.try_receive(), None);
+    }
+}
@@ -0,0 +1,410 @@
+//! Touch scripts: recorded [`TouchEvent`] streams that can
+//! be replayed in place of the touch controller, to run the
+//! same interaction on the Kolibri, Slint and LVGL apps or
+//! to reproduce a bug from a recording.
+//!
+//! A script is plain text, one event per line:
+//!
+//! ```text
+//! # touch-script v1
+//! # t_ms kind x y
+//! 0 p 120 80
+//! 16 m 124 80
+//! 90 r 124 80
+//! ```
+//!
+//! - `t_ms` is the time in milliseconds since the start of
+//!   the replay and must not go backwards,
+//! - `kind` is `p` (press), `m` (move) or `r` (release),
+//! - `x` and `y` are screen coordinates.
+//!
+//! Empty lines and everything after a `#` are ignored.
+//! Every press has to be followed by its release before the
+//! next press.
+//!
+//! With the `touch-record` feature the touch producers
+//! print every event as a script line prefixed with
+//! [`RECORD_PREFIX`]; with `touch-replay` they play the
+//! script the `TOUCH_SCRIPT` environment variable points to
+//! at build time instead of reading the controller (see
+//! [`SCRIPT`]).
+//!
+//! Recording also prints every calibrated sample the touch
+//! filter gets, prefixed with [`SAMPLE_PREFIX`], as
+//! `t_ms x y` lines; those are the streams the filter's
+//! tests read from `testdata`.
+
+use core::fmt::{self, Write};
+
+use embedded_graphics::prelude::Point;
+
+use super::event::{TouchEvent, TouchKind};
+
+/// First line of a recording.
+pub const HEADER: &str = "# touch-script v1";
+
+/// Prefix of recorded lines on the serial console, to tell
+/// them apart from other output.
+pub const RECORD_PREFIX: &str = "touch-script: ";
+
+/// Prefix of the raw samples printed while recording,
+/// before the touch filter sees them.
+pub const SAMPLE_PREFIX: &str = "touch-sample: ";
+
+/// Whether the touch producers should print their events.
+pub const RECORDING: bool = cfg!(feature = "touch-record");
+
+/// The script to replay, built in from the file
+/// `TOUCH_SCRIPT` names (an absolute path) with the
+/// `touch-replay` feature.
+#[cfg(feature = "touch-replay")]
+pub const SCRIPT: Option<&str> =
+    Some(include_str!(env!("TOUCH_SCRIPT")));
+/// The script to replay, built in from the file
+/// `TOUCH_SCRIPT` names (an absolute path) with the
+/// `touch-replay` feature.
+#[cfg(not(feature = "touch-replay"))]
+pub const SCRIPT: Option<&str> = None;
+
+/// How long a replay waits before its first event, so the
+/// app has drawn its first screen.
+pub const START_DELAY_MS: u64 = 1000;
+
+/// Longest line [`Recorder::line`] produces.
+pub const LINE_LEN: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptErrorKind {
+    /// Not `t_ms kind x y`.
+    Syntax,
+    /// The time is before the previous event's.
+    TimeGoesBack,
+    /// A press while already pressed.
+    Pressed,
+    /// A move or release while not pressed.
+    NotPressed,
+    /// The script ends while pressed.
+    Unreleased,
+}
+
+/// A problem in a script, with the (1-based) line it is
+/// on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub kind: ScriptErrorKind,
+}
+
+fn kind_char(kind: TouchKind) -> char {
+    match kind {
+        TouchKind::Press => 'p',
+        TouchKind::Move => 'm',
+        TouchKind::Release => 'r',
+    }
+}
+
+/// Writes `event` as a script line, without the line
+/// break.
+pub fn write_event(
+    w: &mut impl Write,
+    event: &TouchEvent,
+) -> fmt::Result {
+    write!(
+        w,
+        "{} {} {} {}",
+        event.t_ms,
+        kind_char(event.kind),
+        event.point.x,
+        event.point.y
+    )
+}
+
+/// Parses one line of a script; `Ok(None)` for empty and
+/// comment lines.
+pub fn parse_line(
+    line: &str,
+) -> Result<Option<TouchEvent>, ScriptErrorKind> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let mut fields = line.split_whitespace();
+    let mut next =
+        || fields.next().ok_or(ScriptErrorKind::Syntax);
+    let t_ms = next()?
+        .parse()
+        .map_err(|_| ScriptErrorKind::Syntax)?;
+    let kind = match next()? {
+        "p" => TouchKind::Press,
+        "m" => TouchKind::Move,
+        "r" => TouchKind::Release,
+        _ => return Err(ScriptErrorKind::Syntax),
+    };
+    let x = next()?
+        .parse()
+        .map_err(|_| ScriptErrorKind::Syntax)?;
+    let y = next()?
+        .parse()
+        .map_err(|_| ScriptErrorKind::Syntax)?;
+    if fields.next().is_some() {
+        return Err(ScriptErrorKind::Syntax);
+    }
+    Ok(Some(TouchEvent::new(kind, Point::new(x, y), t_ms)))
+}
+
+/// The events of a script, checked as they are read.
+/// Stops after the first error.
+pub struct Script<'a> {
+    lines: core::iter::Enumerate<core::str::Lines<'a>>,
+    last_ms: u64,
+    pressed: bool,
+    done: bool,
+}
+
+impl<'a> Script<'a> {
+    pub fn new(script: &'a str) -> Self {
+        Self {
+            lines: script.lines().enumerate(),
+            last_ms: 0,
+            pressed: false,
+            done: false,
+        }
+    }
+
+    /// Checks the whole script and returns its number of
+    /// events.
+    pub fn validate(
+        script: &str,
+    ) -> Result<usize, ScriptError> {
+        Script::new(script)
+            .try_fold(0, |n, event| event.map(|_| n + 1))
+    }
+
+    fn check(
+        &mut self,
+        event: &TouchEvent,
+    ) -> Result<(), ScriptErrorKind> {
+        if event.t_ms < self.last_ms {
+            return Err(ScriptErrorKind::TimeGoesBack);
+        }
+        match (event.kind, self.pressed) {
+            (TouchKind::Press, true) => {
+                return Err(ScriptErrorKind::Pressed)
+            }
+            (
+                TouchKind::Move | TouchKind::Release,
+                false,
+            ) => return Err(ScriptErrorKind::NotPressed),
+            _ => {}
+        }
+        self.last_ms = event.t_ms;
+        self.pressed = event.kind != TouchKind::Release;
+        Ok(())
+    }
+}
+
+impl Iterator for Script<'_> {
+    type Item = Result<TouchEvent, ScriptError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        for (i, line) in self.lines.by_ref() {
+            let error =
+                |kind| ScriptError { line: i + 1, kind };
+            let event = match parse_line(line) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(kind) => {
+                    self.done = true;
+                    return Some(Err(error(kind)));
+                }
+            };
+            if let Err(kind) = self.check(&event) {
+                self.done = true;
+                return Some(Err(error(kind)));
+            }
+            return Some(Ok(event));
+        }
+        self.done = true;
+        self.pressed.then_some(Err(ScriptError {
+            line: 0,
+            kind: ScriptErrorKind::Unreleased,
+        }))
+    }
+}
+
+/// Turns live events into script lines, with the time
+/// counted from the first one.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    start_ms: Option<u64>,
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        Self { start_ms: None }
+    }
+
+    pub fn line(
+        &mut self,
+        event: &TouchEvent,
+    ) -> heapless::String<LINE_LEN> {
+        let start =
+            *self.start_ms.get_or_insert(event.t_ms);
+        let event = TouchEvent {
+            t_ms: event.t_ms.saturating_sub(start),
+            ..*event
+        };
+        let mut line = heapless::String::new();
+        // the longest line, with 20 digit times and 11
+        // digit coordinates, is 46 characters
+        write_event(&mut line, &event).ok();
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAP_AND_DRAG: &str =
+        include_str!("testdata/synthetic_tap_and_drag.txt");
+
+    fn ev(
+        kind: TouchKind,
+        x: i32,
+        y: i32,
+        t: u64,
+    ) -> TouchEvent {
+        TouchEvent::new(kind, Point::new(x, y), t)
+    }
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(
+            parse_line("16 m 124 -3"),
+            Ok(Some(ev(TouchKind::Move, 124, -3, 16)))
+        );
+        assert_eq!(
+            parse_line("  0\tp 1 2  # the first tap"),
+            Ok(Some(ev(TouchKind::Press, 1, 2, 0)))
+        );
+        assert_eq!(parse_line(""), Ok(None));
+        assert_eq!(parse_line("# comment"), Ok(None));
+        for bad in
+            ["0 x 1 2", "0 p 1", "0 p 1 2 3", "-1 p 1 2"]
+        {
+            assert_eq!(
+                parse_line(bad),
+                Err(ScriptErrorKind::Syntax),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn recording_round_trips() {
+        let events = [
+            ev(TouchKind::Press, 10, 20, 5000),
+            ev(TouchKind::Move, 12, 21, 5016),
+            ev(TouchKind::Release, 12, 21, 5090),
+        ];
+        let mut recorder = Recorder::new();
+        let mut script = std::string::String::from(HEADER);
+        for e in &events {
+            script.push('\n');
+            script.push_str(&recorder.line(e));
+        }
+        assert_eq!(
+            script,
+            "# touch-script v1\n0 p 10 20\n16 m 12 21\n90 r 12 21"
+        );
+        let replayed: Vec<_> = Script::new(&script)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(replayed.len(), 3);
+        for (r, e) in replayed.iter().zip(&events) {
+            assert_eq!(
+                (r.kind, r.point),
+                (e.kind, e.point)
+            );
+            assert_eq!(r.t_ms, e.t_ms - 5000);
+        }
+    }
+
+    #[test]
+    fn longest_line_fits() {
+        let mut recorder = Recorder::new();
+        recorder.line(&ev(TouchKind::Press, 0, 0, 0));
+        let line = recorder.line(&ev(
+            TouchKind::Release,
+            i32::MIN,
+            i32::MIN,
+            u64::MAX,
+        ));
+        assert_eq!(
+            parse_line(&line),
+            Ok(Some(ev(
+                TouchKind::Release,
+                i32::MIN,
+                i32::MIN,
+                u64::MAX
+            )))
+        );
+    }
+
+    #[test]
+    fn test_script_is_valid() {
+        assert_eq!(Script::validate(TAP_AND_DRAG), Ok(7));
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let check = |script: &str| Script::validate(script);
+        assert_eq!(
+            check("0 p 1 1\n\n5 r 1 1\n3 p 1 1"),
+            Err(ScriptError {
+                line: 4,
+                kind: ScriptErrorKind::TimeGoesBack
+            })
+        );
+        assert_eq!(
+            check("0 p 1 1\n1 p 1 1"),
+            Err(ScriptError {
+                line: 2,
+                kind: ScriptErrorKind::Pressed
+            })
+        );
+        assert_eq!(
+            check("# header\n0 m 1 1"),
+            Err(ScriptError {
+                line: 2,
+                kind: ScriptErrorKind::NotPressed
+            })
+        );
+        assert_eq!(
+            check("0 p 1 1\n1 m 2 2"),
+            Err(ScriptError {
+                line: 0,
+                kind: ScriptErrorKind::Unreleased
+            })
+        );
+        assert_eq!(
+            check("0 p 1 1\nnonsense"),
+            Err(ScriptError {
+                line: 2,
+                kind: ScriptErrorKind::Syntax
+            })
+        );
+    }
+
+    #[test]
+    fn iteration_stops_after_an_error() {
+        let mut s =
+            Script::new("0 r 1 1\n1 p 1 1\n2 r 1 1");
+        assert!(matches!(s.next(), Some(Err(_))));
+        assert_eq!(s.next(), None);
+    }
+}
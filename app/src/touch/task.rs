@@ -0,0 +1,208 @@
+use core::cell::RefCell;
+
+use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
+use embassy_sync::blocking_mutex::NoopMutex;
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::{
+    gpio::{GpioPin, Input, Level, Output, Pull},
+    peripherals::SPI3,
+    spi::{master::Spi, FullDuplexMode},
+};
+use esp_println::println;
+
+use super::{
+    driver::{TouchController, MIN_PRESSURE},
+    event::{TouchChannel, TouchTracker},
+    filter::TouchFilter,
+    replay,
+    script::{self, Recorder},
+};
+use crate::{calibration, Edge, TimedDebouncer};
+
+/// How long the controller has to report a touch before it
+/// counts as one.
+const PRESS_DEBOUNCE_MS: u64 = 2;
+/// How long it has to report no touch before the release is
+/// passed on.
+const RELEASE_DEBOUNCE_MS: u64 = 30;
+/// Time between samples while the panel is pressed.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+/// How often the sampling statistics are printed while the
+/// task is awake.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Waits for the panel to be pressed (PENIRQ on GPIO36
+/// going low), samples it until the release has been passed
+/// on and goes back to waiting. Sends presses, moves and
+/// releases in screen coordinates to `touch_events`.
+///
+/// With the `touch-polling` feature it reads the controller
+/// every [`SAMPLE_INTERVAL`] instead, like the tasks before
+/// it did, to compare CPU time and latency. With
+/// `touch-record` it prints the events as a
+/// [script](script), and with `touch-replay` it plays the
+/// built in script before it starts reading the controller.
+#[embassy_executor::task]
+pub async fn touch_task(
+    touch_irq: GpioPin<36>,
+    spi: &'static mut NoopMutex<
+        RefCell<Spi<'static, SPI3, FullDuplexMode>>,
+    >,
+    touch_cs: GpioPin<33>,
+    touch_events: &'static TouchChannel,
+) -> ! {
+    let polling = cfg!(feature = "touch-polling");
+    let calibration = calibration::stored();
+    println!("touch calibration: {:?}", calibration);
+
+    let mut controller =
+        TouchController::new(SpiDevice::new(
+            spi,
+            Output::new(touch_cs, Level::Low),
+        ));
+    let mut pen_irq = Input::new(touch_irq, Pull::Up);
+    let mut filter = TouchFilter::default();
+
+    // The controller sometimes reports a release for a
+    // sample or two in the middle of a drag. Only pass
+    // a release on once it has lasted a while, and keep
+    // reporting the last point until then.
+    let mut debounce = TimedDebouncer::new(
+        PRESS_DEBOUNCE_MS,
+        RELEASE_DEBOUNCE_MS,
+    );
+    let mut last_point = None;
+    let mut tracker = TouchTracker::new();
+    let mut recorder = Recorder::new();
+    let mut stats = Stats::new();
+
+    println!(
+        "touch task ({})",
+        if polling { "polling" } else { "PENIRQ" }
+    );
+
+    if let Some(script) = script::SCRIPT {
+        replay::play(script, touch_events).await;
+    }
+    if script::RECORDING {
+        println!(
+            "{}{}",
+            script::RECORD_PREFIX,
+            script::HEADER
+        );
+    }
+
+    loop {
+        if !polling {
+            pen_irq.wait_for_low().await;
+            stats.wakeups += 1;
+        }
+
+        loop {
+            let now = Instant::now();
+            // PENIRQ is low while the panel is pressed;
+            // only talk to the controller then
+            let pen_down = polling || pen_irq.is_low();
+            let sample = pen_down
+                .then(|| {
+                    let sample = controller
+                        .sample()
+                        .expect(
+                        "Reading touch controller failed",
+                    );
+                    stats.samples += 1;
+                    stats.busy += now.elapsed();
+                    sample
+                })
+                .filter(|s| s.pressure() >= MIN_PRESSURE);
+            let now_ms = now.as_millis();
+            if let Some(sample) = sample {
+                let point =
+                    calibration.apply(sample.point());
+                if script::RECORDING {
+                    println!(
+                        "{}{} {} {}",
+                        script::SAMPLE_PREFIX,
+                        now_ms,
+                        point.x,
+                        point.y
+                    );
+                }
+                last_point =
+                    Some(filter.filter(now_ms, point));
+            }
+            if debounce.update(now_ms, sample.is_some())
+                == Some(Edge::Released)
+            {
+                last_point = None;
+                filter.reset();
+            }
+            let touch =
+                last_point.filter(|_| debounce.read());
+            if let Some(event) =
+                tracker.update(now_ms, touch)
+            {
+                if script::RECORDING {
+                    println!(
+                        "{}{}",
+                        script::RECORD_PREFIX,
+                        recorder.line(&event)
+                    );
+                }
+                if !touch_events.push(event) {
+                    stats.dropped += 1;
+                }
+            }
+            stats.print_every(STATS_INTERVAL);
+
+            // released (or just grazed) and let go: back to
+            // waiting for the next touch
+            if !polling
+                && !debounce.read()
+                && pen_irq.is_high()
+            {
+                break;
+            }
+            Timer::after(SAMPLE_INTERVAL).await;
+        }
+    }
+}
+
+/// How much work the task did, printed every few seconds.
+struct Stats {
+    since: Instant,
+    wakeups: u32,
+    samples: u32,
+    /// Events that didn't fit into the channel.
+    dropped: u32,
+    /// Time spent talking to the controller.
+    busy: Duration,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            wakeups: 0,
+            samples: 0,
+            dropped: 0,
+            busy: Duration::from_ticks(0),
+        }
+    }
+
+    fn print_every(&mut self, interval: Duration) {
+        let elapsed = self.since.elapsed();
+        if elapsed < interval {
+            return;
+        }
+        println!(
+            "touch: {} wakeups, {} samples, {} dropped, {}us busy in {}ms",
+            self.wakeups,
+            self.samples,
+            self.dropped,
+            self.busy.as_micros(),
+            elapsed.as_millis(),
+        );
+        *self = Self::new();
+    }
+}
@@ -0,0 +1,374 @@
+use core::fmt::Write;
+
+use embedded_graphics::{
+    mono_font::{ascii, MonoTextStyleBuilder},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+use heapless::String;
+use kolibri_embedded_gui::ui::Interaction;
+
+use super::DateTime;
+use crate::gesture::Events;
+
+/// Without a touch or button press for this long, the apps
+/// show the [`ClockFace`].
+pub const IDLE_MS: u64 = 60_000;
+
+const CENTER: Point = Point::new(160, 105);
+const RADIUS: i32 = 90;
+const TICK_LEN: i32 = 10;
+const HOUR_HAND: i32 = 45;
+const MINUTE_HAND: i32 = 70;
+const SECOND_HAND: i32 = 75;
+const BACKGROUND: Rgb565 = Rgb565::BLACK;
+
+/// `sin(i * 6°) * 1000` for the first quarter turn.
+const SIN: [i32; 16] = [
+    0, 105, 208, 309, 407, 500, 588, 669, 743, 809, 866,
+    914, 951, 978, 995, 1000,
+];
+
+/// `sin(i * 6°) * 1000`, for `i` in `0..60`.
+fn sin60(i: usize) -> i32 {
+    match i % 60 {
+        i @ 0..=15 => SIN[i],
+        i @ 16..=30 => SIN[30 - i],
+        i @ 31..=45 => -SIN[i - 30],
+        i => -SIN[60 - i],
+    }
+}
+
+/// Tip of a hand `len` long, pointing at minute `i` of the
+/// dial.
+fn tip(i: usize, len: i32) -> Point {
+    let cos = sin60(i + 15);
+    CENTER + Point::new(sin60(i) * len, -cos * len) / 1000
+}
+
+/// Where the hands point, in minutes of the dial.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Hands {
+    hour: usize,
+    minute: usize,
+    second: usize,
+}
+
+impl Hands {
+    fn of(time: &DateTime) -> Self {
+        Self {
+            hour: time.hour as usize % 12 * 5
+                + time.minute as usize / 12,
+            minute: time.minute as usize,
+            second: time.second as usize,
+        }
+    }
+
+    fn draw<D: DrawTarget<Color = Rgb565>>(
+        &self,
+        display: &mut D,
+        erase: bool,
+    ) -> Result<(), D::Error> {
+        let color = |c| if erase { BACKGROUND } else { c };
+        for (i, len, width, c) in [
+            (self.hour, HOUR_HAND, 5, Rgb565::WHITE),
+            (self.minute, MINUTE_HAND, 3, Rgb565::WHITE),
+            (self.second, SECOND_HAND, 1, Rgb565::CSS_RED),
+        ] {
+            Line::new(CENTER, tip(i, len))
+                .into_styled(PrimitiveStyle::with_stroke(
+                    color(c),
+                    width,
+                ))
+                .draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+/// An analog clock with the digital time and date below
+/// it. It is drawn straight onto the display, outside of
+/// Kolibri, and only redraws what changed.
+#[derive(Debug, Clone, Default)]
+pub struct ClockFace {
+    drawn: bool,
+    hands: Option<Hands>,
+    /// The text below the dial, as last drawn.
+    caption: String<24>,
+}
+
+impl ClockFace {
+    pub const fn new() -> Self {
+        Self {
+            drawn: false,
+            hands: None,
+            caption: String::new(),
+        }
+    }
+
+    /// Makes the next [`draw`](Self::draw) start from a
+    /// blank screen, e.g. after the app drew over it.
+    pub fn invalidate(&mut self) {
+        self.drawn = false;
+        self.hands = None;
+        self.caption.clear();
+    }
+
+    /// Draws the face for `time`, or a hint to set the
+    /// time if it is `None`.
+    pub fn draw<D: DrawTarget<Color = Rgb565>>(
+        &mut self,
+        display: &mut D,
+        time: Option<DateTime>,
+    ) -> Result<(), D::Error> {
+        if !self.drawn {
+            display.clear(BACKGROUND)?;
+            self.draw_dial(display)?;
+            self.drawn = true;
+        }
+
+        let hands = time.as_ref().map(Hands::of);
+        if hands != self.hands {
+            if let Some(old) = self.hands {
+                old.draw(display, true)?;
+            }
+            if let Some(new) = hands {
+                new.draw(display, false)?;
+            }
+            Circle::with_center(CENTER, 7)
+                .into_styled(PrimitiveStyle::with_fill(
+                    Rgb565::CSS_RED,
+                ))
+                .draw(display)?;
+            self.hands = hands;
+        }
+
+        let mut caption = String::<24>::new();
+        match &time {
+            Some(t) => write!(
+                caption,
+                "{:02}:{:02}  {:04}-{:02}-{:02}",
+                t.hour, t.minute, t.year, t.month, t.day
+            ),
+            None => write!(caption, "--:--"),
+        }
+        .ok();
+        if caption != self.caption {
+            self.draw_caption(
+                display,
+                &caption,
+                time.is_none(),
+            )?;
+            self.caption = caption;
+        }
+        Ok(())
+    }
+
+    fn draw_dial<D: DrawTarget<Color = Rgb565>>(
+        &self,
+        display: &mut D,
+    ) -> Result<(), D::Error> {
+        Circle::with_center(CENTER, 2 * RADIUS as u32 + 9)
+            .into_styled(PrimitiveStyle::with_stroke(
+                Rgb565::CSS_DIM_GRAY,
+                2,
+            ))
+            .draw(display)?;
+        for i in (0..60).step_by(5) {
+            let len = if i % 15 == 0 {
+                TICK_LEN + 5
+            } else {
+                TICK_LEN
+            };
+            Line::new(tip(i, RADIUS - len), tip(i, RADIUS))
+                .into_styled(PrimitiveStyle::with_stroke(
+                    Rgb565::WHITE,
+                    3,
+                ))
+                .draw(display)?;
+        }
+        Ok(())
+    }
+
+    fn draw_caption<D: DrawTarget<Color = Rgb565>>(
+        &self,
+        display: &mut D,
+        caption: &str,
+        unset: bool,
+    ) -> Result<(), D::Error> {
+        let style = MonoTextStyleBuilder::new()
+            .font(&ascii::FONT_10X20)
+            .text_color(Rgb565::WHITE)
+            .background_color(BACKGROUND)
+            .build();
+        display.fill_solid(
+            &Rectangle::new(
+                Point::new(0, 200),
+                Size::new(320, 40),
+            ),
+            BACKGROUND,
+        )?;
+        Text::with_alignment(
+            caption,
+            Point::new(160, 218),
+            style,
+            Alignment::Center,
+        )
+        .draw(display)?;
+        if unset {
+            let style = MonoTextStyleBuilder::new()
+                .font(&ascii::FONT_6X10)
+                .text_color(Rgb565::CSS_LIGHT_GRAY)
+                .build();
+            Text::with_alignment(
+                "hold to set the time",
+                Point::new(160, 234),
+                style,
+                Alignment::Center,
+            )
+            .draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tells when an app has been left alone for [`IDLE_MS`],
+/// and when it should wake up again.
+#[derive(Debug, Clone)]
+pub struct Idle {
+    last_ms: u64,
+    idle: bool,
+    changed: bool,
+}
+
+impl Idle {
+    pub const fn new(now: u64) -> Self {
+        Self {
+            last_ms: now,
+            idle: false,
+            changed: false,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Whether the app went idle or woke up since the last
+    /// call. Either way the screen needs a full redraw.
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::take(&mut self.changed)
+    }
+
+    /// Something happened, e.g. a button was pressed.
+    pub fn wake(&mut self, now: u64) {
+        self.last_ms = now;
+        self.set_idle(false);
+    }
+
+    /// Feeds the touch `events` of this frame. Any touch
+    /// keeps the app awake. While idle, only a tap wakes
+    /// it, and a long press asks for the set-time page
+    /// (returns `true`, and wakes it as well).
+    pub fn touch(
+        &mut self,
+        now: u64,
+        events: &Events,
+    ) -> bool {
+        if !self.idle {
+            if events.interaction != Interaction::None {
+                self.last_ms = now;
+            }
+            return false;
+        }
+        let set_time = events.long_press().is_some();
+        if set_time || events.tap().is_some() {
+            self.wake(now);
+        }
+        set_time
+    }
+
+    /// Call every frame. While `busy` (a countdown runs,
+    /// an alarm rings, ...) the app stays awake.
+    pub fn update(&mut self, now: u64, busy: bool) {
+        if busy {
+            self.wake(now);
+        } else if now.saturating_sub(self.last_ms)
+            >= IDLE_MS
+        {
+            self.set_idle(true);
+        }
+    }
+
+    fn set_idle(&mut self, idle: bool) {
+        self.changed |= self.idle != idle;
+        self.idle = idle;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gesture::Gesture;
+
+    #[test]
+    fn sine_table_covers_the_dial() {
+        assert_eq!(sin60(0), 0);
+        assert_eq!(sin60(15), 1000);
+        assert_eq!(sin60(30), 0);
+        assert_eq!(sin60(45), -1000);
+        assert_eq!(sin60(50), -866);
+        assert_eq!(tip(0, 10), CENTER + Point::new(0, -10));
+        assert_eq!(tip(15, 10), CENTER + Point::new(10, 0));
+        assert_eq!(tip(30, 10), CENTER + Point::new(0, 10));
+    }
+
+    #[test]
+    fn goes_idle_unless_busy_or_touched() {
+        let mut idle = Idle::new(0);
+        idle.update(IDLE_MS - 1, false);
+        assert!(!idle.is_idle());
+        idle.wake(IDLE_MS - 1);
+        idle.update(IDLE_MS + 10, false);
+        idle.update(3 * IDLE_MS, true);
+        assert!(!idle.is_idle());
+        assert!(!idle.take_changed());
+        idle.update(4 * IDLE_MS, false);
+        assert!(idle.is_idle());
+        assert!(idle.take_changed());
+        idle.update(5 * IDLE_MS, false);
+        assert!(!idle.take_changed());
+        idle.wake(5 * IDLE_MS);
+        assert!(!idle.is_idle());
+        assert!(idle.take_changed());
+    }
+
+    #[test]
+    fn a_long_press_while_idle_asks_for_the_time() {
+        let tap = |gesture| Events {
+            interaction: Interaction::None,
+            gestures: heapless::Vec::from_slice(&[gesture])
+                .unwrap(),
+        };
+        let click = |p| Events {
+            interaction: Interaction::Click(p),
+            gestures: heapless::Vec::new(),
+        };
+        let p = Point::new(10, 10);
+        let mut idle = Idle::new(0);
+        idle.update(IDLE_MS, false);
+        assert!(!idle.touch(IDLE_MS, &click(p)));
+        assert!(idle.is_idle());
+        assert!(idle
+            .touch(IDLE_MS, &tap(Gesture::LongPress(p))));
+        assert!(!idle.is_idle());
+        // awake, a long press is just a long press
+        assert!(!idle
+            .touch(IDLE_MS, &tap(Gesture::LongPress(p))));
+        idle.update(2 * IDLE_MS, false);
+        assert!(!idle.touch(IDLE_MS, &tap(Gesture::Tap(p))));
+        assert!(!idle.is_idle());
+    }
+}
@@ -0,0 +1,612 @@
+//! Time of day for the apps.
+//!
+//! The ESP32's RTC counts milliseconds since power-up and
+//! keeps counting through resets. A [`WallClock`] remembers
+//! which RTC reading belonged to which date and time, so it
+//! can tell the time from any later reading. That anchor is
+//! kept in flash: after a reset the time is still right,
+//! after a power cut (the RTC starts over) it has to be set
+//! again.
+//!
+//! The CYD has no 32 kHz crystal, so the RTC runs on the
+//! internal oscillator and drifts by minutes a day; set the
+//! time now and then.
+//!
+//! The time is set on the [`SetTimePage`], or with a
+//! [`TimeCommand`] on the serial console:
+//!
+//! ```text
+//! time                      prints the time
+//! time 14:30                sets the time of day
+//! time 2026-10-18 14:30:05  sets date and time
+//! ```
+//!
+//! The [`ClockFace`] shows it, and is the idle screen of
+//! the microwave and light-control apps.
+
+use core::fmt;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::storage::{RecordStore, Slot, StorageError};
+
+mod face;
+mod screen;
+
+pub use face::{ClockFace, Idle, IDLE_MS};
+pub use screen::{SetTimeAction, SetTimePage};
+
+/// Earliest year that can be set.
+pub const MIN_YEAR: u16 = 2000;
+/// Latest year that can be set.
+pub const MAX_YEAR: u16 = 2099;
+
+/// A date and time, without time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The date and time `secs` seconds after
+    /// 1970-01-01 00:00:00.
+    pub fn from_unix(secs: u64) -> Self {
+        // Howard Hinnant's `civil_from_days`, for days
+        // after 1970 only
+        let days = secs / 86_400 + 719_468;
+        let era = days / 146_097;
+        let doe = days % 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524
+            - doe / 146_096)
+            / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = era * 400
+            + yoe
+            + if month <= 2 { 1 } else { 0 };
+        let time = secs % 86_400;
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00:00. Only for valid
+    /// dates from 1970 on.
+    pub fn to_unix(&self) -> u64 {
+        // Howard Hinnant's `days_from_civil`
+        let (month, day) =
+            (self.month as u64, self.day as u64);
+        let year = self.year as u64 - (month <= 2) as u64;
+        let era = year / 400;
+        let yoe = year % 400;
+        let doy =
+            (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        days * 86_400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    /// Whether this is a real date in
+    /// [`MIN_YEAR`] ..= [`MAX_YEAR`] and a real time.
+    pub fn is_valid(&self) -> bool {
+        (MIN_YEAR..=MAX_YEAR).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month))
+                .contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0
+            && (year % 100 != 0 || year % 400 == 0) =>
+        {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Which RTC reading belongs to which time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Anchor {
+    unix_ms: u64,
+    rtc_ms: u64,
+}
+
+/// Tells the time from RTC readings, once it has been
+/// [set](Self::set).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WallClock {
+    anchor: Option<Anchor>,
+}
+
+impl WallClock {
+    const FORMAT_VERSION: u8 = 1;
+
+    pub const ENCODED_LEN: usize = 1 + 1 + 8 + 8;
+
+    /// A clock that doesn't know the time yet.
+    pub const fn new() -> Self {
+        Self { anchor: None }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.anchor.is_some()
+    }
+
+    /// Sets the time to `unix_secs` at RTC time `rtc_ms`.
+    pub fn set(&mut self, rtc_ms: u64, unix_secs: u64) {
+        self.anchor = Some(Anchor {
+            unix_ms: unix_secs * 1000,
+            rtc_ms,
+        });
+    }
+
+    /// Seconds since 1970 at RTC time `rtc_ms`, if the time
+    /// is set.
+    pub fn unix_secs(&self, rtc_ms: u64) -> Option<u64> {
+        let anchor = self.anchor?;
+        let ms = anchor.unix_ms
+            + rtc_ms.saturating_sub(anchor.rtc_ms);
+        Some(ms / 1000)
+    }
+
+    /// The time at RTC time `rtc_ms`, if it is set.
+    pub fn now(&self, rtc_ms: u64) -> Option<DateTime> {
+        self.unix_secs(rtc_ms).map(DateTime::from_unix)
+    }
+
+    /// Applies `set` at RTC time `rtc_ms`.
+    pub fn apply(&mut self, rtc_ms: u64, set: SetTime) {
+        let secs = set.apply(self.unix_secs(rtc_ms));
+        self.set(rtc_ms, secs);
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        buf[0] = Self::FORMAT_VERSION;
+        if let Some(anchor) = self.anchor {
+            buf[1] = 1;
+            buf[2..10].copy_from_slice(
+                &anchor.unix_ms.to_le_bytes(),
+            );
+            buf[10..].copy_from_slice(
+                &anchor.rtc_ms.to_le_bytes(),
+            );
+        }
+        buf
+    }
+
+    /// Inverse of [`WallClock::to_bytes`]. Rejects other
+    /// format versions.
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() != Self::ENCODED_LEN
+            || buf[0] != Self::FORMAT_VERSION
+        {
+            return None;
+        }
+        let anchor = match buf[1] {
+            0 => None,
+            1 => Some(Anchor {
+                unix_ms: u64::from_le_bytes(
+                    buf[2..10].try_into().ok()?,
+                ),
+                rtc_ms: u64::from_le_bytes(
+                    buf[10..].try_into().ok()?,
+                ),
+            }),
+            _ => return None,
+        };
+        Some(Self { anchor })
+    }
+
+    pub fn save<F: NorFlash>(
+        &self,
+        store: &mut RecordStore<F>,
+    ) -> Result<(), StorageError<F::Error>> {
+        store.store(Slot::WallClock, &self.to_bytes())
+    }
+
+    /// Loads the stored clock. At RTC time `rtc_ms` before
+    /// the stored one the RTC has started over, and the
+    /// stored clock is of no use any more.
+    pub fn load<F: NorFlash>(
+        store: &mut RecordStore<F>,
+        rtc_ms: u64,
+    ) -> Result<Option<Self>, StorageError<F::Error>> {
+        let mut buf = [0u8; Self::ENCODED_LEN];
+        Ok(store
+            .load(Slot::WallClock, &mut buf)?
+            .and_then(Self::from_bytes)
+            .filter(|c| match c.anchor {
+                Some(a) => a.rtc_ms <= rtc_ms,
+                None => true,
+            }))
+    }
+}
+
+/// Loads the stored clock, or one that isn't set.
+#[cfg(target_os = "none")]
+pub fn stored_clock(rtc_ms: u64) -> WallClock {
+    WallClock::load(&mut crate::storage::open(), rtc_ms)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "none")]
+impl WallClock {
+    /// Applies `set` and stores the result in flash.
+    pub fn set_and_save(
+        &mut self,
+        rtc_ms: u64,
+        set: SetTime,
+    ) {
+        self.apply(rtc_ms, set);
+        self.save(&mut crate::storage::open()).ok();
+    }
+
+    /// Runs `line` from the serial console if it is a
+    /// [`TimeCommand`], and prints the result. Returns
+    /// whether it was one.
+    pub fn run_command(
+        &mut self,
+        line: &str,
+        rtc_ms: u64,
+    ) -> bool {
+        use esp_println::println;
+
+        match TimeCommand::parse(line) {
+            None => return false,
+            Some(Ok(TimeCommand::Show)) => {}
+            Some(Ok(TimeCommand::Set(set))) => {
+                self.set_and_save(rtc_ms, set);
+            }
+            Some(Err(_)) => {
+                println!(
+                    "usage: time [yyyy-mm-dd] [hh:mm[:ss]]"
+                );
+                return true;
+            }
+        }
+        match self.now(rtc_ms) {
+            Some(now) => println!("time: {}", now),
+            None => println!("time: not set"),
+        }
+        true
+    }
+}
+
+/// A new time, as typed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetTime {
+    /// Keeps the date.
+    TimeOfDay {
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+    DateTime(DateTime),
+}
+
+impl SetTime {
+    /// Seconds since 1970 of the new time. A time of day
+    /// goes with the date of `current`, or 1970-01-01 if
+    /// the time isn't set.
+    pub fn apply(self, current: Option<u64>) -> u64 {
+        match self {
+            SetTime::TimeOfDay {
+                hour,
+                minute,
+                second,
+            } => {
+                let day = current.unwrap_or(0) / 86_400;
+                day * 86_400
+                    + hour as u64 * 3600
+                    + minute as u64 * 60
+                    + second as u64
+            }
+            SetTime::DateTime(date_time) => {
+                date_time.to_unix()
+            }
+        }
+    }
+}
+
+/// Why a [`TimeCommand`] can't be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    Date,
+    Time,
+}
+
+/// What can be typed on the serial console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeCommand {
+    /// `time`
+    Show,
+    /// `time hh:mm[:ss]` or `time yyyy-mm-dd hh:mm[:ss]`
+    Set(SetTime),
+}
+
+impl TimeCommand {
+    /// `None` if `line` is no `time` command at all.
+    pub fn parse(
+        line: &str,
+    ) -> Option<Result<Self, ParseError>> {
+        let mut words = line.split_whitespace();
+        if words.next()? != "time" {
+            return None;
+        }
+        let first = words.next();
+        let second = words.next();
+        if words.next().is_some() {
+            return Some(Err(ParseError::Time));
+        }
+        Some(match (first, second) {
+            (None, _) => Ok(TimeCommand::Show),
+            (Some(time), None) => parse_time(time).map(
+                |(hour, minute, second)| {
+                    TimeCommand::Set(SetTime::TimeOfDay {
+                        hour,
+                        minute,
+                        second,
+                    })
+                },
+            ),
+            (Some(date), Some(time)) => {
+                parse_date_time(date, time).map(|dt| {
+                    TimeCommand::Set(SetTime::DateTime(dt))
+                })
+            }
+        })
+    }
+}
+
+/// `hh:mm` or `hh:mm:ss`.
+fn parse_time(
+    text: &str,
+) -> Result<(u8, u8, u8), ParseError> {
+    let mut fields =
+        text.split(':').map(|f| f.parse::<u8>());
+    let mut next = |max: u8, default: Option<u8>| match (
+        fields.next(),
+        default,
+    ) {
+        (Some(Ok(v)), _) if v <= max => Ok(v),
+        (None, Some(v)) => Ok(v),
+        _ => Err(ParseError::Time),
+    };
+    let time = (
+        next(23, None)?,
+        next(59, None)?,
+        next(59, Some(0))?,
+    );
+    match fields.next() {
+        None => Ok(time),
+        Some(_) => Err(ParseError::Time),
+    }
+}
+
+/// `yyyy-mm-dd` and a [`parse_time`] time.
+fn parse_date_time(
+    date: &str,
+    time: &str,
+) -> Result<DateTime, ParseError> {
+    let mut fields = date.split('-');
+    let mut next = || {
+        fields
+            .next()
+            .and_then(|f| f.parse::<u16>().ok())
+            .ok_or(ParseError::Date)
+    };
+    let (year, month, day) = (next()?, next()?, next()?);
+    if fields.next().is_some() || month > 12 || day > 31 {
+        return Err(ParseError::Date);
+    }
+    let (hour, minute, second) = parse_time(time)?;
+    let date_time = DateTime {
+        year,
+        month: month as u8,
+        day: day as u8,
+        hour,
+        minute,
+        second,
+    };
+    if !date_time.is_valid() {
+        return Err(ParseError::Date);
+    }
+    Ok(date_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::MockFlash;
+
+    fn date_time(
+        (year, month, day): (u16, u8, u8),
+        (hour, minute, second): (u8, u8, u8),
+    ) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn unix_time_round_trips() {
+        for (secs, dt) in [
+            (0, date_time((1970, 1, 1), (0, 0, 0))),
+            (
+                951_782_400,
+                date_time((2000, 2, 29), (0, 0, 0)),
+            ),
+            (
+                1_792_333_805,
+                date_time((2026, 10, 18), (14, 30, 5)),
+            ),
+            (
+                4_102_444_799,
+                date_time((2099, 12, 31), (23, 59, 59)),
+            ),
+        ] {
+            assert_eq!(DateTime::from_unix(secs), dt);
+            assert_eq!(dt.to_unix(), secs);
+        }
+        assert_eq!(
+            DateTime::from_unix(1_792_333_805).to_string(),
+            "2026-10-18 14:30:05"
+        );
+    }
+
+    #[test]
+    fn the_clock_follows_the_rtc() {
+        let mut c = WallClock::new();
+        assert_eq!(c.now(5000), None);
+        c.set(5000, 1_792_333_805);
+        assert_eq!(
+            c.now(65_000),
+            Some(date_time((2026, 10, 18), (14, 31, 5)))
+        );
+        // setting the time of day keeps the date
+        c.apply(
+            65_000,
+            SetTime::TimeOfDay {
+                hour: 7,
+                minute: 15,
+                second: 0,
+            },
+        );
+        assert_eq!(
+            c.now(66_000),
+            Some(date_time((2026, 10, 18), (7, 15, 1)))
+        );
+    }
+
+    #[test]
+    fn time_commands() {
+        assert_eq!(TimeCommand::parse("help"), None);
+        assert_eq!(TimeCommand::parse(""), None);
+        assert_eq!(
+            TimeCommand::parse("time"),
+            Some(Ok(TimeCommand::Show))
+        );
+        assert_eq!(
+            TimeCommand::parse(" time 7:05 "),
+            Some(Ok(TimeCommand::Set(
+                SetTime::TimeOfDay {
+                    hour: 7,
+                    minute: 5,
+                    second: 0
+                }
+            )))
+        );
+        assert_eq!(
+            TimeCommand::parse("time 2024-02-29 23:59:30"),
+            Some(Ok(TimeCommand::Set(SetTime::DateTime(
+                date_time((2024, 2, 29), (23, 59, 30))
+            ))))
+        );
+        for (line, error) in [
+            ("time 24:00", ParseError::Time),
+            ("time 12", ParseError::Time),
+            ("time 12:60", ParseError::Time),
+            ("time 1:2:3:4", ParseError::Time),
+            ("time 2023-02-29 12:00", ParseError::Date),
+            ("time 1999-12-31 12:00", ParseError::Date),
+            ("time 2024-13-01 12:00", ParseError::Date),
+            ("time 2024-1 12:00", ParseError::Date),
+            ("time 2024-01-01 12:00 x", ParseError::Time),
+        ] {
+            assert_eq!(
+                TimeCommand::parse(line),
+                Some(Err(error)),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn the_clock_survives_a_reset_but_not_a_power_cut() {
+        let mut store =
+            RecordStore::new(MockFlash::new(), 0);
+        assert_eq!(
+            WallClock::load(&mut store, 0),
+            Ok(None)
+        );
+        let mut c = WallClock::new();
+        c.set(10_000, 1_792_333_805);
+        c.save(&mut store).unwrap();
+        assert_eq!(
+            WallClock::load(&mut store, 20_000),
+            Ok(Some(c))
+        );
+        assert_eq!(
+            WallClock::load(&mut store, 500),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn broken_clocks_are_rejected() {
+        let mut bytes = WallClock::new().to_bytes();
+        assert_eq!(
+            WallClock::from_bytes(&bytes),
+            Some(WallClock::new())
+        );
+        bytes[1] = 2;
+        assert_eq!(WallClock::from_bytes(&bytes), None);
+        bytes[1] = 0;
+        bytes[0] = 0;
+        assert_eq!(WallClock::from_bytes(&bytes), None);
+        assert_eq!(
+            WallClock::from_bytes(&bytes[1..]),
+            None
+        );
+    }
+}
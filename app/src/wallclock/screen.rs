@@ -0,0 +1,167 @@
+use core::fmt::Write;
+
+use embedded_graphics::{
+    mono_font::ascii,
+    pixelcolor::Rgb565,
+    prelude::*,
+};
+use heapless::String;
+use kolibri_embedded_gui::{
+    button::Button,
+    label::Label,
+    smartstate::SmartstateProvider,
+    spacer::Spacer,
+    ui::Ui,
+};
+
+use super::{DateTime, SetTime};
+use crate::focus::Focus;
+
+/// What the user picked on the [`SetTimePage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetTimeAction {
+    Save(SetTime),
+    Cancel,
+}
+
+/// Kolibri page to set the time of day. The date can only
+/// be set over the serial console.
+#[derive(Debug, Clone)]
+pub struct SetTimePage {
+    hour: u8,
+    minute: u8,
+}
+
+impl SetTimePage {
+    /// Starts from `now`, or from 12:00 if the time isn't
+    /// set.
+    pub fn new(now: Option<DateTime>) -> Self {
+        match now {
+            Some(now) => Self {
+                hour: now.hour,
+                minute: now.minute,
+            },
+            None => Self {
+                hour: 12,
+                minute: 0,
+            },
+        }
+    }
+
+    /// Draws the page. Returns the button the user pressed
+    /// this frame, if it closes the page.
+    pub fn show<
+        D: DrawTarget<Color = Rgb565>,
+        const N: usize,
+    >(
+        &mut self,
+        ui: &mut Ui<D, Rgb565>,
+        sm: &mut SmartstateProvider<N>,
+        focus: &mut Focus,
+    ) -> Option<SetTimeAction> {
+        ui.add(Spacer::new(Size::new(0, 20)));
+        ui.add_centered(
+            Label::new("Set the time")
+                .smartstate(sm.next())
+                .with_font(ascii::FONT_10X20),
+        );
+        ui.add(Spacer::new(Size::new(0, 20)));
+
+        ui.add_horizontal(Spacer::new(Size::new(40, 0)));
+        self.hour =
+            Self::field(ui, sm, focus, self.hour, 24);
+        ui.add_horizontal(
+            Label::new(":")
+                .smartstate(sm.next())
+                .with_font(ascii::FONT_10X20),
+        );
+        self.minute =
+            Self::field(ui, sm, focus, self.minute, 60);
+        ui.new_row();
+
+        let mut action = None;
+        ui.add(Spacer::new(Size::new(0, 20)));
+        ui.add_horizontal(Spacer::new(Size::new(80, 0)));
+        if focus
+            .track(ui.add_horizontal(
+                Button::new("Cancel").smartstate(sm.next()),
+            ))
+            .clicked()
+        {
+            action = Some(SetTimeAction::Cancel);
+        }
+        ui.add_horizontal(Spacer::new(Size::new(20, 0)));
+        if focus
+            .track(ui.add(
+                Button::new("Save").smartstate(sm.next()),
+            ))
+            .clicked()
+        {
+            action = Some(SetTimeAction::Save(
+                SetTime::TimeOfDay {
+                    hour: self.hour,
+                    minute: self.minute,
+                    second: 0,
+                },
+            ));
+        }
+
+        ui.add(Spacer::new(Size::new(0, 20)));
+        ui.add_centered(
+            Label::new("date: 'time yyyy-mm-dd hh:mm'")
+                .smartstate(sm.next())
+                .with_font(ascii::FONT_6X10),
+        );
+        ui.add_centered(
+            Label::new("on the serial console")
+                .smartstate(sm.next())
+                .with_font(ascii::FONT_6X10),
+        );
+        action
+    }
+
+    /// A `-` button, the value and a `+` button, in one
+    /// row. Returns the new value, wrapping around at
+    /// `modulo`.
+    fn field<
+        D: DrawTarget<Color = Rgb565>,
+        const N: usize,
+    >(
+        ui: &mut Ui<D, Rgb565>,
+        sm: &mut SmartstateProvider<N>,
+        focus: &mut Focus,
+        value: u8,
+        modulo: u8,
+    ) -> u8 {
+        let mut new = value;
+        if focus
+            .track(ui.add_horizontal(
+                Button::new("-").smartstate(sm.next()),
+            ))
+            .clicked()
+        {
+            new = (value + modulo - 1) % modulo;
+        }
+        let mut text = String::<2>::new();
+        write!(text, "{:02}", value).ok();
+        ui.add_horizontal(
+            Label::new(&text)
+                .smartstate(sm.next())
+                .with_font(ascii::FONT_10X20),
+        );
+        if focus
+            .track(ui.add_horizontal(
+                Button::new("+").smartstate(sm.next()),
+            ))
+            .clicked()
+        {
+            new = (value + 1) % modulo;
+        }
+        if new != value {
+            // the label is drawn already, show the new
+            // value next frame
+            sm.force_redraw_all();
+        }
+        new
+    }
+}
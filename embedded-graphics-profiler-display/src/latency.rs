@@ -0,0 +1,78 @@
+use atomic::{Atomic, Ordering};
+
+/// Marks "no time" in the atomics below.
+const NONE: u64 = u64::MAX;
+
+/// Touches older than this when the UI changes are not counted as its cause.
+pub const STALE_TOUCH_MS: u64 = 1000;
+
+/// Measures touch-to-photon latency: the time from a touch, as stamped by the touch driver, to the
+/// first pixel the display writes after the UI changed because of it.
+///
+/// The app reports each touch with [`touched`](Self::touched) before handing it to the GUI
+/// framework, and calls [`state_changed`](Self::state_changed) wherever it reacts to input (button
+/// callbacks, clicked handlers, ...). A [`ProfilerDisplay`](crate::ProfilerDisplay) set up with
+/// [`with_latency_tag`](crate::ProfilerDisplay::with_latency_tag) then stamps the next write, and
+/// [`take_latency`](Self::take_latency) returns the result.
+///
+/// The tag is meant to live in a `static`, so the handlers can reach it while the GUI holds the
+/// display.
+pub struct LatencyTag {
+    /// Clock the touch timestamps are in, in milliseconds.
+    now_ms: fn() -> u64,
+    /// The last touch handed to the GUI.
+    touched: Atomic<u64>,
+    /// The touch that changed the UI, waiting for its first pixel.
+    armed: Atomic<u64>,
+    latency: Atomic<u64>,
+}
+
+impl LatencyTag {
+    /// Creates a new `LatencyTag`.
+    ///
+    /// # Arguments
+    ///
+    /// * `now_ms` - The clock the touch driver stamps its events with.
+    pub const fn new(now_ms: fn() -> u64) -> Self {
+        LatencyTag {
+            now_ms,
+            touched: Atomic::new(NONE),
+            armed: Atomic::new(NONE),
+            latency: Atomic::new(NONE),
+        }
+    }
+
+    /// Records a touch event, stamped `t_ms` by the driver, that is about to be handled.
+    pub fn touched(&self, t_ms: u64) {
+        self.touched.store(t_ms, Ordering::Relaxed);
+    }
+
+    /// Tags a change of the UI state as caused by the last touch. Does nothing if there was no
+    /// touch in the last [`STALE_TOUCH_MS`], e.g. for changes made by timers or buttons.
+    pub fn state_changed(&self) {
+        let touched = self.touched.swap(NONE, Ordering::Relaxed);
+        if touched != NONE && (self.now_ms)().saturating_sub(touched) <= STALE_TOUCH_MS {
+            self.armed.store(touched, Ordering::Relaxed);
+        }
+    }
+
+    /// Called by the display on every write.
+    pub(crate) fn pixel_written(&self) {
+        if self.armed.load(Ordering::Relaxed) == NONE {
+            return;
+        }
+        let armed = self.armed.swap(NONE, Ordering::Relaxed);
+        if armed != NONE {
+            let latency = (self.now_ms)().saturating_sub(armed);
+            self.latency.store(latency, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the latency measured since the last call, in milliseconds.
+    pub fn take_latency(&self) -> Option<u64> {
+        match self.latency.swap(NONE, Ordering::Relaxed) {
+            NONE => None,
+            latency => Some(latency),
+        }
+    }
+}
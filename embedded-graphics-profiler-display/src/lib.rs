@@ -1,5 +1,7 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 #![no_main]
 
+mod latency;
 mod profiler;
+pub use latency::{LatencyTag, STALE_TOUCH_MS};
 pub use profiler::ProfilerDisplay;
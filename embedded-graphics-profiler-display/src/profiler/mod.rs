@@ -8,12 +8,15 @@ use embedded_graphics::{Drawable, Pixel};
 use embedded_graphics::geometry::Dimensions;
 use embedded_graphics::primitives::Rectangle;
 
+use crate::LatencyTag;
+
 pub struct ProfilerDisplay<DRAW_TARGET: DrawTarget> {
     drawtarget: DRAW_TARGET,
     time_draw: Duration,
     time_draw_iter: Duration,
     time_fill_contiguous: Duration,
     time_fill_solid: Duration,
+    latency_tag: Option<&'static LatencyTag>,
 }
 
 impl<DRAW_TARGET: DrawTarget> Dimensions for ProfilerDisplay<DRAW_TARGET> {
@@ -30,6 +33,7 @@ impl<DRAW_TARGET: DrawTarget> DrawTarget for ProfilerDisplay<DRAW_TARGET> {
     where
         I: IntoIterator<Item=Pixel<Self::Color>>
     {
+        self.mark_written();
         let start = Instant::now();
         let res = self.drawtarget.draw_iter(pixels);
         self.time_draw_iter += Instant::now() - start;
@@ -40,6 +44,7 @@ impl<DRAW_TARGET: DrawTarget> DrawTarget for ProfilerDisplay<DRAW_TARGET> {
     where
         I: IntoIterator<Item=Self::Color>
     {
+        self.mark_written();
         let start = Instant::now();
         let res = self.drawtarget.fill_contiguous(area, colors);
         self.time_fill_contiguous += Instant::now() - start;
@@ -47,6 +52,7 @@ impl<DRAW_TARGET: DrawTarget> DrawTarget for ProfilerDisplay<DRAW_TARGET> {
     }
 
     fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.mark_written();
         let start = Instant::now();
         let res = self.drawtarget.fill_solid(area, color);
         self.time_fill_solid += Instant::now() - start;
@@ -54,6 +60,7 @@ impl<DRAW_TARGET: DrawTarget> DrawTarget for ProfilerDisplay<DRAW_TARGET> {
     }
 
     fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.mark_written();
         let start = Instant::now();
         let res = self.drawtarget.clear(color);
         self.time_fill_solid += Instant::now() - start;
@@ -74,6 +81,23 @@ impl<DRAW_TARGET: DrawTarget> ProfilerDisplay<DRAW_TARGET> {
             time_draw_iter: Duration::from_millis(0),
             time_fill_contiguous: Duration::from_millis(0),
             time_fill_solid: Duration::from_millis(0),
+            latency_tag: None,
+        }
+    }
+
+    /// Stamps the first write after each tagged UI change for touch-to-photon latency.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The tag the app reports touches and UI changes to.
+    pub fn with_latency_tag(mut self, tag: &'static LatencyTag) -> Self {
+        self.latency_tag = Some(tag);
+        self
+    }
+
+    fn mark_written(&self) {
+        if let Some(tag) = self.latency_tag {
+            tag.pixel_written();
         }
     }
 
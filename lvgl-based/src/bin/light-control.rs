@@ -1,8 +1,8 @@
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     cmp::min,
     str::FromStr,
-    sync::mpsc::channel,
+    sync::{mpsc::channel, OnceLock},
     thread,
     time::{Duration, Instant},
 };
@@ -13,11 +13,17 @@ use embedded_graphics_core::{
     draw_target::DrawTarget,
     prelude::Point,
 };
-use embedded_graphics_profiler_display::ProfilerDisplay;
+use embedded_graphics_profiler_display::{LatencyTag, ProfilerDisplay};
 use esp_idf_hal::spi::SpiSingleDeviceDriver;
 use esp_idf_hal::{
     delay::{self, Delay},
     gpio::*,
+    ledc::{
+        config::TimerConfig,
+        LedcDriver,
+        LedcTimerDriver,
+        Resolution,
+    },
     peripherals::Peripherals,
     spi::{
         config::DriverConfig,
@@ -60,6 +66,14 @@ use mipidsi::{
     Builder,
 };
 use xpt2046::Xpt2046;
+use kolibri_cyd_tester_app_embassy::lamp::{LampBackend, LampKind, PwmBackend, MAX_LAMPS};
+use kolibri_cyd_tester_app_embassy::latency::LatencyStats;
+use kolibri_cyd_tester_app_embassy::output::Polarity;
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchKind, TouchTracker},
+    replay,
+    script::{self, Recorder},
+};
 
 fn lerp_fixed(start: u8, end: u8, t: u8, max_t: u8) -> u8 {
     let (start, end, t, max_t) =
@@ -71,11 +85,11 @@ fn lerp_fixed(start: u8, end: u8, t: u8, max_t: u8) -> u8 {
     result as u8
 }
 
+/// A lamp as shown; what it does is kept by the lamp
+/// backend.
 #[derive(Debug, Clone)]
 struct Lamp {
     pub name: heapless::String<64>,
-    pub on: bool,
-    pub brightness: u8,
 }
 
 impl Lamp {
@@ -84,8 +98,6 @@ impl Lamp {
             name: heapless::String::from(
                 heapless::String::from_str(name).unwrap(),
             ),
-            on: false,
-            brightness: 255,
         }
     }
 }
@@ -110,6 +122,31 @@ impl AppData {
     }
 }
 
+/// Shows `on` on the switch, without an event.
+fn show_on(switch: &mut Switch, on: bool) {
+    unsafe {
+        if on {
+            lvgl_sys::lv_obj_add_state(switch.raw().as_ptr(), lvgl_sys::LV_STATE_CHECKED as u16);
+        } else {
+            lvgl_sys::lv_obj_clear_state(switch.raw().as_ptr(), lvgl_sys::LV_STATE_CHECKED as u16);
+        }
+    }
+}
+
+/// Filled by the touch thread, read by LVGL's pointer
+/// callback.
+static TOUCH_EVENTS: TouchChannel = TouchChannel::new();
+
+/// Start of the clock the touch thread stamps its events
+/// with.
+static CLOCK_START: OnceLock<Instant> = OnceLock::new();
+
+/// Stamps the first frame that shows the result of a touch,
+/// in the touch thread's clock.
+static LATENCY: LatencyTag = LatencyTag::new(|| {
+    CLOCK_START.get_or_init(Instant::now).elapsed().as_millis() as u64
+});
+
 fn main() -> Result<(), LvError> {
     const HOR_RES: u32 = 320;
     const VER_RES: u32 = 240;
@@ -189,7 +226,7 @@ fn main() -> Result<(), LvError> {
         .init(&mut Delay::new_default())
         .unwrap();
 
-    let mut raw_display = ProfilerDisplay::new(raw_display);
+    let mut raw_display = ProfilerDisplay::new(raw_display).with_latency_tag(&LATENCY);
 
     // Stack size value - 20,000 for 10 lines,  40,000 for
     // 20 lines let (touch_send, touch_recv) =
@@ -219,7 +256,47 @@ fn main() -> Result<(), LvError> {
     touch_driver.set_num_samples(1);
     touch_driver.init(&mut Delay::new_default()).unwrap();
 
-    let touch_driver = RefCell::new(touch_driver);
+    // Sample the touch controller on its own thread and queue
+    // the changes, so LVGL sees every press and release even
+    // if it reads the pointer less often.
+    let _touch_thread = thread::Builder::new()
+        .stack_size(8_192)
+        .spawn(move || {
+            let start = *CLOCK_START.get_or_init(Instant::now);
+            if let Some(script) = script::SCRIPT {
+                replay::play_blocking(
+                    script,
+                    &TOUCH_EVENTS,
+                    |ms| thread::sleep(Duration::from_millis(ms)),
+                    || start.elapsed().as_millis() as u64,
+                    |args| println!("{}", args),
+                );
+            }
+            if script::RECORDING {
+                println!("{}{}", script::RECORD_PREFIX, script::HEADER);
+            }
+
+            let mut tracker = TouchTracker::new();
+            let mut recorder = Recorder::new();
+            loop {
+                touch_driver.run().expect("Running Touch driver failed");
+                let touch = touch_driver.is_touched().then(|| {
+                    let point = touch_driver.get_touch_point();
+                    Point::new(point.x + 20, 240 - point.y)
+                });
+                let now = start.elapsed().as_millis() as u64;
+                if let Some(event) = tracker.update(now, touch) {
+                    if script::RECORDING {
+                        println!("{}{}", script::RECORD_PREFIX, recorder.line(&event));
+                    }
+                    if !TOUCH_EVENTS.push(event) {
+                        println!("touch event dropped: {:?}", event);
+                    }
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+        .unwrap();
 
     let _lvgl_thread = thread::Builder::new()
         .stack_size(40_000)
@@ -236,18 +313,18 @@ fn main() -> Result<(), LvError> {
 
             // Register a new input device that's capable of reading the current state of
             // the input
-            // let mut last_touch = RefCell::new(None);
+            let touch = Cell::new((false, Point::new(0, 0)));
             let _touch_screen = Pointer::register(
                 || {
-                    let mut td_ref = touch_driver.borrow_mut();
-                    td_ref.run().expect("Running Touch driver failed");
-                    if td_ref.is_touched() {
-                        let point = td_ref.get_touch_point();
-                        // println!("touched {:?}", point);
-                        PointerInputData::Touch(Point::new(point.x + 20, 240 - point.y)).pressed().once()
-                    } else {
-                        // println!("untouched");
-                        PointerInputData::Touch(Point::new(0, 0)).released().once()
+                    // one event per read, so a quick tap still
+                    // shows up as a press and a release
+                    if let Some(event) = TOUCH_EVENTS.try_receive() {
+                        LATENCY.touched(event.t_ms);
+                        touch.set((event.kind != TouchKind::Release, event.point));
+                    }
+                    match touch.get() {
+                        (true, point) => PointerInputData::Touch(point).pressed().once(),
+                        (false, point) => PointerInputData::Touch(point).released().once(),
                     }
                 },
                 &display,
@@ -271,6 +348,30 @@ fn main() -> Result<(), LvError> {
             appdata.add_lamp("Bathroom");
             appdata.add_lamp("Porch");
 
+            // the first three lamps are the colours of the RGB LED
+            let led_timer = LedcTimerDriver::new(
+                peripherals.ledc.timer0,
+                &TimerConfig::default()
+                    .frequency(1.kHz().into())
+                    .resolution(Resolution::Bits8),
+            )
+            .unwrap();
+            let channels = [
+                LedcDriver::new(peripherals.ledc.channel0, &led_timer, pins.gpio4).unwrap(),
+                LedcDriver::new(peripherals.ledc.channel1, &led_timer, pins.gpio16).unwrap(),
+                LedcDriver::new(peripherals.ledc.channel2, &led_timer, pins.gpio17).unwrap(),
+            ];
+            // the UI only dims them, so each is a channel of the LED
+            let kinds = [LampKind::Dimmable; MAX_LAMPS];
+            let lamps = RefCell::new(
+                PwmBackend::new(
+                    channels,
+                    Polarity::ActiveLow,
+                    &kinds[..appdata.lamps.len().min(MAX_LAMPS)],
+                )
+                .unwrap(),
+            );
+
             let mut light_page = Screen::blank().unwrap();
             light_page.set_size(320, 240);
             let mut light_page_style = Style::default();
@@ -321,6 +422,7 @@ fn main() -> Result<(), LvError> {
             back_btn.set_align(Align::TopLeft, 10, 10);
             back_btn.on_event(|_btn, event| {
                 if let Event::Pressed = event {
+                    LATENCY.state_changed();
                     display.set_scr_act(&mut screen);
                 }
             });
@@ -335,37 +437,51 @@ fn main() -> Result<(), LvError> {
 
             let mut page = Page::Home;
 
-            for lamp in appdata.lamps.iter_mut() {
+            for (i, lamp) in appdata.lamps.iter().enumerate() {
                 let mut cont = Btn::new().unwrap();
                 cont.set_size(100, 110);
                 // cont.set_align(Align::TopLeft, 0, 0);
 
                 cont.on_event(|_btn, event| {
                     if let Event::Pressed = event {
+                        LATENCY.state_changed();
                         // println!("lamp {:?}", lamp.name);
                         // page = Page::LampCtrl(lamp);
 
-                        brightness_slider.set_value(lerp_fixed(0, 100, lamp.brightness, 255).into(), AnimationState::OFF);
-                        unsafe {
-                            if lamp.on {
-                                lvgl_sys::lv_obj_add_state(light_switch.raw().as_ptr(), lvgl_sys::LV_STATE_CHECKED as u16);
-                            } else {
-                                lvgl_sys::lv_obj_clear_state(light_switch.raw().as_ptr(), lvgl_sys::LV_STATE_CHECKED as u16);
-                            }
-                        }
-                        light_switch.on_event(|_ls, event| {
+                        let state = lamps.borrow().state(i).unwrap_or_default();
+                        brightness_slider.set_value(lerp_fixed(0, 100, state.brightness, 255).into(), AnimationState::OFF);
+                        show_on(&mut light_switch, state.on);
+                        // the widgets only ask the backend, and then show
+                        // what it confirmed
+                        light_switch.on_event(|mut _ls, event| {
                             if let Event::ValueChanged | Event::Released = event {
+                                LATENCY.state_changed();
                                 let on = unsafe { lvgl_sys::lv_obj_has_state(_ls.raw().as_ptr(), lvgl_sys::LV_STATE_CHECKED as u16) };
-                                lamp.on = on;
+                                let mut lamps = lamps.borrow_mut();
+                                if let Err(e) = lamps.set_on(i, on) {
+                                    println!("lamp {}: {:?}", i, e);
+                                }
+                                let confirmed = lamps.state(i).unwrap_or_default().on;
+                                if confirmed != on {
+                                    show_on(&mut _ls, confirmed);
+                                }
                             }
                         });
-                        brightness_slider.on_event(|_sldr, event| {
+                        brightness_slider.on_event(|mut _sldr, event| {
                             // println!("event: {:?}", event);
                             if let Event::ValueChanged | Event::Released = event {
-                                let brightness = _sldr.get_value();
-                                println!("brightness: {}", brightness);
-                                lamp.brightness = lerp_fixed(0, 255, brightness as u8, 100);
-                                println!("brightness: {}", lamp.brightness);
+                                LATENCY.state_changed();
+                                let value = _sldr.get_value();
+                                let brightness = lerp_fixed(0, 255, value as u8, 100);
+                                let mut lamps = lamps.borrow_mut();
+                                if let Err(e) = lamps.set_brightness(i, brightness) {
+                                    println!("lamp {}: {:?}", i, e);
+                                }
+                                let confirmed = lamps.state(i).unwrap_or_default().brightness;
+                                println!("brightness: {}", confirmed);
+                                if confirmed != brightness {
+                                    _sldr.set_value(lerp_fixed(0, 100, confirmed, 255).into(), AnimationState::OFF);
+                                }
                             }
                         });
                         light_page_title_label.set_text(CString::new(lamp.name.as_str()).unwrap().as_c_str());
@@ -520,12 +636,16 @@ fn main() -> Result<(), LvError> {
 
              */
 
+            let mut latency = LatencyStats::new("lvgl", "light-control");
             let mut last_time = Instant::now();
             loop {
                 let start_time = Instant::now();
 
                 let start_draw_time = Instant::now();
                 lvgl::task_handler();
+                // the lamps only change from the widgets, which show
+                // the confirmed state already
+                while lamps.borrow_mut().poll().is_some() {}
 
                 // Simulate clock - so sleep for one second so time text is incremented in
                 // seconds
@@ -555,6 +675,11 @@ fn main() -> Result<(), LvError> {
                         (draw_time + prep_time + proc_time).as_micros() % 100, );
                 }
                 raw_display.reset_time();
+                if let Some(ms) = LATENCY.take_latency() {
+                    if latency.add(ms) {
+                        println!("{}", latency);
+                    }
+                }
 
                 delay::FreeRtos::delay_ms(2);
             }
@@ -1,8 +1,8 @@
 use std::{
-    cell::RefCell,
+    cell::Cell,
     cmp::min,
     str::FromStr,
-    sync::mpsc::channel,
+    sync::{mpsc::channel, OnceLock},
     thread,
     time::{Duration, Instant},
 };
@@ -13,7 +13,7 @@ use embedded_graphics_core::{
     draw_target::DrawTarget,
     prelude::Point,
 };
-use embedded_graphics_profiler_display::ProfilerDisplay;
+use embedded_graphics_profiler_display::{LatencyTag, ProfilerDisplay};
 use esp_idf_hal::spi::SpiSingleDeviceDriver;
 use esp_idf_hal::{
     delay::{self, Delay},
@@ -60,6 +60,14 @@ use mipidsi::{
     Builder,
 };
 use xpt2046::Xpt2046;
+use kolibri_cyd_tester_app_embassy::countdown::{CountdownTimer, TimerEvent};
+use kolibri_cyd_tester_app_embassy::latency::LatencyStats;
+use kolibri_cyd_tester_app_embassy::microwave::PowerLevel;
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchKind, TouchTracker},
+    replay,
+    script::{self, Recorder},
+};
 
 fn lerp_fixed(start: u8, end: u8, t: u8, max_t: u8) -> u8 {
     let (start, end, t, max_t) =
@@ -71,125 +79,35 @@ fn lerp_fixed(start: u8, end: u8, t: u8, max_t: u8) -> u8 {
     result as u8
 }
 struct AppData {
-    timer_start: Instant,
-    timer_set_duration: Duration,
-    timer_remaining_duration: Duration,
-    timer_running: bool,
-    timer_paused: bool,
-    wattage_level: u8,
+    timer: CountdownTimer<fn() -> u64>,
+    power: PowerLevel,
 }
 
 impl AppData {
     fn new() -> Self {
         Self {
-            timer_start: Instant::now(),
-            timer_set_duration: Duration::from_secs(10),
-            timer_remaining_duration: Duration::from_secs(
-                10,
-            ),
-            timer_running: false,
-            timer_paused: false,
-            wattage_level: 5,
-        }
-    }
-
-    fn set_timer_duration(&mut self, duration: Duration) {
-        self.timer_set_duration = duration;
-    }
-
-    fn add_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_add(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(5999))
-                .min(Duration::from_secs(5999)),
-        );
-    }
-
-    fn sub_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_sub(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(10))
-                .max(Duration::from_secs(10)),
-        );
-    }
-
-    fn start_timer(&mut self) {
-        if !self.timer_paused {
-            self.timer_remaining_duration =
-                self.timer_set_duration;
+            timer: CountdownTimer::new(now_ms),
+            power: PowerLevel::MAX,
         }
-
-        self.timer_start = Instant::now();
-        self.timer_running = true;
-        self.timer_paused = false;
-    }
-
-    fn pause_timer(&mut self) {
-        self.timer_paused = true;
-        self.timer_remaining_duration = self
-            .timer_remaining_duration
-            .checked_sub(self.timer_start.elapsed())
-            .unwrap_or(Duration::from_secs(0));
-    }
-
-    fn reset_timer(&mut self) {
-        self.timer_start = Instant::now();
-        self.timer_paused = false;
-        self.timer_running = false;
-        self.timer_remaining_duration =
-            self.timer_set_duration;
-    }
-
-    fn remaining(&self) -> Duration {
-        if self.timer_stopped() {
-            self.timer_set_duration
-        } else if self.timer_paused() {
-            self.timer_remaining_duration
-        } else {
-            self.timer_remaining_duration
-                .checked_sub(self.timer_start.elapsed())
-                .unwrap_or(Duration::from_secs(0))
-        }
-    }
-
-    fn timer_stopped(&self) -> bool {
-        !self.timer_running
-    }
-
-    fn timer_paused(&self) -> bool {
-        self.timer_running && self.timer_paused
     }
+}
 
-    fn timer_running(&self) -> bool {
-        self.timer_running && !self.timer_paused
-    }
+/// Filled by the touch thread, read by LVGL's pointer
+/// callback.
+static TOUCH_EVENTS: TouchChannel = TouchChannel::new();
 
-    fn timer_finished(&self) -> bool {
-        self.timer_running
-            && self.remaining() == Duration::from_secs(0)
-    }
+/// Start of the clock the touch thread stamps its events
+/// with.
+static CLOCK_START: OnceLock<Instant> = OnceLock::new();
 
-    fn set_wattage_level(&mut self, level: u8) {
-        assert!(
-            level < 6,
-            "wattage level cannot be over 6"
-        );
-        self.wattage_level = level;
-    }
+/// Stamps the first frame that shows the result of a touch,
+/// in the touch thread's clock.
+static LATENCY: LatencyTag = LatencyTag::new(now_ms);
 
-    fn get_wattage_level_str(&self) -> &'static str {
-        match self.wattage_level {
-            0 => "180W",
-            1 => "220W",
-            2 => "360W",
-            3 => "480W",
-            4 => "620W",
-            5 => "800W",
-            _ => unreachable!(),
-        }
-    }
+/// Milliseconds on the touch thread's clock, also used for
+/// the timer.
+fn now_ms() -> u64 {
+    CLOCK_START.get_or_init(Instant::now).elapsed().as_millis() as u64
 }
 
 fn main() -> Result<(), LvError> {
@@ -271,7 +189,7 @@ fn main() -> Result<(), LvError> {
         .init(&mut Delay::new_default())
         .unwrap();
 
-    let mut raw_display = ProfilerDisplay::new(raw_display);
+    let mut raw_display = ProfilerDisplay::new(raw_display).with_latency_tag(&LATENCY);
 
     // Stack size value - 20,000 for 10 lines,  40,000 for
     // 20 lines let (touch_send, touch_recv) =
@@ -301,7 +219,47 @@ fn main() -> Result<(), LvError> {
     touch_driver.set_num_samples(1);
     touch_driver.init(&mut Delay::new_default()).unwrap();
 
-    let touch_driver = RefCell::new(touch_driver);
+    // Sample the touch controller on its own thread and queue
+    // the changes, so LVGL sees every press and release even
+    // if it reads the pointer less often.
+    let _touch_thread = thread::Builder::new()
+        .stack_size(8_192)
+        .spawn(move || {
+            let start = *CLOCK_START.get_or_init(Instant::now);
+            if let Some(script) = script::SCRIPT {
+                replay::play_blocking(
+                    script,
+                    &TOUCH_EVENTS,
+                    |ms| thread::sleep(Duration::from_millis(ms)),
+                    || start.elapsed().as_millis() as u64,
+                    |args| println!("{}", args),
+                );
+            }
+            if script::RECORDING {
+                println!("{}{}", script::RECORD_PREFIX, script::HEADER);
+            }
+
+            let mut tracker = TouchTracker::new();
+            let mut recorder = Recorder::new();
+            loop {
+                touch_driver.run().expect("Running Touch driver failed");
+                let touch = touch_driver.is_touched().then(|| {
+                    let point = touch_driver.get_touch_point();
+                    Point::new(point.x + 20, 240 - point.y)
+                });
+                let now = start.elapsed().as_millis() as u64;
+                if let Some(event) = tracker.update(now, touch) {
+                    if script::RECORDING {
+                        println!("{}{}", script::RECORD_PREFIX, recorder.line(&event));
+                    }
+                    if !TOUCH_EVENTS.push(event) {
+                        println!("touch event dropped: {:?}", event);
+                    }
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+        .unwrap();
 
     let _lvgl_thread = thread::Builder::new()
         .stack_size(40_000)
@@ -318,18 +276,18 @@ fn main() -> Result<(), LvError> {
 
             // Register a new input device that's capable of reading the current state of
             // the input
-            // let mut last_touch = RefCell::new(None);
+            let touch = Cell::new((false, Point::new(0, 0)));
             let _touch_screen = Pointer::register(
                 || {
-                    let mut td_ref = touch_driver.borrow_mut();
-                    td_ref.run().expect("Running Touch driver failed");
-                    if td_ref.is_touched() {
-                        let point = td_ref.get_touch_point();
-                        // println!("touched {:?}", point);
-                        PointerInputData::Touch(Point::new(point.x + 20, 240 - point.y)).pressed().once()
-                    } else {
-                        // println!("untouched");
-                        PointerInputData::Touch(Point::new(0, 0)).released().once()
+                    // one event per read, so a quick tap still
+                    // shows up as a press and a release
+                    if let Some(event) = TOUCH_EVENTS.try_receive() {
+                        LATENCY.touched(event.t_ms);
+                        touch.set((event.kind != TouchKind::Release, event.point));
+                    }
+                    match touch.get() {
+                        (true, point) => PointerInputData::Touch(point).pressed().once(),
+                        (false, point) => PointerInputData::Touch(point).released().once(),
                     }
                 },
                 &display,
@@ -353,7 +311,7 @@ fn main() -> Result<(), LvError> {
             wattage_style.set_text_color(Color::from_rgb((255, 255, 255)));
             unsafe { wattage_style.set_text_font(Font::new_raw(lvgl_sys::lv_font_montserrat_32)) };
             wattage_label.add_style(Part::Main, &mut wattage_style);
-            wattage_label.set_text(CString::new(appdata.get_wattage_level_str()).unwrap().as_c_str());
+            wattage_label.set_text(CString::new(appdata.power.label()).unwrap().as_c_str());
 
 
             let mut power_arc = Arc::create(&mut screen).unwrap();
@@ -368,11 +326,12 @@ fn main() -> Result<(), LvError> {
 
             power_arc.on_event(|arc, event| {
                 if let Event::ValueChanged = event {
+                    LATENCY.state_changed();
                     let mut value = unsafe {
                         lvgl_sys::lv_arc_get_value(arc.raw().as_mut())
                     };
-                    appdata.set_wattage_level((value as u8).min(6));
-                    wattage_label.set_text(CString::new(appdata.get_wattage_level_str()).unwrap().as_c_str());
+                    appdata.power = PowerLevel::saturating(value.max(0) as u8);
+                    wattage_label.set_text(CString::new(appdata.power.label()).unwrap().as_c_str());
                 }
             });
 
@@ -402,17 +361,15 @@ fn main() -> Result<(), LvError> {
             // Add timer control logic from timer.rs
             button_add.on_event(|_btn, event| {
                 if let Event::Pressed = event {
-                    if appdata.timer_stopped() {
-                        appdata.add_secs(10);
-                    }
+                    LATENCY.state_changed();
+                    appdata.timer.add_secs(10);
                 }
             });
 
             button_sub.on_event(|_btn, event| {
                 if let Event::Pressed = event {
-                    if appdata.timer_stopped() {
-                        appdata.sub_secs(10);
-                    }
+                    LATENCY.state_changed();
+                    appdata.timer.sub_secs(10);
                 }
             });
 
@@ -437,7 +394,8 @@ fn main() -> Result<(), LvError> {
             // Add event handlers
             button_reset.on_event(|_btn, event| {
                 if let Event::Pressed = event {
-                    appdata.reset_timer();
+                    LATENCY.state_changed();
+                    appdata.timer.reset();
                     btn_lbl4.set_text(CString::new(PLAY).unwrap().as_c_str());
                     unsafe {
                         lvgl_sys::lv_obj_clear_state(button_add.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
@@ -449,8 +407,9 @@ fn main() -> Result<(), LvError> {
 
             button_start_stop.on_event(|_btn, event| {
                 if let Event::Pressed = event {
-                    if appdata.timer_finished() {
-                        appdata.reset_timer();
+                    LATENCY.state_changed();
+                    if appdata.timer.is_finished() {
+                        appdata.timer.reset();
                         btn_lbl4.set_text(CString::new(PLAY).unwrap().as_c_str());
                         // Enable all buttons
                         unsafe {
@@ -458,8 +417,8 @@ fn main() -> Result<(), LvError> {
                             lvgl_sys::lv_obj_clear_state(button_sub.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
                             lvgl_sys::lv_obj_clear_state(power_arc.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
                         }
-                    } else if appdata.timer_running() {
-                        appdata.pause_timer();
+                    } else if appdata.timer.is_running() {
+                        appdata.timer.pause();
                         btn_lbl4.set_text(CString::new(PLAY).unwrap().as_c_str());
                         // Enable all buttons
                         unsafe {
@@ -468,30 +427,26 @@ fn main() -> Result<(), LvError> {
                             lvgl_sys::lv_obj_clear_state(power_arc.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
                         }
                     } else {
-                        appdata.start_timer();
-                        if appdata.timer_finished() {
-                            btn_lbl4.set_text(CString::new(STOP).unwrap().as_c_str());
-                        } else {
-                            btn_lbl4.set_text(CString::new(PAUSE).unwrap().as_c_str());
-                            // Disable buttons while running
-                            unsafe {
-                                lvgl_sys::lv_obj_add_state(button_add.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
-                                lvgl_sys::lv_obj_add_state(button_sub.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
-                                lvgl_sys::lv_obj_add_state(power_arc.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
-                            }
+                        appdata.timer.start();
+                        btn_lbl4.set_text(CString::new(PAUSE).unwrap().as_c_str());
+                        // Disable buttons while running
+                        unsafe {
+                            lvgl_sys::lv_obj_add_state(button_add.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
+                            lvgl_sys::lv_obj_add_state(button_sub.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
+                            lvgl_sys::lv_obj_add_state(power_arc.raw().as_mut(), lvgl_sys::LV_STATE_DISABLED as u16);
                         }
                     }
                 }
             });
 
-            let mut was_finished = false;
-            let mut last_rem_time: Duration = appdata.remaining() + Duration::from_millis(10);
+            let mut last_rem_time = Duration::from_millis(appdata.timer.remaining_ms() + 10);
 
+            let mut latency = LatencyStats::new("lvgl", "microwave-ui");
             let mut last_time = Instant::now();
             loop {
                 let start_time = Instant::now();
 
-                let dur = appdata.remaining();
+                let dur = Duration::from_millis(appdata.timer.remaining_ms());
                 if last_rem_time != dur {
                     let val = CString::new(format!("{:02}:{:02}:{:03}",
                                                     dur.as_secs() / 60,
@@ -502,10 +457,9 @@ fn main() -> Result<(), LvError> {
                     last_rem_time = dur;
                 }
 
-                if !was_finished && appdata.timer_finished() {
+                if appdata.timer.update() == Some(TimerEvent::Finished) {
                     btn_lbl4.set_text(CString::new(STOP).unwrap().as_c_str());
                 }
-                was_finished = appdata.timer_finished();
 
                 let start_draw_time = Instant::now();
                 lvgl::task_handler();
@@ -533,6 +487,11 @@ fn main() -> Result<(), LvError> {
                         (draw_time + prep_time + proc_time).as_micros() % 100, );
                 }
                 raw_display.reset_time();
+                if let Some(ms) = LATENCY.take_latency() {
+                    if latency.add(ms) {
+                        println!("{}", latency);
+                    }
+                }
 
                 delay::FreeRtos::delay_ms(2);
             }
@@ -1,5 +1,5 @@
 use std::{
-    cell::RefCell,
+    cell::Cell,
     sync::mpsc::channel,
     thread,
     time::{Duration, Instant},
@@ -39,8 +39,17 @@ use mipidsi::{
     Builder,
 };
 use xpt2046::Xpt2046;
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchKind, TouchTracker},
+    replay,
+    script::{self, Recorder},
+};
 use embedded_graphics_profiler_display::ProfilerDisplay;
 
+/// Filled by the touch thread, read by LVGL's pointer
+/// callback.
+static TOUCH_EVENTS: TouchChannel = TouchChannel::new();
+
 fn main() -> Result<(), LvError> {
     const HOR_RES: u32 = 320;
     const VER_RES: u32 = 240;
@@ -143,7 +152,46 @@ fn main() -> Result<(), LvError> {
     touch_driver.set_num_samples(1);
     touch_driver.init(&mut Delay::new_default()).unwrap();
 
-    let touch_driver = RefCell::new(touch_driver);
+    // Sample the touch controller on its own thread and queue
+    // the changes, so LVGL sees every press and release even
+    // if it reads the pointer less often.
+    let _touch_thread = thread::Builder::new()
+        .stack_size(8_192)
+        .spawn(move || {
+            let start = Instant::now();
+            if let Some(script) = script::SCRIPT {
+                replay::play_blocking(
+                    script,
+                    &TOUCH_EVENTS,
+                    |ms| thread::sleep(Duration::from_millis(ms)),
+                    || start.elapsed().as_millis() as u64,
+                    |args| println!("{}", args),
+                );
+            }
+            if script::RECORDING {
+                println!("{}{}", script::RECORD_PREFIX, script::HEADER);
+            }
+
+            let mut tracker = TouchTracker::new();
+            let mut recorder = Recorder::new();
+            loop {
+                touch_driver.run().expect("Running Touch driver failed");
+                let touch = touch_driver
+                    .is_touched()
+                    .then(|| touch_driver.get_touch_point());
+                let now = start.elapsed().as_millis() as u64;
+                if let Some(event) = tracker.update(now, touch) {
+                    if script::RECORDING {
+                        println!("{}{}", script::RECORD_PREFIX, recorder.line(&event));
+                    }
+                    if !TOUCH_EVENTS.push(event) {
+                        println!("touch event dropped: {:?}", event);
+                    }
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+        .unwrap();
 
     let _lvgl_thread = thread::Builder::new()
         .stack_size(40_000)
@@ -158,16 +206,17 @@ fn main() -> Result<(), LvError> {
 
             // Register a new input device that's capable of reading the current state of
             // the input
-            // let mut last_touch = RefCell::new(None);
+            let touch = Cell::new((false, Point::new(0, 0)));
             let _touch_screen = Pointer::register(
                 || {
-                    let mut td_ref = touch_driver.borrow_mut();
-                    td_ref.run().expect("Running Touch driver failed");
-                    if td_ref.is_touched() {
-                        let point = td_ref.get_touch_point();
-                        PointerInputData::Touch(point).pressed().once()
-                    } else {
-                        PointerInputData::Touch(Point::new(0, 0)).released().once()
+                    // one event per read, so a quick tap still
+                    // shows up as a press and a release
+                    if let Some(event) = TOUCH_EVENTS.try_receive() {
+                        touch.set((event.kind != TouchKind::Release, event.point));
+                    }
+                    match touch.get() {
+                        (true, point) => PointerInputData::Touch(point).pressed().once(),
+                        (false, point) => PointerInputData::Touch(point).released().once(),
                     }
                 },
                 &display,
@@ -1,7 +1,7 @@
 use std::{
-    cell::RefCell,
+    cell::Cell,
     cmp::min,
-    sync::mpsc::channel,
+    sync::{mpsc::channel, OnceLock},
     thread,
     time::{Duration, Instant},
 };
@@ -12,7 +12,7 @@ use embedded_graphics_core::{
     draw_target::DrawTarget,
     prelude::Point,
 };
-use embedded_graphics_profiler_display::ProfilerDisplay;
+use embedded_graphics_profiler_display::{LatencyTag, ProfilerDisplay};
 use esp_idf_hal::spi::SpiSingleDeviceDriver;
 use esp_idf_hal::{
     delay::{self, Delay},
@@ -56,105 +56,30 @@ use mipidsi::{
     Builder,
 };
 use xpt2046::Xpt2046;
+use kolibri_cyd_tester_app_embassy::countdown::{CountdownTimer, TimerEvent};
+use kolibri_cyd_tester_app_embassy::latency::LatencyStats;
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchKind, TouchTracker},
+    replay,
+    script::{self, Recorder},
+};
 
-struct AppData {
-    timer_start: Instant,
-    timer_set_duration: Duration,
-    timer_remaining_duration: Duration,
-    timer_running: bool,
-    timer_paused: bool,
-}
-
-impl AppData {
-    fn new() -> Self {
-        Self {
-            timer_start: Instant::now(),
-            timer_set_duration: Duration::from_secs(10),
-            timer_remaining_duration: Duration::from_secs(
-                10,
-            ),
-            timer_running: false,
-            timer_paused: false,
-        }
-    }
-
-    fn set_timer_duration(&mut self, duration: Duration) {
-        self.timer_set_duration = duration;
-    }
-
-    fn add_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_add(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(5999))
-                .min(Duration::from_secs(5999)),
-        );
-    }
-
-    fn sub_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_sub(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(10))
-                .max(Duration::from_secs(10)),
-        );
-    }
-
-    fn start_timer(&mut self) {
-        if !self.timer_paused {
-            self.timer_remaining_duration =
-                self.timer_set_duration;
-        }
-
-        self.timer_start = Instant::now();
-        self.timer_running = true;
-        self.timer_paused = false;
-    }
-
-    fn pause_timer(&mut self) {
-        self.timer_paused = true;
-        self.timer_remaining_duration = self
-            .timer_remaining_duration
-            .checked_sub(self.timer_start.elapsed())
-            .unwrap_or(Duration::from_secs(0));
-    }
-
-    fn reset_timer(&mut self) {
-        self.timer_start = Instant::now();
-        self.timer_paused = false;
-        self.timer_running = false;
-        self.timer_remaining_duration =
-            self.timer_set_duration;
-    }
-
-    fn remaining(&self) -> Duration {
-        if self.timer_stopped() {
-            self.timer_set_duration
-        } else if self.timer_paused() {
-            self.timer_remaining_duration
-        } else {
-            self.timer_remaining_duration
-                .checked_sub(self.timer_start.elapsed())
-                .unwrap_or(Duration::from_secs(0))
-        }
-    }
+/// Filled by the touch thread, read by LVGL's pointer
+/// callback.
+static TOUCH_EVENTS: TouchChannel = TouchChannel::new();
 
-    fn timer_stopped(&self) -> bool {
-        !self.timer_running
-    }
+/// Start of the clock the touch thread stamps its events
+/// with.
+static CLOCK_START: OnceLock<Instant> = OnceLock::new();
 
-    fn timer_paused(&self) -> bool {
-        self.timer_running && self.timer_paused
-    }
+/// Stamps the first frame that shows the result of a touch,
+/// in the touch thread's clock.
+static LATENCY: LatencyTag = LatencyTag::new(now_ms);
 
-    fn timer_running(&self) -> bool {
-        self.timer_running && !self.timer_paused
-    }
-
-    fn timer_finished(&self) -> bool {
-        self.timer_running
-            && self.remaining() == Duration::from_secs(0)
-    }
+/// Milliseconds on the touch thread's clock, also used for
+/// the timer.
+fn now_ms() -> u64 {
+    CLOCK_START.get_or_init(Instant::now).elapsed().as_millis() as u64
 }
 
 fn main() -> Result<(), LvError> {
@@ -236,7 +161,7 @@ fn main() -> Result<(), LvError> {
         .init(&mut Delay::new_default())
         .unwrap();
 
-    let mut raw_display = ProfilerDisplay::new(raw_display);
+    let mut raw_display = ProfilerDisplay::new(raw_display).with_latency_tag(&LATENCY);
 
     // Stack size value - 20,000 for 10 lines,  40,000 for
     // 20 lines let (touch_send, touch_recv) =
@@ -266,14 +191,54 @@ fn main() -> Result<(), LvError> {
     touch_driver.set_num_samples(1);
     touch_driver.init(&mut Delay::new_default()).unwrap();
 
-    let touch_driver = RefCell::new(touch_driver);
+    // Sample the touch controller on its own thread and queue
+    // the changes, so LVGL sees every press and release even
+    // if it reads the pointer less often.
+    let _touch_thread = thread::Builder::new()
+        .stack_size(8_192)
+        .spawn(move || {
+            let start = *CLOCK_START.get_or_init(Instant::now);
+            if let Some(script) = script::SCRIPT {
+                replay::play_blocking(
+                    script,
+                    &TOUCH_EVENTS,
+                    |ms| thread::sleep(Duration::from_millis(ms)),
+                    || start.elapsed().as_millis() as u64,
+                    |args| println!("{}", args),
+                );
+            }
+            if script::RECORDING {
+                println!("{}{}", script::RECORD_PREFIX, script::HEADER);
+            }
+
+            let mut tracker = TouchTracker::new();
+            let mut recorder = Recorder::new();
+            loop {
+                touch_driver.run().expect("Running Touch driver failed");
+                let touch = touch_driver.is_touched().then(|| {
+                    let point = touch_driver.get_touch_point();
+                    Point::new(point.x + 20, 240 - point.y)
+                });
+                let now = start.elapsed().as_millis() as u64;
+                if let Some(event) = tracker.update(now, touch) {
+                    if script::RECORDING {
+                        println!("{}{}", script::RECORD_PREFIX, recorder.line(&event));
+                    }
+                    if !TOUCH_EVENTS.push(event) {
+                        println!("touch event dropped: {:?}", event);
+                    }
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+        .unwrap();
 
     let _lvgl_thread = thread::Builder::new()
         .stack_size(40_000)
         .spawn(move || {
             println!("thread started");
 
-            let mut appdata = AppData::new();
+            let mut timer = CountdownTimer::new(now_ms);
 
             let buffer = DrawBuffer::<{ (HOR_RES * LINES) as usize }>::default();
             let display = Display::register(buffer, HOR_RES, VER_RES, |refresh| {
@@ -283,18 +248,18 @@ fn main() -> Result<(), LvError> {
 
             // Register a new input device that's capable of reading the current state of
             // the input
-            // let mut last_touch = RefCell::new(None);
+            let touch = Cell::new((false, Point::new(0, 0)));
             let _touch_screen = Pointer::register(
                 || {
-                    let mut td_ref = touch_driver.borrow_mut();
-                    td_ref.run().expect("Running Touch driver failed");
-                    if td_ref.is_touched() {
-                        let point = td_ref.get_touch_point();
-                        // println!("touched {:?}", point);
-                        PointerInputData::Touch(Point::new(point.x + 20, 240 - point.y)).pressed().once()
-                    } else {
-                        // println!("untouched");
-                        PointerInputData::Touch(Point::new(0, 0)).released().once()
+                    // one event per read, so a quick tap still
+                    // shows up as a press and a release
+                    if let Some(event) = TOUCH_EVENTS.try_receive() {
+                        LATENCY.touched(event.t_ms);
+                        touch.set((event.kind != TouchKind::Release, event.point));
+                    }
+                    match touch.get() {
+                        (true, point) => PointerInputData::Touch(point).pressed().once(),
+                        (false, point) => PointerInputData::Touch(point).released().once(),
                     }
                 },
                 &display,
@@ -342,10 +307,9 @@ fn main() -> Result<(), LvError> {
 
             button_add.on_event(|_btn, event| {
                 if let Event::Pressed = event {
+                    LATENCY.state_changed();
                     println!("pressed");
-                    if appdata.timer_stopped() {
-                        appdata.add_secs(10);
-                    }
+                    timer.add_secs(10);
                 }
                 // println!("Button received event: {:?}", event);
             });
@@ -360,9 +324,8 @@ fn main() -> Result<(), LvError> {
 
             button_sub.on_event(|_btn, event| {
                 if let Event::Pressed = event {
-                    if appdata.timer_stopped() {
-                        appdata.sub_secs(10);
-                    }
+                    LATENCY.state_changed();
+                    timer.sub_secs(10);
                 }
             });
 
@@ -394,42 +357,38 @@ fn main() -> Result<(), LvError> {
 
             button_reset.on_event(|_btn, event| {
                 if let Event::Pressed = event {
-                    appdata.reset_timer();
+                    LATENCY.state_changed();
+                    timer.reset();
                     btn_lbl4.set_text(CString::new(PLAY).unwrap().as_c_str());
                 }
             });
 
             button_start_stop.on_event(|_btn, event| {
                 if let Event::Pressed = event {
-                    if appdata.timer_finished() {
+                    LATENCY.state_changed();
+                    if timer.is_finished() {
                         // println!("Resetting finished timer");
-                        appdata.reset_timer();
+                        timer.reset();
                         btn_lbl4.set_text(CString::new(PLAY).unwrap().as_c_str());
-                    } else if appdata.timer_running() {
+                    } else if timer.is_running() {
                         // println!("Pausing timer");
-                        appdata.pause_timer();
+                        timer.pause();
                         btn_lbl4.set_text(CString::new(PLAY).unwrap().as_c_str());
                     } else {
                         // println!("Starting timer");
-                        appdata.start_timer();
-                        if appdata.timer_finished() {
-                            // println!("Timer already finished");
-                            btn_lbl4.set_text(CString::new(STOP).unwrap().as_c_str());
-                        } else {
-                            // println!("Timer running");
-                            btn_lbl4.set_text(CString::new(PAUSE).unwrap().as_c_str());
-                        }
+                        timer.start();
+                        btn_lbl4.set_text(CString::new(PAUSE).unwrap().as_c_str());
                     }
                 }
             });
 
-            let mut was_finished = false;
-            let mut last_rem_time: Duration = appdata.remaining() + Duration::from_millis(10);
+            let mut last_rem_time = Duration::from_millis(timer.remaining_ms() + 10);
 
+            let mut latency = LatencyStats::new("lvgl", "timer");
             let mut last_time = Instant::now();
             loop {
                 let start_time = Instant::now();
-                let rem_time = appdata.remaining();
+                let rem_time = Duration::from_millis(timer.remaining_ms());
                 if rem_time != last_rem_time {
                     let val = CString::new(format!("{:02}:{:02}:{:03}",
                                                    rem_time.as_secs() / 60,
@@ -441,11 +400,9 @@ fn main() -> Result<(), LvError> {
                 last_rem_time = rem_time;
 
 
-                if !was_finished && appdata.timer_finished() {
-                    was_finished = true;
+                if timer.update() == Some(TimerEvent::Finished) {
                     btn_lbl4.set_text(CString::new(STOP).unwrap().as_c_str());
                 }
-                was_finished = appdata.timer_finished();
 
                 let start_draw_time = Instant::now();
                 lvgl::task_handler();
@@ -478,6 +435,11 @@ fn main() -> Result<(), LvError> {
                         (draw_time + prep_time + proc_time).as_micros() % 100,            );
                 }
                 raw_display.reset_time();
+                if let Some(ms) = LATENCY.take_latency() {
+                    if latency.add(ms) {
+                        println!("{}", latency);
+                    }
+                }
 
                 delay::FreeRtos::delay_ms(2);
             }
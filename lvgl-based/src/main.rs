@@ -2,8 +2,9 @@ use cstr_core::CString;
 
 use embedded_graphics_core::draw_target::DrawTarget;
 
+use std::io::BufRead;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 use display_interface_spi::{SPIInterface};
 use esp_idf_hal::{
     delay::{self, Delay},
@@ -13,6 +14,7 @@ use esp_idf_hal::{
     units::FromValueType, // for converting 26MHz to value
 };
 
+use kolibri_cyd_tester_app_embassy::wallclock::{DateTime, TimeCommand, MIN_YEAR};
 use lvgl::font::Font;
 use lvgl::style::Style;
 use lvgl::widgets::Label;
@@ -131,22 +133,23 @@ fn main() -> Result<(), LvError> {
             // Time text will be centered in screen
             time.set_align(Align::Center, 0, 0);
 
-            let mut i = 0;
+            let mut shown = None;
 
             println!("starting lvgl loop");
             loop {
                 let start = Instant::now();
-                if i > 59 {
-                    i = 0;
+                let text = match system_time().map(DateTime::from_unix) {
+                    Some(now) => format!("{:02}:{:02}:{:02}", now.hour, now.minute, now.second),
+                    None => "--:--".to_string(),
+                };
+                if shown.as_ref() != Some(&text) {
+                    let val = CString::new(text.as_str()).unwrap();
+                    time.set_text(&val).unwrap();
+                    shown = Some(text);
                 }
 
-                let val = CString::new(format!("21:{:02}", i)).unwrap();
-                time.set_text(&val).unwrap();
-                i += 1;
-
                 lvgl::task_handler();
 
-                // Simulate clock - so sleep for one second so time text is incremented in seconds
                 delay::FreeRtos::delay_ms(1);
 
                 lvgl::tick_inc(Instant::now().duration_since(start));
@@ -154,8 +157,69 @@ fn main() -> Result<(), LvError> {
         })
         .unwrap();
 
+    // Don't exit application
+    time_console()
+}
+
+/// Seconds since 1970 of the system time, if it has been set.
+///
+/// ESP-IDF keeps the system time in the RTC; until it is set it starts at 1970.
+fn system_time() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+        .filter(|&secs| DateTime::from_unix(secs).year >= MIN_YEAR)
+}
+
+/// Sets the system time with the `time` commands of the Kolibri apps (see
+/// `wallclock::TimeCommand`), typed on the serial console.
+fn time_console() -> ! {
+    let mut stdin = std::io::stdin().lock();
+    let mut line = String::new();
     loop {
-        // Don't exit application
-        delay::FreeRtos::delay_ms(1000);
+        // reads from the console may not block, so a line can take several
+        match stdin.read_line(&mut line) {
+            Ok(_) if line.ends_with('\n') => {}
+            _ => {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+        }
+        let command = line.trim();
+        if !command.is_empty() {
+            run_time_command(command);
+        }
+        line.clear();
+    }
+}
+
+/// Runs `line` if it is a [`TimeCommand`] and prints the time.
+fn run_time_command(line: &str) {
+    match TimeCommand::parse(line) {
+        None => {
+            println!("unknown command: {}", line);
+            return;
+        }
+        Some(Ok(TimeCommand::Show)) => {}
+        Some(Ok(TimeCommand::Set(set))) => {
+            let time = esp_idf_svc::sys::timeval {
+                tv_sec: set.apply(system_time()) as _,
+                tv_usec: 0,
+            };
+            // SAFETY: `time` lives through the call, and no time zone is passed
+            if unsafe { esp_idf_svc::sys::settimeofday(&time, core::ptr::null()) } != 0 {
+                println!("time: can't set the system time");
+                return;
+            }
+        }
+        Some(Err(_)) => {
+            println!("usage: time [yyyy-mm-dd] [hh:mm[:ss]]");
+            return;
+        }
+    }
+    match system_time() {
+        Some(now) => println!("time: {}", DateTime::from_unix(now)),
+        None => println!("time: not set"),
     }
 }
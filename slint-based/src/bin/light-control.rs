@@ -3,22 +3,20 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::rc::Rc;
 use core::mem::MaybeUninit;
 use core::{cell::RefCell, cmp::min, fmt};
 use display_interface_spi::SPIInterface;
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_sync::{
-    blocking_mutex::{raw::NoopRawMutex, NoopMutex},
-    signal::Signal,
-};
+use embassy_sync::blocking_mutex::NoopMutex;
 use embassy_time::Delay;
 use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics_core::pixelcolor::Rgb565;
 use embedded_graphics_core::prelude::*;
 use embedded_graphics_core::primitives::Rectangle;
-use embedded_graphics_profiler_display::ProfilerDisplay;
+use embedded_graphics_profiler_display::{LatencyTag, ProfilerDisplay};
 use embedded_hal::digital::OutputPin;
 use embedded_hal_bus::spi::ExclusiveDevice;
 use esp_backtrace as _;
@@ -29,6 +27,11 @@ use esp_hal::{
     gpio::{
         GpioPin, Input, Io, Level, Output, Pull, NO_PIN,
     },
+    ledc::{
+        channel::{self, ChannelIFace},
+        timer::{self, TimerIFace},
+        LSGlobalClkSource, Ledc, LowSpeed,
+    },
     peripherals::{Peripherals, SPI2, SPI3},
     prelude::*,
     rtc_cntl::Rtc,
@@ -38,6 +41,16 @@ use esp_hal::{
 };
 use esp_hal_embassy::InterruptExecutor;
 use esp_println::println;
+use kolibri_cyd_tester_app_embassy::lamp::{
+    LampBackend, LampKind, PwmBackend, MAX_LAMPS,
+};
+use kolibri_cyd_tester_app_embassy::latency::LatencyStats;
+use kolibri_cyd_tester_app_embassy::output::Polarity;
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchTracker},
+    replay,
+    script::{self, Recorder},
+};
 use mipidsi::{
     models::ILI9486Rgb565,
     options::{
@@ -46,11 +59,10 @@ use mipidsi::{
     Builder, Display,
 };
 use slint::platform::software_renderer::MinimalSoftwareWindow;
-use slint::platform::{
-    Platform, PointerEventButton, WindowEvent,
-};
+use slint::platform::Platform;
+use slint::{Model, VecModel};
 use slint::private_unstable_api::re_exports::LogicalPoint;
-use slint::LogicalPosition;
+use slint_mcu_rust_template::window_event;
 use static_cell::StaticCell;
 use xpt2046::Xpt2046;
 
@@ -88,10 +100,7 @@ async fn touch_task(
         Output<'static, GpioPin<33>>,
         &'static mut Delay,
     >,
-    touch_signal: &'static Signal<
-        NoopRawMutex,
-        Option<Point>,
-    >,
+    touch_events: &'static TouchChannel,
 ) -> ! {
     let mut touch_driver = Xpt2046::new(
         spi,
@@ -103,18 +112,39 @@ async fn touch_task(
 
     esp_println::println!("touch task");
 
+    if let Some(script) = script::SCRIPT {
+        replay::play(script, touch_events).await;
+    }
+    if script::RECORDING {
+        println!(
+            "{}{}",
+            script::RECORD_PREFIX,
+            script::HEADER
+        );
+    }
+
+    let mut tracker = TouchTracker::new();
+    let mut recorder = Recorder::new();
     loop {
         touch_driver
             .run()
             .expect("Running Touch driver failed");
-        if touch_driver.is_touched() {
+        let touch = touch_driver.is_touched().then(|| {
             let point = touch_driver.get_touch_point();
-            touch_signal.signal(Some(Point::new(
-                point.x + 25,
-                240 - point.y,
-            )));
-        } else {
-            touch_signal.signal(None);
+            Point::new(point.x + 25, 240 - point.y)
+        });
+        let now = Instant::now().as_millis();
+        if let Some(event) = tracker.update(now, touch) {
+            if script::RECORDING {
+                println!(
+                    "{}{}",
+                    script::RECORD_PREFIX,
+                    recorder.line(&event)
+                );
+            }
+            if !touch_events.push(event) {
+                println!("touch event dropped: {:?}", event);
+            }
         }
         Timer::after(Duration::from_millis(1)).await; // 100 a second
 
@@ -122,10 +152,6 @@ async fn touch_task(
     }
 }
 
-fn point_to_logical_pos(point: Point) -> LogicalPosition {
-    LogicalPosition::new(point.x as f32, point.y as f32)
-}
-
 struct CYDPlatform {
     window: Rc<slint::platform::software_renderer::MinimalSoftwareWindow>,
 }
@@ -154,6 +180,18 @@ impl Platform for CYDPlatform {
     }
 }
 
+/// A change of a light the UI asks the lamp backend for.
+#[derive(Debug, Clone, Copy)]
+enum LampRequest {
+    On(usize, bool),
+    Brightness(usize, u8),
+}
+
+/// Stamps the first frame that shows the result of a touch,
+/// in the touch task's clock.
+static LATENCY: LatencyTag =
+    LatencyTag::new(|| Instant::now().as_millis());
+
 #[main]
 async fn main(spawner: Spawner) {
     init_heap();
@@ -207,11 +245,10 @@ async fn main(spawner: Spawner) {
     )
     .unwrap();
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<
-        Signal<NoopRawMutex, Option<Point>>,
-    > = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
+    static TOUCH_EVENTS: StaticCell<TouchChannel> =
+        StaticCell::new();
+    let touch_events =
+        &*TOUCH_EVENTS.init(TouchChannel::new());
 
     // let sw_int = system.software_interrupt_control.software_interrupt2;
 
@@ -223,7 +260,7 @@ async fn main(spawner: Spawner) {
         .spawn(touch_task(
             touch_irq,
             touch_spi,
-            touch_signal,
+            touch_events,
         ))
         .unwrap();
 
@@ -278,7 +315,8 @@ async fn main(spawner: Spawner) {
         .init(&mut embassy_time::Delay)
         .unwrap();
 
-    let mut display = ProfilerDisplay::new(display);
+    let mut display = ProfilerDisplay::new(display)
+        .with_latency_tag(&LATENCY);
 
     backlight.set_high();
 
@@ -294,6 +332,77 @@ async fn main(spawner: Spawner) {
 
     let ui = create_slint_app();
 
+    // the first three lamps are the colours of the RGB LED
+    let mut ledc = Ledc::new(peripherals.LEDC, &clocks);
+    ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+    let mut led_timer =
+        ledc.get_timer::<LowSpeed>(timer::Number::Timer0);
+    led_timer
+        .configure(timer::config::Config {
+            duty: timer::config::Duty::Duty8Bit,
+            clock_source: timer::LSClockSource::APBClk,
+            frequency: 1.kHz(),
+        })
+        .unwrap();
+    let led_config = channel::config::Config {
+        timer: &led_timer,
+        // the LED is active low: off
+        duty_pct: 100,
+        pin_config: channel::config::PinConfig::PushPull,
+    };
+    let mut red = ledc.get_channel(channel::Number::Channel0, io.pins.gpio4);
+    red.configure(led_config).unwrap();
+    let mut green = ledc.get_channel(channel::Number::Channel1, io.pins.gpio16);
+    green.configure(led_config).unwrap();
+    let mut blue = ledc.get_channel(channel::Number::Channel2, io.pins.gpio17);
+    blue.configure(led_config).unwrap();
+    let channels: [&mut dyn embedded_hal::pwm::SetDutyCycle<
+        Error = channel::Error,
+    >; 3] = [&mut red, &mut green, &mut blue];
+
+    // the lights start out as the .slint file has them; from
+    // then on they show what the backend confirmed
+    let lights = Rc::new(VecModel::from(
+        ui.get_lights().iter().collect::<alloc::vec::Vec<_>>(),
+    ));
+    ui.set_lights(lights.clone().into());
+    // the UI only dims them, so each is a channel of the LED
+    let kinds = [LampKind::Dimmable; MAX_LAMPS];
+    let mut lamps = PwmBackend::new(
+        channels,
+        Polarity::ActiveLow,
+        &kinds[..lights.row_count().min(MAX_LAMPS)],
+    )
+    .unwrap();
+    for (i, light) in lights.iter().enumerate() {
+        lamps.set_brightness(i, light.brightness.clamp(0, 255) as u8).ok();
+        lamps.set_on(i, light.on).ok();
+    }
+    while lamps.poll().is_some() {}
+
+    // the callbacks outlive the backend, which borrows the
+    // LEDC, so they only queue the requests
+    let requests = Rc::new(RefCell::new(VecDeque::new()));
+    ui.on_set_light_on({
+        let requests = requests.clone();
+        move |i, on| {
+            requests
+                .borrow_mut()
+                .push_back(LampRequest::On(i as usize, on))
+        }
+    });
+    ui.on_set_light_brightness({
+        let requests = requests.clone();
+        move |i, brightness| {
+            requests.borrow_mut().push_back(
+                LampRequest::Brightness(
+                    i as usize,
+                    brightness.clamp(0, 255) as u8,
+                ),
+            )
+        }
+    });
+
     window.set_size(slint::PhysicalSize::new(320, 240));
 
     let mut buffer_provider = DrawBuffer {
@@ -301,45 +410,36 @@ async fn main(spawner: Spawner) {
         buffer: &mut [slint::platform::software_renderer::Rgb565Pixel(0); 320],
     };
 
-    let mut last_touch = None;
+    let mut latency = LatencyStats::new("slint", "light-control");
 
     loop {
         let start_time = Instant::now();
-        if let Some(touch) = touch_signal.try_take() {
-            // println!("touch: {:?}, last_touch: {:?}", touch, last_touch);
-            let button = PointerEventButton::Left;
-            let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => {
-                    Some(WindowEvent::PointerMoved {
-                        position: point_to_logical_pos(
-                            point,
-                        ),
-                    })
-                }
-                (Some(point), None) => {
-                    Some(WindowEvent::PointerPressed {
-                        position: point_to_logical_pos(
-                            point,
-                        ),
-                        button,
-                    })
-                }
-                (None, Some(point)) => {
-                    Some(WindowEvent::PointerReleased {
-                        position: point_to_logical_pos(
-                            point,
-                        ),
-                        button,
-                    })
+        while let Some(event) = touch_events.try_receive() {
+            LATENCY.touched(event.t_ms);
+            window.dispatch_event(window_event(&event));
+            // the callbacks don't tell which touches change a
+            // light, and without animations Slint only redraws
+            // after a change, so every touch counts as one
+            LATENCY.state_changed();
+        }
+
+        while let Some(request) = requests.borrow_mut().pop_front() {
+            let result = match request {
+                LampRequest::On(i, on) => lamps.set_on(i, on),
+                LampRequest::Brightness(i, brightness) => {
+                    lamps.set_brightness(i, brightness)
                 }
-                (None, None) => None,
             };
-            if let Some(event) = interact {
-                // println!("event: {:?}", event);
-                window.dispatch_event(event);
+            if let Err(e) = result {
+                println!("{:?}: {:?}", request, e);
+            }
+        }
+        while let Some(event) = lamps.poll() {
+            if let Some(mut light) = lights.row_data(event.lamp) {
+                light.on = event.state.on;
+                light.brightness = event.state.brightness as i32;
+                lights.set_row_data(event.lamp, light);
             }
-
-            last_touch = touch;
         }
 
         slint::platform::update_timers_and_animations();
@@ -355,6 +455,11 @@ async fn main(spawner: Spawner) {
         }
 
         let display = &mut buffer_provider.display;
+        if let Some(ms) = LATENCY.take_latency() {
+            if latency.add(ms) {
+                println!("{}", latency);
+            }
+        }
 
         let end_time = embassy_time::Instant::now();
         let draw_time = display.get_time();
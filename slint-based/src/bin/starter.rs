@@ -9,10 +9,7 @@ use core::{cell::RefCell, cmp::min, fmt};
 use display_interface_spi::SPIInterface;
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 use embassy_executor::Spawner;
-use embassy_sync::{
-    blocking_mutex::{raw::NoopRawMutex, NoopMutex},
-    signal::Signal,
-};
+use embassy_sync::blocking_mutex::NoopMutex;
 use embassy_time::Delay;
 use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics_core::pixelcolor::Rgb565;
@@ -36,6 +33,11 @@ use esp_hal::{
 };
 use esp_hal_embassy::InterruptExecutor;
 use esp_println::println;
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchTracker},
+    replay,
+    script::{self, Recorder},
+};
 use mipidsi::{
     models::ILI9486Rgb565,
     options::{ColorInversion, ColorOrder, Orientation, Rotation},
@@ -43,9 +45,9 @@ use mipidsi::{
 };
 use panic_halt as _;
 use slint::platform::software_renderer::MinimalSoftwareWindow;
-use slint::platform::{Platform, PointerEventButton, WindowEvent};
+use slint::platform::{Platform, PointerEventButton};
 use slint::private_unstable_api::re_exports::LogicalPoint;
-use slint::LogicalPosition;
+use slint_mcu_rust_template::window_event;
 use static_cell::StaticCell;
 use xpt2046::Xpt2046;
 
@@ -79,7 +81,7 @@ async fn touch_task(
         Output<'static, GpioPin<33>>,
         &'static mut Delay,
     >,
-    touch_signal: &'static Signal<NoopRawMutex, Option<Point>>,
+    touch_events: &'static TouchChannel,
 ) -> ! {
     let mut touch_driver =
         Xpt2046::new(spi, Input::new(touch_irq, Pull::Up), xpt2046::Orientation::LandscapeFlipped);
@@ -88,13 +90,28 @@ async fn touch_task(
 
     esp_println::println!("touch task");
 
+    if let Some(script) = script::SCRIPT {
+        replay::play(script, touch_events).await;
+    }
+    if script::RECORDING {
+        println!("{}{}", script::RECORD_PREFIX, script::HEADER);
+    }
+
+    let mut tracker = TouchTracker::new();
+    let mut recorder = Recorder::new();
     loop {
         touch_driver.run().expect("Running Touch driver failed");
-        if touch_driver.is_touched() {
+        let touch = touch_driver.is_touched().then(|| {
             let point = touch_driver.get_touch_point();
-            touch_signal.signal(Some(Point::new(point.x + 25, 240 - point.y)));
-        } else {
-            touch_signal.signal(None);
+            Point::new(point.x + 25, 240 - point.y)
+        });
+        if let Some(event) = tracker.update(Instant::now().as_millis(), touch) {
+            if script::RECORDING {
+                println!("{}{}", script::RECORD_PREFIX, recorder.line(&event));
+            }
+            if !touch_events.push(event) {
+                println!("touch event dropped: {:?}", event);
+            }
         }
         Timer::after(Duration::from_millis(1)).await; // 100 a second
 
@@ -102,10 +119,6 @@ async fn touch_task(
     }
 }
 
-fn point_to_logical_pos(point: Point) -> LogicalPosition {
-    LogicalPosition::new(point.x as f32, point.y as f32)
-}
-
 struct CYDPlatform {
     window: Rc<slint::platform::software_renderer::MinimalSoftwareWindow>,
 }
@@ -127,101 +140,6 @@ impl Platform for CYDPlatform {
     }
 }
 
-struct AppData {
-    timer_start: Instant,
-    timer_set_duration: Duration,
-    timer_remaining_duration: Duration,
-    timer_running: bool,
-    timer_paused: bool,
-}
-
-impl AppData {
-    fn new() -> Self {
-        Self {
-            timer_start: Instant::now(),
-            timer_set_duration: Duration::from_secs(10),
-            timer_remaining_duration: Duration::from_secs(10),
-            timer_running: false,
-            timer_paused: false,
-        }
-    }
-
-    fn set_timer_duration(&mut self, duration: Duration) {
-        self.timer_set_duration = duration;
-    }
-
-    fn add_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_add(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(5999))
-                .min(Duration::from_secs(5999)),
-        );
-    }
-
-    fn sub_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_sub(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(10))
-                .max(Duration::from_secs(10)),
-        );
-    }
-
-    fn start_timer(&mut self) {
-        if !self.timer_paused {
-            self.timer_remaining_duration = self.timer_set_duration;
-        }
-
-        self.timer_start = Instant::now();
-        self.timer_running = true;
-        self.timer_paused = false;
-    }
-
-    fn pause_timer(&mut self) {
-        self.timer_paused = true;
-        self.timer_remaining_duration = self
-            .timer_remaining_duration
-            .checked_sub(self.timer_start.elapsed())
-            .unwrap_or(Duration::from_secs(0));
-    }
-
-    fn reset_timer(&mut self) {
-        self.timer_start = Instant::now();
-        self.timer_paused = false;
-        self.timer_running = false;
-        self.timer_remaining_duration = self.timer_set_duration;
-    }
-
-    fn remaining(&self) -> Duration {
-        if self.timer_stopped() {
-            self.timer_set_duration
-        } else if self.timer_paused() {
-            self.timer_remaining_duration
-        } else {
-            self.timer_remaining_duration
-                .checked_sub(self.timer_start.elapsed())
-                .unwrap_or(Duration::from_secs(0))
-        }
-    }
-
-    fn timer_stopped(&self) -> bool {
-        !self.timer_running
-    }
-
-    fn timer_paused(&self) -> bool {
-        self.timer_running && self.timer_paused
-    }
-
-    fn timer_running(&self) -> bool {
-        self.timer_running && !self.timer_paused
-    }
-
-    fn timer_finished(&self) -> bool {
-        self.timer_running && self.remaining() == Duration::from_secs(0)
-    }
-}
-
 #[main]
 async fn main(spawner: Spawner) {
     init_heap();
@@ -260,9 +178,8 @@ async fn main(spawner: Spawner) {
     let touch_spi =
         ExclusiveDevice::new(touch_spi, Output::new(touch_cs, Level::Low), delay).unwrap();
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<Signal<NoopRawMutex, Option<Point>>> = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
+    static TOUCH_EVENTS: StaticCell<TouchChannel> = StaticCell::new();
+    let touch_events = &*TOUCH_EVENTS.init(TouchChannel::new());
 
     // let sw_int = system.software_interrupt_control.software_interrupt2;
 
@@ -270,7 +187,7 @@ async fn main(spawner: Spawner) {
     // let executor = InterruptExecutor::<2>::new(sw_int);
     // let executor = EXECUTOR.init(executor);
 
-    spawner.spawn(touch_task(touch_irq, touch_spi, touch_signal)).unwrap();
+    spawner.spawn(touch_task(touch_irq, touch_spi, touch_events)).unwrap();
 
     // executor.start(Priority::Priority1);
 
@@ -325,33 +242,10 @@ async fn main(spawner: Spawner) {
         buffer: &mut [slint::platform::software_renderer::Rgb565Pixel(0); 320],
     };
 
-    let mut last_touch = None;
-
     loop {
         let start_time = Instant::now();
-        if let Some(touch) = touch_signal.try_take() {
-            // println!("touch: {:?}, last_touch: {:?}", touch, last_touch);
-            let button = PointerEventButton::Left;
-            let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => {
-                    Some(WindowEvent::PointerMoved { position: point_to_logical_pos(point) })
-                }
-                (Some(point), None) => Some(WindowEvent::PointerPressed {
-                    position: point_to_logical_pos(point),
-                    button,
-                }),
-                (None, Some(point)) => Some(WindowEvent::PointerReleased {
-                    position: point_to_logical_pos(point),
-                    button,
-                }),
-                (None, None) => None,
-            };
-            if let Some(event) = interact {
-                // println!("event: {:?}", event);
-                window.dispatch_event(event);
-            }
-
-            last_touch = touch;
+        while let Some(event) = touch_events.try_receive() {
+            window.dispatch_event(window_event(&event));
         }
 
         slint::platform::update_timers_and_animations();
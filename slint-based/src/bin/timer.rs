@@ -8,14 +8,12 @@ use core::mem::MaybeUninit;
 use core::{cell::RefCell, cmp::min};
 use display_interface_spi::SPIInterface;
 use embassy_executor::Spawner;
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::signal::Signal;
 use embassy_time::Delay;
 use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics_core::pixelcolor::Rgb565;
 use embedded_graphics_core::prelude::*;
 use embedded_graphics_core::primitives::Rectangle;
-use embedded_graphics_profiler_display::ProfilerDisplay;
+use embedded_graphics_profiler_display::{LatencyTag, ProfilerDisplay};
 use embedded_hal::digital::OutputPin;
 use embedded_hal_bus::spi::ExclusiveDevice;
 use esp_hal::{self, prelude::*};
@@ -32,15 +30,23 @@ use esp_hal::{
     timer::timg::TimerGroup,
 };
 use esp_println::println;
+use kolibri_cyd_tester_app_embassy::countdown::{
+    CountdownTimer, EmbassyClock,
+};
+use kolibri_cyd_tester_app_embassy::latency::LatencyStats;
+use kolibri_cyd_tester_app_embassy::touch::{
+    event::{TouchChannel, TouchTracker},
+    replay,
+    script::{self, Recorder},
+};
 use mipidsi::{
     options::{ColorOrder, Orientation, Rotation},
     Builder,
 };
 use slint::platform::software_renderer::MinimalSoftwareWindow;
-use slint::platform::{
-    Platform, PointerEventButton, WindowEvent,
-};
-use slint::{format, LogicalPosition};
+use slint::platform::Platform;
+use slint::format;
+use slint_mcu_rust_template::window_event;
 use static_cell::StaticCell;
 use xpt2046::Xpt2046;
 
@@ -77,10 +83,7 @@ async fn touch_task(
         Output<'static, GpioPin<33>>,
         &'static mut Delay,
     >,
-    touch_signal: &'static Signal<
-        CriticalSectionRawMutex,
-        Option<Point>,
-    >,
+    touch_events: &'static TouchChannel,
 ) -> ! {
     let mut touch_driver = Xpt2046::new(
         spi,
@@ -92,18 +95,39 @@ async fn touch_task(
 
     esp_println::println!("touch task");
 
+    if let Some(script) = script::SCRIPT {
+        replay::play(script, touch_events).await;
+    }
+    if script::RECORDING {
+        println!(
+            "{}{}",
+            script::RECORD_PREFIX,
+            script::HEADER
+        );
+    }
+
+    let mut tracker = TouchTracker::new();
+    let mut recorder = Recorder::new();
     loop {
         touch_driver
             .run()
             .expect("Running Touch driver failed");
-        if touch_driver.is_touched() {
+        let touch = touch_driver.is_touched().then(|| {
             let point = touch_driver.get_touch_point();
-            touch_signal.signal(Some(Point::new(
-                point.x + 25,
-                240 - point.y,
-            )));
-        } else {
-            touch_signal.signal(None);
+            Point::new(point.x + 25, 240 - point.y)
+        });
+        let now = Instant::now().as_millis();
+        if let Some(event) = tracker.update(now, touch) {
+            if script::RECORDING {
+                println!(
+                    "{}{}",
+                    script::RECORD_PREFIX,
+                    recorder.line(&event)
+                );
+            }
+            if !touch_events.push(event) {
+                println!("touch event dropped: {:?}", event);
+            }
         }
         Timer::after(Duration::from_millis(1)).await; // 100 a second
 
@@ -111,10 +135,6 @@ async fn touch_task(
     }
 }
 
-fn point_to_logical_pos(point: Point) -> LogicalPosition {
-    LogicalPosition::new(point.x as f32, point.y as f32)
-}
-
 struct CYDPlatform {
     window: Rc<slint::platform::software_renderer::MinimalSoftwareWindow>,
 }
@@ -143,105 +163,10 @@ impl Platform for CYDPlatform {
     }
 }
 
-struct AppData {
-    timer_start: Instant,
-    timer_set_duration: Duration,
-    timer_remaining_duration: Duration,
-    timer_running: bool,
-    timer_paused: bool,
-}
-
-impl AppData {
-    fn new() -> Self {
-        Self {
-            timer_start: Instant::now(),
-            timer_set_duration: Duration::from_secs(10),
-            timer_remaining_duration: Duration::from_secs(
-                10,
-            ),
-            timer_running: false,
-            timer_paused: false,
-        }
-    }
-
-    fn set_timer_duration(&mut self, duration: Duration) {
-        self.timer_set_duration = duration;
-    }
-
-    fn add_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_add(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(5999))
-                .min(Duration::from_secs(5999)),
-        );
-    }
-
-    fn sub_secs(&mut self, secs: u64) {
-        self.set_timer_duration(
-            self.timer_set_duration
-                .checked_sub(Duration::from_secs(secs))
-                .unwrap_or(Duration::from_secs(10))
-                .max(Duration::from_secs(10)),
-        );
-    }
-
-    fn start_timer(&mut self) {
-        if !self.timer_paused {
-            self.timer_remaining_duration =
-                self.timer_set_duration;
-        }
-
-        self.timer_start = Instant::now();
-        self.timer_running = true;
-        self.timer_paused = false;
-    }
-
-    fn pause_timer(&mut self) {
-        self.timer_paused = true;
-        self.timer_remaining_duration = self
-            .timer_remaining_duration
-            .checked_sub(self.timer_start.elapsed())
-            .unwrap_or(Duration::from_secs(0));
-    }
-
-    fn reset_timer(&mut self) {
-        self.timer_start = Instant::now();
-        self.timer_paused = false;
-        self.timer_running = false;
-        self.timer_remaining_duration =
-            self.timer_set_duration;
-    }
-
-    fn remaining(&self) -> Duration {
-        if self.timer_stopped() {
-            self.timer_set_duration
-        } else if self.timer_paused() {
-            self.timer_remaining_duration
-        } else {
-            self.timer_remaining_duration
-                .checked_sub(self.timer_start.elapsed())
-                .unwrap_or(Duration::from_secs(0))
-        }
-    }
-
-    fn timer_stopped(&self) -> bool {
-        !self.timer_running
-    }
-
-    fn timer_paused(&self) -> bool {
-        self.timer_running && self.timer_paused
-    }
-
-    fn timer_running(&self) -> bool {
-        self.timer_running && !self.timer_paused
-    }
-
-    fn timer_finished(&self) -> bool {
-        self.timer_running
-            && self.remaining() == Duration::from_secs(0)
-    }
-}
+/// Stamps the first frame that shows the result of a touch,
+/// in the touch task's clock.
+static LATENCY: LatencyTag =
+    LatencyTag::new(|| Instant::now().as_millis());
 
 #[main]
 async fn main(spawner: Spawner) {
@@ -296,11 +221,10 @@ async fn main(spawner: Spawner) {
     )
     .unwrap();
 
-    let touch_signal = Signal::new();
-    static TOUCH_SIGNAL: StaticCell<
-        Signal<CriticalSectionRawMutex, Option<Point>>,
-    > = StaticCell::new();
-    let touch_signal = &*TOUCH_SIGNAL.init(touch_signal);
+    static TOUCH_EVENTS: StaticCell<TouchChannel> =
+        StaticCell::new();
+    let touch_events =
+        &*TOUCH_EVENTS.init(TouchChannel::new());
 
     // let sw_int = system.software_interrupt_control.software_interrupt2;
 
@@ -319,7 +243,7 @@ async fn main(spawner: Spawner) {
         .spawn(touch_task(
             touch_irq,
             touch_spi,
-            touch_signal,
+            touch_events,
         ))
         .unwrap();
 
@@ -372,7 +296,8 @@ async fn main(spawner: Spawner) {
         .init(&mut embassy_time::Delay)
         .unwrap();
 
-    let mut display = ProfilerDisplay::new(display);
+    let mut display = ProfilerDisplay::new(display)
+        .with_latency_tag(&LATENCY);
 
     backlight.set_high();
 
@@ -395,85 +320,59 @@ async fn main(spawner: Spawner) {
         buffer: &mut [slint::platform::software_renderer::Rgb565Pixel(0); 320],
     };
 
-    let mut last_touch = None;
+    let timer =
+        Rc::new(RefCell::new(CountdownTimer::new(EmbassyClock)));
 
-    let mut appdata = Rc::new(RefCell::new(AppData::new()));
-
-    let mut cl_appdata = appdata.clone();
+    let cl_timer = timer.clone();
     ui.on_add_10s(move || {
-        cl_appdata.borrow_mut().add_secs(10)
+        if cl_timer.borrow_mut().add_secs(10) {
+            LATENCY.state_changed();
+        }
     });
-    let mut cl_appdata = appdata.clone();
+    let cl_timer = timer.clone();
     ui.on_sub_10s(move || {
-        cl_appdata.borrow_mut().sub_secs(10)
+        if cl_timer.borrow_mut().sub_secs(10) {
+            LATENCY.state_changed();
+        }
     });
-    let mut cl_appdata = appdata.clone();
+    let cl_timer = timer.clone();
     ui.on_start_timer(move || {
-        cl_appdata.borrow_mut().start_timer()
+        if cl_timer.borrow_mut().start().is_some() {
+            LATENCY.state_changed();
+        }
     });
-    let mut cl_appdata = appdata.clone();
+    let cl_timer = timer.clone();
     ui.on_stop_timer(move || {
-        cl_appdata.borrow_mut().pause_timer()
+        if cl_timer.borrow_mut().pause().is_some() {
+            LATENCY.state_changed();
+        }
     });
-    let mut cl_appdata = appdata.clone();
+    let cl_timer = timer.clone();
     ui.on_reset_timer(move || {
-        cl_appdata.borrow_mut().reset_timer()
+        if cl_timer.borrow_mut().reset().is_some() {
+            LATENCY.state_changed();
+        }
     });
 
+    let mut latency = LatencyStats::new("slint", "timer");
+
     loop {
         let start_time = Instant::now();
-        if let Some(touch) = touch_signal.try_take() {
-            // println!("touch: {:?}, last_touch: {:?}", touch, last_touch);
-            let button = PointerEventButton::Left;
-            let interact = match (touch, last_touch) {
-                (Some(point), Some(_)) => {
-                    Some(WindowEvent::PointerMoved {
-                        position: point_to_logical_pos(
-                            point,
-                        ),
-                    })
-                }
-                (Some(point), None) => {
-                    Some(WindowEvent::PointerPressed {
-                        position: point_to_logical_pos(
-                            point,
-                        ),
-                        button,
-                    })
-                }
-                (None, Some(point)) => {
-                    Some(WindowEvent::PointerReleased {
-                        position: point_to_logical_pos(
-                            point,
-                        ),
-                        button,
-                    })
-                }
-                (None, None) => None,
-            };
-            if let Some(event) = interact {
-                // println!("event: {:?}", event);
-                window.dispatch_event(event);
-            }
-
-            last_touch = touch;
+        while let Some(event) = touch_events.try_receive() {
+            LATENCY.touched(event.t_ms);
+            window.dispatch_event(window_event(&event));
         }
 
         {
-            let appdata = appdata.borrow();
-            let remaining = appdata.remaining();
-            ui.set_show_reset_timer(
-                appdata.timer_finished(),
-            );
+            let mut timer = timer.borrow_mut();
+            timer.update();
+            let remaining =
+                Duration::from_millis(timer.remaining_ms());
+            ui.set_show_reset_timer(timer.is_finished());
             ui.set_show_start_timer(
-                !appdata.timer_running()
-                    || appdata.timer_paused()
-                        && !appdata.timer_finished(),
-            );
-            ui.set_show_stop_timer(
-                appdata.timer_running()
-                    && !appdata.timer_finished(),
+                timer.is_stopped() || timer.is_paused(),
             );
+            ui.set_show_stop_timer(timer.is_running());
 
             ui.set_timer_text(format!(
                 "{:02}:{:02}:{:03}",
@@ -497,6 +396,11 @@ async fn main(spawner: Spawner) {
         }
 
         let display = &mut buffer_provider.display;
+        if let Some(ms) = LATENCY.take_latency() {
+            if latency.add(ms) {
+                println!("{}", latency);
+            }
+        }
 
         let end_time = embassy_time::Instant::now();
         let draw_time = display.get_time();
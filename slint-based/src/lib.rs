@@ -3,6 +3,33 @@
 
 extern crate alloc;
 
+use kolibri_cyd_tester_app_embassy::touch::event::{
+    TouchEvent, TouchKind,
+};
+use slint::platform::{PointerEventButton, WindowEvent};
+use slint::LogicalPosition;
+
 // mod cyd;
 
 // pub use cyd::*;
+
+/// The Slint pointer event for an event from the shared
+/// touch channel.
+pub fn window_event(event: &TouchEvent) -> WindowEvent {
+    let position = LogicalPosition::new(
+        event.point.x as f32,
+        event.point.y as f32,
+    );
+    let button = PointerEventButton::Left;
+    match event.kind {
+        TouchKind::Press => {
+            WindowEvent::PointerPressed { position, button }
+        }
+        TouchKind::Move => {
+            WindowEvent::PointerMoved { position }
+        }
+        TouchKind::Release => {
+            WindowEvent::PointerReleased { position, button }
+        }
+    }
+}